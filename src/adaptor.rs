@@ -0,0 +1,405 @@
+//! Adaptor signatures.
+//!
+//! An adaptor signature (or pre-signature) for an adaptor point `T = t*G` can be
+//! turned into a valid signature by anyone who knows `t`, and the published signature
+//! reveals `t` to anyone who knows the pre-signature.
+//!
+//! Schnorr pre-signatures are serialized as `R' || s'` (65 bytes) where the final
+//! BIP-340 signature has nonce `R = R' + T`. ECDSA pre-signatures follow the scheme
+//! by Lloyd Fournier and are serialized as `R || R_a || s' || e || z` (162 bytes)
+//! where `e, z` is a DLEQ proof that `R_a` and `R` share the same discrete log
+//! relative to `G` and `T`. The proof uses the "hal/adaptor/dleq" tagged hash, so
+//! these can't be used with secp256k1-zkp or any other implementation, even though
+//! they have the same size as the ones of secp256k1-zkp.
+
+use secp256k1::{ecdsa, schnorr, Message, Parity, PublicKey, SecretKey, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+
+use crate::scalar::{add, from_bytes, inv, mul, reduce, scalar, schnorr_challenge, sub, tagged_hash};
+use crate::{HexBytes, SECP};
+
+pub const SCHNORR_PRESIGNATURE_SIZE: usize = 65;
+pub const ECDSA_PRESIGNATURE_SIZE: usize = 162;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SchnorrPreSignature {
+	/// The signer's nonce point R'.
+	pub nonce: PublicKey,
+	pub s: SecretKey,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EcdsaPreSignature {
+	/// The nonce point R = k*T.
+	pub nonce: PublicKey,
+	/// The nonce point R_a = k*G.
+	pub nonce_g: PublicKey,
+	pub s: SecretKey,
+	pub proof_e: SecretKey,
+	pub proof_z: SecretKey,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PreSignature {
+	Schnorr(SchnorrPreSignature),
+	Ecdsa(EcdsaPreSignature),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct PreSignatureInfo {
+	#[serde(rename = "type")]
+	pub type_: String,
+	pub pre_signature: HexBytes,
+	pub nonce: HexBytes,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub nonce_g: Option<HexBytes>,
+	/// The r value or R x-coordinate of the final signature.
+	pub final_r: HexBytes,
+	pub s: HexBytes,
+}
+
+fn point(sk: &SecretKey) -> PublicKey {
+	PublicKey::from_secret_key(&SECP, sk)
+}
+
+fn point_mul(p: &PublicKey, sk: &SecretKey) -> PublicKey {
+	p.mul_tweak(&SECP, &scalar(sk)).expect("non-zero scalar")
+}
+
+fn combine(a: &PublicKey, b: &PublicKey) -> Result<PublicKey, String> {
+	a.combine(b).map_err(|_| "point at infinity".to_owned())
+}
+
+/// Derive a nonce from the secret key, auxiliary randomness and the other inputs.
+fn derive_nonce(tag: &str, sk: &SecretKey, aux: &[u8; 32], data: &[&[u8]]) -> SecretKey {
+	for counter in 0u32.. {
+		let mut input: Vec<&[u8]> = vec![&sk[..], aux];
+		input.extend_from_slice(data);
+		let counter_bytes = counter.to_be_bytes();
+		input.push(&counter_bytes);
+		if let Ok(k) = from_bytes(&tagged_hash(tag, &input)) {
+			return k;
+		}
+	}
+	unreachable!()
+}
+
+/// The x-coordinate of the point reduced modulo the curve order.
+fn x_scalar(p: &PublicKey) -> Result<SecretKey, String> {
+	let mut x = [0u8; 32];
+	x.copy_from_slice(&p.serialize()[1..]);
+	reduce(&x).ok_or_else(|| "nonce x-coordinate is zero modulo the curve order".to_owned())
+}
+
+/// The ECDSA message scalar z: the digest reduced modulo the curve order, [None] if zero.
+fn message_scalar(msg: &Message) -> Option<SecretKey> {
+	let mut z = [0u8; 32];
+	z.copy_from_slice(msg.as_ref());
+	reduce(&z)
+}
+
+fn from_slice(bytes: &[u8]) -> Result<SecretKey, String> {
+	let mut arr = [0u8; 32];
+	arr.copy_from_slice(bytes);
+	from_bytes(&arr)
+}
+
+fn to_array(sk: &SecretKey) -> [u8; 32] {
+	sk.secret_bytes()
+}
+
+impl SchnorrPreSignature {
+	pub fn serialize(&self) -> Vec<u8> {
+		let mut ret = self.nonce.serialize().to_vec();
+		ret.extend_from_slice(&self.s[..]);
+		ret
+	}
+
+	pub fn from_slice(bytes: &[u8]) -> Result<SchnorrPreSignature, String> {
+		if bytes.len() != SCHNORR_PRESIGNATURE_SIZE {
+			return Err(format!("invalid Schnorr pre-signature length: {}", bytes.len()));
+		}
+		Ok(SchnorrPreSignature {
+			nonce: PublicKey::from_slice(&bytes[0..33]).map_err(|e| format!("invalid nonce: {}", e))?,
+			s: SecretKey::from_slice(&bytes[33..65]).map_err(|e| format!("invalid s value: {}", e))?,
+		})
+	}
+
+	/// The final nonce R = R' + T.
+	fn final_nonce(&self, adaptor: &PublicKey) -> Result<PublicKey, String> {
+		combine(&self.nonce, adaptor)
+	}
+
+	/// Create a Schnorr pre-signature for the message and adaptor point.
+	pub fn create(
+		secret_key: &SecretKey,
+		msg: &Message,
+		adaptor: &PublicKey,
+		aux: &[u8; 32],
+	) -> SchnorrPreSignature {
+		let (pubkey, parity) = point(secret_key).x_only_public_key();
+		let x = if parity == Parity::Odd { secret_key.negate() } else { *secret_key };
+		let msg = msg.as_ref();
+
+		// Find a nonce such that the final nonce R = R' + T has an even y-coordinate.
+		let mut aux = *aux;
+		loop {
+			let k = derive_nonce("hal/adaptor/schnorr", &x, &aux, &[&adaptor.serialize(), msg]);
+			let nonce = point(&k);
+			let r = match combine(&nonce, adaptor) {
+				Ok(r) => r.x_only_public_key(),
+				Err(_) => (pubkey, Parity::Odd),
+			};
+			if r.1 == Parity::Even {
+				let e = schnorr_challenge(&r.0.serialize(), &pubkey.serialize(), msg)
+					.expect("negligible probability");
+				let s = add(&k, &mul(&e, &x)).expect("negligible probability");
+				return SchnorrPreSignature { nonce: nonce, s: s };
+			}
+			aux = tagged_hash("hal/adaptor/aux", &[&aux]);
+		}
+	}
+
+	/// Verify the pre-signature for the public key, message and adaptor point.
+	pub fn verify(&self, pubkey: &XOnlyPublicKey, msg: &Message, adaptor: &PublicKey) -> Result<(), String> {
+		let (r, parity) = self.final_nonce(adaptor)?.x_only_public_key();
+		if parity != Parity::Even {
+			return Err("final nonce has an odd y-coordinate".to_owned());
+		}
+		let e = schnorr_challenge(&r.serialize(), &pubkey.serialize(), msg.as_ref())?;
+		let p = pubkey.public_key(Parity::Even);
+		// s'*G == R' + e*P
+		if point(&self.s) != combine(&self.nonce, &point_mul(&p, &e))? {
+			return Err("invalid pre-signature".to_owned());
+		}
+		Ok(())
+	}
+
+	/// Complete the pre-signature into a BIP-340 signature using the adaptor secret.
+	pub fn complete(&self, secret: &SecretKey) -> Result<schnorr::Signature, String> {
+		let (r, _) = self.final_nonce(&point(secret))?.x_only_public_key();
+		let s = add(&self.s, secret).ok_or("invalid adaptor secret")?;
+		let mut sig = r.serialize().to_vec();
+		sig.extend_from_slice(&s[..]);
+		Ok(schnorr::Signature::from_slice(&sig).expect("64 bytes"))
+	}
+
+	/// Extract the adaptor secret from the completed signature.
+	pub fn extract(&self, sig: &schnorr::Signature, adaptor: &PublicKey) -> Result<SecretKey, String> {
+		let s = from_slice(&sig.as_ref()[32..64])?;
+		let t = sub(&s, &self.s).ok_or("signature s value equals pre-signature s value")?;
+		if point(&t) != *adaptor {
+			return Err("signature was not completed from this pre-signature".to_owned());
+		}
+		Ok(t)
+	}
+
+	pub fn get_info(&self, adaptor: &PublicKey) -> Result<PreSignatureInfo, String> {
+		Ok(PreSignatureInfo {
+			type_: "schnorr".to_owned(),
+			pre_signature: self.serialize().into(),
+			nonce: self.nonce.serialize()[..].into(),
+			nonce_g: None,
+			final_r: self.final_nonce(adaptor)?.x_only_public_key().0.serialize()[..].into(),
+			s: self.s[..].into(),
+		})
+	}
+}
+
+/// The challenge of the DLEQ proof.
+fn dleq_challenge(points: &[&PublicKey]) -> Result<SecretKey, String> {
+	let serialized = points.iter().map(|p| PublicKey::serialize(p)).collect::<Vec<_>>();
+	let data = serialized.iter().map(|p| &p[..]).collect::<Vec<_>>();
+	from_bytes(&tagged_hash("hal/adaptor/dleq", &data))
+}
+
+impl EcdsaPreSignature {
+	pub fn serialize(&self) -> Vec<u8> {
+		let mut ret = Vec::with_capacity(ECDSA_PRESIGNATURE_SIZE);
+		ret.extend_from_slice(&self.nonce.serialize());
+		ret.extend_from_slice(&self.nonce_g.serialize());
+		ret.extend_from_slice(&self.s[..]);
+		ret.extend_from_slice(&self.proof_e[..]);
+		ret.extend_from_slice(&self.proof_z[..]);
+		ret
+	}
+
+	pub fn from_slice(bytes: &[u8]) -> Result<EcdsaPreSignature, String> {
+		if bytes.len() != ECDSA_PRESIGNATURE_SIZE {
+			return Err(format!("invalid ECDSA pre-signature length: {}", bytes.len()));
+		}
+		let pk = |b: &[u8]| PublicKey::from_slice(b).map_err(|e| format!("invalid nonce: {}", e));
+		let sk = |b: &[u8]| SecretKey::from_slice(b).map_err(|e| format!("invalid scalar: {}", e));
+		Ok(EcdsaPreSignature {
+			nonce: pk(&bytes[0..33])?,
+			nonce_g: pk(&bytes[33..66])?,
+			s: sk(&bytes[66..98])?,
+			proof_e: sk(&bytes[98..130])?,
+			proof_z: sk(&bytes[130..162])?,
+		})
+	}
+
+	/// Create an ECDSA pre-signature for the message and adaptor point.
+	pub fn create(
+		secret_key: &SecretKey,
+		msg: &Message,
+		adaptor: &PublicKey,
+		aux: &[u8; 32],
+	) -> EcdsaPreSignature {
+		let z = message_scalar(msg);
+		let k = derive_nonce("hal/adaptor/ecdsa", secret_key, aux, &[&adaptor.serialize(), msg.as_ref()]);
+		let nonce = point_mul(adaptor, &k);
+		let nonce_g = point(&k);
+		let r = x_scalar(&nonce).expect("negligible probability");
+		let rx = mul(&r, secret_key);
+		let z_rx = match z {
+			Some(z) => add(&z, &rx).expect("negligible probability"),
+			None => rx,
+		};
+		let s = mul(&inv(&k), &z_rx);
+
+		// Prove that nonce_g = k*G and nonce = k*T.
+		let a = derive_nonce("hal/adaptor/dleq-nonce", &k, aux, &[]);
+		let e = dleq_challenge(&[adaptor, &nonce_g, &nonce, &point(&a), &point_mul(adaptor, &a)])
+			.expect("negligible probability");
+		let proof_z = add(&a, &mul(&e, &k)).expect("negligible probability");
+		EcdsaPreSignature {
+			nonce: nonce,
+			nonce_g: nonce_g,
+			s: s,
+			proof_e: e,
+			proof_z: proof_z,
+		}
+	}
+
+	/// Verify the pre-signature for the public key, message and adaptor point.
+	pub fn verify(&self, pubkey: &PublicKey, msg: &Message, adaptor: &PublicKey) -> Result<(), String> {
+		// a*G = z*G - e*R_a and a*T = z*T - e*R
+		let neg_e = self.proof_e.negate();
+		let a_g = combine(&point(&self.proof_z), &point_mul(&self.nonce_g, &neg_e))?;
+		let a_t = combine(&point_mul(adaptor, &self.proof_z), &point_mul(&self.nonce, &neg_e))?;
+		if dleq_challenge(&[adaptor, &self.nonce_g, &self.nonce, &a_g, &a_t])? != self.proof_e {
+			return Err("invalid DLEQ proof".to_owned());
+		}
+
+		// s'^-1 * (z*G + r*X) == R_a
+		let r = x_scalar(&self.nonce)?;
+		let s_inv = inv(&self.s);
+		let r_x = point_mul(pubkey, &mul(&r, &s_inv));
+		let check = match message_scalar(msg) {
+			Some(z) => combine(&point(&mul(&z, &s_inv)), &r_x)?,
+			None => r_x,
+		};
+		if check != self.nonce_g {
+			return Err("invalid pre-signature".to_owned());
+		}
+		Ok(())
+	}
+
+	/// Complete the pre-signature into a low-S ECDSA signature using the adaptor secret.
+	pub fn complete(&self, secret: &SecretKey) -> Result<ecdsa::Signature, String> {
+		let r = x_scalar(&self.nonce)?;
+		let s = mul(&self.s, &inv(secret));
+		let mut compact = [0u8; 64];
+		compact[0..32].copy_from_slice(&to_array(&r));
+		compact[32..64].copy_from_slice(&to_array(&s));
+		let mut sig = ecdsa::Signature::from_compact(&compact).map_err(|e| e.to_string())?;
+		sig.normalize_s();
+		Ok(sig)
+	}
+
+	/// Extract the adaptor secret from the completed signature.
+	pub fn extract(&self, sig: &ecdsa::Signature, adaptor: &PublicKey) -> Result<SecretKey, String> {
+		let compact = sig.serialize_compact();
+		if compact[0..32] != to_array(&x_scalar(&self.nonce)?) {
+			return Err("signature has a different nonce than the pre-signature".to_owned());
+		}
+		let s = from_slice(&compact[32..64])?;
+		let t = mul(&self.s, &inv(&s));
+		// The signature might have been normalized.
+		for t in &[t, t.negate()] {
+			if point(t) == *adaptor {
+				return Ok(*t);
+			}
+		}
+		Err("signature was not completed from this pre-signature".to_owned())
+	}
+
+	pub fn get_info(&self) -> Result<PreSignatureInfo, String> {
+		Ok(PreSignatureInfo {
+			type_: "ecdsa".to_owned(),
+			pre_signature: self.serialize().into(),
+			nonce: self.nonce.serialize()[..].into(),
+			nonce_g: Some(self.nonce_g.serialize()[..].into()),
+			final_r: to_array(&x_scalar(&self.nonce)?)[..].into(),
+			s: self.s[..].into(),
+		})
+	}
+}
+
+impl PreSignature {
+	/// Parse a pre-signature, detecting the type from its length.
+	pub fn from_slice(bytes: &[u8]) -> Result<PreSignature, String> {
+		match bytes.len() {
+			SCHNORR_PRESIGNATURE_SIZE => Ok(PreSignature::Schnorr(SchnorrPreSignature::from_slice(bytes)?)),
+			ECDSA_PRESIGNATURE_SIZE => Ok(PreSignature::Ecdsa(EcdsaPreSignature::from_slice(bytes)?)),
+			n => Err(format!(
+				"invalid pre-signature length {}, expected {} for Schnorr or {} for ECDSA",
+				n, SCHNORR_PRESIGNATURE_SIZE, ECDSA_PRESIGNATURE_SIZE,
+			)),
+		}
+	}
+
+	pub fn serialize(&self) -> Vec<u8> {
+		match self {
+			PreSignature::Schnorr(s) => s.serialize(),
+			PreSignature::Ecdsa(s) => s.serialize(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_adaptor() {
+		let sk = SecretKey::from_slice(&[0x01; 32]).unwrap();
+		let secret = SecretKey::from_slice(&[0x02; 32]).unwrap();
+		let adaptor = point(&secret);
+		let msg = Message::from_digest([0x03; 32]);
+		let aux = [0x04; 32];
+
+		// Schnorr
+		let (xonly, _) = point(&sk).x_only_public_key();
+		let pre = SchnorrPreSignature::create(&sk, &msg, &adaptor, &aux);
+		assert_eq!(SchnorrPreSignature::from_slice(&pre.serialize()).unwrap(), pre);
+		pre.verify(&xonly, &msg, &adaptor).unwrap();
+		assert!(pre.verify(&xonly, &Message::from_digest([0x05; 32]), &adaptor).is_err());
+		assert!(pre.verify(&xonly, &msg, &point(&sk)).is_err());
+		let sig = pre.complete(&secret).unwrap();
+		SECP.verify_schnorr(&sig, &msg, &xonly).unwrap();
+		assert_eq!(pre.extract(&sig, &adaptor).unwrap(), secret);
+
+		// ECDSA
+		let pubkey = point(&sk);
+		let pre = EcdsaPreSignature::create(&sk, &msg, &adaptor, &aux);
+		assert_eq!(EcdsaPreSignature::from_slice(&pre.serialize()).unwrap(), pre);
+		pre.verify(&pubkey, &msg, &adaptor).unwrap();
+		assert!(pre.verify(&pubkey, &Message::from_digest([0x05; 32]), &adaptor).is_err());
+		assert!(pre.verify(&pubkey, &msg, &pubkey).is_err());
+		let sig = pre.complete(&secret).unwrap();
+		SECP.verify_ecdsa(&msg, &sig, &pubkey).unwrap();
+		assert_eq!(pre.extract(&sig, &adaptor).unwrap(), secret);
+
+		// Digests are reduced modulo the curve order and can be zero.
+		for digest in &[[0x00; 32], [0xff; 32]] {
+			let msg = Message::from_digest(*digest);
+			let pre = EcdsaPreSignature::create(&sk, &msg, &adaptor, &aux);
+			pre.verify(&pubkey, &msg, &adaptor).unwrap();
+			let sig = pre.complete(&secret).unwrap();
+			SECP.verify_ecdsa(&msg, &sig, &pubkey).unwrap();
+			assert_eq!(pre.extract(&sig, &adaptor).unwrap(), secret);
+		}
+	}
+}
@@ -1,13 +1,18 @@
 
 use bitcoin::{
-	address, Address, Network, Script, PubkeyHash, ScriptHash, WPubkeyHash, WScriptHash,
+	address, base58, Address, Network, Script, PubkeyHash, ScriptHash, WPubkeyHash, WScriptHash,
 };
+use bitcoin::hashes::{sha256d, Hash};
 use secp256k1::XOnlyPublicKey;
 use serde::{Deserialize, Serialize};
 
-use crate::SECP;
+use crate::{SECP, HexBytes};
+use crate::bech32::{CorrectionCandidate, CORRECTION_WARNING};
 use crate::tx;
 
+/// The base58 alphabet used by Bitcoin.
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
 #[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
 pub struct AddressInfo {
 	#[serde(rename = "type")]
@@ -89,3 +94,85 @@ impl Addresses {
 		}
 	}
 }
+
+/// Diagnostics for a string that failed base58check decoding.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct Base58ErrorInfo {
+	/// Zero-based positions of characters that are not in the base58 alphabet
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub invalid_characters: Vec<usize>,
+	/// Length of the decoded payload, including the checksum
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub decoded_length: Option<usize>,
+	/// The checksum found at the end of the decoded data
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub checksum: Option<HexBytes>,
+	/// The checksum calculated over the decoded data
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub expected_checksum: Option<HexBytes>,
+	/// Candidate single-character corrections; UNSAFE to use without confirmation
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub suggested_corrections: Vec<CorrectionCandidate>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub warning: Option<String>,
+}
+
+/// Diagnose a base58check string and look for single-character substitutions that
+/// make the checksum valid.
+///
+/// The `accept` closure can be used to filter candidates, f.e. to only keep valid addresses.
+pub fn diagnose_base58<F>(s: &str, accept: F) -> Base58ErrorInfo
+where
+	F: Fn(&str) -> bool,
+{
+	let invalid = s.char_indices()
+		.filter(|(_, c)| !c.is_ascii() || !BASE58_ALPHABET.contains(&(*c as u8)))
+		.map(|(i, _)| i)
+		.collect::<Vec<_>>();
+
+	let mut ret = Base58ErrorInfo {
+		invalid_characters: invalid.clone(),
+		decoded_length: None,
+		checksum: None,
+		expected_checksum: None,
+		suggested_corrections: Vec::new(),
+		warning: None,
+	};
+	if let Ok(data) = base58::decode(s) {
+		ret.decoded_length = Some(data.len());
+		if data.len() >= 4 {
+			let (payload, checksum) = data.split_at(data.len() - 4);
+			ret.checksum = Some(checksum.into());
+			ret.expected_checksum = Some(sha256d::Hash::hash(payload)[0..4].into());
+		}
+	}
+
+	// We can only correct a single error, so if there are multiple invalid
+	// characters, there is no point in searching.
+	if !s.is_ascii() || invalid.len() > 1 {
+		return ret;
+	}
+	let positions = if invalid.is_empty() {
+		(0..s.len()).collect()
+	} else {
+		invalid
+	};
+	for pos in positions {
+		for c in BASE58_ALPHABET.iter().filter(|c| **c != s.as_bytes()[pos]) {
+			let mut candidate = s.as_bytes().to_vec();
+			candidate[pos] = *c;
+			let candidate = String::from_utf8(candidate).expect("ascii");
+			if base58::decode_check(&candidate).is_ok() && accept(&candidate) {
+				ret.suggested_corrections.push(CorrectionCandidate {
+					corrected: candidate,
+					error_positions: vec![pos],
+					checksum: None,
+				});
+			}
+		}
+	}
+	if !ret.suggested_corrections.is_empty() {
+		ret.warning = Some(CORRECTION_WARNING.to_owned());
+	}
+	ret
+}
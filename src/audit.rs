@@ -0,0 +1,500 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::hashes::Hash;
+use bitcoin::sighash::{Annex, EcdsaSighashType, Prevouts, SighashCache, TapSighashType};
+use bitcoin::taproot::{ControlBlock, TapLeafHash};
+use bitcoin::{Network, NetworkKind, OutPoint, PrivateKey, Script, ScriptBuf, Transaction, TxOut, Txid};
+use secp256k1::{ecdsa, schnorr, Message, PublicKey, SecretKey, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+
+use crate::scalar::{from_bytes as to_scalar, inv, mul, schnorr_challenge, sub};
+use crate::sig::{self, SignatureFormat};
+use crate::{HexBytes, SECP};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureKind {
+	Ecdsa,
+	Schnorr,
+}
+
+/// A signature found in a transaction or provided directly.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FoundSignature {
+	/// The transaction and input index the signature was found in.
+	pub location: Option<(Txid, usize)>,
+	pub kind: SignatureKind,
+	pub r: [u8; 32],
+	pub s: [u8; 32],
+	/// The serialized public key: compressed for ECDSA, x-only for Schnorr.
+	pub pubkey: Option<Vec<u8>>,
+	/// The signed message, if the sighash could be calculated.
+	pub message: Option<[u8; 32]>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct SignatureRef {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub txid: Option<Txid>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub input: Option<usize>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub public_key: Option<HexBytes>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub sighash: Option<HexBytes>,
+	pub s: HexBytes,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct NonceReuseInfo {
+	pub kind: SignatureKind,
+	pub r: HexBytes,
+	/// Whether all signatures are from the same key, which leaks the key.
+	pub same_key: bool,
+	pub signatures: Vec<SignatureRef>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub private_key: Option<PrivateKey>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub recovery_error: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct NonceReuseReport {
+	pub transactions: usize,
+	pub signatures: usize,
+	/// Number of signatures for which the sighash couldn't be calculated.
+	pub missing_sighash: usize,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub warnings: Vec<String>,
+	pub reused_nonces: Vec<NonceReuseInfo>,
+}
+
+/// Recover the private key from two ECDSA signatures with the same nonce and key.
+///
+/// Both signs of the second s value are tried, in case a signature was normalized.
+pub fn recover_ecdsa_key(
+	r: &[u8; 32],
+	sig1: (&[u8; 32], &[u8; 32]),
+	sig2: (&[u8; 32], &[u8; 32]),
+) -> Result<SecretKey, String> {
+	let r_inv = inv(&to_scalar(r)?);
+	let (s1, z1) = (to_scalar(sig1.0)?, to_scalar(sig1.1)?);
+	let (s2, z2) = (to_scalar(sig2.0)?, to_scalar(sig2.1)?);
+	let dz = sub(&z1, &z2).ok_or("both signatures sign the same message")?;
+
+	let mut compact = [0u8; 64];
+	compact[0..32].copy_from_slice(r);
+	compact[32..64].copy_from_slice(sig1.0);
+	let signature = ecdsa::Signature::from_compact(&compact).map_err(|e| e.to_string())?;
+	let msg = Message::from_digest(*sig1.1);
+	for s2 in &[s2, s2.negate()] {
+		let ds = match sub(&s1, s2) {
+			Some(ds) => ds,
+			None => continue,
+		};
+		let k = mul(&dz, &inv(&ds));
+		// x = (s1 * k - z1) / r
+		let x = match sub(&mul(&s1, &k), &z1) {
+			Some(v) => mul(&v, &r_inv),
+			None => continue,
+		};
+		let pk = PublicKey::from_secret_key(&SECP, &x);
+		let mut normalized = signature;
+		normalized.normalize_s();
+		if SECP.verify_ecdsa(&msg, &normalized, &pk).is_ok() {
+			return Ok(x);
+		}
+	}
+	Err("no key found, the signatures might be from different keys".to_owned())
+}
+
+/// Recover the private key from two Schnorr signatures with the same nonce and key.
+///
+/// The key is returned with the parity that matches the x-only public key.
+pub fn recover_schnorr_key(
+	r: &[u8; 32],
+	pubkey: &XOnlyPublicKey,
+	sig1: (&[u8; 32], &[u8; 32]),
+	sig2: (&[u8; 32], &[u8; 32]),
+) -> Result<SecretKey, String> {
+	let pk = pubkey.serialize();
+	let e1 = schnorr_challenge(r, &pk, sig1.1)?;
+	let e2 = schnorr_challenge(r, &pk, sig2.1)?;
+	let ds = sub(&to_scalar(sig1.0)?, &to_scalar(sig2.0)?).ok_or("the signatures are identical")?;
+	let de = sub(&e1, &e2).ok_or("both signatures sign the same message")?;
+	let x = mul(&ds, &inv(&de));
+	let (xonly, parity) = PublicKey::from_secret_key(&SECP, &x).x_only_public_key();
+	if xonly != *pubkey {
+		return Err("no key found, the signatures might be from different keys".to_owned());
+	}
+	Ok(if parity == secp256k1::Parity::Odd { x.negate() } else { x })
+}
+
+/// Get all the pushes in a push-only script, or [None] if it's not push-only.
+fn pushes(script: &Script) -> Option<Vec<Vec<u8>>> {
+	script.instructions().map(|i| match i {
+		Ok(Instruction::PushBytes(b)) => Some(b.as_bytes().to_vec()),
+		_ => None,
+	}).collect()
+}
+
+/// Get all pushes in a script that are valid public keys.
+fn script_pubkeys(script: &Script) -> Vec<Vec<u8>> {
+	script.instructions().filter_map(|i| match i {
+		Ok(Instruction::PushBytes(b)) => {
+			let b = b.as_bytes();
+			if b.len() == 32 {
+				XOnlyPublicKey::from_slice(b).ok().map(|_| b.to_vec())
+			} else {
+				PublicKey::from_slice(b).ok().map(|pk| pk.serialize().to_vec())
+			}
+		}
+		_ => None,
+	}).collect()
+}
+
+/// How the sighash of a signature in an input can be calculated.
+enum SighashContext {
+	Legacy(ScriptBuf),
+	SegwitV0(ScriptBuf, Option<bitcoin::Amount>),
+	Taproot(Option<TapLeafHash>, Option<Vec<u8>>),
+}
+
+/// Find all signatures in a transaction.
+///
+/// The previous outputs are used to calculate the sighashes.
+/// Returns the signatures and warnings for inputs that couldn't be fully analyzed.
+pub fn extract_signatures(
+	tx: &Transaction,
+	prevouts: &HashMap<OutPoint, TxOut>,
+) -> (Vec<FoundSignature>, Vec<String>) {
+	let txid = tx.compute_txid();
+	let mut cache = SighashCache::new(tx);
+	let all_prevouts = tx.input.iter().map(|i| prevouts.get(&i.previous_output).cloned())
+		.collect::<Option<Vec<_>>>();
+	let mut sigs = Vec::new();
+	let mut warnings = Vec::new();
+
+	for (idx, input) in tx.input.iter().enumerate() {
+		if input.previous_output.is_null() {
+			continue;
+		}
+		let prevout = prevouts.get(&input.previous_output);
+		let script_sig = match pushes(&input.script_sig) {
+			Some(p) => p,
+			None => {
+				warnings.push(format!("{}:{}: scriptSig is not push-only", txid, idx));
+				continue;
+			}
+		};
+		let mut witness = input.witness.iter().map(|w| w.to_vec()).collect::<Vec<_>>();
+
+		// Determine the candidate signatures, pubkeys and the sighash context.
+		let (candidates, pubkeys, context) = if witness.is_empty() {
+			if script_sig.is_empty() {
+				warnings.push(format!("{}:{}: input is not signed", txid, idx));
+				continue;
+			}
+			let last = script_sig.last().cloned().unwrap_or_default();
+			if script_sig.len() == 2 && PublicKey::from_slice(&last).is_ok() {
+				let pk = bitcoin::PublicKey::from_slice(&last).expect("checked");
+				let script_code = ScriptBuf::new_p2pkh(&pk.pubkey_hash());
+				(vec![script_sig[0].clone()], vec![pk.inner.serialize().to_vec()], SighashContext::Legacy(script_code))
+			} else if prevout.map(|p| p.script_pubkey.is_p2sh()).unwrap_or(!last.is_empty() && last[0] != 0x30) {
+				let redeem = ScriptBuf::from(last);
+				let pks = script_pubkeys(&redeem);
+				let n = script_sig.len() - 1;
+				(script_sig[..n].to_vec(), pks, SighashContext::Legacy(redeem))
+			} else if let Some(prevout) = prevout {
+				let pks = script_pubkeys(&prevout.script_pubkey);
+				(script_sig.clone(), pks, SighashContext::Legacy(prevout.script_pubkey.clone()))
+			} else {
+				warnings.push(format!("{}:{}: missing previous output for bare script", txid, idx));
+				(script_sig.clone(), vec![], SighashContext::Legacy(ScriptBuf::new()))
+			}
+		} else {
+			let amount = prevout.map(|p| p.value);
+			let program = match (script_sig.len(), prevout) {
+				(1, _) => Some(ScriptBuf::from(script_sig[0].clone())),
+				(_, Some(p)) => Some(p.script_pubkey.clone()),
+				_ => None,
+			};
+			// Strip the annex in taproot spends.
+			let is_taproot = program.as_ref().map(|p| p.is_p2tr())
+				.unwrap_or_else(|| script_sig.is_empty() && witness.len() == 1 && witness[0].len() >= 64
+					&& witness[0].len() <= 65 && witness[0][0] != 0x30);
+			let annex = if is_taproot && witness.len() >= 2 && witness.last().unwrap().first() == Some(&0x50) {
+				witness.pop()
+			} else {
+				None
+			};
+			let last = witness.last().cloned().unwrap_or_default();
+
+			if program.as_ref().map(|p| p.is_p2wpkh()).unwrap_or(witness.len() == 2 && last.len() == 33) {
+				let pk = match bitcoin::CompressedPublicKey::from_slice(&last) {
+					Ok(pk) => pk,
+					Err(_) => {
+						warnings.push(format!("{}:{}: invalid p2wpkh public key", txid, idx));
+						continue;
+					}
+				};
+				let spk = ScriptBuf::new_p2wpkh(&pk.wpubkey_hash());
+				(vec![witness[0].clone()], vec![pk.to_bytes().to_vec()], SighashContext::SegwitV0(spk, amount))
+			} else if is_taproot && witness.len() == 1 {
+				let pk = program.as_ref().map(|p| p.as_bytes()[2..34].to_vec());
+				(witness.clone(), pk.into_iter().collect(), SighashContext::Taproot(None, annex))
+			} else if is_taproot {
+				let n = witness.len();
+				let script = Script::from_bytes(&witness[n - 2]);
+				let leaf = ControlBlock::decode(&witness[n - 1]).ok()
+					.map(|cb| TapLeafHash::from_script(script, cb.leaf_version));
+				let pks = script_pubkeys(script);
+				(witness[..n - 2].to_vec(), pks, SighashContext::Taproot(leaf, annex))
+			} else {
+				let n = witness.len();
+				let ws = ScriptBuf::from(last);
+				let pks = script_pubkeys(&ws);
+				(witness[..n - 1].to_vec(), pks, SighashContext::SegwitV0(ws, amount))
+			}
+		};
+
+		for cand in candidates {
+			let parsed = match context {
+				SighashContext::Taproot(..) => sig::Signature::parse(&cand, Some(SignatureFormat::Schnorr)),
+				_ if cand.first() == Some(&0x30) => sig::Signature::parse(&cand, Some(SignatureFormat::Der)),
+				_ => continue,
+			};
+			let parsed = match parsed {
+				Ok(s) => s,
+				Err(_) => continue,
+			};
+
+			let (kind, r, s, sighash) = match parsed {
+				sig::Signature::Ecdsa { signature, sighash, .. } => {
+					let c = signature.serialize_compact();
+					(SignatureKind::Ecdsa, c[0..32].to_vec(), c[32..64].to_vec(), sighash.map(|b| b as u32))
+				}
+				sig::Signature::Schnorr { signature, sighash } => {
+					let c = signature.as_ref();
+					(SignatureKind::Schnorr, c[0..32].to_vec(), c[32..64].to_vec(), sighash.map(|b| b as u32))
+				}
+			};
+			let message = match (&context, sighash) {
+				(SighashContext::Legacy(code), Some(ty)) => {
+					cache.legacy_signature_hash(idx, code, ty).ok().map(|h| h.to_byte_array())
+				}
+				(SighashContext::SegwitV0(code, Some(amount)), Some(ty)) => {
+					let ty = EcdsaSighashType::from_consensus(ty);
+					if code.is_p2wpkh() {
+						cache.p2wpkh_signature_hash(idx, code, *amount, ty).ok().map(|h| h.to_byte_array())
+					} else {
+						cache.p2wsh_signature_hash(idx, code, *amount, ty).ok().map(|h| h.to_byte_array())
+					}
+				}
+				(SighashContext::Taproot(leaf, annex), ty) => {
+					let ty = match ty {
+						Some(t) => TapSighashType::from_consensus_u8(t as u8).ok(),
+						None => Some(TapSighashType::Default),
+					};
+					match (all_prevouts.as_ref(), ty) {
+						(Some(all), Some(ty)) => {
+							let annex = annex.as_ref().and_then(|a| Annex::new(a).ok());
+							cache.taproot_signature_hash(
+								idx, &Prevouts::All(all), annex, leaf.map(|l| (l, 0xffffffff)), ty,
+							).ok().map(|h| h.to_byte_array())
+						}
+						_ => None,
+					}
+				}
+				_ => None,
+			};
+
+			// Find the key that made the signature.
+			let pubkey = if pubkeys.len() == 1 {
+				Some(pubkeys[0].clone())
+			} else if let Some(msg) = message {
+				let msg = Message::from_digest(msg);
+				pubkeys.iter().find(|pk| match kind {
+					SignatureKind::Ecdsa => {
+						let mut compact = [0u8; 64];
+						compact[0..32].copy_from_slice(&r);
+						compact[32..64].copy_from_slice(&s);
+						let mut sig = ecdsa::Signature::from_compact(&compact).expect("parsed before");
+						sig.normalize_s();
+						PublicKey::from_slice(pk).map(|pk| SECP.verify_ecdsa(&msg, &sig, &pk).is_ok())
+							.unwrap_or(false)
+					}
+					SignatureKind::Schnorr => {
+						let mut bytes = r.clone();
+						bytes.extend_from_slice(&s);
+						let sig = schnorr::Signature::from_slice(&bytes).expect("parsed before");
+						XOnlyPublicKey::from_slice(pk)
+							.map(|pk| SECP.verify_schnorr(&sig, &msg, &pk).is_ok())
+							.unwrap_or(false)
+					}
+				}).cloned()
+			} else {
+				None
+			};
+
+			let mut r_arr = [0u8; 32];
+			r_arr.copy_from_slice(&r);
+			let mut s_arr = [0u8; 32];
+			s_arr.copy_from_slice(&s);
+			sigs.push(FoundSignature {
+				location: Some((txid, idx)),
+				kind: kind,
+				r: r_arr,
+				s: s_arr,
+				pubkey: pubkey,
+				message: message,
+			});
+		}
+	}
+	(sigs, warnings)
+}
+
+/// Find signatures that reuse the same nonce.
+///
+/// When `recover` is set, the private key is recovered when two signatures
+/// from the same key with known messages are found.
+pub fn find_nonce_reuse(
+	sigs: &[FoundSignature],
+	recover: bool,
+	network: Network,
+) -> Vec<NonceReuseInfo> {
+	let mut groups: BTreeMap<(SignatureKind, [u8; 32]), Vec<&FoundSignature>> = BTreeMap::new();
+	for sig in sigs {
+		let group = groups.entry((sig.kind, sig.r)).or_default();
+		// Ignore the same signature found multiple times.
+		if !group.iter().any(|g| g.s == sig.s && g.message == sig.message && g.pubkey == sig.pubkey) {
+			group.push(sig);
+		}
+	}
+
+	groups.into_iter().filter(|(_, g)| g.len() > 1).map(|((kind, r), group)| {
+		let first_pk = &group[0].pubkey;
+		let same_key = first_pk.is_some() && group.iter().all(|s| s.pubkey == *first_pk);
+
+		let mut private_key = None;
+		let mut recovery_error = None;
+		if recover {
+			let known = group.iter().filter(|s| s.message.is_some()).collect::<Vec<_>>();
+			let res = if known.len() < 2 {
+				Err("the sighashes of two signatures are required".to_owned())
+			} else {
+				let (a, b) = (known[0], known[1]);
+				let (sa, sb) = ((&a.s, a.message.as_ref().unwrap()), (&b.s, b.message.as_ref().unwrap()));
+				match kind {
+					SignatureKind::Ecdsa => recover_ecdsa_key(&r, sa, sb),
+					SignatureKind::Schnorr => match a.pubkey.as_ref().map(|p| XOnlyPublicKey::from_slice(p)) {
+						Some(Ok(pk)) => recover_schnorr_key(&r, &pk, sa, sb),
+						_ => Err("the public key is required for Schnorr signatures".to_owned()),
+					},
+				}
+			};
+			match res {
+				Ok(sk) => {
+					let network: NetworkKind = network.into();
+					private_key = Some(PrivateKey { compressed: true, network: network, inner: sk });
+				}
+				Err(e) => recovery_error = Some(e),
+			}
+		}
+
+		NonceReuseInfo {
+			kind: kind,
+			r: r[..].into(),
+			same_key: same_key,
+			signatures: group.iter().map(|s| SignatureRef {
+				txid: s.location.map(|l| l.0),
+				input: s.location.map(|l| l.1),
+				public_key: s.pubkey.as_ref().map(|p| p[..].into()),
+				sighash: s.message.as_ref().map(|m| m[..].into()),
+				s: s.s[..].into(),
+			}).collect(),
+			private_key: private_key,
+			recovery_error: recovery_error,
+		}
+	}).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::scalar::scalar;
+
+	#[test]
+	fn test_key_recovery() {
+		let x = SecretKey::from_slice(&[0x42; 32]).unwrap();
+		let k = SecretKey::from_slice(&[0x07; 32]).unwrap();
+		assert_eq!(mul(&k, &inv(&k)), SecretKey::from_slice(&{
+			let mut one = [0u8; 32];
+			one[31] = 1;
+			one
+		}).unwrap());
+
+		// ECDSA: s = k^-1 (z + r x)
+		let r = PublicKey::from_secret_key(&SECP, &k).serialize()[1..].to_vec();
+		let mut r_arr = [0u8; 32];
+		r_arr.copy_from_slice(&r);
+		let ecdsa_s = |z: &[u8; 32]| -> [u8; 32] {
+			let zx = to_scalar(z).unwrap().add_tweak(&scalar(&mul(&to_scalar(&r_arr).unwrap(), &x))).unwrap();
+			let s = mul(&inv(&k), &zx);
+			// Normalize to low-S like a real signer.
+			let mut compact = [0u8; 64];
+			compact[0..32].copy_from_slice(&r_arr);
+			compact[32..].copy_from_slice(&s.secret_bytes());
+			let mut sig = ecdsa::Signature::from_compact(&compact).unwrap();
+			sig.normalize_s();
+			let mut ret = [0u8; 32];
+			ret.copy_from_slice(&sig.serialize_compact()[32..]);
+			ret
+		};
+		let (z1, z2) = ([1u8; 32], [2u8; 32]);
+		let (s1, s2) = (ecdsa_s(&z1), ecdsa_s(&z2));
+		assert_eq!(recover_ecdsa_key(&r_arr, (&s1, &z1), (&s2, &z2)).unwrap(), x);
+
+		// Schnorr with a fixed nonce, via the auxiliary-less signing equation.
+		let keypair = secp256k1::Keypair::from_secret_key(&SECP, &x);
+		let (pk, parity) = keypair.x_only_public_key();
+		let x_even = if parity == secp256k1::Parity::Odd { x.negate() } else { x };
+		let (rx, r_parity) = PublicKey::from_secret_key(&SECP, &k).x_only_public_key();
+		let k_even = if r_parity == secp256k1::Parity::Odd { k.negate() } else { k };
+		let schnorr_s = |m: &[u8; 32]| -> [u8; 32] {
+			let e = schnorr_challenge(&rx.serialize(), &pk.serialize(), m).unwrap();
+			let s = k_even.add_tweak(&scalar(&mul(&e, &x_even))).unwrap();
+			let mut sig = rx.serialize().to_vec();
+			sig.extend_from_slice(&s.secret_bytes());
+			let sig = schnorr::Signature::from_slice(&sig).unwrap();
+			SECP.verify_schnorr(&sig, &Message::from_digest(*m), &pk).unwrap();
+			s.secret_bytes()
+		};
+		let (s1, s2) = (schnorr_s(&z1), schnorr_s(&z2));
+		assert_eq!(recover_schnorr_key(&rx.serialize(), &pk, (&s1, &z1), (&s2, &z2)).unwrap(), x_even);
+	}
+
+	#[test]
+	fn test_extract_unsigned_p2sh() {
+		let prev = OutPoint::new(Txid::all_zeros(), 0);
+		let tx = Transaction {
+			version: bitcoin::transaction::Version::TWO,
+			lock_time: bitcoin::absolute::LockTime::ZERO,
+			input: vec![bitcoin::TxIn {
+				previous_output: prev,
+				..Default::default()
+			}],
+			output: vec![TxOut {
+				value: bitcoin::Amount::ZERO,
+				script_pubkey: ScriptBuf::from(vec![0x51]),
+			}],
+		};
+		let spk = ScriptBuf::new_p2sh(&ScriptBuf::from(vec![0x51]).script_hash());
+		let prevouts = vec![(prev, TxOut { value: bitcoin::Amount::from_sat(1000), script_pubkey: spk })]
+			.into_iter().collect();
+		let (sigs, warnings) = extract_signatures(&tx, &prevouts);
+		assert!(sigs.is_empty());
+		assert_eq!(warnings, vec![format!("{}:0: input is not signed", tx.compute_txid())]);
+	}
+}
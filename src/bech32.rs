@@ -1,7 +1,30 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 
 use crate::HexBytes;
 
+/// The bech32 character set, indexed by 5-bit value.
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// The generator coefficients of the bech32 BCH code.
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+/// The checksum constant for the original bech32 variant (BIP-173).
+const BECH32_CONST: u32 = 1;
+
+/// The checksum constant for the bech32m variant (BIP-350).
+const BECH32M_CONST: u32 = 0x2bc830a3;
+
+/// The maximum number of substitution errors the bech32 BCH code can correct.
+///
+/// The code guarantees detection of up to 4 errors, so it can correct up to 2.
+pub const MAX_CORRECTABLE_ERRORS: usize = 2;
+
+/// Warning attached to all suggested corrections.
+pub const CORRECTION_WARNING: &str = "UNSAFE: suggested corrections are guesses based \
+	on the checksum only; never use them without confirming with the source of the string";
+
 #[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
 pub struct Bech32Info {
 	/// Bech32 encoded string
@@ -11,3 +34,226 @@ pub struct Bech32Info {
 	/// Hex-encoded data payload
 	pub payload: HexBytes,
 }
+
+/// A candidate correction for a string with an invalid checksum.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct CorrectionCandidate {
+	/// The corrected string
+	pub corrected: String,
+	/// Zero-based character positions that were changed
+	pub error_positions: Vec<usize>,
+	/// The checksum variant the corrected string is valid for
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub checksum: Option<String>,
+}
+
+/// Diagnostics for a string that failed bech32 decoding.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct Bech32ErrorInfo {
+	/// The human-readable part, which is assumed to be correct
+	pub hrp: String,
+	/// The checksum variant for which the checksum is valid, if any
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub valid_checksum: Option<String>,
+	/// Zero-based positions of characters that are not in the bech32 character set
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub invalid_characters: Vec<usize>,
+	/// Zero-based positions of characters found to be wrong,
+	/// only set when all candidates agree on them
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub error_positions: Vec<usize>,
+	/// Candidate corrections; UNSAFE to use without confirmation
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub suggested_corrections: Vec<CorrectionCandidate>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub warning: Option<String>,
+}
+
+fn polymod(start: u32, values: impl IntoIterator<Item = u8>) -> u32 {
+	let mut chk = start;
+	for v in values {
+		let b = chk >> 25;
+		chk = (chk & 0x1ffffff) << 5 ^ v as u32;
+		for (i, g) in GENERATOR.iter().enumerate() {
+			if (b >> i) & 1 == 1 {
+				chk ^= g;
+			}
+		}
+	}
+	chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+	let mut ret = hrp.bytes().map(|b| b >> 5).collect::<Vec<_>>();
+	ret.push(0);
+	ret.extend(hrp.bytes().map(|b| b & 0x1f));
+	ret
+}
+
+/// Locate substitution errors in a bech32 or bech32m string and find candidate corrections.
+///
+/// Because the checksum is linear, the contribution of every possible single-character
+/// error can be precomputed and matched against the residue of the string, which finds
+/// all combinations of up to [MAX_CORRECTABLE_ERRORS] errors in the data part.
+/// Characters outside the bech32 character set are always treated as errors.
+/// The human-readable part is assumed to be correct.
+///
+/// The `accept` closure can be used to filter candidates, f.e. to only keep valid addresses.
+///
+/// Returns [None] if the string has no separator or is too short to hold a checksum.
+pub fn locate_errors<F>(s: &str, accept: F) -> Option<Bech32ErrorInfo>
+where
+	F: Fn(&str) -> bool,
+{
+	let lower = s.to_lowercase();
+	let sep = lower.rfind('1')?;
+	let (hrp, data) = (&lower[..sep], &lower[sep + 1..]);
+	if hrp.is_empty() || data.len() < 6 || !lower.is_ascii() {
+		return None;
+	}
+	let offset = sep + 1;
+
+	let mut invalid = Vec::new();
+	let values = data.bytes().enumerate().map(|(i, c)| {
+		match CHARSET.iter().position(|x| *x == c) {
+			Some(v) => v as u8,
+			None => {
+				invalid.push(i);
+				0
+			}
+		}
+	}).collect::<Vec<u8>>();
+
+	let residue = polymod(1, hrp_expand(hrp).into_iter().chain(values.iter().cloned()));
+	let mut ret = Bech32ErrorInfo {
+		hrp: hrp.to_owned(),
+		valid_checksum: None,
+		invalid_characters: invalid.iter().map(|i| i + offset).collect(),
+		error_positions: Vec::new(),
+		suggested_corrections: Vec::new(),
+		warning: None,
+	};
+	if invalid.is_empty() {
+		if residue == BECH32_CONST {
+			ret.valid_checksum = Some("bech32".to_owned());
+		} else if residue == BECH32M_CONST {
+			ret.valid_checksum = Some("bech32m".to_owned());
+		}
+	}
+	if ret.valid_checksum.is_some() || invalid.len() > MAX_CORRECTABLE_ERRORS {
+		return Some(ret);
+	}
+
+	// The residue contribution of an error of value e at position p.
+	let len = values.len();
+	let syndrome = |p: usize, e: u8| -> u32 {
+		polymod(0, (0..len).map(|i| if i == p { e } else { 0 }))
+	};
+	let mut singles: HashMap<u32, Vec<(usize, u8)>> = HashMap::new();
+	for p in (0..len).filter(|p| !invalid.contains(p)) {
+		for e in 1..32 {
+			singles.entry(syndrome(p, e)).or_default().push((p, e));
+		}
+	}
+
+	let mut solutions: Vec<(Vec<(usize, u8)>, &'static str)> = Vec::new();
+	for &(target, name) in &[(BECH32_CONST, "bech32"), (BECH32M_CONST, "bech32m")] {
+		// Enumerate all values for the invalid characters.
+		let nb_forced = invalid.len() as u32;
+		for assignment in 0..32usize.pow(nb_forced) {
+			let mut forced = Vec::new();
+			let mut s = residue ^ target;
+			for (i, p) in invalid.iter().enumerate() {
+				let e = ((assignment >> (5 * i)) & 0x1f) as u8;
+				s ^= syndrome(*p, e);
+				forced.push((*p, e));
+			}
+			let budget = MAX_CORRECTABLE_ERRORS - invalid.len();
+
+			if s == 0 {
+				solutions.push((forced.clone(), name));
+			}
+			if budget >= 1 {
+				for &(p, e) in singles.get(&s).into_iter().flatten() {
+					let mut sol = forced.clone();
+					sol.push((p, e));
+					solutions.push((sol, name));
+				}
+			}
+			if budget >= 2 {
+				for (&s1, errs1) in singles.iter() {
+					for &(p2, e2) in singles.get(&(s ^ s1)).into_iter().flatten() {
+						for &(p1, e1) in errs1.iter().filter(|(p1, _)| *p1 < p2) {
+							let mut sol = forced.clone();
+							sol.push((p1, e1));
+							sol.push((p2, e2));
+							solutions.push((sol, name));
+						}
+					}
+				}
+			}
+		}
+	}
+
+	for (errors, name) in solutions {
+		let mut corrected = values.clone();
+		for (p, e) in errors.iter() {
+			corrected[*p] ^= e;
+		}
+		let mut candidate = lower[..offset].to_owned();
+		candidate.extend(corrected.iter().map(|v| CHARSET[*v as usize] as char));
+		if !accept(&candidate) {
+			continue;
+		}
+		let mut positions = errors.iter().map(|(p, _)| p + offset).collect::<Vec<_>>();
+		positions.sort();
+		ret.suggested_corrections.push(CorrectionCandidate {
+			corrected: candidate,
+			error_positions: positions,
+			checksum: Some(name.to_owned()),
+		});
+	}
+
+	if let Some(first) = ret.suggested_corrections.first() {
+		if ret.suggested_corrections.iter().all(|c| c.error_positions == first.error_positions) {
+			ret.error_positions = first.error_positions.clone();
+		}
+		ret.warning = Some(CORRECTION_WARNING.to_owned());
+	}
+	Some(ret)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_locate_errors() {
+		let valid = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+		let info = locate_errors(valid, |_| true).unwrap();
+		assert_eq!(info.valid_checksum.as_deref(), Some("bech32"));
+		assert!(info.suggested_corrections.is_empty());
+
+		// one substitution
+		let typo = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5";
+		let info = locate_errors(typo, |_| true).unwrap();
+		assert_eq!(info.valid_checksum, None);
+		assert_eq!(info.error_positions, vec![41]);
+		assert_eq!(info.suggested_corrections.len(), 1);
+		assert_eq!(info.suggested_corrections[0].corrected, valid);
+
+		// two substitutions, one of which an invalid character
+		let typo = "bc1qw5o8d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5";
+		let info = locate_errors(typo, |_| true).unwrap();
+		assert_eq!(info.invalid_characters, vec![6]);
+		assert_eq!(info.error_positions, vec![6, 41]);
+		assert_eq!(info.suggested_corrections[0].corrected, valid);
+
+		// bech32m
+		let valid = "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0";
+		let typo = "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jjq";
+		let info = locate_errors(typo, |_| true).unwrap();
+		assert_eq!(info.suggested_corrections[0].corrected, valid);
+		assert_eq!(info.suggested_corrections[0].checksum.as_deref(), Some("bech32m"));
+	}
+}
@@ -2,7 +2,9 @@
 use std::borrow::Borrow;
 use std::str::FromStr;
 
+use bitcoin::bip32;
 use bitcoin::consensus::encode;
+use bitcoin::secp256k1::rand::{self, RngCore};
 use bitcoin::{Network, NetworkKind};
 use secp256k1::{self, XOnlyPublicKey};
 
@@ -51,6 +53,23 @@ pub fn opt_yaml() -> clap::Arg<'static, 'static> {
 		.global(true)
 }
 
+/// Options to provide entropy from dice, cards or coin flips, see
+/// [ArgMatchesExt::physical_entropy].
+pub fn opts_physical_entropy<'a>() -> Vec<clap::Arg<'a, 'a>> {
+	vec![
+		opt("dice", "dice rolls to use as entropy, like 3512 or \"12 7 20\" for dice with \
+			more than 9 sides")
+			.conflicts_with_all(&["cards", "coins"]),
+		opt("sides", "the number of sides of the dice").default_value("6"),
+		opt("cards", "cards drawn from shuffled 52-card decks to use as entropy, \
+			like \"AS 10H QD 2C\", with multiple decks separated by /")
+			.conflicts_with("coins"),
+		opt("coins", "coin flips to use as entropy, as H and T or 1 and 0"),
+		flag("mix-rng", "mix the dice, cards or coin flips with randomness from the \
+			operating system"),
+	]
+}
+
 /// A flexible pubkey return type that accepts both xonly and regular pubkeys.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FlexiblePubkey {
@@ -90,6 +109,15 @@ pub trait ArgMatchesExt<'a>: Borrow<clap::ArgMatches<'a>> {
 
 	fn privkey(&self, key: &str) -> Option<bitcoin::PrivateKey> {
 		self.borrow().value_of(key).map(|s| {
+			if hal::bip38::is_encrypted_key(&s) {
+				let prompt = format!("BIP-38 passphrase for '{}': ", key);
+				let passphrase = crate::util::prompt_passphrase(&prompt);
+				let mut privkey = hal::bip38::decrypt(&s, &passphrase).unwrap_or_else(|e| {
+					exit!("failed to decrypt BIP-38 key for argument '{}': {}", key, e);
+				});
+				privkey.network = self.network().into();
+				return privkey;
+			}
 			bitcoin::PrivateKey::from_str(&s).unwrap_or_else(|_| {
 				let key = secp256k1::SecretKey::from_str(&s).unwrap_or_else(|_| {
 					exit!("invalid WIF/hex private key provided for argument '{}'", key);
@@ -109,6 +137,68 @@ pub trait ArgMatchesExt<'a>: Borrow<clap::ArgMatches<'a>> {
 		})
 	}
 
+	/// Get a master xpriv from either a BIP-39 mnemonic or an xpriv.
+	///
+	/// The BIP-39 passphrase is taken from the "passphrase" argument.
+	fn need_master_xpriv(&self, key: &str) -> bip32::Xpriv {
+		let s = self.borrow().value_of(key).unwrap_or_else(|| {
+			exit!("expected a mnemonic or xpriv for argument '{}'", key);
+		});
+		let passphrase = self.borrow().value_of("passphrase");
+		match hal::bip32::parse_ext_key(s) {
+			Ok((_, Some(xpriv), _)) => {
+				if passphrase.is_some() {
+					exit!("a passphrase can only be used with a mnemonic");
+				}
+				if xpriv.depth != 0 {
+					warn!("The xpriv is not a master key, derivations will be relative to it.");
+				}
+				xpriv
+			}
+			Ok((_, None, _)) => exit!("an xpub can't be used, provide an xpriv or a mnemonic"),
+			Err(_) => {
+				let mnemonic = bip39::Mnemonic::parse(s).unwrap_or_else(|_| {
+					exit!("invalid mnemonic or xpriv provided for argument '{}'", key);
+				});
+				let seed = mnemonic.to_seed(passphrase.unwrap_or(""));
+				bip32::Xpriv::new_master(self.network(), &seed[..]).unwrap_or_else(|e| {
+					exit!("invalid seed: {}", e);
+				})
+			}
+		}
+	}
+
+	/// Get entropy from the options in [opts_physical_entropy], if any were given.
+	///
+	/// Exits if less than the given number of bytes of entropy were provided.
+	fn physical_entropy(&self, nb_bytes: usize) -> Option<Vec<u8>> {
+		let args = self.borrow();
+		let (entropy, inputs) = if let Some(rolls) = args.value_of("dice") {
+			let sides = args.value_of("sides").unwrap().parse::<u32>().unwrap_or_else(|_| {
+				exit!("invalid number of sides");
+			});
+			(hal::entropy::PhysicalEntropy::from_dice(rolls, sides), "dice rolls")
+		} else if let Some(cards) = args.value_of("cards") {
+			(hal::entropy::PhysicalEntropy::from_cards(cards), "cards")
+		} else if let Some(flips) = args.value_of("coins") {
+			(hal::entropy::PhysicalEntropy::from_coins(flips), "coin flips")
+		} else {
+			return None;
+		};
+		let entropy = entropy.unwrap_or_else(|e| exit!("invalid entropy: {}", e));
+
+		eprintln!("Collected {} bits of entropy from {} {}.", entropy.nb_bits(), entropy.inputs, inputs);
+		let mut bytes = entropy.to_bytes(nb_bytes).unwrap_or_else(|e| exit!("{}", e));
+		if args.is_present("mix-rng") {
+			let mut random = vec![0u8; nb_bytes];
+			rand::thread_rng().fill_bytes(&mut random);
+			for (b, r) in bytes.iter_mut().zip(random) {
+				*b ^= r;
+			}
+		}
+		Some(bytes)
+	}
+
 	fn pubkey(&self, key: &str) -> Option<bitcoin::PublicKey> {
 		self.borrow().value_of(key).map(|s| {
 			bitcoin::PublicKey::from_str(&s).unwrap_or_else(|_| {
@@ -0,0 +1,168 @@
+use std::process;
+
+use bitcoin::secp256k1::rand;
+use clap;
+
+use hal::adaptor::{EcdsaPreSignature, PreSignature, SchnorrPreSignature};
+use hal::sig::{Signature, SignatureFormat};
+
+use crate::prelude::*;
+
+pub fn subcommand<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand_group("adaptor", "create and use Schnorr and ECDSA adaptor signatures")
+		.subcommand(cmd_create())
+		.subcommand(cmd_verify())
+		.subcommand(cmd_complete())
+		.subcommand(cmd_extract())
+}
+
+pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
+	match args.subcommand() {
+		("create", Some(ref m)) => exec_create(&m),
+		("verify", Some(ref m)) => exec_verify(&m),
+		("complete", Some(ref m)) => exec_complete(&m),
+		("extract", Some(ref m)) => exec_extract(&m),
+		(_, _) => unreachable!("clap prints help"),
+	};
+}
+
+fn arg_message<'a>() -> clap::Arg<'a, 'a> {
+	args::arg("message", "the message to be signed in hex (must be 32 bytes)").required(true)
+}
+
+fn arg_adaptor<'a>() -> clap::Arg<'a, 'a> {
+	args::arg("adaptor-point", "the adaptor point T = t*G").required(true)
+}
+
+fn arg_pre_signature<'a>() -> clap::Arg<'a, 'a> {
+	args::arg("pre-signature", "the adaptor signature in hex").required(true)
+}
+
+fn parse_message<'a>(args: &clap::ArgMatches<'a>) -> secp256k1::Message {
+	let mut bytes = hex::decode(args.value_of("message").need("no message given"))
+		.need("invalid hex message");
+	if args.is_present("reverse") {
+		bytes.reverse();
+	}
+	secp256k1::Message::from_digest_slice(&bytes).need("invalid message to be signed")
+}
+
+fn parse_pre_signature<'a>(args: &clap::ArgMatches<'a>) -> PreSignature {
+	let bytes = hex::decode(args.value_of("pre-signature").need("no pre-signature given"))
+		.need("invalid pre-signature hex");
+	PreSignature::from_slice(&bytes).need("invalid pre-signature")
+}
+
+fn parse_secret<'a>(args: &clap::ArgMatches<'a>) -> secp256k1::SecretKey {
+	args.need_privkey("secret").inner
+}
+
+fn cmd_create<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("create", "create an adaptor signature")
+		.arg(args::arg("privkey", "the private key in hex or WIF").required(true))
+		.arg(arg_message())
+		.arg(arg_adaptor())
+		.arg(args::flag("ecdsa", "create an ECDSA adaptor signature instead of Schnorr"))
+		.arg(args::flag("reverse", "reverse the message"))
+		.long_about("Create an adaptor signature (pre-signature) for the adaptor point T.\n\n\
+			The pre-signature can be completed into a valid signature only with the secret t \
+			of the adaptor point. Once the completed signature is published, the secret can \
+			be extracted from it using the pre-signature.\n\n\
+			Schnorr pre-signatures complete into BIP-340 signatures. ECDSA pre-signatures use \
+			Lloyd Fournier's scheme with a DLEQ proof and complete into low-S ECDSA signatures \
+			usable in segwit v0 scripts.\n\n\
+			Note that ECDSA pre-signatures created by hal can't be used with secp256k1-zkp or \
+			any other implementation, and hal can't use theirs: the DLEQ proof uses hal's own \
+			\"hal/adaptor/dleq\" tagged hash, even though the 162-byte size is the same.")
+}
+
+fn exec_create<'a>(args: &clap::ArgMatches<'a>) {
+	let privkey = args.need_privkey("privkey").inner;
+	let msg = parse_message(args);
+	let adaptor = args.need_pubkey("adaptor-point").inner;
+	let aux: [u8; 32] = rand::random();
+
+	let info = if args.is_present("ecdsa") {
+		EcdsaPreSignature::create(&privkey, &msg, &adaptor, &aux).get_info()
+	} else {
+		SchnorrPreSignature::create(&privkey, &msg, &adaptor, &aux).get_info(&adaptor)
+	};
+	args.print_output(&info.need("failed to create pre-signature"))
+}
+
+fn cmd_verify<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("verify", "verify an adaptor signature")
+		.arg(args::arg("pubkey", "the signer's public key").required(true))
+		.arg(arg_message())
+		.arg(arg_adaptor())
+		.arg(arg_pre_signature())
+		.arg(args::flag("reverse", "reverse the message"))
+}
+
+fn exec_verify<'a>(args: &clap::ArgMatches<'a>) {
+	let msg = parse_message(args);
+	let adaptor = args.need_pubkey("adaptor-point").inner;
+	let res = match parse_pre_signature(args) {
+		PreSignature::Schnorr(pre) => pre.verify(&args.need_xonly_pubkey("pubkey"), &msg, &adaptor),
+		PreSignature::Ecdsa(pre) => pre.verify(&args.need_pubkey("pubkey").inner, &msg, &adaptor),
+	};
+
+	match res {
+		Ok(()) => println!("Pre-signature is valid."),
+		Err(e) => {
+			eprintln!("Pre-signature is invalid: {}", e);
+			process::exit(1);
+		}
+	}
+}
+
+fn cmd_complete<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("complete", "complete an adaptor signature using the adaptor secret")
+		.arg(arg_pre_signature())
+		.arg(args::arg("secret", "the adaptor secret t in hex or WIF").required(true))
+		.arg(args::flag("compact", "output ECDSA signatures in compact format instead of DER"))
+}
+
+fn exec_complete<'a>(args: &clap::ArgMatches<'a>) {
+	let secret = parse_secret(args);
+	match parse_pre_signature(args) {
+		PreSignature::Schnorr(pre) => {
+			print!("{:x}", pre.complete(&secret).need("failed to complete pre-signature"));
+		}
+		PreSignature::Ecdsa(pre) => {
+			let sig = pre.complete(&secret).need("failed to complete pre-signature");
+			if args.is_present("compact") {
+				print!("{}", hex::encode(&sig.serialize_compact()[..]));
+			} else {
+				print!("{}", hex::encode(&sig.serialize_der()[..]));
+			}
+		}
+	}
+}
+
+fn cmd_extract<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("extract", "extract the adaptor secret from a completed signature")
+		.arg(arg_pre_signature())
+		.arg(args::arg("signature", "the completed signature in hex, compact or DER for ECDSA")
+			.required(true))
+		.arg(arg_adaptor())
+}
+
+fn exec_extract<'a>(args: &clap::ArgMatches<'a>) {
+	let adaptor = args.need_pubkey("adaptor-point").inner;
+	let sig_bytes = hex::decode(args.value_of("signature").need("no signature given"))
+		.need("invalid signature hex");
+	let pre = parse_pre_signature(args);
+	let format = match pre {
+		PreSignature::Schnorr(_) => SignatureFormat::Schnorr,
+		PreSignature::Ecdsa(_) if sig_bytes.len() == 64 => SignatureFormat::Compact,
+		PreSignature::Ecdsa(_) => SignatureFormat::Der,
+	};
+	let sig = Signature::parse(&sig_bytes, Some(format)).need("invalid signature");
+	let secret = match (pre, sig) {
+		(PreSignature::Schnorr(pre), Signature::Schnorr { signature, .. }) => pre.extract(&signature, &adaptor),
+		(PreSignature::Ecdsa(pre), Signature::Ecdsa { signature, .. }) => pre.extract(&signature, &adaptor),
+		_ => exit!("the signature type doesn't match the pre-signature"),
+	};
+	print!("{}", hex::encode(&secret.need("failed to extract secret")[..]));
+}
@@ -4,28 +4,15 @@ use std::str::FromStr;
 use bitcoin::address::AddressData;
 use bitcoin::hashes::Hash;
 use bitcoin::hashes::hex::FromHex;
-use bitcoin::{Address, ScriptBuf, WPubkeyHash, WScriptHash};
+use bitcoin::{Address, ScriptBuf, WPubkeyHash, WScriptHash, XOnlyPublicKey};
 use clap;
 
 use hal;
 
 use crate::prelude::*;
 
-lazy_static! {
-	/// The H point as used in BIP-341 which is constructed by taking the hash
-	/// of the standard uncompressed encoding of the secp256k1 base point G as
-	/// X coordinate.
-	///
-	/// See: https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki#constructing-and-spending-taproot-outputs
-	static ref NUMS_H: secp256k1::PublicKey = secp256k1::PublicKey::from_str(
-		"0250929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0"
-	).unwrap();
-}
-
-/// Create a NUMS point from the given entropy.
-fn nums(entropy: secp256k1::Scalar) -> secp256k1::PublicKey {
-	NUMS_H.add_exp_tweak(&SECP, &entropy).need("invalid NUMS entropy")
-}
+/// The human-readable parts used by segwit addresses on the different networks.
+const BECH32_HRPS: &[&str] = &["bc", "tb", "bcrt"];
 
 pub fn subcommand<'a>() -> clap::App<'a, 'a> {
 	cmd::subcommand_group("address", "work with addresses")
@@ -46,9 +33,18 @@ fn cmd_create<'a>() -> clap::App<'a, 'a> {
 		.arg(args::opt("scriptpubkey", "a scriptPubkey in hex"))
 		.arg(args::opt("pubkey", "a public key in hex"))
 		.arg(args::opt("script", "a script in hex"))
+		.arg(args::opt(
+			"tap-tree",
+			"a p2tr script tree of hex scripts in the form {A,{B,C}}",
+		))
+		.arg(args::opt(
+			"tap-leaf",
+			"a p2tr leaf script in the form <weight>:<hex>, the tree is built\n\
+			using Huffman coding on the weights; can be passed multiple times",
+		).multiple(true).number_of_values(1))
 		.arg(args::opt(
 			"internal-key",
-			"internal pubkey to use with --script for p2tr",
+			"internal pubkey to use with --script or a tap tree for p2tr",
 		))
 		.arg(args::opt(
 			"nums-internal-key",
@@ -84,49 +80,70 @@ fn exec_create<'a>(args: &clap::ArgMatches<'a>) {
 		let script_bytes = hex::decode(script_hex).need("invalid script hex");
 		let script = ScriptBuf::from(script_bytes);
 
-		let p2tr = {
-			// If the user provided NUMS information we can add a p2tr address.
-			// If not, we assume H NUMS from BIP-341.
-			if util::more_than_one(&[
-				args.is_present("internal-key"),
-				args.is_present("nums-internal-key-h"),
-				args.is_present("nums-internal-key-entropy"),
-				// deprecated
-				args.is_present("nums-internal-key"),
-			]) {
-				println!("Use only either nums-h, nums-internal-key or \
-					nums-internal-key-entropy.\n");
-				cmd_create().print_help().unwrap();
-				std::process::exit(1);
-			}
-			let internal = if args.is_present("nums-internal-key-h") {
-				*NUMS_H
-			} else if let Some(int) = args.value_of("nums-internal-key") {
-				eprintln!("--nums-internal-key is deprecated in favor of --internal-key");
-				int.parse().need("invalid nums internal key")
-			} else if let Some(int) = args.value_of("internal-key") {
-				int.parse().need("invalid nums internal key")
-			} else if let Some(ent) = args.value_of("nums-internal-key-entropy") {
-				let scalar = <[u8; 32]>::from_hex(ent)
-					.need("invalid entropy format: must be 32-byte hex");
-				nums(secp256k1::Scalar::from_be_bytes(scalar).need("invalid NUMS entropy"))
-			} else {
-				eprintln!("No NUMS key info provided, will use H NUMS from BIP-341 for p2tr.");
-				*NUMS_H
-			};
-			Address::from_script(&script.to_p2tr(&SECP, internal.into()), network).unwrap()
-		};
+		// If the user provided NUMS information we can add a p2tr address.
+		// If not, we assume H NUMS from BIP-341.
+		let internal = taproot_internal_key(args);
+		let p2tr = Address::from_script(&script.to_p2tr(&SECP, internal), network).unwrap();
 
 		let mut ret = hal::address::Addresses::from_script(&script, network);
 		assert!(ret.p2tr.replace(p2tr.as_unchecked().clone()).is_none(), "Addresses::from_script shouldn't set p2tr");
 
 		args.print_output(&ret)
+	} else if args.is_present("tap-tree") || args.is_present("tap-leaf") {
+		if args.is_present("tap-tree") && args.is_present("tap-leaf") {
+			exit!("Use only either --tap-tree or --tap-leaf.");
+		}
+
+		let internal = taproot_internal_key(args);
+		let (spend_info, scripts) = if let Some(expr) = args.value_of("tap-tree") {
+			hal::taproot::tree_from_expression(expr, internal)
+		} else {
+			let leaves = args.values_of("tap-leaf").unwrap()
+				.map(|l| hal::taproot::parse_weighted_leaf(l).need("invalid tap leaf"))
+				.collect();
+			hal::taproot::tree_from_weights(leaves, internal)
+		}.need("invalid tap tree");
+		let info = hal::taproot::TaprootInfo::from_spend_info(&spend_info, &scripts, network);
+		args.print_output(&info)
 	} else {
 		cmd_create().print_help().unwrap();
 		std::process::exit(1);
 	}
 }
 
+/// Determine the taproot internal key from the internal key arguments.
+fn taproot_internal_key<'a>(args: &clap::ArgMatches<'a>) -> XOnlyPublicKey {
+	if util::more_than_one(&[
+		args.is_present("internal-key"),
+		args.is_present("nums-internal-key-h"),
+		args.is_present("nums-internal-key-entropy"),
+		// deprecated
+		args.is_present("nums-internal-key"),
+	]) {
+		println!("Use only either nums-h, nums-internal-key or \
+			nums-internal-key-entropy.\n");
+		cmd_create().print_help().unwrap();
+		std::process::exit(1);
+	}
+
+	if args.is_present("nums-internal-key-h") {
+		hal::taproot::NUMS_H.x_only_public_key().0
+	} else if args.is_present("nums-internal-key") {
+		eprintln!("--nums-internal-key is deprecated in favor of --internal-key");
+		args.need_xonly_pubkey("nums-internal-key")
+	} else if args.is_present("internal-key") {
+		args.need_xonly_pubkey("internal-key")
+	} else if let Some(ent) = args.value_of("nums-internal-key-entropy") {
+		let scalar = <[u8; 32]>::from_hex(ent)
+			.need("invalid entropy format: must be 32-byte hex");
+		let entropy = secp256k1::Scalar::from_be_bytes(scalar).need("invalid NUMS entropy");
+		hal::taproot::nums(&entropy).need("invalid NUMS entropy").x_only_public_key().0
+	} else {
+		eprintln!("No NUMS key info provided, will use H NUMS from BIP-341 for p2tr.");
+		hal::taproot::NUMS_H.x_only_public_key().0
+	}
+}
+
 fn cmd_inspect<'a>() -> clap::App<'a, 'a> {
 	cmd::subcommand("inspect", "inspect addresses")
 		.arg(args::arg("address", "the address").required(true))
@@ -134,9 +151,22 @@ fn cmd_inspect<'a>() -> clap::App<'a, 'a> {
 
 fn exec_inspect<'a>(args: &clap::ArgMatches<'a>) {
 	let address_str = args.value_of("address").need("no address provided");
-	let address = Address::from_str(address_str)
-		.need("invalid address format")
-		.assume_checked();
+	let address = match Address::from_str(address_str) {
+		Ok(address) => address.assume_checked(),
+		Err(e) => {
+			// Try to help the user find the typo.
+			let accept = |s: &str| Address::from_str(s).is_ok();
+			let lower = address_str.to_lowercase();
+			if BECH32_HRPS.iter().any(|hrp| lower.starts_with(&format!("{}1", hrp))) {
+				if let Some(info) = hal::bech32::locate_errors(address_str, accept) {
+					args.print_output(&info);
+				}
+			} else {
+				args.print_output(&hal::address::diagnose_base58(address_str, accept));
+			}
+			exit!("invalid address format: {}", e);
+		}
+	};
 	let script_pk = address.script_pubkey();
 
 	let mut info = hal::address::AddressInfo {
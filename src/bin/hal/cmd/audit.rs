@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+
+use bitcoin::consensus::encode::deserialize;
+use bitcoin::{Amount, Block, OutPoint, Transaction, TxOut};
+
+use hal::audit::{FoundSignature, NonceReuseReport, SignatureKind};
+use hal::sig::Signature;
+
+use crate::prelude::*;
+
+pub fn subcommand<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand_group("audit", "audit transactions and signatures for weaknesses")
+		.subcommand(cmd_nonce_reuse())
+}
+
+pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
+	match args.subcommand() {
+		("nonce-reuse", Some(ref m)) => exec_nonce_reuse(&m),
+		(_, _) => unreachable!("clap prints help"),
+	};
+}
+
+/// Read data given either directly in hex or as a path to a file in hex or binary.
+fn hex_or_file(s: &str, what: &str) -> Vec<u8> {
+	match fs::read(s) {
+		Ok(content) => match hex::decode(String::from_utf8_lossy(&content).trim()) {
+			Ok(bytes) => bytes,
+			Err(_) => content,
+		},
+		Err(_) => hex::decode(s).need(&format!("invalid {}: not hex or a readable file", what)),
+	}
+}
+
+fn cmd_nonce_reuse<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("nonce-reuse", "find signatures that reuse the same nonce")
+		.arg(args::arg("raw-tx", "raw transactions in hex or files containing them; \
+			read from stdin if no input is given").multiple(true).required(false))
+		.arg(args::opt("block", "a raw block in hex or a file containing it \
+			(can be used multiple times)").multiple(true).number_of_values(1))
+		.arg(args::opt("prevout", "a previous output in the format \
+			<txid>:<vout>:<value-sat>:<scriptPubKey-hex> (can be used multiple times)")
+			.multiple(true).number_of_values(1))
+		.arg(args::opt("prev-tx", "a raw previous transaction in hex or a file containing it, \
+			all its outputs are used as previous outputs (can be used multiple times)")
+			.multiple(true).number_of_values(1))
+		.arg(args::opt("signature", "a signature given directly, in the format \
+			<signature-hex>:<digest-hex>[:<pubkey-hex>] (can be used multiple times)")
+			.multiple(true).number_of_values(1))
+		.arg(args::flag("recover", "recover and show private keys leaked by nonce reuse"))
+		.long_about("Find signatures that reuse the same nonce.\n\n\
+			All ECDSA and Schnorr signatures in the inputs of the given transactions are \
+			collected and signatures sharing the same R value are reported. The sighash of \
+			each signature is calculated to find out which key made it; this requires the \
+			previous outputs for segwit and taproot inputs, which can be given with \
+			--prevout or --prev-tx. Outputs of the scanned transactions themselves are used \
+			automatically.\n\n\
+			When two signatures from the same key with known sighashes share a nonce, \
+			the private key can be recovered with --recover.")
+}
+
+fn parse_direct_signature(s: &str) -> FoundSignature {
+	let parts = s.split(':').collect::<Vec<_>>();
+	if parts.len() != 2 && parts.len() != 3 {
+		exit!("invalid signature format: {}", s);
+	}
+	let bytes = hex::decode(parts[0]).need("invalid signature hex");
+	let (kind, compact) = match Signature::parse(&bytes, None).need("invalid signature") {
+		Signature::Ecdsa { signature, .. } => (SignatureKind::Ecdsa, signature.serialize_compact()),
+		Signature::Schnorr { signature, .. } => {
+			let mut compact = [0u8; 64];
+			compact.copy_from_slice(signature.as_ref());
+			(SignatureKind::Schnorr, compact)
+		}
+	};
+	let digest = hex::decode(parts[1]).need("invalid digest hex");
+	if digest.len() != 32 {
+		exit!("digest must be 32 bytes");
+	}
+	let pubkey = parts.get(2).map(|p| {
+		let bytes = hex::decode(p).need("invalid public key hex");
+		match kind {
+			SignatureKind::Ecdsa => secp256k1::PublicKey::from_slice(&bytes)
+				.need("invalid public key").serialize().to_vec(),
+			SignatureKind::Schnorr => secp256k1::XOnlyPublicKey::from_slice(&bytes)
+				.need("invalid x-only public key").serialize().to_vec(),
+		}
+	});
+
+	let mut ret = FoundSignature {
+		location: None,
+		kind: kind,
+		r: [0; 32],
+		s: [0; 32],
+		pubkey: pubkey,
+		message: Some([0; 32]),
+	};
+	ret.r.copy_from_slice(&compact[0..32]);
+	ret.s.copy_from_slice(&compact[32..64]);
+	ret.message.as_mut().unwrap().copy_from_slice(&digest);
+	ret
+}
+
+fn exec_nonce_reuse<'a>(args: &clap::ArgMatches<'a>) {
+	let mut txs = Vec::new();
+	for raw in args.values_of("raw-tx").into_iter().flatten() {
+		let bytes = hex_or_file(raw, "transaction");
+		txs.push(deserialize::<Transaction>(&bytes).need("invalid transaction format"));
+	}
+	for raw in args.values_of("block").into_iter().flatten() {
+		let bytes = hex_or_file(raw, "block");
+		let block: Block = deserialize(&bytes).need("invalid block format");
+		txs.extend(block.txdata);
+	}
+	let direct = args.values_of("signature").into_iter().flatten()
+		.map(parse_direct_signature).collect::<Vec<_>>();
+	if txs.is_empty() && direct.is_empty() {
+		let mut input = String::new();
+		io::stdin().read_to_string(&mut input).need("failed to read stdin");
+		for raw in input.split_whitespace() {
+			let bytes = hex::decode(raw).need("invalid transaction hex on stdin");
+			txs.push(deserialize::<Transaction>(&bytes).need("invalid transaction format"));
+		}
+	}
+
+	let mut prevouts = HashMap::new();
+	let prev_txs = args.values_of("prev-tx").into_iter().flatten().map(|raw| {
+		let bytes = hex_or_file(raw, "previous transaction");
+		deserialize::<Transaction>(&bytes).need("invalid previous transaction format")
+	}).collect::<Vec<_>>();
+	for tx in prev_txs.iter().chain(txs.iter()) {
+		let txid = tx.compute_txid();
+		for (vout, out) in tx.output.iter().enumerate() {
+			prevouts.insert(OutPoint::new(txid, vout as u32), out.clone());
+		}
+	}
+	for s in args.values_of("prevout").into_iter().flatten() {
+		let parts = s.split(':').collect::<Vec<_>>();
+		if parts.len() != 4 {
+			exit!("invalid prevout format: {}", s);
+		}
+		let outpoint = OutPoint {
+			txid: parts[0].parse().need("invalid prevout txid"),
+			vout: parts[1].parse().need("invalid prevout vout"),
+		};
+		let txout = TxOut {
+			value: Amount::from_sat(parts[2].parse().need("invalid prevout value")),
+			script_pubkey: hex::decode(parts[3]).need("invalid prevout scriptPubKey hex").into(),
+		};
+		prevouts.insert(outpoint, txout);
+	}
+
+	let mut sigs = direct;
+	let mut warnings = Vec::new();
+	for tx in &txs {
+		let (found, warns) = hal::audit::extract_signatures(tx, &prevouts);
+		sigs.extend(found);
+		warnings.extend(warns);
+	}
+
+	let report = NonceReuseReport {
+		transactions: txs.len(),
+		signatures: sigs.len(),
+		missing_sighash: sigs.iter().filter(|s| s.message.is_none()).count(),
+		warnings: warnings,
+		reused_nonces: hal::audit::find_nonce_reuse(&sigs, args.is_present("recover"), args.network()),
+	};
+	args.print_output(&report)
+}
@@ -56,9 +56,18 @@ fn cmd_decode<'a>() -> clap::App<'a, 'a> {
 }
 
 fn exec_decode<'a>(args: &clap::ArgMatches<'a>) {
-	let s = util::arg_or_stdin(args, "string");
+	let s = util::arg_or_stdin(args, "bech32");
 
-	let (hrp, payload) = bech32::decode(&s).need("invalid bech32");
+	let (hrp, payload) = match bech32::decode(&s) {
+		Ok(ret) => ret,
+		Err(e) => {
+			let accept = |c: &str| bech32::decode(c).is_ok();
+			if let Some(info) = hal::bech32::locate_errors(&s, accept) {
+				args.print_output(&info);
+			}
+			exit!("invalid bech32: {}", e);
+		}
+	};
 
 	let info = hal::bech32::Bech32Info {
 		bech32: s.to_string(),
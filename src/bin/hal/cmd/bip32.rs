@@ -1,105 +1,122 @@
 
-use std::str::FromStr;
-
 use bitcoin::bip32;
 use clap;
 
+use hal::bip32::Slip132Version;
+
 use crate::prelude::*;
 
 pub fn subcommand<'a>() -> clap::App<'a, 'a> {
 	cmd::subcommand_group("bip32", "BIP-32 key derivation")
 		.subcommand(cmd_derive())
 		.subcommand(cmd_inspect())
+		.subcommand(cmd_convert())
 }
 
 pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
 	match args.subcommand() {
 		("derive", Some(ref m)) => exec_derive(&m),
 		("inspect", Some(ref m)) => exec_inspect(&m),
+		("convert", Some(ref m)) => exec_convert(&m),
 		(_, _) => unreachable!("clap prints help"),
 	};
 }
 
+/// Parse an extended public or private key in any SLIP-132 version.
+fn parse_ext_key(s: &str) -> (bip32::Xpub, Option<bip32::Xpriv>, &'static Slip132Version) {
+	hal::bip32::parse_ext_key(s).need("invalid extended key")
+}
+
 fn cmd_derive<'a>() -> clap::App<'a, 'a> {
 	cmd::subcommand("derive", "derive keys from an extended key")
-		.arg(args::arg("ext-key", "extended public or private key").required(true))
-		.arg(args::arg("derivation-path", "the derivation path").required(true))
+		.arg(args::arg("ext-key", "extended public or private key, \
+			optionally with a key origin like [d34db33f/84'/0'/0']xpub...").required(true))
+		.arg(args::arg("derivation-path", "the derivation path, \
+			can contain a wildcard like 0/* and a multipath step like <0;1>").required(true))
+		.arg(args::opt("range", "the range of indices for a wildcard: \
+			<start>..<end> (exclusive), <start>..=<end> (inclusive) or <count>"))
+		.long_about("Derive keys from an extended key.\n\n\
+			Paths with a wildcard (like m/84'/0'/0'/0/*) or a multipath step (like <0;1>) \
+			derive a list of keys; a wildcard requires --range. Every derived key is shown \
+			with its key origin, relative to the master fingerprint of the given key, or to the \
+			key origin given in front of it.")
 }
 
 fn exec_derive<'a>(args: &clap::ArgMatches<'a>) {
 	let path_str = args.value_of("derivation-path").unwrap();
-	let path: bip32::DerivationPath = path_str.parse().need("error parsing derivation path");
-	let key_str = args.value_of("ext-key").unwrap();
-
-	let master_fingerprint;
-	let mut derived_xpriv = None;
-	let derived_xpub = match bip32::Xpriv::from_str(&key_str) {
-		Ok(ext_priv) => {
-			derived_xpriv = Some(ext_priv.derive_priv(&SECP, &path).need("derivation error"));
-			master_fingerprint = ext_priv.fingerprint(&SECP);
-			bip32::Xpub::from_priv(&SECP, derived_xpriv.as_ref().unwrap())
-		}
-		Err(_) => {
-			let ext_pub = bip32::Xpub::from_str(key_str).need("invalid extended key");
-			master_fingerprint = ext_pub.fingerprint();
-			ext_pub.derive_pub(&SECP, &path).need("derivation error")
-		}
-	};
-
-	let info = hal::bip32::DerivationInfo {
-		network: derived_xpub.network,
-		master_fingerprint: Some(master_fingerprint),
-		path: Some(path),
-		xpriv: derived_xpriv,
-		xpub: derived_xpub,
-		chain_code: derived_xpub.chain_code,
-		identifier: derived_xpub.identifier(),
-		fingerprint: derived_xpub.fingerprint(),
-		public_key: derived_xpub.public_key,
-		private_key: derived_xpriv.map(|x| x.private_key),
-		addresses: hal::address::Addresses::from_pubkey(
-			&bitcoin::PublicKey::new(derived_xpub.public_key),
+	let template: hal::bip32::PathTemplate = path_str.parse().need("error parsing derivation path");
+	let range = args.value_of("range").map(|r| hal::bip32::parse_range(r).need("invalid range"));
+	let (origin, key_str) = hal::bip32::split_key_origin(args.value_of("ext-key").unwrap())
+		.need("invalid key origin");
+	let (xpub, xpriv, version) = parse_ext_key(key_str);
+	let (master_fingerprint, origin_path) = origin.unwrap_or((xpub.fingerprint(), vec![].into()));
+
+	let derive = |path: bip32::DerivationPath| {
+		let (derived_xpub, derived_xpriv) = match xpriv {
+			Some(xpriv) => {
+				let derived = xpriv.derive_priv(&SECP, &path).need("derivation error");
+				(bip32::Xpub::from_priv(&SECP, &derived), Some(derived))
+			}
+			None => (xpub.derive_pub(&SECP, &path).need("derivation error"), None),
+		};
+		let full_path = origin_path.extend(&path);
+		hal::bip32::DerivationInfo::new(
+			derived_xpub,
+			derived_xpriv,
+			Some((master_fingerprint, full_path)),
+			version,
 			args.network_from_kind(derived_xpub.network),
-		),
+		)
 	};
 
-	args.print_output(&info)
+	if template.is_ranged() || range.is_some() {
+		let paths = template.expand(range).need("invalid derivation path");
+		let infos = paths.into_iter().map(derive).collect::<Vec<_>>();
+		args.print_output(&infos)
+	} else {
+		let path = template.expand(None).need("invalid derivation path").pop().unwrap();
+		args.print_output(&derive(path))
+	}
 }
 
 fn cmd_inspect<'a>() -> clap::App<'a, 'a> {
 	cmd::subcommand("inspect", "inspect a BIP-32 xpub or xpriv")
 		.arg(args::arg("ext-key", "extended public or private key").required(true))
+		.long_about("Inspect a BIP-32 xpub or xpriv.\n\n\
+			All SLIP-132 versions are accepted, like ypub, zpub and the multisig Ypub and Zpub. \
+			For those, only the address of the script type implied by the version is shown.")
 }
 
 fn exec_inspect<'a>(args: &clap::ArgMatches<'a>) {
-	let key_str = args.value_of("ext-key").unwrap();
+	let (origin, key_str) = hal::bip32::split_key_origin(args.value_of("ext-key").unwrap())
+		.need("invalid key origin");
+	let (xpub, xpriv, version) = parse_ext_key(key_str);
+	let info = hal::bip32::DerivationInfo::new(
+		xpub, xpriv, origin, version, args.network_from_kind(xpub.network),
+	);
+	args.print_output(&info)
+}
 
-	let mut xpriv = None;
+fn cmd_convert<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("convert", "convert an extended key to another SLIP-132 version")
+		.arg(args::arg("ext-key", "extended public or private key").required(true))
+		.arg(args::opt("to", "the version to convert to, like xpub, zpub or vpub").required(true))
+		.long_about("Convert an extended key to another SLIP-132 version.\n\n\
+			Converting a private key to a public version like zpub gives the public key, use \
+			the private name like zprv to keep the private key. Supported versions are xpub, ypub, Ypub, zpub and Zpub for \
+			mainnet and tpub, upub, Upub, vpub and Vpub for testnet.")
+}
 
-	let xpub = match bip32::Xpriv::from_str(&key_str) {
-		Ok(ext_priv) => {
-			xpriv = Some(ext_priv);
-			bip32::Xpub::from_priv(&SECP, xpriv.as_ref().unwrap())
+fn exec_convert<'a>(args: &clap::ArgMatches<'a>) {
+	let (xpub, xpriv, _) = parse_ext_key(args.value_of("ext-key").unwrap());
+	let to = args.value_of("to").unwrap();
+	let version = Slip132Version::from_name(to).need("unknown SLIP-132 version");
+	if to == version.private_name {
+		match xpriv {
+			Some(xpriv) => print!("{}", version.encode_xpriv(&xpriv)),
+			None => exit!("can't convert a public key to {}", to),
 		}
-		Err(_) => key_str.parse().need("invalid extended key"),
-	};
-
-	let info = hal::bip32::DerivationInfo {
-		network: xpub.network,
-		master_fingerprint: None,
-		path: None,
-		xpriv: xpriv,
-		xpub: xpub,
-		chain_code: xpub.chain_code,
-		identifier: xpub.identifier(),
-		fingerprint: xpub.fingerprint(),
-		public_key: xpub.public_key,
-		private_key: xpriv.map(|x| x.private_key),
-		addresses: hal::address::Addresses::from_pubkey(
-			&bitcoin::PublicKey::new(xpub.public_key),
-			args.network_from_kind(xpub.network),
-		),
-	};
-
-	args.print_output(&info)
+	} else {
+		print!("{}", version.encode_xpub(&xpub));
+	}
 }
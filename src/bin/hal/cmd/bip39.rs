@@ -1,6 +1,6 @@
-use std::io;
+use std::{io, thread};
 
-use bip39::Mnemonic;
+use bip39::{Language, Mnemonic};
 use bitcoin::hashes::{sha256, Hash};
 use bitcoin::secp256k1::rand::{self, RngCore};
 use clap;
@@ -26,12 +26,14 @@ pub fn subcommand<'a>() -> clap::App<'a, 'a> {
 	cmd::subcommand_group("bip39", "BIP-39 mnemonics")
 		.subcommand(cmd_generate())
 		.subcommand(cmd_get_seed())
+		.subcommand(cmd_recover())
 }
 
 pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
 	match args.subcommand() {
 		("generate", Some(ref m)) => exec_generate(&m),
 		("get-seed", Some(ref m)) => exec_get_seed(&m),
+		("recover", Some(ref m)) => exec_recover(&m),
 		(_, _) => unreachable!("clap prints help"),
 	};
 }
@@ -53,8 +55,11 @@ fn cmd_generate<'a>() -> clap::App<'a, 'a> {
 			.long("language").short("l")
 			.default_value("english")
 			.help(&LANGUAGE_HELP))
-		.arg(args::arg("entropy", "hex-encoded entropy data").long("entropy"))
-		.arg(args::flag("stdin", "read entropy from stdin"))
+		.arg(args::arg("entropy", "hex-encoded entropy data").long("entropy")
+			.conflicts_with_all(&["dice", "cards", "coins"]))
+		.arg(args::flag("stdin", "read entropy from stdin")
+			.conflicts_with_all(&["dice", "cards", "coins"]))
+		.args(&args::opts_physical_entropy())
 }
 
 fn exec_generate<'a>(args: &clap::ArgMatches<'a>) {
@@ -72,7 +77,7 @@ fn exec_generate<'a>(args: &clap::ArgMatches<'a>) {
 	}
 	let nb_entropy_bytes = (word_count / 3) * 4;
 
-	let mut entropy;
+	let entropy;
 	match (args.is_present("entropy"), args.is_present("stdin")) {
 		(true, true) => exit!("can't provide --entropy and --stdin"),
 		(true, false) => {
@@ -95,8 +100,11 @@ fn exec_generate<'a>(args: &clap::ArgMatches<'a>) {
 			entropy = sha256::Hash::from_engine(hasher)[0..nb_entropy_bytes].to_vec();
 		}
 		(false, false) => {
-			entropy = vec![0; nb_entropy_bytes];
-			rand::thread_rng().fill_bytes(&mut entropy);
+			entropy = args.physical_entropy(nb_entropy_bytes).unwrap_or_else(|| {
+				let mut entropy = vec![0; nb_entropy_bytes];
+				rand::thread_rng().fill_bytes(&mut entropy);
+				entropy
+			});
 		}
 	}
 
@@ -118,8 +126,19 @@ fn exec_get_seed<'a>(args: &clap::ArgMatches<'a>) {
 	let network = args.network();
 
 	let mnemonic = args.value_of("mnemonic").need("no mnemonic provided");
-	let mnemonic = Mnemonic::parse(mnemonic)
-		.need("invalid mnemonic phrase");
+	let electrum = hal::electrum::seed_type(mnemonic);
+	let mnemonic = match Mnemonic::parse(mnemonic) {
+		Ok(m) => m,
+		Err(_) if electrum.is_some() => {
+			exit!("invalid BIP-39 mnemonic, but a valid Electrum {} seed: use electrum get-seed",
+				electrum.unwrap().name());
+		}
+		Err(e) => exit!("invalid mnemonic phrase: {}", e),
+	};
+	if let Some(seed_type) = electrum {
+		warn!("The mnemonic is also a valid Electrum {} seed, which gives a different seed: \
+			use electrum get-seed if it was created by Electrum", seed_type.name());
+	}
 
 	let info = ::hal::bip39::MnemonicInfo::from_mnemonic_with_passphrase(
 		&mnemonic,
@@ -129,6 +148,62 @@ fn exec_get_seed<'a>(args: &clap::ArgMatches<'a>) {
 	args.print_output(&info)
 }
 
+fn cmd_recover<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("recover", "recover a mnemonic with missing or misspelled words")
+		.arg(args::arg("mnemonic", "the mnemonic phrase, with ? for unknown words").required(true))
+		.arg(args::opt("language", "the language of the mnemonic").default_value("english"))
+		.arg(args::opt("passphrase", "the BIP-39 passphrase, used when filtering"))
+		.arg(args::opt("address", "the first receive address of a BIP-44, 49, 84 or 86 account")
+			.conflicts_with_all(&["xpub", "fingerprint"]))
+		.arg(args::opt("xpub", "the master xpub or the account xpub of a BIP-44, 49, 84 or 86 account")
+			.conflicts_with("fingerprint"))
+		.arg(args::opt("fingerprint", "the master fingerprint"))
+		.long_about("Recover a mnemonic with missing or misspelled words.\n\n\
+			Unknown words can be given as ?, misspelled words are matched against the closest \
+			words in the word list and abbreviations against all words starting with them. \
+			All combinations with a valid checksum are listed, unless they are filtered by \
+			--address, --xpub or --fingerprint, which requires deriving every candidate.\n\n\
+			At most 2^24 combinations are searched, which allows for two unknown words.")
+}
+
+/// The candidate word indices for the word, exiting if there are none.
+fn word_candidates(word: &str, language: Language) -> Vec<u16> {
+	let ret = hal::bip39::word_candidates(word, language);
+	if ret.is_empty() {
+		exit!("no word in the word list is similar to '{}'", word);
+	}
+	if word != "?" && language.find_word(word).is_none() {
+		info!("'{}' could be: {}", word,
+			ret.iter().map(|i| language.word_list()[*i as usize]).collect::<Vec<_>>().join(", "));
+	}
+	ret
+}
+
+fn exec_recover<'a>(args: &clap::ArgMatches<'a>) {
+	let language = hal::bip39::parse_language(args.value_of("language").unwrap())
+		.need("invalid language string");
+	let candidates = args.value_of("mnemonic").unwrap().split_whitespace()
+		.map(|w| word_candidates(&w.to_lowercase(), language))
+		.collect::<Vec<_>>();
+	let target = if let Some(addr) = args.value_of("address") {
+		let addr = addr.parse().need("invalid address");
+		Some(hal::bip39::RecoveryTarget::from_address(addr).need("unsupported address"))
+	} else if let Some(xpub) = args.value_of("xpub") {
+		let (xpub, _, _) = hal::bip32::parse_ext_key(xpub).need("invalid xpub");
+		Some(hal::bip39::RecoveryTarget::Xpub(xpub))
+	} else {
+		args.value_of("fingerprint").map(|fp| {
+			hal::bip39::RecoveryTarget::Fingerprint(fp.parse().need("invalid fingerprint"))
+		})
+	};
+	let passphrase = args.value_of("passphrase").unwrap_or("");
+
+	let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+	let info = hal::bip39::recover(&candidates, language, target.as_ref().map(|t| (t, passphrase)), threads)
+		.need("recovery failed");
+	args.print_output(&info)
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -0,0 +1,57 @@
+use clap;
+
+use hal::bip85::Application;
+
+use crate::prelude::*;
+
+pub fn subcommand<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand_group("bip85", "BIP-85 deterministic entropy from a master seed")
+		.subcommand(cmd_app("bip39", "derive a BIP-39 mnemonic")
+			.arg(args::opt("words", "the number of words: 12, 18 or 24").default_value("24"))
+			.arg(args::opt("language", "the language of the mnemonic").default_value("english")))
+		.subcommand(cmd_app("wif", "derive a private key for an HD-wallet seed in WIF"))
+		.subcommand(cmd_app("xprv", "derive a master xprv"))
+		.subcommand(cmd_app("hex", "derive hex entropy")
+			.arg(args::opt("bytes", "the number of bytes, between 16 and 64").default_value("64")))
+		.subcommand(cmd_app("base64", "derive a base64 password")
+			.arg(args::opt("length", "the password length, between 20 and 86").default_value("21")))
+		.subcommand(cmd_app("base85", "derive a base85 password")
+			.arg(args::opt("length", "the password length, between 10 and 80").default_value("12")))
+		.subcommand(cmd_app("dice", "derive dice rolls")
+			.arg(args::opt("sides", "the number of sides of the die").default_value("6"))
+			.arg(args::opt("rolls", "the number of rolls, at most 10000").required(true)))
+}
+
+pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
+	let (name, args) = match args.subcommand() {
+		(name, Some(m)) => (name, m),
+		(_, None) => unreachable!("clap prints help"),
+	};
+	let number = |arg: &str| args.value_of(arg).unwrap().parse::<u32>()
+		.need(&format!("invalid value for --{}", arg));
+	let app = match name {
+		"bip39" => Application::Bip39 {
+			language: hal::bip39::parse_language(args.value_of("language").unwrap())
+				.need("invalid language string"),
+			words: number("words") as usize,
+		},
+		"wif" => Application::HdSeedWif,
+		"xprv" => Application::Xprv,
+		"hex" => Application::Hex { bytes: number("bytes") as usize },
+		"base64" => Application::PasswordBase64 { length: number("length") as usize },
+		"base85" => Application::PasswordBase85 { length: number("length") as usize },
+		"dice" => Application::Dice { sides: number("sides"), rolls: number("rolls") },
+		_ => unreachable!("clap prints help"),
+	};
+
+	let master = args.need_master_xpriv("seed");
+	let info = hal::bip85::derive(&master, app, number("index")).need("derivation failed");
+	args.print_output(&info)
+}
+
+fn cmd_app<'a>(name: &'static str, about: &'static str) -> clap::App<'a, 'a> {
+	cmd::subcommand(name, about)
+		.arg(args::arg("seed", "the master BIP-39 mnemonic or xpriv").required(true))
+		.arg(args::opt("passphrase", "the BIP-39 passphrase of the mnemonic"))
+		.arg(args::opt("index", "the index of the derived child").default_value("0"))
+}
@@ -1,7 +1,11 @@
-use std::io::Write;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::thread;
 
 use bitcoin::consensus::encode::{deserialize, serialize};
-use bitcoin::{block, Block};
+use bitcoin::bip158::{BlockFilter, FilterHeader};
+use bitcoin::hashes::Hash;
+use bitcoin::{block, Amount, Block, Network, ScriptBuf, TxOut};
 
 use hal::block::{BlockHeaderInfo, BlockInfo};
 use crate::prelude::*;
@@ -9,30 +13,130 @@ use crate::cmd::tx::create_transaction;
 
 pub fn subcommand<'a>() -> clap::App<'a, 'a> {
 	cmd::subcommand_group("block", "manipulate blocks")
+		.subcommand(cmd_coinbase())
 		.subcommand(cmd_create())
 		.subcommand(cmd_decode())
+		.subcommand(cmd_filter())
+		.subcommand(cmd_filter_headers())
+		.subcommand(cmd_filter_match())
+		.subcommand(cmd_header_chain())
 }
 
 pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
 	match args.subcommand() {
+		("coinbase", Some(ref m)) => exec_coinbase(&m),
 		("create", Some(ref m)) => exec_create(&m),
 		("decode", Some(ref m)) => exec_decode(&m),
+		("filter", Some(ref m)) => exec_filter(&m),
+		("filter-headers", Some(ref m)) => exec_filter_headers(&m),
+		("filter-match", Some(ref m)) => exec_filter_match(&m),
+		("header-chain", Some(ref m)) => exec_header_chain(&m),
 		(_, _) => unreachable!("clap prints help"),
 	};
 }
 
+/// Parse an address or a script in hex.
+fn parse_script(s: &str, network: Network) -> ScriptBuf {
+	match s.parse::<bitcoin::Address<_>>() {
+		Ok(addr) => addr.require_network(network).need("invalid network on address").script_pubkey(),
+		Err(_) => hex::decode(s).need("expected an address or script hex").into(),
+	}
+}
+
+fn cmd_coinbase<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("coinbase", "create a coinbase transaction")
+		.args(&[
+			args::opt("height", "the height of the block").required(true),
+			args::opt("payout", "an output as <address or script hex>[:<amount in sat>], \
+				the one without amount gets the rest of the subsidy and fees")
+				.multiple(true).number_of_values(1).required(true),
+			args::opt("fees", "the fees of the transactions in the block in sat").default_value("0"),
+			args::opt("extra-data", "hex data to push in the coinbase script after the height"),
+			args::opt("extranonce", "an extranonce to push in the coinbase script as 8 bytes"),
+			args::opt("wtxid", "the wtxid of a transaction in the block to add to the witness \
+				commitment").multiple(true).number_of_values(1),
+			args::flag("witness-commitment", "add a witness commitment, also without --wtxid"),
+			args::opt("signet-solution", "the hex signet solution to add to the witness commitment"),
+			args::flag("signet-placeholder", "add an empty signet solution as a placeholder \
+				for signing")
+				.conflicts_with("signet-solution"),
+			args::flag("raw-stdout", "output the raw bytes of the result to stdout")
+				.short("r"),
+		])
+		.long_about("Create a coinbase transaction.\n\n\
+			The coinbase script starts with the height as required by BIP-34. The payouts \
+			can pay at most the block subsidy at the height and the given fees, and one of \
+			them can be left without an amount to get the remainder.\n\n\
+			With --wtxid or --witness-commitment, the BIP-141 witness commitment to the \
+			wtxids is added as the last output, with a zero witness reserved value. A signet \
+			solution as in BIP-325 is added to the witness commitment output, \
+			--signet-placeholder adds an empty one to compute the signet signature hash with.")
+}
+
+fn exec_coinbase<'a>(args: &clap::ArgMatches<'a>) {
+	let network = args.network();
+	let outputs = args.values_of("payout").unwrap().map(|payout| {
+		let mut parts = payout.splitn(2, ':');
+		let script = parse_script(parts.next().unwrap(), network);
+		let amount = parts.next().map(|a| {
+			bitcoin::Amount::from_sat(a.parse::<u64>().need("invalid payout amount"))
+		});
+		(script, amount)
+	}).collect();
+
+	let signet_solution = match args.value_of("signet-solution") {
+		Some(hex) => Some(hex::decode(hex).need("invalid signet solution hex")),
+		None if args.is_present("signet-placeholder") => Some(Vec::new()),
+		None => None,
+	};
+	let wtxids = args.values_of("wtxid").map(|w| w.map(|w| w.parse().need("invalid wtxid")).collect());
+	let params = hal::block::CoinbaseParams {
+		height: args.value_of("height").unwrap().parse().need("invalid height"),
+		outputs: outputs,
+		fees: bitcoin::Amount::from_sat(args.value_of("fees").unwrap().parse().need("invalid fees")),
+		extra_data: args.value_of("extra-data").map(|d| hex::decode(d).need("invalid extra data hex"))
+			.unwrap_or_default(),
+		extranonce: args.value_of("extranonce").map(|n| n.parse().need("invalid extranonce")),
+		witness_commitment: match wtxids {
+			Some(wtxids) => Some(wtxids),
+			None if args.is_present("witness-commitment") => Some(Vec::new()),
+			None => None,
+		},
+		signet_solution: signet_solution,
+	};
+
+	let tx = hal::block::create_coinbase(&params, network).need("failed to create coinbase");
+	let tx_bytes = serialize(&tx);
+	if args.is_present("raw-stdout") {
+		::std::io::stdout().write_all(&tx_bytes).unwrap();
+	} else {
+		print!("{}", hex::encode(&tx_bytes));
+	}
+}
+
 fn cmd_create<'a>() -> clap::App<'a, 'a> {
 	cmd::subcommand("create", "create a raw block from JSON")
 		.args(&[
 			args::arg("block-info", "the block info in JSON").required(false),
 			args::flag("raw-stdout", "output the raw bytes of the result to stdout")
 				.short("r"),
+			args::flag("fix-merkle", "recompute the witness commitment in the coinbase and \
+				the merkle root"),
+			args::flag("mine", "grind the nonce and the extranonce in the coinbase until the \
+				block hash meets the target of the bits, only practical for low difficulties"),
+			args::opt("threads", "the number of threads to mine with, defaults to the number \
+				of CPUs").requires("mine"),
 		])
 		.long_about(r#"
 Create a block from JSON. Use the same format as the `hal block decode` output.
 
 It's possible to pass the JSON string as the first argument or pass it via stdin.
 
+With --mine, the merkle root is recomputed and the nonce is ground until the block hash
+meets the target, which is useful to create regtest blocks. When all nonces are tried, an
+extranonce is pushed to the end of the coinbase script. With --fix-merkle, the witness
+commitment in the coinbase is recomputed as well.
+
 One can chose to pass in transaction info objects like used for `hal tx create` into the
 "transactions" field or hexadecimal raw transactions in the "raw_transactions" field.
 
@@ -83,7 +187,7 @@ fn exec_create<'a>(args: &clap::ArgMatches<'a>) {
 	}
 
 	let mut used_network = cmd::tx::UsedNetwork::new(args.explicit_network());
-	let block = Block {
+	let mut block = Block {
 		header: create_block_header(info.header),
 		txdata: match (info.transactions, info.raw_transactions) {
 			(Some(_), Some(_)) => exit!("Can't provide transactions both in JSON and raw."),
@@ -98,6 +202,18 @@ fn exec_create<'a>(args: &clap::ArgMatches<'a>) {
 		},
 	};
 
+	if args.is_present("fix-merkle") {
+		hal::block::fix_merkle(&mut block).need("failed to fix the merkle root");
+	}
+	if args.is_present("mine") {
+		let threads = match args.value_of("threads") {
+			Some(t) => t.parse::<usize>().need("invalid number of threads"),
+			None => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+		};
+		block = hal::block::mine(&block, threads).need("mining failed");
+		info!("Found nonce {} for block {}", block.header.nonce, block.block_hash());
+	}
+
 	let block_bytes = serialize(&block);
 	if args.is_present("raw-stdout") {
 		::std::io::stdout().write_all(&block_bytes).unwrap();
@@ -110,25 +226,70 @@ fn cmd_decode<'a>() -> clap::App<'a, 'a> {
 	cmd::subcommand("decode", "decode a raw block to JSON")
 		.arg(args::arg("raw-block", "the raw block in hex").required(false))
 		.arg(args::flag("txids", "provide transactions IDs instead of full transactions"))
+		.arg(args::flag("check", "check the block for consistency"))
+		.arg(args::opt("prevouts", "a file with the outputs spent by the block, to check fees")
+			.requires("check"))
+		.arg(args::opt("height", "the height of the block, to check the BIP-34 height and subsidy")
+			.requires("check"))
+		.long_about("Decode a raw block to JSON.\n\n\
+			With --check, the merkle root, the witness commitment, the coinbase and its BIP-34 \
+			height, duplicate txids, the block weight and the sigop cost are checked.\n\n\
+			The fees and the coinbase value are only checked if the outputs spent by the block \
+			are given with --prevouts: a file with one line per input in block order, skipping \
+			the coinbase, with the amount in satoshi and the scriptPubkey in hex separated by a \
+			space. Without them, the sigop cost doesn't include p2sh and witness sigops.")
+}
+
+/// Read the outputs spent by a block from a file.
+///
+/// Each line has the amount in satoshi and the scriptPubkey in hex, or only the
+/// scriptPubkey if the amount is not needed.
+pub fn read_prevouts(path: &str, need_amounts: bool) -> Vec<TxOut> {
+	let content = fs::read_to_string(path).need("failed to read prevouts file");
+	content.lines().map(|l| l.trim()).filter(|l| !l.is_empty()).map(|line| {
+		let mut parts = line.split_whitespace();
+		let (amount, script) = match (parts.next(), parts.next(), parts.next()) {
+			(Some(amount), Some(script), None) => {
+				(amount.parse::<u64>().need("invalid prevout amount"), script)
+			}
+			(Some(script), None, None) if !need_amounts => (0, script),
+			_ => exit!("invalid prevout line: {}", line),
+		};
+		TxOut {
+			value: Amount::from_sat(amount),
+			script_pubkey: ScriptBuf::from_hex(script).need("invalid prevout script hex"),
+		}
+	}).collect()
 }
 
 fn exec_decode<'a>(args: &clap::ArgMatches<'a>) {
 	let hex_tx = util::arg_or_stdin(args, "raw-block");
 	let raw_tx = hex::decode(hex_tx.as_ref()).need("could not decode raw block hex");
 
+	let check = |block: &Block| if args.is_present("check") {
+		let prevouts = args.value_of("prevouts").map(|p| read_prevouts(p, true));
+		let height = args.value_of("height").map(|h| h.parse::<u32>().need("invalid height"));
+		Some(hal::block::check_block(block, prevouts.as_ref().map(|p| &p[..]), height, args.network())
+			.need("failed to check block"))
+	} else {
+		None
+	};
+
 	if args.is_present("txids") {
 		let block: Block = deserialize(&raw_tx).need("invalid block format");
 		let info = hal::block::BlockInfo {
 			header: hal::GetInfo::get_info(&block.header, args.network()),
-			bip34_block_height: block.bip34_block_height().ok(),
+			bip34_block_height: hal::block::bip34_block_height(&block),
 			txids: Some(block.txdata.iter().map(|t| t.compute_txid()).collect()),
 			transactions: None,
 			raw_transactions: None,
+			checks: check(&block),
 		};
 		args.print_output(&info)
 	} else {
 		let block: Block = match deserialize(&raw_tx) {
 			Ok(block) => block,
+			Err(_) if args.is_present("check") => exit!("invalid block format"),
 			Err(_) => {
 				let header = deserialize::<block::Header>(&raw_tx).expect("invalid block format");
 				let block = Block {
@@ -138,7 +299,125 @@ fn exec_decode<'a>(args: &clap::ArgMatches<'a>) {
 				block
 			},
 		};
-		let info = hal::GetInfo::get_info(&block, args.network());
+		let mut info = hal::GetInfo::get_info(&block, args.network());
+		info.checks = check(&block);
 		args.print_output(&info)
 	}
 }
+
+fn cmd_filter<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("filter", "build the BIP-158 basic filter of a block")
+		.arg(args::arg("raw-block", "the raw block in hex").required(false))
+		.arg(args::opt("prevouts", "a file with the scripts spent by the block"))
+		.arg(args::opt("prev-header", "the filter header of the previous block, to compute \
+			the filter header"))
+		.arg(args::opt("match", "an address or script hex to match against the filter")
+			.multiple(true).number_of_values(1))
+		.long_about("Build the BIP-158 basic filter of a block.\n\n\
+			The filter contains the output scripts of the block and the scripts spent by its \
+			inputs. The spent scripts are given with --prevouts: a file with one line per \
+			input in block order, skipping the coinbase, with the scriptPubkey in hex, \
+			optionally preceded by the amount like for block decode --check.\n\n\
+			The filter header is computed if the header of the previous filter is given with \
+			--prev-header, which is all zeros for the genesis block.")
+}
+
+fn exec_filter<'a>(args: &clap::ArgMatches<'a>) {
+	let hex_block = util::arg_or_stdin(args, "raw-block");
+	let raw_block = hex::decode(hex_block.as_ref()).need("could not decode raw block hex");
+	let block: Block = deserialize(&raw_block).need("invalid block format");
+
+	let prevouts = args.value_of("prevouts").map(|p| read_prevouts(p, false)).unwrap_or_default();
+	let prev_header = args.value_of("prev-header")
+		.map(|h| h.parse::<FilterHeader>().need("invalid previous filter header"));
+	let scripts = args.values_of("match")
+		.map(|m| m.map(|s| parse_script(s, args.network())).collect::<Vec<_>>());
+
+	let info = hal::block::block_filter_info(
+		&block, &prevouts, prev_header, scripts.as_ref().map(|s| &s[..]),
+	).need("failed to build filter");
+	args.print_output(&info)
+}
+
+fn cmd_filter_headers<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("filter-headers", "compute the filter header chain of BIP-158 filters")
+		.arg(args::arg("filters", "the filters in hex of consecutive blocks, read from stdin \
+			one per line if omitted").multiple(true).required(false))
+		.arg(args::opt("prev-header", "the filter header of the block before the first filter, \
+			defaults to all zeros for a chain starting at the genesis block"))
+}
+
+fn exec_filter_headers<'a>(args: &clap::ArgMatches<'a>) {
+	let input = match args.values_of("filters") {
+		Some(filters) => filters.map(|f| f.to_owned()).collect::<Vec<_>>(),
+		None => io::stdin().lock().lines().map(|l| l.need("failed to read stdin"))
+			.filter(|l| !l.trim().is_empty()).collect(),
+	};
+	let filters = input.iter().map(|f| {
+		BlockFilter::new(&hex::decode(f.trim()).need("invalid filter hex"))
+	}).collect::<Vec<_>>();
+	let prev_header = args.value_of("prev-header")
+		.map(|h| h.parse::<FilterHeader>().need("invalid previous filter header"))
+		.unwrap_or(FilterHeader::all_zeros());
+
+	let headers = hal::block::filter_header_chain(&filters, prev_header);
+	args.print_output(&headers)
+}
+
+fn cmd_filter_match<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("filter-match", "match addresses or scripts against a BIP-158 filter")
+		.arg(args::arg("block-hash", "the hash of the block of the filter").required(true))
+		.arg(args::arg("filter", "the filter in hex").required(true))
+		.arg(args::arg("scripts", "the addresses or scripts in hex to match")
+			.multiple(true).required(true))
+		.long_about("Match addresses or scripts against a BIP-158 filter.\n\n\
+			Filters have false positives, about 1 in 784931 per script, so a match means \
+			the block should be fetched to check whether it really contains the script.")
+}
+
+fn exec_filter_match<'a>(args: &clap::ArgMatches<'a>) {
+	let block_hash = args.value_of("block-hash").unwrap().parse().need("invalid block hash");
+	let filter = BlockFilter::new(&hex::decode(args.value_of("filter").unwrap()).need("invalid filter hex"));
+	let scripts = args.values_of("scripts").unwrap()
+		.map(|s| parse_script(s, args.network())).collect::<Vec<_>>();
+
+	let matches = hal::block::match_filter(&filter, &block_hash, &scripts).need("failed to match filter");
+	args.print_output(&matches)
+}
+
+fn cmd_header_chain<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("header-chain", "check a chain of block headers")
+		.arg(args::arg("headers", "the raw headers in hex, read from stdin one per line if omitted")
+			.multiple(true).required(false))
+		.arg(args::opt("height", "the height of the first header, needed to check retargeting"))
+		.long_about("Check a chain of block headers.\n\n\
+			The headers are checked for linkage, proof of work, the median time rule and \
+			difficulty retargeting, and their total work is calculated. The headers can be \
+			given one by one or concatenated.\n\n\
+			The difficulty can only be checked if the --height of the first header is given, \
+			and a retarget only if the whole previous difficulty period is included. The \
+			median time is only checked against the headers that are included.")
+}
+
+fn exec_header_chain<'a>(args: &clap::ArgMatches<'a>) {
+	let input = match args.values_of("headers") {
+		Some(headers) => headers.map(|h| h.to_owned()).collect::<Vec<_>>(),
+		None => io::stdin().lock().lines().map(|l| l.need("failed to read stdin"))
+			.filter(|l| !l.trim().is_empty()).collect(),
+	};
+	let mut headers = Vec::new();
+	for hex_headers in input {
+		let raw = hex::decode(hex_headers.trim()).need("invalid header hex");
+		if raw.is_empty() || raw.len() % 80 != 0 {
+			exit!("invalid header length: {} bytes", raw.len());
+		}
+		for chunk in raw.chunks(80) {
+			headers.push(deserialize::<block::Header>(chunk).need("invalid header"));
+		}
+	}
+	let height = args.value_of("height").map(|h| h.parse::<u32>().need("invalid height"));
+
+	let info = hal::block::check_header_chain(&headers, height, args.network())
+		.need("failed to check headers");
+	args.print_output(&info)
+}
@@ -0,0 +1,66 @@
+use bip39::Mnemonic;
+use bitcoin::secp256k1::rand::thread_rng;
+use clap;
+
+use hal::electrum::SeedType;
+
+use crate::prelude::*;
+
+pub fn subcommand<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand_group("electrum", "Electrum v2 seeds")
+		.subcommand(cmd_generate())
+		.subcommand(cmd_get_seed())
+}
+
+pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
+	match args.subcommand() {
+		("generate", Some(ref m)) => exec_generate(&m),
+		("get-seed", Some(ref m)) => exec_get_seed(&m),
+		(_, _) => unreachable!("clap prints help"),
+	};
+}
+
+fn cmd_generate<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("generate", "generate a new Electrum seed")
+		.unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+		.arg(args::opt("type", "the seed type: standard, segwit, 2fa or 2fa_segwit")
+			.default_value("segwit"))
+		.arg(args::opt("language", "the language of the word list").default_value("english"))
+}
+
+fn exec_generate<'a>(args: &clap::ArgMatches<'a>) {
+	let seed_type = SeedType::from_name(args.value_of("type").unwrap()).need("invalid seed type");
+	let language = hal::bip39::parse_language(args.value_of("language").unwrap())
+		.need("invalid language string");
+
+	let mnemonic = hal::electrum::generate(seed_type, language, &mut thread_rng());
+	let info = hal::electrum::seed_info(&mnemonic, "", args.network()).need("invalid seed");
+	args.print_output(&info)
+}
+
+fn cmd_get_seed<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("get-seed", "get the seed type, BIP-32 seed and keys of an Electrum seed")
+		.arg(args::arg("mnemonic", "the Electrum seed phrase").required(true))
+		.arg(args::opt("passphrase", "the seed extension passphrase"))
+		.long_about("Get the seed type, BIP-32 seed and keys of an Electrum seed.\n\n\
+			Electrum v2 seeds encode their type in a hash of the words instead of a checksum \
+			and use the \"electrum\" salt to derive the BIP-32 seed. The keys are derived at \
+			the path Electrum uses for the seed type: the master key for standard wallets, \
+			m/0' for segwit wallets and m/0' and m/1' for 2fa wallets.\n\n\
+			Note that Electrum normalizes the passphrase like the seed words, including \
+			lowercasing it.")
+}
+
+fn exec_get_seed<'a>(args: &clap::ArgMatches<'a>) {
+	let mnemonic = args.value_of("mnemonic").unwrap();
+	if hal::electrum::seed_type(mnemonic).is_none() {
+		if Mnemonic::parse(mnemonic).is_ok() {
+			exit!("not an Electrum seed, but a valid BIP-39 mnemonic: use bip39 get-seed");
+		}
+		exit!("not an Electrum seed");
+	}
+
+	let passphrase = args.value_of("passphrase").unwrap_or("");
+	let info = hal::electrum::seed_info(mnemonic, passphrase, args.network()).need("invalid seed");
+	args.print_output(&info)
+}
@@ -1,59 +1,113 @@
-use std::io::Write;
+use std::fs::File;
+use std::io::{self, Read, Write};
 
-use bitcoin::hashes::hex::FromHex;
-use bitcoin::hashes::{sha256, sha256d, Hash};
+use hal::hash::{Algorithm, Hasher};
 
 use crate::prelude::*;
 
+/// The hash commands with their algorithm and description.
+const COMMANDS: &[(&str, &str)] = &[
+	("sha256", "hash input with SHA-256"),
+	("sha256d", "hash input with double SHA-256"),
+	("sha512", "hash input with SHA-512"),
+	("ripemd160", "hash input with RIPEMD-160"),
+	("hash160", "hash input with RIPEMD-160 of SHA-256"),
+	("hmac-sha256", "calculate an HMAC-SHA256 of the input"),
+	("hmac-sha512", "calculate an HMAC-SHA512 of the input"),
+	("siphash", "hash input with SipHash-2-4"),
+];
+
 pub fn subcommand<'a>() -> clap::App<'a, 'a> {
-	cmd::subcommand_group("hash", "commands to hash data")
-		.subcommand(cmd_sha256())
-		.subcommand(cmd_sha256d())
+	let mut ret = cmd::subcommand_group("hash", "commands to hash data");
+	for (name, about) in COMMANDS {
+		ret = ret.subcommand(cmd_hash(name, about));
+	}
+	ret
 }
 
 pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
 	match args.subcommand() {
-		("sha256", Some(ref m)) => exec_sha256(&m),
-		("sha256d", Some(ref m)) => exec_sha256d(&m),
-		(_, _) => unreachable!("clap prints help"),
+		(name, Some(ref m)) => exec_hash(name.parse().expect("only known algorithms"), &m),
+		(_, None) => unreachable!("clap prints help"),
 	};
 }
 
-fn cmd_sha256<'a>() -> clap::App<'a, 'a> {
-	cmd::subcommand("sha256", "hash input with SHA-256")
-		.arg(args::arg("hex", "the input bytes in hex to hash").required(true))
+fn cmd_hash<'a>(name: &'static str, about: &'static str) -> clap::App<'a, 'a> {
+	let algorithm = name.parse::<Algorithm>().expect("only known algorithms");
+	let mut ret = cmd::subcommand(name, about)
+		.arg(args::arg("input", "the input bytes in hex to hash, read from stdin if omitted")
+			.required(false))
+		.arg(args::flag("text", "interpret the input as UTF-8 text instead of hex"))
+		.arg(args::opt("file", "hash the contents of a file, use - for raw bytes from stdin")
+			.short("f").conflicts_with("input").conflicts_with("text"))
 		.arg(args::flag("raw-stdout", "output the raw bytes of the result to stdout")
-			.short("r"))
+			.short("r"));
+	if algorithm.needs_key() {
+		ret = ret.arg(args::opt("key", if algorithm == Algorithm::Siphash {
+			"the 16-byte key in hex"
+		} else {
+			"the key in hex"
+		}).required(true));
+	}
+	if algorithm == Algorithm::Sha256 {
+		ret = ret.arg(args::opt("tag", "calculate a BIP-340 tagged hash with this tag, \
+			like TapLeaf or BIP0340/challenge"));
+	}
+	if algorithm.display_backward() {
+		ret = ret.long_about("Hash input with double SHA-256.\n\n\
+			The result is shown in reverse byte order, like txids and block hashes are.")
+	}
+	ret
 }
 
-fn exec_sha256<'a>(args: &clap::ArgMatches<'a>) {
-	let hex = args.value_of("hex").need("no input bytes given");
-	let bytes = Vec::<u8>::from_hex(&hex).need("invalid hex");
+/// Feed the input into the hasher, streaming files.
+fn hash_input<'a>(args: &clap::ArgMatches<'a>, hasher: &mut Hasher) {
+	if let Some(path) = args.value_of("file") {
+		if path == "-" {
+			io::copy(&mut io::stdin().lock(), hasher).need("failed to read stdin");
+		} else {
+			let mut file = File::open(path).need("failed to open input file");
+			io::copy(&mut file, hasher).need("failed to read input file");
+		}
+		return;
+	}
 
-	let ret = sha256::Hash::hash(&bytes);
-	if args.is_present("raw-stdout") {
-		::std::io::stdout().write_all(&ret[..]).unwrap();
+	let input = match args.value_of("input") {
+		Some(i) => i.to_owned(),
+		None => {
+			let mut buf = String::new();
+			io::stdin().read_to_string(&mut buf).need("failed to read stdin");
+			if args.is_present("text") {
+				buf.trim_end_matches(['\n', '\r']).to_owned()
+			} else {
+				buf.trim().to_owned()
+			}
+		}
+	};
+	if args.is_present("text") {
+		hasher.input(input.as_bytes());
 	} else {
-		print!("{}", ret);
+		hasher.input(&hex::decode(&input).need("invalid hex"));
 	}
 }
 
-fn cmd_sha256d<'a>() -> clap::App<'a, 'a> {
-	cmd::subcommand("sha256d", "hash input with double SHA-256")
-		.arg(args::arg("hex", "the input bytes in hex to hash").required(true))
-		.arg(args::flag("raw-stdout", "output the raw bytes of the result to stdout")
-			.short("r"))
-}
-
-fn exec_sha256d<'a>(args: &clap::ArgMatches<'a>) {
-	let hex = args.value_of("hex").need("no input bytes given");
-	let bytes = Vec::<u8>::from_hex(&hex).need("invalid hex");
+fn exec_hash<'a>(algorithm: Algorithm, args: &clap::ArgMatches<'a>) {
+	let mut hasher = match args.value_of("tag") {
+		Some(tag) => Hasher::tagged(tag),
+		None => {
+			let key = args.value_of("key").map(|k| hex::decode(k).need("invalid key hex"));
+			Hasher::new(algorithm, key.as_ref().map(|k| &k[..])).need("invalid hash parameters")
+		}
+	};
+	hash_input(args, &mut hasher);
 
-	let ret = sha256d::Hash::hash(&bytes);
+	let mut ret = hasher.finalize();
 	if args.is_present("raw-stdout") {
-		::std::io::stdout().write_all(&ret[..]).unwrap();
+		io::stdout().write_all(&ret).unwrap();
 	} else {
-		print!("{}", ret);
+		if algorithm.display_backward() {
+			ret.reverse();
+		}
+		print!("{}", hex::encode(&ret));
 	}
 }
-
@@ -1,4 +1,5 @@
 use std::process;
+use std::str::FromStr;
 
 use bitcoin::secp256k1;
 use bitcoin::secp256k1::rand;
@@ -22,6 +23,11 @@ pub fn subcommand<'a>() -> clap::App<'a, 'a> {
 		.subcommand(cmd_negate_pubkey())
 		.subcommand(cmd_pubkey_tweak_add())
 		.subcommand(cmd_pubkey_combine())
+		.subcommand(cmd_ecdh())
+		.subcommand(cmd_bip38_encrypt())
+		.subcommand(cmd_bip38_decrypt())
+		.subcommand(cmd_bip38_intermediate())
+		.subcommand(cmd_recover())
 }
 
 pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
@@ -38,6 +44,11 @@ pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
 		("negate-pubkey", Some(ref m)) => exec_negate_pubkey(&m),
 		("pubkey-tweak-add", Some(ref m)) => exec_pubkey_tweak_add(&m),
 		("pubkey-combine", Some(ref m)) => exec_pubkey_combine(&m),
+		("ecdh", Some(ref m)) => exec_ecdh(&m),
+		("bip38-encrypt", Some(ref m)) => exec_bip38_encrypt(&m),
+		("bip38-decrypt", Some(ref m)) => exec_bip38_decrypt(&m),
+		("bip38-intermediate", Some(ref m)) => exec_bip38_intermediate(&m),
+		("recover", Some(ref m)) => exec_recover(&m),
 		(_, _) => unreachable!("clap prints help"),
 	};
 }
@@ -45,13 +56,15 @@ pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
 fn cmd_generate<'a>() -> clap::App<'a, 'a> {
 	cmd::subcommand("generate", "generate a new ECDSA keypair")
 		.unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+		.args(&args::opts_physical_entropy())
 }
 
 fn exec_generate<'a>(args: &clap::ArgMatches<'a>) {
 	let network = args.network();
 
-	let entropy: [u8; 32] = rand::random();
-	let secret_key = secp256k1::SecretKey::from_slice(&entropy[..]).unwrap();
+	let entropy = args.physical_entropy(32).unwrap_or_else(|| rand::random::<[u8; 32]>().to_vec());
+	let secret_key = secp256k1::SecretKey::from_slice(&entropy[..])
+		.need("the entropy is not a valid private key");
 	let privkey = bitcoin::PrivateKey {
 		compressed: true,
 		network: network.into(),
@@ -305,3 +318,223 @@ fn exec_pubkey_combine<'a>(args: &clap::ArgMatches<'a>) {
 	}
 }
 
+fn cmd_ecdh<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("ecdh", "calculate an ECDH shared secret")
+		.arg(args::arg("privkey", "the private key in hex or WIF").required(true))
+		.arg(args::arg("pubkey", "the other party's public key").required(true))
+		.long_about("Calculate an ECDH shared secret.\n\n\
+			The shared point is given together with the most common ways to hash it: \
+			\"sha256\" is the default of libsecp256k1 and \"sha512\" is used by the \
+			Electrum ECIES scheme.")
+}
+
+fn exec_ecdh<'a>(args: &clap::ArgMatches<'a>) {
+	let privkey = args.need_privkey("privkey");
+	let pubkey = args.need_pubkey("pubkey");
+
+	let info = hal::ecies::ecdh(&privkey.inner, &pubkey.inner);
+	args.print_output(&info)
+}
+
+fn opt_passphrase<'a>() -> clap::Arg<'a, 'a> {
+	args::opt("passphrase", "the BIP-38 passphrase; prompted for when not provided")
+}
+
+/// Get the BIP-38 passphrase from the arguments or prompt for it.
+fn bip38_passphrase<'a>(args: &clap::ArgMatches<'a>, confirm: bool) -> String {
+	if let Some(pass) = args.value_of("passphrase") {
+		return pass.to_owned();
+	}
+	let pass = util::prompt_passphrase("BIP-38 passphrase: ");
+	if confirm && util::prompt_passphrase("Repeat passphrase: ") != pass {
+		exit!("passphrases don't match");
+	}
+	pass
+}
+
+fn cmd_bip38_encrypt<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("bip38-encrypt", "encrypt a private key with BIP-38")
+		.arg(args::arg("privkey", "the private key in hex or WIF").required(false))
+		.arg(opt_passphrase())
+		.arg(args::opt("intermediate-code", "generate a new EC-multiplied encrypted key \
+			from an intermediate code, instead of encrypting a private key"))
+		.arg(args::flag("uncompressed", "use an uncompressed public key for the EC-multiplied key"))
+		.long_about("Encrypt a private key with BIP-38.\n\n\
+			When an intermediate code is given instead of a private key, a new encrypted key \
+			is generated using EC multiplication, together with a confirmation code. \
+			Only the owner of the passphrase used to create the intermediate code \
+			can decrypt the key. See bip38-intermediate.")
+}
+
+fn exec_bip38_encrypt<'a>(args: &clap::ArgMatches<'a>) {
+	if let Some(code) = args.value_of("intermediate-code") {
+		if args.is_present("privkey") || args.is_present("passphrase") {
+			exit!("a private key or passphrase can't be used with an intermediate code");
+		}
+		let seedb: [u8; 24] = rand::random();
+		let info = hal::bip38::encrypt_ec_multiply(code, seedb, !args.is_present("uncompressed"))
+			.need("failed to generate encrypted key");
+		args.print_output(&info)
+	} else {
+		if args.is_present("uncompressed") {
+			exit!("--uncompressed can only be used with --intermediate-code");
+		}
+		let privkey = args.privkey("privkey").need("no private key or intermediate code provided");
+		let passphrase = bip38_passphrase(args, true);
+		print!("{}", hal::bip38::encrypt(&privkey, &passphrase));
+	}
+}
+
+fn cmd_bip38_decrypt<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("bip38-decrypt", "decrypt a BIP-38 encrypted private key")
+		.arg(args::arg("encrypted", "the encrypted key or a confirmation code").required(true))
+		.arg(opt_passphrase())
+		.long_about("Decrypt a BIP-38 encrypted private key.\n\n\
+			When a confirmation code (cfrm38...) is given, it is verified \
+			and the address of the encrypted key is shown.")
+}
+
+fn exec_bip38_decrypt<'a>(args: &clap::ArgMatches<'a>) {
+	let encrypted = args.value_of("encrypted").need("no encrypted key provided");
+	let passphrase = bip38_passphrase(args, false);
+
+	if hal::bip38::is_confirmation_code(encrypted) {
+		let info = hal::bip38::verify_confirmation(encrypted, &passphrase)
+			.need("invalid confirmation code");
+		args.print_output(&info)
+	} else {
+		let mut privkey = hal::bip38::decrypt(encrypted, &passphrase).need("failed to decrypt");
+		let network = args.network();
+		privkey.network = network.into();
+		args.print_output(&privkey.get_info(network))
+	}
+}
+
+fn cmd_bip38_intermediate<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("bip38-intermediate", "create a BIP-38 intermediate code")
+		.arg(opt_passphrase())
+		.arg(args::opt("lot", "the lot number to include (up to 1048575)"))
+		.arg(args::opt("sequence", "the sequence number to include (up to 4095)"))
+		.long_about("Create a BIP-38 intermediate code.\n\n\
+			The intermediate code can be given to a third party to generate encrypted \
+			keys for you with bip38-encrypt --intermediate-code, without them learning \
+			the private keys.")
+}
+
+fn exec_bip38_intermediate<'a>(args: &clap::ArgMatches<'a>) {
+	let lot_sequence = match (args.value_of("lot"), args.value_of("sequence")) {
+		(Some(lot), Some(seq)) => Some((
+			lot.parse().need("invalid lot number"),
+			seq.parse().need("invalid sequence number"),
+		)),
+		(None, None) => None,
+		_ => exit!("--lot and --sequence must be used together"),
+	};
+	let passphrase = bip38_passphrase(args, true);
+
+	let owner_salt: [u8; 8] = rand::random();
+	let code = hal::bip38::intermediate_code(&passphrase, owner_salt, lot_sequence)
+		.need("failed to create intermediate code");
+	print!("{}", code);
+}
+
+fn cmd_recover<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand(
+		"recover",
+		"recover the public key from an ECDSA signature\n\nNOTE!! For SHA-256-d hashes, the --reverse \
+		flag must be used because Bitcoin Core reverses the hex order for those!",
+	)
+	.arg(args::arg("digest", "the signed digest in hex (must be 32 bytes)").required(true))
+	.arg(args::arg("signature", "the signature in hex: 64 bytes compact, or 65 bytes with \
+		a recovery header byte in front (as in signed messages) or a recovery id at the end")
+		.required(true))
+	.arg(args::opt("recid", "the recovery id (0-3); all are tried if not known"))
+	.arg(args::opt("layout", "where the recovery id is in a 65-byte signature: \
+		header or trailing; both are tried if ambiguous"))
+	.arg(args::flag("reverse", "reverse the digest"))
+	.arg(args::opt("address", "only keep candidates with this address"))
+	.arg(args::opt("der-signature", "only keep candidates for which this other DER signature \
+		by the same key is valid"))
+	.arg(args::opt("der-digest", "the digest in hex signed by --der-signature, \
+		if different from the recovered digest"))
+	.long_about("Recover the public key from an ECDSA signature.\n\n\
+		All possible public keys are listed with their addresses. Use --address or \
+		--der-signature to confirm which candidate is the signer.\n\n\
+		A first byte between 27 and 42 can be a BIP-137 header byte or the first byte of r \
+		with a trailing recovery id. Both are tried then, and the candidates show the \
+		signature layout they were recovered with, unless --layout is given.")
+}
+
+fn exec_recover<'a>(args: &clap::ArgMatches<'a>) {
+	let network = args.network();
+
+	let digest_hex = args.value_of("digest").need("no digest given");
+	let mut digest = hex::decode(&digest_hex).need("invalid hex digest");
+	if args.is_present("reverse") {
+		digest.reverse();
+	}
+	let msg = secp256k1::Message::from_digest_slice(&digest[..]).need("invalid digest");
+
+	let sig_hex = args.value_of("signature").need("no signature given");
+	let sig_bytes = hex::decode(&sig_hex).need("invalid signature hex");
+	let layout = args.value_of("layout")
+		.map(|l| hal::key::SignatureLayout::from_name(l).need("invalid signature layout"));
+	let mut sigs = hal::key::parse_recoverable_signature(&sig_bytes, layout).need("invalid signature");
+	if let Some(id) = args.value_of("recid") {
+		let id = id.parse::<u8>().ok().filter(|id| *id <= 3).need("invalid recovery id: must be 0-3");
+		sigs.retain(|s| s.recovery_id.unwrap_or(id) == id);
+		if sigs.is_empty() {
+			exit!("the recovery id doesn't match the one in the signature");
+		}
+		for sig in sigs.iter_mut() {
+			sig.recovery_id = Some(id);
+		}
+	}
+	let ambiguous = sigs.len() > 1;
+
+	let address = args.value_of("address").map(|a| {
+		bitcoin::Address::from_str(a).need("invalid address")
+	});
+	let der_sig = args.value_of("der-signature").map(|s| {
+		let bytes = hex::decode(s).need("invalid DER signature hex");
+		secp256k1::ecdsa::Signature::from_der_lax(&bytes).need("invalid DER signature")
+	});
+	let der_msg = match args.value_of("der-digest") {
+		Some(d) => {
+			let mut digest = hex::decode(d).need("invalid hex digest for --der-digest");
+			if args.is_present("reverse") {
+				digest.reverse();
+			}
+			secp256k1::Message::from_digest_slice(&digest).need("invalid digest for --der-digest")
+		}
+		None => msg,
+	};
+
+	let mut infos = Vec::new();
+	for sig in sigs {
+		let keys = hal::key::recover_pubkeys(&msg, &sig.compact, sig.recovery_id).need("recovery failed");
+		for (id, pk) in keys {
+			let mut info = hal::key::RecoveredKeyInfo::new(id, pk, sig.compressed, network);
+			if ambiguous {
+				info.signature_layout = sig.layout;
+			}
+			if address.is_some() || der_sig.is_some() {
+				let addr_ok = address.as_ref().map(|a| info.has_address(a)).unwrap_or(true);
+				let sig_ok = der_sig.map(|s| SECP.verify_ecdsa(&der_msg, &s, &pk).is_ok()).unwrap_or(true);
+				info.matches = Some(addr_ok && sig_ok);
+			}
+			infos.push(info);
+		}
+	}
+	if infos.is_empty() {
+		exit!("no public key can be recovered from the signature");
+	}
+
+	if address.is_some() || der_sig.is_some() {
+		infos.retain(|i| i.matches == Some(true));
+		if infos.is_empty() {
+			exit!("none of the recovered public keys match");
+		}
+	}
+	args.print_output(&infos)
+}
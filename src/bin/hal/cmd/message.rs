@@ -1,9 +1,15 @@
+use std::fs;
+use std::io::Write;
 use std::str::FromStr;
 
 use bitcoin::hashes::Hash;
-use bitcoin::secp256k1;
-use bitcoin::{Address, AddressType, PublicKey};
+use bitcoin::secp256k1::{self, rand};
+use bitcoin::{Address, AddressType, Amount, OutPoint, Psbt, PublicKey, ScriptBuf, TxOut};
 use clap;
+use miniscript::psbt::PsbtExt;
+
+use hal::bip322::SignatureFormat;
+use hal::message::ArmoredMessage;
 
 use crate::prelude::*;
 
@@ -13,6 +19,10 @@ pub fn subcommand<'a>() -> clap::App<'a, 'a> {
 		.subcommand(cmd_sign())
 		.subcommand(cmd_verify())
 		.subcommand(cmd_recover())
+		.subcommand(cmd_bip322_psbt())
+		.subcommand(cmd_bip322_finalize())
+		.subcommand(cmd_encrypt())
+		.subcommand(cmd_decrypt())
 }
 
 pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
@@ -21,6 +31,10 @@ pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
 		("sign", Some(ref m)) => exec_sign(&m),
 		("verify", Some(ref m)) => exec_verify(&m),
 		("recover", Some(ref m)) => exec_recover(&m),
+		("bip322-psbt", Some(ref m)) => exec_bip322_psbt(&m),
+		("bip322-finalize", Some(ref m)) => exec_bip322_finalize(&m),
+		("encrypt", Some(ref m)) => exec_encrypt(&m),
+		("decrypt", Some(ref m)) => exec_decrypt(&m),
 		(_, _) => unreachable!("clap prints help"),
 	};
 }
@@ -36,21 +50,119 @@ fn exec_hash<'a>(args: &clap::ArgMatches<'a>) {
 		sha256: bitcoin::hashes::sha256::Hash::hash(msg.as_bytes()),
 		sha256d: bitcoin::hashes::sha256d::Hash::hash(msg.as_bytes()),
 		sign_hash: bitcoin::sign_message::signed_msg_hash(&msg),
+		bip322: hal::bip322::message_hash(msg.as_bytes()),
 	};
 
 	args.print_output(&res)
 }
 
+fn opt_format<'a>() -> clap::Arg<'a, 'a> {
+	args::opt("format", "the signature format: legacy, simple or full")
+		.possible_values(&["legacy", "simple", "full"])
+}
+
+fn opt_utxo<'a>() -> clap::Arg<'a, 'a> {
+	args::opt(
+		"utxo",
+		"a UTXO to add to a BIP-322 proof of funds, \
+		in the format <txid>:<vout>:<value-sat>[:<scriptPubKey-hex>]; \
+		the scriptPubKey defaults to the signer's (can be used multiple times)",
+	).multiple(true).number_of_values(1)
+}
+
+/// Parse the --utxo arguments, using the given scriptPubKey for those that don't specify one.
+fn parse_utxos<'a>(args: &clap::ArgMatches<'a>, default_spk: &ScriptBuf) -> Vec<(OutPoint, TxOut)> {
+	args.values_of("utxo").map(|v| v.map(|s| {
+		let parts = s.split(':').collect::<Vec<_>>();
+		if parts.len() != 3 && parts.len() != 4 {
+			exit!("invalid utxo format: {}", s);
+		}
+		let outpoint = OutPoint {
+			txid: parts[0].parse().need("invalid utxo txid"),
+			vout: parts[1].parse().need("invalid utxo vout"),
+		};
+		let script_pubkey = match parts.get(3) {
+			Some(spk) => hex::decode(spk).need("invalid utxo scriptPubKey hex").into(),
+			None => default_spk.clone(),
+		};
+		let txout = TxOut {
+			value: Amount::from_sat(parts[2].parse().need("invalid utxo value")),
+			script_pubkey: script_pubkey,
+		};
+		(outpoint, txout)
+	}).collect()).unwrap_or_default()
+}
+
+/// Parse a signature or PSBT given in either hex or base64.
+fn parse_hex_or_base64(s: &str, what: &str) -> Vec<u8> {
+	match (hex::decode(s), base64::decode(s)) {
+		(Ok(b), Err(_)) => b,
+		(Err(_), Ok(b)) => b,
+		(Ok(b), Ok(_)) => {
+			debug!("{} is both valid hex and base64, assuming it's hex.", what);
+			b
+		}
+		(Err(e1), Err(e2)) => exit!("Invalid {}: \"{}\"; \"{}\"", what, e1, e2),
+	}
+}
+
 fn cmd_sign<'a>() -> clap::App<'a, 'a> {
 	cmd::subcommand("sign", "create a new Bitcoin Signed Message")
 		.arg(args::arg("key", "the private key to sign with in WIF format").required(true))
 		.arg(args::arg("message", "the message to sign (without prefix)").required(false))
+		.arg(opt_format())
+		.arg(args::opt("address", "the address to sign for with BIP-322 \
+			(p2pkh, p2wpkh, p2sh-p2wpkh or p2tr; defaults to the key's p2wpkh address)"))
+		.arg(opt_utxo())
+		.arg(args::flag("armor", "output an armored signed message including message and address"))
+		.long_about("Create a new Bitcoin Signed Message.\n\n\
+			By default, the legacy format is used. When --address is given, a BIP-322 \
+			signature in the simple format is created instead, and when --utxo is used, \
+			a full BIP-322 proof of funds is created. Use --format to override.\n\n\
+			For addresses that can't be signed for with a single key, use bip322-psbt.")
 }
 
 fn exec_sign<'a>(args: &clap::ArgMatches<'a>) {
 	let privkey = args.need_privkey("key");
 
 	let msg = util::arg_or_stdin(args, "message");
+
+	let format = match args.value_of("format") {
+		Some(f) => f.parse().need("invalid signature format"),
+		None if args.is_present("utxo") => SignatureFormat::Full,
+		None if args.is_present("address") => SignatureFormat::Simple,
+		None => SignatureFormat::Legacy,
+	};
+	if format != SignatureFormat::Legacy {
+		let network = args.network();
+		let spk = match args.value_of("address") {
+			Some(addr) => Address::from_str(addr).need("invalid address")
+				.require_network(network).need("invalid network on address")
+				.script_pubkey(),
+			None => {
+				if !privkey.compressed {
+					exit!("Uncompressed key in Segwit, provide an address with --address");
+				}
+				let pk = bitcoin::CompressedPublicKey(privkey.public_key(&SECP).inner);
+				Address::p2wpkh(&pk, network).script_pubkey()
+			}
+		};
+		let utxos = parse_utxos(args, &spk);
+		let tx = hal::bip322::sign(&privkey, &spk, msg.as_bytes(), &utxos)
+			.need("failed to sign message");
+		let sig = hal::bip322::encode_signature(&tx, format).need("failed to encode signature");
+		if args.is_present("armor") {
+			let addr = Address::from_script(&spk, network).need("invalid address");
+			print_armored(&msg, &addr.to_string(), &sig);
+		} else {
+			print!("{}", base64::encode(&sig));
+		}
+		return;
+	}
+	if args.is_present("address") || args.is_present("utxo") {
+		exit!("--address and --utxo can't be used with the legacy format");
+	}
+
 	let hash = bitcoin::sign_message::signed_msg_hash(&msg);
 
 	let signature = SECP.sign_ecdsa_recoverable(
@@ -66,18 +178,101 @@ fn exec_sign<'a>(args: &clap::ArgMatches<'a>) {
 	}
 	serialized[1..].copy_from_slice(&raw[..]);
 
-	print!("{}", base64::encode(&serialized[..]));
+	if args.is_present("armor") {
+		let addr = Address::p2pkh(&privkey.public_key(&SECP), args.network());
+		print_armored(&msg, &addr.to_string(), &serialized[..]);
+	} else {
+		print!("{}", base64::encode(&serialized[..]));
+	}
+}
+
+fn print_armored(message: &str, signer: &str, signature: &[u8]) {
+	let armored = ArmoredMessage {
+		message: message.to_owned(),
+		signer: signer.to_owned(),
+		signature: base64::encode(signature),
+	};
+	print!("{}", armored);
 }
 
 fn cmd_verify<'a>() -> clap::App<'a, 'a> {
-	cmd::subcommand("verify", "recover the pubkey and address of a Bitcoin Signed Messages")
-		.arg(args::arg("signer", "the signer's public key or address").required(true))
-		.arg(args::arg("signature", "the signature in hex").required(true))
+	cmd::subcommand("verify", "verify a Bitcoin Signed Message or a BIP-322 signature")
+		.arg(args::arg("signer", "the signer's public key or address, \
+			or an armored signed message or a file containing one")
+			.required(false))
+		.arg(args::arg("signature", "the signature in hex or base64").required(false))
 		.arg(args::arg("message", "the message that was signed (without prefix)").required(false))
+		.arg(opt_utxo())
+		.arg(args::flag("ignore-trailing-whitespace",
+			"for armored messages, retry without trailing whitespace if the signature is invalid"))
+		// Armored messages start with dashes.
+		.setting(clap::AppSettings::AllowLeadingHyphen)
+		.long_about("Verify a Bitcoin Signed Message or a BIP-322 signature.\n\n\
+			Instead of the signer, signature and message, a single armored signed message \
+			starting with \"-----BEGIN BITCOIN SIGNED MESSAGE-----\" can be provided, either \
+			as an argument, as the path to a file containing it, or on stdin.\n\n\
+			Trailing whitespace often gets mangled when copy-pasting armored messages. With \
+			--ignore-trailing-whitespace, the message is verified again without it if the \
+			signature is invalid for the message as given.")
 }
 
 fn exec_verify<'a>(args: &clap::ArgMatches<'a>) {
-	let signer = args.value_of("signer").need("no signer provided");
+	let armored = match (args.value_of("signer"), args.value_of("signature")) {
+		(None, _) => Some(util::arg_or_stdin(args, "signer").into_owned()),
+		(Some(s), None) if hal::message::is_armored(s) => Some(s.to_owned()),
+		(Some(s), None) => match fs::read_to_string(s) {
+			Ok(contents) => Some(contents),
+			Err(_) => exit!("no signature provided"),
+		},
+		(Some(_), Some(_)) => None,
+	};
+
+	if let Some(armored) = armored {
+		if args.is_present("message") {
+			exit!("the message can't be provided separately for an armored signed message");
+		}
+		let armored = ArmoredMessage::parse(&armored).need("invalid armored signed message");
+		let sig_bytes = parse_hex_or_base64(&armored.signature, "signature");
+		match verify_message(args, &armored.signer, &sig_bytes, &armored.message) {
+			Ok(res) => eprintln!("{}", res),
+			Err(e) => {
+				let trimmed = armored.trimmed_message();
+				if trimmed == armored.message {
+					exit!("{}", e);
+				}
+				let res = match verify_message(args, &armored.signer, &sig_bytes, &trimmed) {
+					Ok(res) => res,
+					Err(_) => exit!("{}", e),
+				};
+				if !args.is_present("ignore-trailing-whitespace") {
+					exit!("{}; it is valid for the message without trailing whitespace, \
+						use --ignore-trailing-whitespace to accept it", e);
+				}
+				eprintln!("{}", res);
+				eprintln!("Verified the message without trailing whitespace: {:?}", trimmed);
+			}
+		}
+	} else {
+		let signer = args.value_of("signer").need("no signer provided");
+		let sig = args.value_of("signature").need("no signature provided");
+		let sig_bytes = parse_hex_or_base64(&sig, "signature");
+		let msg = util::arg_or_stdin(args, "message");
+		match verify_message(args, signer, &sig_bytes, &msg) {
+			Ok(res) => eprintln!("{}", res),
+			Err(e) => exit!("{}", e),
+		}
+	}
+}
+
+/// Verify a legacy or BIP-322 message signature.
+///
+/// Returns a message describing the valid signature.
+fn verify_message<'a>(
+	args: &clap::ArgMatches<'a>,
+	signer: &str,
+	sig_bytes: &[u8],
+	msg: &str,
+) -> Result<String, String> {
 	let signer_addr_res = Address::from_str(&signer);
 	let signer_pubk_res = PublicKey::from_str(&signer);
 	if signer_addr_res.is_err() && signer_pubk_res.is_err() {
@@ -87,80 +282,86 @@ fn exec_verify<'a>(args: &clap::ArgMatches<'a>) {
 		if let Err(e) = signer_pubk_res {
 			error!("Error parsing signer as public key: {}", e);
 		}
-		exit!("Failed to parse signer.");
+		return Err("Failed to parse signer.".to_owned());
 	}
 	if signer_addr_res.is_ok() && signer_pubk_res.is_ok() {
 		debug!("Rare/impossible case that signer can both be parsed as pubkey and address.");
 	}
 
-	let sig = args.value_of("signature").need("no signature provided");
-	let sig_bytes = match (hex::decode(&sig), base64::decode(&sig)) {
-		(Ok(b), Err(_)) => b,
-		(Err(_), Ok(b)) => b,
-		(Ok(b), Ok(_)) => {
-			debug!("Signature is both valid hex and base64, assuming it's hex.");
-			b
-		}
-		(Err(e1), Err(e2)) => exit!("Invalid signature: \"{}\"; \"{}\"", e1, e2),
-	};
+	let network = args.network();
 
+	// Legacy signatures are always 65 bytes, BIP-322 signatures never are.
 	if sig_bytes.len() != 65 {
-		exit!("Invalid signature: length is {} instead of 65 bytes", sig_bytes.len());
+		let addr = match signer_addr_res {
+			Ok(addr) => addr.require_network(network)
+				.map_err(|e| format!("invalid network on expected address: {}", e))?,
+			Err(_) => return Err("BIP-322 signatures can only be verified against an address".into()),
+		};
+		let spk = addr.script_pubkey();
+		let utxos = parse_utxos(args, &spk);
+		let (format, tx) = hal::bip322::verify(&spk, msg.as_bytes(), &sig_bytes, &utxos)
+			.map_err(|e| format!("invalid BIP-322 signature: {}", e))?;
+		if tx.input.len() > 1 {
+			let total = utxos.iter()
+				.filter(|u| tx.input.iter().any(|i| i.previous_output == u.0))
+				.map(|u| u.1.value)
+				.sum::<Amount>();
+			eprintln!("Proof of funds for {} in {} UTXOs.", total, tx.input.len() - 1);
+		}
+		return Ok(format!("Signature is valid ({} BIP-322 format).", format));
 	}
-	let recid = secp256k1::ecdsa::RecoveryId::from_i32(((sig_bytes[0] - 27) & 0x03) as i32)
-		.need("invalid recoverable signature (invalid recid)");
-	let compressed = ((sig_bytes[0] - 27) & 0x04) != 0;
+	let recid = secp256k1::ecdsa::RecoveryId::from_i32(((sig_bytes[0].wrapping_sub(27)) & 0x03) as i32)
+		.map_err(|_| "invalid recoverable signature (invalid recid)")?;
+	let compressed = ((sig_bytes[0].wrapping_sub(27)) & 0x04) != 0;
 	let signature = secp256k1::ecdsa::RecoverableSignature::from_compact(&sig_bytes[1..], recid)
-		.need("invalid recoverable signature");
+		.map_err(|e| format!("invalid recoverable signature: {}", e))?;
 
-	let msg = util::arg_or_stdin(args, "message");
 	let hash = bitcoin::sign_message::signed_msg_hash(&msg);
 
 	let pubkey = PublicKey {
 		inner: SECP
 			.recover_ecdsa(&secp256k1::Message::from_digest(hash.to_byte_array()), &signature)
-			.need("invalid signature"),
+			.map_err(|e| format!("invalid signature: {}", e))?,
 		compressed: compressed,
 	};
 
-	let network = args.network();
 	if let Ok(pk) = signer_pubk_res {
 		if pubkey != pk {
-			exit!("Signed for pubkey {}, expected {}", pubkey, pk);
+			return Err(format!("Signed for pubkey {}, expected {}", pubkey, pk));
 		}
 	} else if let Ok(expected) = signer_addr_res {
 		let expected = expected.require_network(network)
-			.need("invalid network on expected address");
+			.map_err(|e| format!("invalid network on expected address: {}", e))?;
 		let addr = match expected.address_type() {
-			None => exit!("Unknown address type provided"),
+			None => return Err("Unknown address type provided".into()),
 			Some(AddressType::P2pkh) => Address::p2pkh(&pubkey, network),
 			Some(AddressType::P2wpkh) => if compressed {
 				let pk = bitcoin::CompressedPublicKey(pubkey.inner);
 				Address::p2wpkh(&pk, network)
 			} else {
-				exit!("Uncompressed key in Segwit");
+				return Err("Uncompressed key in Segwit".into());
 			},
 			Some(AddressType::P2sh) => if compressed {
 				let pk = bitcoin::CompressedPublicKey(pubkey.inner);
 				Address::p2shwpkh(&pk, network)
 			} else {
-				exit!("Uncompressed key in Segwit")
+				return Err("Uncompressed key in Segwit".into());
 			},
-			Some(tp) => exit!("Address of type {} can't sign messages.", tp),
+			Some(tp) => return Err(format!("Address of type {} can't sign messages.", tp)),
 		};
 		// We need to use to_string because regtest and testnet addresses are the same.
 		if addr.to_string() != expected.to_string() {
-			exit!(
+			return Err(format!(
 				"Signed for address {:?}, expected {:?} ({})",
 				addr,
 				expected,
 				expected.address_type().map(|t| t.to_string()).unwrap_or("unknown type".into()),
-			);
+			));
 		}
 	} else {
 		unreachable!();
 	}
-	eprintln!("Signature is valid.");
+	Ok("Signature is valid.".to_owned())
 }
 
 fn cmd_recover<'a>() -> clap::App<'a, 'a> {
@@ -171,15 +372,7 @@ fn cmd_recover<'a>() -> clap::App<'a, 'a> {
 
 fn exec_recover<'a>(args: &clap::ArgMatches<'a>) {
 	let sig = args.value_of("signature").need("no signature provided");
-	let sig_bytes = match (hex::decode(&sig), base64::decode(&sig)) {
-		(Ok(b), Err(_)) => b,
-		(Err(_), Ok(b)) => b,
-		(Ok(b), Ok(_)) => {
-			debug!("Signature is both valid hex and base64, assuming it's hex.");
-			b
-		}
-		(Err(e1), Err(e2)) => exit!("Invalid signature: \"{}\"; \"{}\"", e1, e2),
-	};
+	let sig_bytes = parse_hex_or_base64(&sig, "signature");
 
 	if sig_bytes.len() != 65 {
 		exit!("Invalid signature: length is {} instead of 65 bytes", sig_bytes.len());
@@ -204,3 +397,104 @@ fn exec_recover<'a>(args: &clap::ArgMatches<'a>) {
 	let info = hal::GetInfo::get_info(&bitcoin_key, args.network());
 	args.print_output(&info)
 }
+
+fn cmd_bip322_psbt<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("bip322-psbt", "create a PSBT to sign a BIP-322 message")
+		.arg(args::arg("address", "the address to sign for").required(true))
+		.arg(args::arg("message", "the message to sign (without prefix)").required(false))
+		.arg(args::opt("redeem-script", "the redeem script in hex, for p2sh addresses"))
+		.arg(args::opt("witness-script", "the witness script in hex, for p2wsh addresses"))
+		.arg(opt_utxo())
+		.long_about("Create a PSBT of the BIP-322 to_sign transaction.\n\n\
+			This can be used to sign messages for addresses like multisig that can't \
+			be signed for with a single key. Sign the PSBT with any PSBT signer and \
+			then use bip322-finalize to create the signature.")
+}
+
+fn exec_bip322_psbt<'a>(args: &clap::ArgMatches<'a>) {
+	let addr = args.value_of("address").need("no address provided");
+	let addr = Address::from_str(addr).need("invalid address")
+		.require_network(args.network()).need("invalid network on address");
+	let spk = addr.script_pubkey();
+	let msg = util::arg_or_stdin(args, "message");
+
+	let redeem_script = args.value_of("redeem-script")
+		.map(|s| ScriptBuf::from(hex::decode(s).need("invalid redeem script hex")));
+	let witness_script = args.value_of("witness-script")
+		.map(|s| ScriptBuf::from(hex::decode(s).need("invalid witness script hex")));
+	let utxos = parse_utxos(args, &spk);
+
+	let psbt = hal::bip322::to_sign_psbt(&spk, msg.as_bytes(), &utxos, redeem_script, witness_script)
+		.need("failed to create PSBT");
+	print!("{}", base64::encode(&psbt.serialize()));
+}
+
+fn cmd_bip322_finalize<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("bip322-finalize", "finalize a signed BIP-322 PSBT into a signature")
+		.arg(args::arg("psbt", "the signed PSBT in hex or base64").required(false))
+		.arg(opt_format().default_value("simple"))
+}
+
+fn exec_bip322_finalize<'a>(args: &clap::ArgMatches<'a>) {
+	let raw = parse_hex_or_base64(&util::arg_or_stdin(args, "psbt"), "PSBT");
+	let psbt = Psbt::deserialize(&raw).need("invalid PSBT format");
+	let prevouts = psbt.inputs.iter().map(|i| i.witness_utxo.clone())
+		.collect::<Option<Vec<_>>>();
+
+	let psbt = psbt.finalize(&SECP).unwrap_or_else(|(_, errs)| {
+		exit!("failed to finalize: {}", errs.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", "))
+	});
+	let tx = psbt.extract_tx_unchecked_fee_rate();
+
+	// Only common templates can be checked, so failure isn't fatal.
+	if let Some(prevouts) = prevouts {
+		for idx in 0..tx.input.len() {
+			if let Err(e) = hal::bip322::verify_input(&tx, idx, &prevouts) {
+				warn!("Couldn't verify the signature of input {}: {}", idx, e);
+			}
+		}
+	}
+
+	let format = args.value_of("format").need("no format provided")
+		.parse().need("invalid signature format");
+	let sig = hal::bip322::encode_signature(&tx, format).need("failed to encode signature");
+	print!("{}", base64::encode(&sig));
+}
+
+fn cmd_encrypt<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("encrypt", "encrypt a message to a public key using ECIES")
+		.arg(args::arg("pubkey", "the public key to encrypt to").required(true))
+		.arg(args::arg("message", "the message to encrypt").required(false))
+		.long_about("Encrypt a message to a public key using the ECIES scheme \
+			used by Electrum (BIE1). The output is base64-encoded.")
+}
+
+fn exec_encrypt<'a>(args: &clap::ArgMatches<'a>) {
+	let pubkey = args.need_pubkey("pubkey");
+	let msg = util::arg_or_stdin(args, "message");
+
+	let ephemeral = secp256k1::SecretKey::new(&mut rand::thread_rng());
+	let encrypted = hal::ecies::encrypt(&pubkey.inner, msg.as_bytes(), &ephemeral);
+	print!("{}", base64::encode(&encrypted));
+}
+
+fn cmd_decrypt<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("decrypt", "decrypt an ECIES-encrypted message with a private key")
+		.arg(args::arg("privkey", "the private key in hex or WIF").required(true))
+		.arg(args::arg("message", "the encrypted message in base64 or hex").required(false))
+		.arg(args::flag("raw-stdout", "output the raw bytes of the result to stdout").short("r"))
+}
+
+fn exec_decrypt<'a>(args: &clap::ArgMatches<'a>) {
+	let privkey = args.need_privkey("privkey");
+	let encrypted = parse_hex_or_base64(&util::arg_or_stdin(args, "message"), "encrypted message");
+
+	let decrypted = hal::ecies::decrypt(&privkey.inner, &encrypted).need("failed to decrypt");
+	if args.is_present("raw-stdout") {
+		::std::io::stdout().write_all(&decrypted).unwrap();
+	} else {
+		let msg = String::from_utf8(decrypted)
+			.need("decrypted message is not valid UTF-8, use --raw-stdout");
+		print!("{}", msg);
+	}
+}
@@ -1,8 +1,12 @@
+pub mod adaptor;
 pub mod address;
+pub mod audit;
 pub mod bech32;
 pub mod bip32;
 pub mod bip39;
+pub mod bip85;
 pub mod block;
+pub mod electrum;
 pub mod hash;
 pub mod key;
 pub mod ln;
@@ -12,16 +16,24 @@ pub mod miniscript;
 pub mod psbt;
 pub mod random;
 pub mod script;
+pub mod shamir;
+pub mod sig;
+pub mod taproot;
 pub mod tx;
+pub mod wallet;
 
 /// Build a list of all built-in subcommands.
 pub fn subcommands() -> Vec<clap::App<'static, 'static>> {
 	vec![
+		adaptor::subcommand(),
 		address::subcommand(),
+		audit::subcommand(),
 		bech32::subcommand(),
 		bip32::subcommand(),
 		bip39::subcommand(),
+		bip85::subcommand(),
 		block::subcommand(),
+		electrum::subcommand(),
 		hash::subcommand(),
 		key::subcommand(),
 		ln::subcommand(),
@@ -31,7 +43,11 @@ pub fn subcommands() -> Vec<clap::App<'static, 'static>> {
 		psbt::subcommand(),
 		random::subcommand(),
 		script::subcommand(),
+		shamir::subcommand(),
+		sig::subcommand(),
+		taproot::subcommand(),
 		tx::subcommand(),
+		wallet::subcommand(),
 	]
 }
 
@@ -21,15 +21,19 @@ fn cmd_bytes<'a>() -> clap::App<'a, 'a> {
 		.arg(args::arg("number", "the number of bytes").required(true))
 		.arg(args::flag("raw-stdout", "output the raw bytes of the result to stdout")
 			.short("r"))
+		.args(&args::opts_physical_entropy())
 }
 
 fn exec_bytes<'a>(args: &clap::ArgMatches<'a>) {
 	let nb = args.value_of("number").need("no number of bytes given")
 		.parse::<usize>()
 		.need("invalid number of bytes");
-	let mut bytes = vec![0u8; nb];
-	rand::thread_rng().fill_bytes(&mut bytes);
-	
+	let bytes = args.physical_entropy(nb).unwrap_or_else(|| {
+		let mut bytes = vec![0u8; nb];
+		rand::thread_rng().fill_bytes(&mut bytes);
+		bytes
+	});
+
 	if args.is_present("raw-stdout") {
 		::std::io::stdout().write_all(&bytes).unwrap();
 	} else {
@@ -0,0 +1,154 @@
+use std::io::{self, BufRead};
+
+use bip39::Mnemonic;
+use bitcoin::secp256k1::rand::thread_rng;
+use clap;
+
+use hal::{codex32, slip39};
+
+use crate::prelude::*;
+
+pub fn subcommand<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand_group("shamir", "SLIP-39 and codex32 secret shares")
+		.subcommand(cmd_split())
+		.subcommand(cmd_combine())
+		.subcommand(cmd_inspect())
+}
+
+pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
+	match args.subcommand() {
+		("split", Some(ref m)) => exec_split(&m),
+		("combine", Some(ref m)) => exec_combine(&m),
+		("inspect", Some(ref m)) => exec_inspect(&m),
+		(_, _) => unreachable!("clap prints help"),
+	};
+}
+
+fn is_codex32(share: &str) -> bool {
+	share.to_lowercase().starts_with("ms1")
+}
+
+fn cmd_split<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("split", "split a secret into SLIP-39 or codex32 shares")
+		.arg(args::opt("mnemonic", "a BIP-39 mnemonic to share the entropy of")
+			.conflicts_with("secret").required_unless("secret"))
+		.arg(args::opt("secret", "the master secret in hex"))
+		.arg(args::flag("codex32", "create codex32 shares instead of SLIP-39"))
+		.arg(args::opt("threshold", "the number of shares needed to recover the secret")
+			.conflicts_with("group"))
+		.arg(args::opt("shares", "the number of shares to create").requires("threshold"))
+		.arg(args::opt("group", "a SLIP-39 group as <threshold>of<count>, like 2of3")
+			.multiple(true).number_of_values(1).conflicts_with("codex32"))
+		.arg(args::opt("group-threshold", "the number of SLIP-39 groups needed to recover the secret, \
+			defaults to 1").requires("group"))
+		.arg(args::opt("passphrase", "the SLIP-39 passphrase to encrypt the secret with")
+			.conflicts_with("codex32"))
+		.arg(args::opt("iteration-exponent", "the SLIP-39 iteration exponent for the encryption")
+			.default_value("1"))
+		.arg(args::opt("identifier", "the 4-character codex32 identifier, \
+			defaults to the start of the BIP-32 fingerprint of the secret"))
+		.long_about("Split a secret into SLIP-39 or codex32 shares.\n\n\
+			With --mnemonic, the entropy of the BIP-39 mnemonic is shared, so that combining the \
+			shares gives back the same mnemonic. Note that wallets importing SLIP-39 or codex32 \
+			shares use the secret as the BIP-32 seed directly, which gives different keys than \
+			the BIP-39 mnemonic. To share the BIP-32 seed of a mnemonic instead, pass the seed \
+			from bip39 get-seed as --secret.\n\n\
+			SLIP-39 shares are created in a single group using --threshold and --shares, or in \
+			multiple groups using --group and --group-threshold.")
+}
+
+fn exec_split<'a>(args: &clap::ArgMatches<'a>) {
+	let secret = match args.value_of("mnemonic") {
+		Some(m) => Mnemonic::parse(m).need("invalid mnemonic").to_entropy(),
+		None => hex::decode(args.value_of("secret").unwrap()).need("invalid secret hex"),
+	};
+	let number = |arg: &str| args.value_of(arg).map(|v| v.parse::<u8>()
+		.need(&format!("invalid value for --{}", arg)));
+
+	if args.is_present("codex32") {
+		let threshold = number("threshold").need("--threshold is required");
+		let count = number("shares").unwrap_or(threshold);
+		let identifier = match args.value_of("identifier") {
+			Some(id) => id.to_owned(),
+			None => codex32::fingerprint_identifier(&secret).need("invalid secret"),
+		};
+		let shares = codex32::split(&secret, threshold, count, &identifier, &mut thread_rng())
+			.need("failed to split secret");
+		args.print_output(&shares.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+	} else {
+		let groups = match args.values_of("group") {
+			Some(groups) => groups.map(|g| {
+				let mut parts = g.splitn(2, "of");
+				let threshold = parts.next().unwrap().parse().need("invalid group threshold");
+				let count = parts.next().need("groups should be formatted as <threshold>of<count>")
+					.parse().need("invalid group count");
+				(threshold, count)
+			}).collect(),
+			None => {
+				let threshold = number("threshold").need("either --threshold or --group is required");
+				vec![(threshold, number("shares").unwrap_or(threshold))]
+			}
+		};
+		let info = slip39::split(
+			&secret,
+			args.value_of("passphrase").unwrap_or(""),
+			number("group-threshold").unwrap_or(1),
+			&groups,
+			true,
+			number("iteration-exponent").unwrap(),
+			&mut thread_rng(),
+		).need("failed to split secret");
+		args.print_output(&info)
+	}
+}
+
+fn cmd_combine<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("combine", "recover a secret from SLIP-39 or codex32 shares")
+		.arg(args::arg("shares", "the shares, read from stdin one per line if omitted")
+			.multiple(true).required(false))
+		.arg(args::opt("passphrase", "the SLIP-39 passphrase"))
+		.arg(args::opt("language", "the language of the BIP-39 mnemonic to show")
+			.default_value("english"))
+}
+
+fn exec_combine<'a>(args: &clap::ArgMatches<'a>) {
+	let shares = match args.values_of("shares") {
+		Some(shares) => shares.map(|s| s.to_owned()).collect::<Vec<_>>(),
+		None => io::stdin().lock().lines().map(|l| l.need("failed to read stdin"))
+			.filter(|l| !l.trim().is_empty()).collect(),
+	};
+	if shares.is_empty() {
+		exit!("no shares given");
+	}
+	let language = hal::bip39::parse_language(args.value_of("language").unwrap())
+		.need("invalid language string");
+
+	let secret = if is_codex32(&shares[0]) {
+		if args.is_present("passphrase") {
+			exit!("codex32 doesn't use a passphrase");
+		}
+		let shares = shares.iter().map(|s| codex32::Share::parse(s.trim()).need("invalid codex32 share"))
+			.collect::<Vec<_>>();
+		codex32::combine(&shares).need("failed to recover secret").secret()
+	} else {
+		let shares = shares.iter().map(|s| slip39::Share::from_mnemonic(s).need("invalid SLIP-39 share"))
+			.collect::<Vec<_>>();
+		slip39::combine(&shares, args.value_of("passphrase").unwrap_or(""))
+			.need("failed to recover secret")
+	};
+	args.print_output(&hal::bip39::SecretInfo::new(&secret, language))
+}
+
+fn cmd_inspect<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("inspect", "verify the checksum of a SLIP-39 or codex32 share and show its contents")
+		.arg(args::arg("share", "the share").required(true))
+}
+
+fn exec_inspect<'a>(args: &clap::ArgMatches<'a>) {
+	let share = args.value_of("share").unwrap();
+	if is_codex32(share) {
+		args.print_output(&codex32::Share::parse(share).need("invalid codex32 share").get_info())
+	} else {
+		args.print_output(&slip39::Share::from_mnemonic(share).need("invalid SLIP-39 share"))
+	}
+}
@@ -0,0 +1,62 @@
+use clap;
+
+use hal::sig::{Signature, SignatureFormat};
+
+use crate::prelude::*;
+
+pub fn subcommand<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand_group("sig", "inspect ECDSA and Schnorr signatures")
+		.subcommand(cmd_inspect())
+		.subcommand(cmd_normalize())
+}
+
+pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
+	match args.subcommand() {
+		("inspect", Some(ref m)) => exec_inspect(&m),
+		("normalize", Some(ref m)) => exec_normalize(&m),
+		(_, _) => unreachable!("clap prints help"),
+	};
+}
+
+fn opt_format<'a>() -> clap::Arg<'a, 'a> {
+	args::opt("format", "the signature format; by default DER is tried first \
+		and other 64 or 65-byte signatures are assumed to be Schnorr")
+		.possible_values(&["der", "compact", "schnorr"])
+}
+
+fn parse_signature<'a>(args: &clap::ArgMatches<'a>) -> Signature {
+	let hex = util::arg_or_stdin(args, "signature");
+	let bytes = hex::decode(hex.as_ref()).need("invalid signature hex");
+	let format = args.value_of("format")
+		.map(|f| f.parse::<SignatureFormat>().need("invalid format"));
+	Signature::parse(&bytes, format).need("invalid signature")
+}
+
+fn cmd_inspect<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("inspect", "inspect a signature")
+		.arg(args::arg("signature", "the signature in hex, with optional sighash byte")
+			.required(false))
+		.arg(opt_format())
+		.long_about("Inspect a signature.\n\n\
+			Strict DER (BIP-66), lax DER, compact and Schnorr signatures are supported, \
+			all with an optional trailing sighash byte. For ECDSA signatures, low-S (BIP-146) \
+			and low-R are checked and a low-S normalized signature is shown when needed.")
+}
+
+fn exec_inspect<'a>(args: &clap::ArgMatches<'a>) {
+	let sig = parse_signature(args);
+	args.print_output(&sig.get_info())
+}
+
+fn cmd_normalize<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("normalize", "normalize an ECDSA signature to low-S and strict DER")
+		.arg(args::arg("signature", "the signature in hex, with optional sighash byte")
+			.required(false))
+		.arg(opt_format())
+}
+
+fn exec_normalize<'a>(args: &clap::ArgMatches<'a>) {
+	let sig = parse_signature(args);
+	let normalized = sig.normalize_s().need("failed to normalize signature");
+	print!("{}", hex::encode(&normalized));
+}
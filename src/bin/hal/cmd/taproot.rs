@@ -0,0 +1,223 @@
+use std::process;
+use std::str::FromStr;
+
+use bitcoin::consensus::encode::deserialize;
+use bitcoin::secp256k1::Parity;
+use bitcoin::sighash::{Annex, Prevouts, SighashCache, TapSighashType};
+use bitcoin::taproot::{ControlBlock, LeafVersion, TapLeafHash, TapNodeHash, TaprootSpendInfo};
+use bitcoin::{Address, Amount, ScriptBuf, Transaction, TxOut, XOnlyPublicKey};
+use clap;
+
+use crate::prelude::*;
+
+pub fn subcommand<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand_group("taproot", "taproot tweaks, commitments and hashes")
+		.subcommand(cmd_tweak())
+		.subcommand(cmd_tweak_privkey())
+		.subcommand(cmd_verify_commitment())
+		.subcommand(cmd_leaf_hash())
+		.subcommand(cmd_branch_hash())
+		.subcommand(cmd_sighash())
+}
+
+pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
+	match args.subcommand() {
+		("tweak", Some(ref m)) => exec_tweak(&m),
+		("tweak-privkey", Some(ref m)) => exec_tweak_privkey(&m),
+		("verify-commitment", Some(ref m)) => exec_verify_commitment(&m),
+		("leaf-hash", Some(ref m)) => exec_leaf_hash(&m),
+		("branch-hash", Some(ref m)) => exec_branch_hash(&m),
+		("sighash", Some(ref m)) => exec_sighash(&m),
+		(_, _) => unreachable!("clap prints help"),
+	};
+}
+
+fn opts_tree<'a>() -> Vec<clap::Arg<'a, 'a>> {
+	vec![
+		args::opt("merkle-root", "the merkle root of the script tree in hex").conflicts_with("tap-tree"),
+		args::opt("tap-tree", "the script tree in descriptor format with hex leaves, \
+			like {A,{B,C}}"),
+	]
+}
+
+/// Build the script tree from the --tap-tree argument, if any.
+fn tap_tree<'a>(args: &clap::ArgMatches<'a>, internal: XOnlyPublicKey) -> Option<(TaprootSpendInfo, Vec<ScriptBuf>)> {
+	args.value_of("tap-tree").map(|expr| {
+		hal::taproot::tree_from_expression(expr, internal).need("invalid tap tree")
+	})
+}
+
+/// Determine the merkle root from either --merkle-root or --tap-tree.
+fn merkle_root<'a>(args: &clap::ArgMatches<'a>, internal: XOnlyPublicKey) -> Option<TapNodeHash> {
+	if let Some(root) = args.value_of("merkle-root") {
+		Some(TapNodeHash::from_str(root).need("invalid merkle root"))
+	} else {
+		tap_tree(args, internal).and_then(|(info, _)| info.merkle_root())
+	}
+}
+
+fn cmd_tweak<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("tweak", "compute the taproot tweak and output key for an internal key")
+		.arg(args::arg("internal-key", "the internal public key").required(true))
+		.args(&opts_tree())
+}
+
+fn exec_tweak<'a>(args: &clap::ArgMatches<'a>) {
+	let internal = args.need_xonly_pubkey("internal-key");
+	let (info, scripts) = match tap_tree(args, internal) {
+		Some(tree) => tree,
+		None => (TaprootSpendInfo::new_key_spend(&SECP, internal, merkle_root(args, internal)), vec![]),
+	};
+	args.print_output(&hal::taproot::TaprootInfo::from_spend_info(&info, &scripts, args.network()))
+}
+
+fn cmd_tweak_privkey<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("tweak-privkey", "tweak a private key for key-path spending")
+		.arg(args::arg("privkey", "the internal private key in hex or WIF").required(true))
+		.args(&opts_tree())
+}
+
+fn exec_tweak_privkey<'a>(args: &clap::ArgMatches<'a>) {
+	let privkey = args.need_privkey("privkey");
+	let internal = privkey.inner.x_only_public_key(&SECP).0;
+	let root = merkle_root(args, internal);
+	args.print_output(&hal::taproot::tweak_private_key(&privkey, root))
+}
+
+fn cmd_verify_commitment<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("verify-commitment", "verify that an output key commits to an internal key and tree")
+		.arg(args::arg("output-key", "the output key or a p2tr address").required(true))
+		.arg(args::arg("internal-key", "the internal public key").required(true))
+		.args(&opts_tree())
+		.arg(args::opt("parity", "the expected parity of the output key").possible_values(&["0", "1"]))
+		.arg(args::opt("script", "a leaf script in hex to check with --control-block"))
+		.arg(args::opt("control-block", "the control block in hex to verify the leaf script with"))
+		.long_about("Verify that an output key commits to an internal key and script tree.\n\n\
+			The tree can be given with either --merkle-root or --tap-tree, or left out for \
+			outputs without scripts. Alternatively, a leaf script can be verified using its \
+			control block with --script and --control-block.")
+}
+
+fn exec_verify_commitment<'a>(args: &clap::ArgMatches<'a>) {
+	let output_str = args.value_of("output-key").need("no output key given");
+	let output_key = match Address::from_str(output_str) {
+		Ok(addr) => {
+			let spk = addr.assume_checked().script_pubkey();
+			if !spk.is_p2tr() {
+				exit!("address is not a p2tr address");
+			}
+			XOnlyPublicKey::from_slice(&spk.as_bytes()[2..]).need("invalid output key")
+		}
+		Err(_) => args.need_xonly_pubkey("output-key"),
+	};
+	let internal = args.need_xonly_pubkey("internal-key");
+	let parity = args.value_of("parity").map(|p| {
+		Parity::from_u8(p.parse().need("invalid parity")).need("invalid parity")
+	});
+
+	let valid = match (args.value_of("script"), args.value_of("control-block")) {
+		(Some(script), Some(cb)) => {
+			let script = ScriptBuf::from(hex::decode(script).need("invalid script hex"));
+			let cb = ControlBlock::decode(&hex::decode(cb).need("invalid control block hex"))
+				.need("invalid control block");
+			if cb.internal_key != internal {
+				eprintln!("The control block has a different internal key: {}", cb.internal_key);
+			}
+			cb.internal_key == internal
+				&& parity.map(|p| p == cb.output_key_parity).unwrap_or(true)
+				&& cb.verify_taproot_commitment(&SECP, output_key, &script)
+		}
+		(None, None) => {
+			let root = merkle_root(args, internal);
+			hal::taproot::check_commitment(&output_key, parity, &internal, root)
+		}
+		_ => exit!("--script and --control-block must be used together"),
+	};
+
+	if valid {
+		println!("Commitment is valid.");
+	} else {
+		eprintln!("Commitment is invalid!");
+		process::exit(1);
+	}
+}
+
+fn cmd_leaf_hash<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("leaf-hash", "compute the TapLeaf hash of a script")
+		.arg(args::arg("script", "the leaf script in hex").required(true))
+		.arg(args::opt("leaf-version", "the leaf version in hex").default_value("c0"))
+}
+
+fn exec_leaf_hash<'a>(args: &clap::ArgMatches<'a>) {
+	let script = ScriptBuf::from(hex::decode(args.value_of("script").unwrap()).need("invalid script hex"));
+	let version = u8::from_str_radix(args.value_of("leaf-version").unwrap(), 16)
+		.need("invalid leaf version");
+	let version = LeafVersion::from_consensus(version).need("invalid leaf version");
+	print!("{}", TapLeafHash::from_script(&script, version));
+}
+
+fn cmd_branch_hash<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("branch-hash", "compute the TapBranch hash of two nodes")
+		.arg(args::arg("left", "the first node hash in hex").required(true))
+		.arg(args::arg("right", "the second node hash in hex").required(true))
+		.long_about("Compute the TapBranch hash of two nodes.\n\n\
+			The node hashes are sorted before hashing, so their order doesn't matter.")
+}
+
+fn exec_branch_hash<'a>(args: &clap::ArgMatches<'a>) {
+	let left = TapNodeHash::from_str(args.value_of("left").unwrap()).need("invalid left node hash");
+	let right = TapNodeHash::from_str(args.value_of("right").unwrap()).need("invalid right node hash");
+	print!("{}", TapNodeHash::from_node_hashes(left, right));
+}
+
+fn cmd_sighash<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("sighash", "compute the TapSighash for a transaction input")
+		.arg(args::arg("raw-tx", "the raw transaction in hex").required(true))
+		.arg(args::arg("input", "the index of the input to sign").required(true))
+		.arg(args::opt("prevout", "the previous output of every input, in order, \
+			in the format <value-sat>:<scriptPubKey-hex>").multiple(true).number_of_values(1)
+			.required(true))
+		.arg(args::opt("sighash-type", "the sighash type, like SIGHASH_ALL or a number")
+			.default_value("SIGHASH_DEFAULT"))
+		.arg(args::opt("leaf-hash", "the TapLeaf hash for script-path spends"))
+		.arg(args::opt("script", "the leaf script in hex for script-path spends"))
+		.arg(args::opt("annex", "the annex in hex, including the 0x50 prefix"))
+}
+
+fn exec_sighash<'a>(args: &clap::ArgMatches<'a>) {
+	let raw = hex::decode(args.value_of("raw-tx").unwrap()).need("invalid transaction hex");
+	let tx: Transaction = deserialize(&raw).need("invalid transaction format");
+	let input = args.value_of("input").unwrap().parse::<usize>().need("invalid input index");
+	let prevouts = args.values_of("prevout").unwrap().map(|s| {
+		let mut parts = s.splitn(2, ':');
+		let value = parts.next().unwrap().parse().need("invalid prevout value");
+		let spk = hex::decode(parts.next().need("prevouts should be formatted as <value>:<hex>"))
+			.need("invalid prevout scriptPubKey hex");
+		TxOut { value: Amount::from_sat(value), script_pubkey: spk.into() }
+	}).collect::<Vec<_>>();
+	if prevouts.len() != tx.input.len() {
+		exit!("expected {} prevouts, one for every input, got {}", tx.input.len(), prevouts.len());
+	}
+
+	let ty_str = args.value_of("sighash-type").unwrap();
+	let ty = TapSighashType::from_str(ty_str).or_else(|_| {
+		TapSighashType::from_consensus_u8(ty_str.parse().need("invalid sighash type"))
+	}).need("invalid sighash type");
+
+	let leaf = match (args.value_of("leaf-hash"), args.value_of("script")) {
+		(Some(_), Some(_)) => exit!("Use only either --leaf-hash or --script."),
+		(Some(h), None) => Some(TapLeafHash::from_str(h).need("invalid leaf hash")),
+		(None, Some(s)) => {
+			let script = ScriptBuf::from(hex::decode(s).need("invalid script hex"));
+			Some(TapLeafHash::from_script(&script, LeafVersion::TapScript))
+		}
+		(None, None) => None,
+	};
+	let annex_bytes = args.value_of("annex").map(|a| hex::decode(a).need("invalid annex hex"));
+	let annex = annex_bytes.as_ref().map(|a| Annex::new(a).need("invalid annex"));
+
+	let sighash = SighashCache::new(&tx).taproot_signature_hash(
+		input, &Prevouts::All(&prevouts), annex, leaf.map(|l| (l, 0xffffffff)), ty,
+	).need("failed to compute sighash");
+	print!("{}", sighash);
+}
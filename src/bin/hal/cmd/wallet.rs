@@ -0,0 +1,51 @@
+use clap;
+
+use crate::prelude::*;
+
+pub fn subcommand<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand_group("wallet", "wallet setup and export")
+		.subcommand(cmd_descriptors())
+}
+
+pub fn execute<'a>(args: &clap::ArgMatches<'a>) {
+	match args.subcommand() {
+		("descriptors", Some(ref m)) => exec_descriptors(&m),
+		(_, _) => unreachable!("clap prints help"),
+	};
+}
+
+fn cmd_descriptors<'a>() -> clap::App<'a, 'a> {
+	cmd::subcommand("descriptors", "derive the standard account descriptors from a seed")
+		.arg(args::arg("seed", "a BIP-39 mnemonic or a master xpriv").required(true))
+		.arg(args::opt("passphrase", "the BIP-39 passphrase"))
+		.arg(args::opt("account", "the account number").default_value("0"))
+		.arg(args::flag("private", "use the xprv in the descriptors instead of the xpub"))
+		.arg(args::flag("import-descriptors", "output the request for Bitcoin Core's \
+			importdescriptors RPC"))
+		.arg(args::opt("timestamp", "the timestamp to use with --import-descriptors, \
+			\"now\" or a UNIX timestamp to rescan from").default_value("now"))
+		.long_about("Derive the standard account descriptors from a seed.\n\n\
+			The BIP-44, BIP-49, BIP-84 and BIP-86 accounts are shown with their receive and \
+			change descriptors. For the BIP-48 multisig accounts only the key with its origin \
+			is shown, to be combined with the keys of the other cosigners.\n\n\
+			With --import-descriptors, the output can be passed to Bitcoin Core's \
+			importdescriptors RPC. Use --timestamp 0 to rescan the whole chain for an \
+			existing wallet.")
+}
+
+fn exec_descriptors<'a>(args: &clap::ArgMatches<'a>) {
+	let master = args.need_master_xpriv("seed");
+	let account = args.value_of("account").unwrap().parse::<u32>().need("invalid account number");
+
+	let wallet = hal::wallet::account_descriptors(&master, account, args.is_present("private"))
+		.need("derivation error");
+	if args.is_present("import-descriptors") {
+		let timestamp = match args.value_of("timestamp").unwrap() {
+			"now" => "now".into(),
+			t => t.parse::<u64>().need("invalid timestamp").into(),
+		};
+		args.print_output(&wallet.import_descriptors(timestamp))
+	} else {
+		args.print_output(&wallet)
+	}
+}
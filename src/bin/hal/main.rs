@@ -11,6 +11,7 @@ extern crate clap;
 extern crate fern;
 extern crate hex;
 extern crate jobserver;
+extern crate rpassword;
 extern crate serde_json;
 extern crate secp256k1;
 extern crate shell_escape;
@@ -130,11 +131,15 @@ fn main() {
 	}
 
 	match args.subcommand() {
+		("adaptor", Some(ref m)) => cmd::adaptor::execute(&m),
 		("address", Some(ref m)) => cmd::address::execute(&m),
+		("audit", Some(ref m)) => cmd::audit::execute(&m),
 		("bech32", Some(ref m)) => cmd::bech32::execute(&m),
 		("bip32", Some(ref m)) => cmd::bip32::execute(&m),
 		("bip39", Some(ref m)) => cmd::bip39::execute(&m),
+		("bip85", Some(ref m)) => cmd::bip85::execute(&m),
 		("block", Some(ref m)) => cmd::block::execute(&m),
+		("electrum", Some(ref m)) => cmd::electrum::execute(&m),
 		("hash", Some(ref m)) => cmd::hash::execute(&m),
 		("key", Some(ref m)) => cmd::key::execute(&m),
 		("ln", Some(ref m)) => cmd::ln::execute(&m),
@@ -144,7 +149,11 @@ fn main() {
 		("psbt", Some(ref m)) => cmd::psbt::execute(&m),
 		("random", Some(ref m)) => cmd::random::execute(&m),
 		("script", Some(ref m)) => cmd::script::execute(&m),
+		("shamir", Some(ref m)) => cmd::shamir::execute(&m),
+		("sig", Some(ref m)) => cmd::sig::execute(&m),
+		("taproot", Some(ref m)) => cmd::taproot::execute(&m),
 		("tx", Some(ref m)) => cmd::tx::execute(&m),
+		("wallet", Some(ref m)) => cmd::wallet::execute(&m),
 		(cmd, subcommand_args) => {
 			// Try execute an external subcommand.
 
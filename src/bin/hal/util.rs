@@ -167,6 +167,11 @@ pub fn more_than_one(bools: &[bool]) -> bool {
 	false
 }
 
+/// Prompt for a passphrase on the terminal, without echoing it.
+pub fn prompt_passphrase(prompt: &str) -> String {
+	rpassword::prompt_password(prompt).need("failed to read passphrase from the terminal")
+}
+
 pub trait ResultExt<T, E: fmt::Display>: Into<Result<T, E>> {
     #[track_caller]
     fn need(self, msg: &str) -> T {
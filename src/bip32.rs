@@ -1,5 +1,9 @@
+use std::ops::Range;
+use std::str::FromStr;
 
-use bitcoin::{bip32, NetworkKind};
+use bitcoin::base58;
+use bitcoin::bip32::{ChildNumber, DerivationPath, Fingerprint};
+use bitcoin::{bip32, Network, NetworkKind};
 use serde::{Deserialize, Serialize};
 
 use crate::address;
@@ -12,6 +16,9 @@ pub struct DerivationInfo {
 	pub master_fingerprint: Option<bip32::Fingerprint>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub path: Option<bip32::DerivationPath>,
+	/// The key origin in descriptor format: `[fingerprint/path]`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub key_origin: Option<String>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub xpriv: Option<bip32::Xpriv>,
 	pub xpub: bip32::Xpub,
@@ -21,5 +28,408 @@ pub struct DerivationInfo {
 	pub public_key: bitcoin::secp256k1::PublicKey,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub private_key: Option<bitcoin::secp256k1::SecretKey>,
+	/// The script type implied by the SLIP-132 version of the key.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub script_type: Option<ScriptType>,
+	/// The keys in their SLIP-132 encoding, if not a plain xpub or tpub.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub slip132_xpub: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub slip132_xpriv: Option<String>,
+	/// The addresses for the key, only the one matching the script type if it's known.
 	pub addresses: address::Addresses,
 }
+
+impl DerivationInfo {
+	/// Describe the extended key, optionally with its origin.
+	///
+	/// The SLIP-132 version determines the script type and which addresses are shown.
+	pub fn new(
+		xpub: bip32::Xpub,
+		xpriv: Option<bip32::Xpriv>,
+		origin: Option<KeyOrigin>,
+		version: &Slip132Version,
+		network: Network,
+	) -> DerivationInfo {
+		let addresses = address::Addresses::from_pubkey(&bitcoin::PublicKey::new(xpub.public_key), network);
+		let standard = version.script_type.is_none();
+		DerivationInfo {
+			network: xpub.network,
+			key_origin: origin.as_ref().map(|(fp, path)| format_key_origin(*fp, path)),
+			master_fingerprint: origin.as_ref().map(|o| o.0),
+			path: origin.map(|o| o.1),
+			xpriv: xpriv,
+			xpub: xpub,
+			chain_code: xpub.chain_code,
+			identifier: xpub.identifier(),
+			fingerprint: xpub.fingerprint(),
+			public_key: xpub.public_key,
+			private_key: xpriv.map(|x| x.private_key),
+			script_type: version.script_type,
+			slip132_xpub: if standard { None } else { Some(version.encode_xpub(&xpub)) },
+			slip132_xpriv: if standard { None } else { xpriv.map(|x| version.encode_xpriv(&x)) },
+			addresses: match version.script_type {
+				None => addresses,
+				Some(ScriptType::P2pkh) => address::Addresses { p2pkh: addresses.p2pkh, ..Default::default() },
+				Some(ScriptType::P2shP2wpkh) => address::Addresses { p2shwpkh: addresses.p2shwpkh, ..Default::default() },
+				Some(ScriptType::P2wpkh) => address::Addresses { p2wpkh: addresses.p2wpkh, ..Default::default() },
+				// Multisig keys don't have addresses on their own.
+				Some(_) => Default::default(),
+			},
+		}
+	}
+}
+
+/// The script types that SLIP-132 versions imply.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub enum ScriptType {
+	#[serde(rename = "p2pkh")]
+	P2pkh,
+	/// Legacy p2sh multisig, which has no SLIP-132 version.
+	#[serde(rename = "p2sh")]
+	P2sh,
+	#[serde(rename = "p2sh-p2wpkh")]
+	P2shP2wpkh,
+	#[serde(rename = "p2wpkh")]
+	P2wpkh,
+	#[serde(rename = "p2sh-p2wsh")]
+	P2shP2wsh,
+	#[serde(rename = "p2wsh")]
+	P2wsh,
+}
+
+/// An extended key version as registered in SLIP-132.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Slip132Version {
+	/// The prefix of the public key, like "zpub".
+	pub public_name: &'static str,
+	/// The prefix of the private key, like "zprv".
+	pub private_name: &'static str,
+	pub public: [u8; 4],
+	pub private: [u8; 4],
+	pub network: NetworkKind,
+	/// The implied script type, [None] for xpub and tpub which are used for all types.
+	pub script_type: Option<ScriptType>,
+}
+
+macro_rules! version {
+	($pubname:expr, $privname:expr, $pub:expr, $priv:expr, $net:ident, $st:expr) => {
+		Slip132Version {
+			public_name: $pubname,
+			private_name: $privname,
+			public: $pub,
+			private: $priv,
+			network: NetworkKind::$net,
+			script_type: $st,
+		}
+	};
+}
+
+/// All SLIP-132 versions for Bitcoin.
+pub const SLIP132_VERSIONS: &[Slip132Version] = &[
+	version!("xpub", "xprv", [0x04, 0x88, 0xb2, 0x1e], [0x04, 0x88, 0xad, 0xe4], Main, None),
+	version!("ypub", "yprv", [0x04, 0x9d, 0x7c, 0xb2], [0x04, 0x9d, 0x78, 0x78], Main, Some(ScriptType::P2shP2wpkh)),
+	version!("Ypub", "Yprv", [0x02, 0x95, 0xb4, 0x3f], [0x02, 0x95, 0xb0, 0x05], Main, Some(ScriptType::P2shP2wsh)),
+	version!("zpub", "zprv", [0x04, 0xb2, 0x47, 0x46], [0x04, 0xb2, 0x43, 0x0c], Main, Some(ScriptType::P2wpkh)),
+	version!("Zpub", "Zprv", [0x02, 0xaa, 0x7e, 0xd3], [0x02, 0xaa, 0x7a, 0x99], Main, Some(ScriptType::P2wsh)),
+	version!("tpub", "tprv", [0x04, 0x35, 0x87, 0xcf], [0x04, 0x35, 0x83, 0x94], Test, None),
+	version!("upub", "uprv", [0x04, 0x4a, 0x52, 0x62], [0x04, 0x4a, 0x4e, 0x28], Test, Some(ScriptType::P2shP2wpkh)),
+	version!("Upub", "Uprv", [0x02, 0x42, 0x89, 0xef], [0x02, 0x42, 0x85, 0xb5], Test, Some(ScriptType::P2shP2wsh)),
+	version!("vpub", "vprv", [0x04, 0x5f, 0x1c, 0xf6], [0x04, 0x5f, 0x18, 0xbc], Test, Some(ScriptType::P2wpkh)),
+	version!("Vpub", "Vprv", [0x02, 0x57, 0x54, 0x83], [0x02, 0x57, 0x50, 0x48], Test, Some(ScriptType::P2wsh)),
+];
+
+impl Slip132Version {
+	/// Find a version by either its public or private name.
+	pub fn from_name(name: &str) -> Option<&'static Slip132Version> {
+		SLIP132_VERSIONS.iter().find(|v| v.public_name == name || v.private_name == name)
+	}
+
+	/// The plain xpub or tpub version for the network.
+	pub fn standard(network: NetworkKind) -> &'static Slip132Version {
+		match network {
+			NetworkKind::Main => &SLIP132_VERSIONS[0],
+			NetworkKind::Test => &SLIP132_VERSIONS[5],
+		}
+	}
+
+	fn encode(bytes: [u8; 78], version: [u8; 4]) -> String {
+		let mut bytes = bytes;
+		bytes[0..4].copy_from_slice(&version);
+		base58::encode_check(&bytes)
+	}
+
+	pub fn encode_xpub(&self, xpub: &bip32::Xpub) -> String {
+		Slip132Version::encode(xpub.encode(), self.public)
+	}
+
+	pub fn encode_xpriv(&self, xpriv: &bip32::Xpriv) -> String {
+		Slip132Version::encode(xpriv.encode(), self.private)
+	}
+}
+
+/// Parse an extended key in any SLIP-132 version.
+///
+/// Returns the public key, the private key if it was one, and the version.
+pub fn parse_ext_key(
+	s: &str,
+) -> Result<(bip32::Xpub, Option<bip32::Xpriv>, &'static Slip132Version), String> {
+	let mut data = base58::decode_check(s.trim()).map_err(|e| format!("invalid base58: {}", e))?;
+	if data.len() != 78 {
+		return Err(format!("invalid extended key length: {}", data.len()));
+	}
+	let mut prefix = [0u8; 4];
+	prefix.copy_from_slice(&data[0..4]);
+	let (version, private) = SLIP132_VERSIONS.iter().find_map(|v| {
+		if v.public == prefix {
+			Some((v, false))
+		} else if v.private == prefix {
+			Some((v, true))
+		} else {
+			None
+		}
+	}).ok_or_else(|| format!("unknown extended key version: {}", hex::encode(&prefix)))?;
+
+	let standard = Slip132Version::standard(version.network);
+	if private {
+		data[0..4].copy_from_slice(&standard.private);
+		let xpriv = bip32::Xpriv::decode(&data).map_err(|e| format!("invalid extended key: {}", e))?;
+		Ok((bip32::Xpub::from_priv(&crate::SECP, &xpriv), Some(xpriv), version))
+	} else {
+		data[0..4].copy_from_slice(&standard.public);
+		let xpub = bip32::Xpub::decode(&data).map_err(|e| format!("invalid extended key: {}", e))?;
+		Ok((xpub, None, version))
+	}
+}
+
+/// Format a key origin like in output descriptors: `[d34db33f/84'/0'/0']`.
+pub fn format_key_origin(fingerprint: Fingerprint, path: &DerivationPath) -> String {
+	let mut ret = format!("[{}", fingerprint);
+	for child in path.into_iter() {
+		ret.push('/');
+		ret.push_str(&child.to_string());
+	}
+	ret.push(']');
+	ret
+}
+
+/// A key origin: the master fingerprint and the derivation path from it.
+pub type KeyOrigin = (Fingerprint, DerivationPath);
+
+/// Split a descriptor-style key origin `[fingerprint/path]` from the start of a key.
+pub fn split_key_origin(s: &str) -> Result<(Option<KeyOrigin>, &str), String> {
+	let s = s.trim();
+	if !s.starts_with('[') {
+		return Ok((None, s));
+	}
+	let end = s.find(']').ok_or("missing ']' in key origin")?;
+	let origin = &s[1..end];
+	let (fp, path) = match origin.find('/') {
+		Some(idx) => (&origin[..idx], &origin[idx + 1..]),
+		None => (origin, ""),
+	};
+	let fp = Fingerprint::from_str(fp).map_err(|e| format!("invalid key origin fingerprint: {}", e))?;
+	let path = parse_path(path).map_err(|e| format!("invalid key origin path: {}", e))?;
+	Ok((Some((fp, path)), &s[end + 1..]))
+}
+
+/// Parse a derivation path that may or may not start with `m/`.
+fn parse_path(s: &str) -> Result<DerivationPath, String> {
+	let s = s.trim_start_matches('m').trim_start_matches('/');
+	s.split('/').filter(|c| !c.is_empty()).map(parse_child).collect::<Result<Vec<_>, _>>()
+		.map(DerivationPath::from)
+}
+
+fn parse_child(s: &str) -> Result<ChildNumber, String> {
+	let s = s.replace(['h', 'H'], "'");
+	ChildNumber::from_str(&s).map_err(|e| format!("invalid child number '{}': {}", s, e))
+}
+
+/// The maximum number of paths a [PathTemplate] may expand to.
+pub const MAX_EXPANDED_PATHS: usize = 100_000;
+
+/// A step in a [PathTemplate].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PathStep {
+	Child(ChildNumber),
+	/// A multipath step like `<0;1>` as in BIP-389.
+	Multi(Vec<ChildNumber>),
+	/// A wildcard `*`, hardened for `*'` or `*h`.
+	Wildcard {
+		hardened: bool,
+	},
+}
+
+/// A derivation path with wildcard and multipath steps, like `m/84'/0'/0'/<0;1>/*`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PathTemplate {
+	pub steps: Vec<PathStep>,
+}
+
+impl FromStr for PathTemplate {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<PathTemplate, String> {
+		let s = s.trim().trim_start_matches('m').trim_start_matches('/');
+		let mut steps = Vec::new();
+		for step in s.split('/').filter(|c| !c.is_empty()) {
+			if steps.iter().any(|s| matches!(s, PathStep::Wildcard { .. })) {
+				return Err("a wildcard can only be used as the last step".to_owned());
+			}
+
+			if step == "*" {
+				steps.push(PathStep::Wildcard { hardened: false });
+			} else if step == "*'" || step == "*h" || step == "*H" {
+				steps.push(PathStep::Wildcard { hardened: true });
+			} else if step.starts_with('<') && step.ends_with('>') {
+				if steps.iter().any(|s| matches!(s, PathStep::Multi(_))) {
+					return Err("only one multipath step is allowed".to_owned());
+				}
+				let children = step[1..step.len() - 1].split(';').map(parse_child)
+					.collect::<Result<Vec<_>, _>>()?;
+				if children.len() < 2 {
+					return Err("a multipath step needs at least two children".to_owned());
+				}
+				steps.push(PathStep::Multi(children));
+			} else {
+				steps.push(PathStep::Child(parse_child(step)?));
+			}
+		}
+		Ok(PathTemplate { steps: steps })
+	}
+}
+
+impl PathTemplate {
+	/// Whether the template has a wildcard or multipath step.
+	pub fn is_ranged(&self) -> bool {
+		self.steps.iter().any(|s| !matches!(s, PathStep::Child(_)))
+	}
+
+	pub fn has_wildcard(&self) -> bool {
+		matches!(self.steps.last(), Some(PathStep::Wildcard { .. }))
+	}
+
+	/// Expand the template into all paths it describes.
+	///
+	/// Paths are ordered by multipath branch first and then by wildcard index.
+	/// The range is required for templates with a wildcard.
+	pub fn expand(&self, range: Option<Range<u32>>) -> Result<Vec<DerivationPath>, String> {
+		if self.has_wildcard() && range.is_none() {
+			return Err("a range is required for paths with a wildcard".to_owned());
+		}
+		if !self.has_wildcard() && range.is_some() {
+			return Err("a range can only be used with paths with a wildcard".to_owned());
+		}
+
+		let branches = self.steps.iter().fold(1usize, |n, s| match s {
+			PathStep::Multi(c) => n.saturating_mul(c.len()),
+			_ => n,
+		});
+		let count = branches.saturating_mul(range.as_ref().map(|r| r.len()).unwrap_or(1));
+		if count > MAX_EXPANDED_PATHS {
+			return Err(format!("the path expands to {} paths, at most {} are allowed",
+				count, MAX_EXPANDED_PATHS));
+		}
+
+		let mut paths: Vec<Vec<ChildNumber>> = vec![vec![]];
+		for step in &self.steps {
+			let children = match step {
+				PathStep::Child(c) => vec![*c],
+				PathStep::Multi(c) => c.clone(),
+				PathStep::Wildcard { hardened } => range.clone().unwrap().map(|i| {
+					if *hardened {
+						ChildNumber::from_hardened_idx(i)
+					} else {
+						ChildNumber::from_normal_idx(i)
+					}.map_err(|e| format!("invalid index in range: {}", e))
+				}).collect::<Result<Vec<_>, _>>()?,
+			};
+			paths = paths.into_iter().flat_map(|p| children.iter().map(move |c| {
+				let mut p = p.clone();
+				p.push(*c);
+				p
+			})).collect();
+		}
+		Ok(paths.into_iter().map(DerivationPath::from).collect())
+	}
+}
+
+/// Parse a range in the format `<start>..<end>` (exclusive) or `<start>..=<end>`
+/// (inclusive). A single number `<n>` means `0..<n>`.
+pub fn parse_range(s: &str) -> Result<Range<u32>, String> {
+	let num = |n: &str| n.trim().parse::<u32>().map_err(|e| format!("invalid range bound '{}': {}", n, e));
+	let range = if let Some(idx) = s.find("..=") {
+		num(&s[..idx])?..num(&s[idx + 3..])?.checked_add(1).ok_or("range end too large")?
+	} else if let Some(idx) = s.find("..") {
+		num(&s[..idx])?..num(&s[idx + 2..])?
+	} else {
+		0..num(s)?
+	};
+	if range.start >= range.end {
+		return Err("empty range".to_owned());
+	}
+	if range.len() > MAX_EXPANDED_PATHS {
+		return Err(format!("range too large: {} indices, at most {} are allowed",
+			range.len(), MAX_EXPANDED_PATHS));
+	}
+	Ok(range)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_path_template() {
+		let tpl = PathTemplate::from_str("m/84'/0h/0'/<0;1>/*").unwrap();
+		assert!(tpl.is_ranged());
+		let paths = tpl.expand(Some(parse_range("0..=1").unwrap())).unwrap();
+		let paths = paths.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+		assert_eq!(paths, vec![
+			"84'/0'/0'/0/0", "84'/0'/0'/0/1", "84'/0'/0'/1/0", "84'/0'/0'/1/1",
+		]);
+
+		let tpl = PathTemplate::from_str("0/*h").unwrap();
+		assert_eq!(tpl.expand(Some(5..7)).unwrap()[1].to_string(), "0/6'");
+		assert!(tpl.expand(None).is_err());
+		assert!(!PathTemplate::from_str("m/0/1").unwrap().is_ranged());
+		assert!(PathTemplate::from_str("m/*/0").is_err());
+		assert!(PathTemplate::from_str("m/<0;1>/<2;3>").is_err());
+		assert!(PathTemplate::from_str("m/<0>").is_err());
+		assert!(parse_range("0..1000000").is_err());
+		assert!(PathTemplate::from_str("m/<0;1;2>/*").unwrap().expand(Some(0..50_000)).is_err());
+		assert_eq!(parse_range("10").unwrap(), 0..10);
+
+		let (origin, key) = split_key_origin("[d34db33f/84h/0'/0']xpub").unwrap();
+		let (fp, path) = origin.unwrap();
+		assert_eq!(key, "xpub");
+		assert_eq!(format_key_origin(fp, &path), "[d34db33f/84'/0'/0']");
+		assert_eq!(split_key_origin("xpub").unwrap(), (None, "xpub"));
+	}
+
+	#[test]
+	fn test_slip132() {
+		// The BIP-84 test vector account key.
+		let zpub = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+		let (xpub, xpriv, version) = parse_ext_key(zpub).unwrap();
+		assert!(xpriv.is_none());
+		assert_eq!(version.public_name, "zpub");
+		assert_eq!(version.script_type, Some(ScriptType::P2wpkh));
+		assert_eq!(version.encode_xpub(&xpub), zpub);
+		assert_eq!(
+			xpub.to_string(),
+			"xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V",
+		);
+
+		let info = DerivationInfo::new(xpub, None, None, version, Network::Bitcoin);
+		assert!(info.addresses.p2pkh.is_none() && info.addresses.p2tr.is_none());
+		assert!(info.addresses.p2wpkh.is_some());
+
+		let tpub = Slip132Version::from_name("vpub").unwrap().encode_xpub(&xpub);
+		assert!(tpub.starts_with("vpub"));
+		assert_eq!(parse_ext_key(&tpub).unwrap().0.network, NetworkKind::Test);
+		assert!(parse_ext_key("xpub").is_err());
+		// A bad checksum.
+		assert!(parse_ext_key("xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6W").is_err());
+	}
+}
@@ -0,0 +1,745 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::blockdata::opcodes::{self, all::*};
+use bitcoin::blockdata::script::{Builder, Instruction, PushBytesBuf};
+use bitcoin::consensus::encode::{deserialize, serialize};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::key::{Keypair, TapTweak};
+use bitcoin::sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType};
+use bitcoin::taproot::{ControlBlock, TapLeafHash};
+use bitcoin::{
+	absolute, ecdsa, taproot, transaction, Amount, CompressedPublicKey, OutPoint, PrivateKey,
+	Psbt, PublicKey, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+	XOnlyPublicKey,
+};
+use secp256k1::Message;
+
+use crate::SECP;
+
+/// The tag used for the BIP-322 message hash.
+pub const MESSAGE_TAG: &str = "BIP0322-signed-message";
+
+/// The different formats a message signature can have.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SignatureFormat {
+	/// The legacy Bitcoin Signed Message format, only for p2pkh.
+	Legacy,
+	/// BIP-322 simple format: only the witness stack of the to_sign tx.
+	Simple,
+	/// BIP-322 full format: the entire to_sign tx.
+	Full,
+}
+
+impl fmt::Display for SignatureFormat {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SignatureFormat::Legacy => f.write_str("legacy"),
+			SignatureFormat::Simple => f.write_str("simple"),
+			SignatureFormat::Full => f.write_str("full"),
+		}
+	}
+}
+
+impl FromStr for SignatureFormat {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"legacy" => Ok(SignatureFormat::Legacy),
+			"simple" => Ok(SignatureFormat::Simple),
+			"full" => Ok(SignatureFormat::Full),
+			_ => Err(format!("unknown signature format: {}", s)),
+		}
+	}
+}
+
+/// Calculate the BIP-322 tagged message hash.
+pub fn message_hash(message: &[u8]) -> sha256::Hash {
+	let tag = sha256::Hash::hash(MESSAGE_TAG.as_bytes());
+	let mut engine = sha256::Hash::engine();
+	engine.input(&tag[..]);
+	engine.input(&tag[..]);
+	engine.input(message);
+	sha256::Hash::from_engine(engine)
+}
+
+/// Create the virtual to_spend transaction committing to the message.
+pub fn to_spend(script_pubkey: &Script, message: &[u8]) -> Transaction {
+	Transaction {
+		version: transaction::Version(0),
+		lock_time: absolute::LockTime::ZERO,
+		input: vec![TxIn {
+			previous_output: OutPoint::new(Txid::all_zeros(), 0xFFFFFFFF),
+			script_sig: Builder::new()
+				.push_opcode(opcodes::OP_0)
+				.push_slice(message_hash(message).to_byte_array())
+				.into_script(),
+			sequence: Sequence::ZERO,
+			witness: Witness::new(),
+		}],
+		output: vec![TxOut {
+			value: Amount::ZERO,
+			script_pubkey: script_pubkey.to_owned(),
+		}],
+	}
+}
+
+/// Create the unsigned virtual to_sign transaction.
+///
+/// The additional outpoints are added as inputs for a proof of funds.
+pub fn to_sign(to_spend: &Transaction, proof_of_funds: &[OutPoint]) -> Transaction {
+	let input = |prevout| TxIn {
+		previous_output: prevout,
+		script_sig: ScriptBuf::new(),
+		sequence: Sequence::ZERO,
+		witness: Witness::new(),
+	};
+	Transaction {
+		version: transaction::Version(0),
+		lock_time: absolute::LockTime::ZERO,
+		input: Some(input(OutPoint::new(to_spend.compute_txid(), 0))).into_iter()
+			.chain(proof_of_funds.iter().cloned().map(input))
+			.collect(),
+		output: vec![TxOut {
+			value: Amount::ZERO,
+			script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+		}],
+	}
+}
+
+/// Create a PSBT for the to_sign transaction, to sign for script addresses.
+///
+/// The scripts are set on all inputs that spend the message challenge.
+pub fn to_sign_psbt(
+	script_pubkey: &Script,
+	message: &[u8],
+	proof_of_funds: &[(OutPoint, TxOut)],
+	redeem_script: Option<ScriptBuf>,
+	witness_script: Option<ScriptBuf>,
+) -> Result<Psbt, String> {
+	let to_spend = to_spend(script_pubkey, message);
+	let outpoints = proof_of_funds.iter().map(|u| u.0).collect::<Vec<_>>();
+	let mut psbt = Psbt::from_unsigned_tx(to_sign(&to_spend, &outpoints))
+		.map_err(|e| format!("failed to create PSBT: {}", e))?;
+
+	let prevouts = Some(to_spend.output[0].clone()).into_iter()
+		.chain(proof_of_funds.iter().map(|u| u.1.clone()));
+	for (input, prevout) in psbt.inputs.iter_mut().zip(prevouts) {
+		if prevout.script_pubkey == *script_pubkey {
+			input.redeem_script = redeem_script.clone();
+			input.witness_script = witness_script.clone();
+		}
+		input.witness_utxo = Some(prevout);
+	}
+	psbt.inputs[0].non_witness_utxo = Some(to_spend);
+	Ok(psbt)
+}
+
+fn ecdsa_sign(sighash: [u8; 32], privkey: &PrivateKey) -> ecdsa::Signature {
+	ecdsa::Signature {
+		signature: SECP.sign_ecdsa(&Message::from_digest(sighash), &privkey.inner),
+		sighash_type: EcdsaSighashType::All,
+	}
+}
+
+fn push_script(script: &Script) -> ScriptBuf {
+	let bytes = PushBytesBuf::try_from(script.to_bytes()).expect("script too large to push");
+	Builder::new().push_slice(bytes).into_script()
+}
+
+/// Sign a single input of the to_sign tx with a single key.
+///
+/// Returns the scriptSig and witness for the input.
+fn sign_input(
+	tx: &Transaction,
+	idx: usize,
+	prevouts: &[TxOut],
+	privkey: &PrivateKey,
+) -> Result<(ScriptBuf, Witness), String> {
+	let pubkey = privkey.public_key(&SECP);
+	let spk = &prevouts[idx].script_pubkey;
+	let mut cache = SighashCache::new(tx);
+
+	if spk.is_p2pkh() {
+		if *spk != ScriptBuf::new_p2pkh(&pubkey.pubkey_hash()) {
+			return Err(format!("key can't sign for input {}", idx));
+		}
+		let sighash = cache.legacy_signature_hash(idx, spk, EcdsaSighashType::All.to_u32())
+			.map_err(|e| e.to_string())?;
+		let sig = ecdsa_sign(sighash.to_byte_array(), privkey);
+		let script_sig = Builder::new()
+			.push_slice(sig.serialize())
+			.push_key(&pubkey)
+			.into_script();
+		Ok((script_sig, Witness::new()))
+	} else if spk.is_p2wpkh() || spk.is_p2sh() {
+		if !pubkey.compressed {
+			return Err("segwit requires compressed keys".to_owned());
+		}
+		let wpkh = ScriptBuf::new_p2wpkh(&CompressedPublicKey(pubkey.inner).wpubkey_hash());
+		let script_sig = if spk.is_p2sh() {
+			if *spk != ScriptBuf::new_p2sh(&wpkh.script_hash()) {
+				return Err(format!("key can't sign for p2sh input {}, only p2sh-p2wpkh \
+					is supported for single keys", idx));
+			}
+			push_script(&wpkh)
+		} else {
+			if *spk != wpkh {
+				return Err(format!("key can't sign for input {}", idx));
+			}
+			ScriptBuf::new()
+		};
+		let sighash = cache.p2wpkh_signature_hash(
+			idx, &wpkh, prevouts[idx].value, EcdsaSighashType::All,
+		).map_err(|e| e.to_string())?;
+		let sig = ecdsa_sign(sighash.to_byte_array(), privkey);
+		Ok((script_sig, Witness::p2wpkh(&sig, &pubkey.inner)))
+	} else if spk.is_p2tr() {
+		let keypair = Keypair::from_secret_key(&SECP, &privkey.inner).tap_tweak(&SECP, None);
+		let (output_key, _) = keypair.to_inner().x_only_public_key();
+		if spk.as_bytes()[2..] != output_key.serialize() {
+			return Err(format!("key can't sign for input {}", idx));
+		}
+		let sighash = cache.taproot_key_spend_signature_hash(
+			idx, &Prevouts::All(prevouts), TapSighashType::Default,
+		).map_err(|e| e.to_string())?;
+		let msg = Message::from_digest(sighash.to_byte_array());
+		let sig = taproot::Signature {
+			signature: SECP.sign_schnorr(&msg, &keypair.to_inner()),
+			sighash_type: TapSighashType::Default,
+		};
+		Ok((ScriptBuf::new(), Witness::p2tr_key_spend(&sig)))
+	} else {
+		Err(format!("unsupported scriptPubKey type for single-key signing on input {}", idx))
+	}
+}
+
+/// Sign a message for the given scriptPubKey with a single private key.
+///
+/// Supported are p2pkh, p2wpkh, p2sh-p2wpkh and p2tr key-spend outputs.
+/// The UTXOs for a proof of funds must be spendable by the same key.
+///
+/// Returns the signed to_sign transaction.
+pub fn sign(
+	privkey: &PrivateKey,
+	script_pubkey: &Script,
+	message: &[u8],
+	proof_of_funds: &[(OutPoint, TxOut)],
+) -> Result<Transaction, String> {
+	let to_spend = to_spend(script_pubkey, message);
+	let outpoints = proof_of_funds.iter().map(|u| u.0).collect::<Vec<_>>();
+	let mut tx = to_sign(&to_spend, &outpoints);
+	let prevouts = Some(to_spend.output[0].clone()).into_iter()
+		.chain(proof_of_funds.iter().map(|u| u.1.clone()))
+		.collect::<Vec<_>>();
+
+	for idx in 0..tx.input.len() {
+		let (script_sig, witness) = sign_input(&tx, idx, &prevouts, privkey)?;
+		tx.input[idx].script_sig = script_sig;
+		tx.input[idx].witness = witness;
+	}
+	Ok(tx)
+}
+
+/// Encode the signed to_sign tx as a BIP-322 signature.
+pub fn encode_signature(to_sign: &Transaction, format: SignatureFormat) -> Result<Vec<u8>, String> {
+	match format {
+		SignatureFormat::Legacy => Err("the legacy format is not a BIP-322 format".to_owned()),
+		SignatureFormat::Simple => {
+			if to_sign.input.len() > 1 {
+				return Err("a proof of funds requires the full format".to_owned());
+			}
+			if to_sign.input[0].witness.is_empty() {
+				return Err("non-segwit signatures require the full format".to_owned());
+			}
+			Ok(serialize(&to_sign.input[0].witness))
+		}
+		SignatureFormat::Full => Ok(serialize(to_sign)),
+	}
+}
+
+/// Decode a BIP-322 signature into the signed to_sign tx.
+///
+/// For the simple format, the scriptSig for p2sh-wrapped segwit is reconstructed
+/// from the witness.
+pub fn decode_signature(
+	to_spend: &Transaction,
+	signature: &[u8],
+) -> Result<(SignatureFormat, Transaction), String> {
+	if let Ok(tx) = deserialize::<Transaction>(signature) {
+		return Ok((SignatureFormat::Full, tx));
+	}
+
+	let witness = deserialize::<Witness>(signature)
+		.map_err(|e| format!("signature is neither a witness stack nor a tx: {}", e))?;
+	let mut tx = to_sign(to_spend, &[]);
+	if to_spend.output[0].script_pubkey.is_p2sh() {
+		let last = witness.last().ok_or("empty witness")?;
+		let program = if witness.len() == 2 && last.len() == 33 {
+			let pk = CompressedPublicKey::from_slice(last).map_err(|e| e.to_string())?;
+			ScriptBuf::new_p2wpkh(&pk.wpubkey_hash())
+		} else {
+			ScriptBuf::new_p2wsh(&Script::from_bytes(last).wscript_hash())
+		};
+		tx.input[0].script_sig = push_script(&program);
+	}
+	tx.input[0].witness = witness;
+	Ok((SignatureFormat::Simple, tx))
+}
+
+/// Get all the pushes in a push-only script.
+fn script_pushes(script: &Script) -> Result<Vec<Vec<u8>>, String> {
+	script.instructions().map(|i| match i {
+		Ok(Instruction::PushBytes(b)) => Ok(b.as_bytes().to_vec()),
+		Ok(Instruction::Op(_)) => Err("scriptSig is not push-only".to_owned()),
+		Err(e) => Err(format!("invalid script: {}", e)),
+	}).collect()
+}
+
+fn small_int(ins: &Instruction) -> Option<usize> {
+	match ins {
+		Instruction::Op(op) => {
+			let op = op.to_u8();
+			if op >= OP_PUSHNUM_1.to_u8() && op <= OP_PUSHNUM_16.to_u8() {
+				Some((op - OP_PUSHNUM_1.to_u8() + 1) as usize)
+			} else {
+				None
+			}
+		}
+		_ => None,
+	}
+}
+
+/// Check a script of the form `<pk> OP_CHECKSIG` or a bare k-of-n multisig
+/// against the stack, verifying signatures with the given closure.
+fn verify_ecdsa_script<F>(script: &Script, stack: &[Vec<u8>], mut check: F) -> Result<(), String>
+where
+	F: FnMut(&[u8], &PublicKey) -> bool,
+{
+	let ins = script.instructions().collect::<Result<Vec<_>, _>>()
+		.map_err(|e| format!("invalid script: {}", e))?;
+	let key = |i: &Instruction| match i {
+		Instruction::PushBytes(b) => PublicKey::from_slice(b.as_bytes()).ok(),
+		_ => None,
+	};
+
+	// <pk> OP_CHECKSIG
+	if ins.len() == 2 && ins[1] == Instruction::Op(OP_CHECKSIG) {
+		let pk = key(&ins[0]).ok_or("invalid public key in script")?;
+		if stack.len() != 1 {
+			return Err("expected a single signature on the stack".to_owned());
+		}
+		return if check(&stack[0], &pk) {
+			Ok(())
+		} else {
+			Err("invalid signature".to_owned())
+		};
+	}
+
+	// OP_k <pk>... OP_n OP_CHECKMULTISIG
+	if ins.len() >= 4 && ins[ins.len() - 1] == Instruction::Op(OP_CHECKMULTISIG) {
+		let k = small_int(&ins[0]).ok_or("unsupported multisig script")?;
+		let n = small_int(&ins[ins.len() - 2]).ok_or("unsupported multisig script")?;
+		let keys = ins[1..ins.len() - 2].iter().map(key).collect::<Option<Vec<_>>>()
+			.ok_or("invalid public key in multisig script")?;
+		if keys.len() != n || k > n {
+			return Err("invalid multisig script".to_owned());
+		}
+		if stack.len() != k + 1 || !stack[0].is_empty() {
+			return Err(format!("expected an empty dummy element and {} signatures", k));
+		}
+		// Like OP_CHECKMULTISIG, signatures must be in the order of the keys.
+		let mut keys = keys.iter();
+		for sig in &stack[1..] {
+			if !keys.by_ref().any(|pk| check(sig, pk)) {
+				return Err("invalid or out-of-order multisig signature".to_owned());
+			}
+		}
+		return Ok(());
+	}
+
+	Err("unsupported script, only single-key and multisig scripts are supported".to_owned())
+}
+
+/// Check a tapscript of the form `<pk> OP_CHECKSIG` or a multi_a script
+/// against the stack, verifying signatures with the given closure.
+fn verify_tapscript<F>(script: &Script, stack: &[&[u8]], mut check: F) -> Result<(), String>
+where
+	F: FnMut(&[u8], &XOnlyPublicKey) -> bool,
+{
+	let ins = script.instructions().collect::<Result<Vec<_>, _>>()
+		.map_err(|e| format!("invalid script: {}", e))?;
+	let key = |i: &Instruction| match i {
+		Instruction::PushBytes(b) => XOnlyPublicKey::from_slice(b.as_bytes()).ok(),
+		_ => None,
+	};
+
+	// <pk> OP_CHECKSIG
+	if ins.len() == 2 && ins[1] == Instruction::Op(OP_CHECKSIG) {
+		let pk = key(&ins[0]).ok_or("invalid public key in script")?;
+		if stack.len() != 1 {
+			return Err("expected a single signature on the stack".to_owned());
+		}
+		return if check(stack[0], &pk) {
+			Ok(())
+		} else {
+			Err("invalid signature".to_owned())
+		};
+	}
+
+	// <pk1> OP_CHECKSIG <pk2> OP_CHECKSIGADD ... <k> OP_NUMEQUAL
+	if ins.len() >= 4 && ins.len() % 2 == 0 && ins[ins.len() - 1] == Instruction::Op(OP_NUMEQUAL) {
+		let k = small_int(&ins[ins.len() - 2]).ok_or("unsupported multi_a script")?;
+		let mut keys = Vec::new();
+		for (i, pair) in ins[..ins.len() - 2].chunks(2).enumerate() {
+			let op = if i == 0 { OP_CHECKSIG } else { OP_CHECKSIGADD };
+			if pair[1] != Instruction::Op(op) {
+				return Err("unsupported multi_a script".to_owned());
+			}
+			keys.push(key(&pair[0]).ok_or("invalid public key in multi_a script")?);
+		}
+		if stack.len() != keys.len() {
+			return Err(format!("expected {} stack elements", keys.len()));
+		}
+		// The first key's signature is on top of the stack.
+		let mut valid = 0;
+		for (sig, pk) in stack.iter().rev().zip(keys.iter()) {
+			if sig.is_empty() {
+				continue;
+			}
+			if !check(sig, pk) {
+				return Err("invalid multi_a signature".to_owned());
+			}
+			valid += 1;
+		}
+		return if valid == k {
+			Ok(())
+		} else {
+			Err(format!("expected {} signatures, got {}", k, valid))
+		};
+	}
+
+	Err("unsupported tapscript, only single-key and multi_a scripts are supported".to_owned())
+}
+
+/// Verify a segwit v0 spend of the given witness program.
+fn verify_segwit_v0(
+	cache: &mut SighashCache<&Transaction>,
+	idx: usize,
+	program: &Script,
+	value: Amount,
+	witness: &Witness,
+) -> Result<(), String> {
+	if program.is_p2wpkh() {
+		if witness.len() != 2 {
+			return Err("expected two witness elements for p2wpkh".to_owned());
+		}
+		let pk = CompressedPublicKey::from_slice(&witness[1]).map_err(|e| e.to_string())?;
+		if ScriptBuf::new_p2wpkh(&pk.wpubkey_hash()) != *program {
+			return Err("public key doesn't match the p2wpkh program".to_owned());
+		}
+		let sig = ecdsa::Signature::from_slice(&witness[0]).map_err(|e| e.to_string())?;
+		let sighash = cache.p2wpkh_signature_hash(idx, program, value, sig.sighash_type)
+			.map_err(|e| e.to_string())?;
+		let msg = Message::from_digest(sighash.to_byte_array());
+		SECP.verify_ecdsa(&msg, &sig.signature, &pk.0).map_err(|_| "invalid signature".to_owned())
+	} else if program.is_p2wsh() {
+		let mut stack = witness.iter().map(|w| w.to_vec()).collect::<Vec<_>>();
+		let script = ScriptBuf::from(stack.pop().ok_or("empty witness")?);
+		if ScriptBuf::new_p2wsh(&script.wscript_hash()) != *program {
+			return Err("witness script doesn't match the p2wsh program".to_owned());
+		}
+		verify_ecdsa_script(&script, &stack, |sig, pk| {
+			let sig = match ecdsa::Signature::from_slice(sig) {
+				Ok(sig) => sig,
+				Err(_) => return false,
+			};
+			match cache.p2wsh_signature_hash(idx, &script, value, sig.sighash_type) {
+				Ok(sighash) => {
+					let msg = Message::from_digest(sighash.to_byte_array());
+					SECP.verify_ecdsa(&msg, &sig.signature, &pk.inner).is_ok()
+				}
+				Err(_) => false,
+			}
+		})
+	} else {
+		Err("unsupported witness program".to_owned())
+	}
+}
+
+/// Verify a taproot spend, either key-spend or script-spend.
+fn verify_taproot(
+	cache: &mut SighashCache<&Transaction>,
+	idx: usize,
+	script_pubkey: &Script,
+	prevouts: &[TxOut],
+	witness: &Witness,
+) -> Result<(), String> {
+	let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])
+		.map_err(|e| e.to_string())?;
+	let mut stack = witness.iter().collect::<Vec<_>>();
+	if stack.len() >= 2 && stack.last().unwrap().first() == Some(&0x50) {
+		return Err("witnesses with an annex are not supported".to_owned());
+	}
+
+	if stack.len() == 1 {
+		let sig = taproot::Signature::from_slice(stack[0]).map_err(|e| e.to_string())?;
+		let sighash = cache.taproot_key_spend_signature_hash(
+			idx, &Prevouts::All(prevouts), sig.sighash_type,
+		).map_err(|e| e.to_string())?;
+		let msg = Message::from_digest(sighash.to_byte_array());
+		return SECP.verify_schnorr(&sig.signature, &msg, &output_key)
+			.map_err(|_| "invalid signature".to_owned());
+	}
+
+	let cb = ControlBlock::decode(stack.pop().ok_or("empty witness")?)
+		.map_err(|e| format!("invalid control block: {}", e))?;
+	let script = Script::from_bytes(stack.pop().ok_or("missing tapscript")?);
+	if !cb.verify_taproot_commitment(&SECP, output_key, script) {
+		return Err("control block doesn't match the output key".to_owned());
+	}
+	let leaf_hash = TapLeafHash::from_script(script, cb.leaf_version);
+	verify_tapscript(script, &stack, |sig, pk| {
+		let sig = match taproot::Signature::from_slice(sig) {
+			Ok(sig) => sig,
+			Err(_) => return false,
+		};
+		match cache.taproot_script_spend_signature_hash(
+			idx, &Prevouts::All(prevouts), leaf_hash, sig.sighash_type,
+		) {
+			Ok(sighash) => {
+				let msg = Message::from_digest(sighash.to_byte_array());
+				SECP.verify_schnorr(&sig.signature, &msg, pk).is_ok()
+			}
+			Err(_) => false,
+		}
+	})
+}
+
+/// Verify that an input of the to_sign tx validly spends its previous output.
+///
+/// This is not a full script interpreter, only standard single-key and
+/// multisig constructions are supported.
+pub fn verify_input(tx: &Transaction, idx: usize, prevouts: &[TxOut]) -> Result<(), String> {
+	let input = &tx.input[idx];
+	let spk = &prevouts[idx].script_pubkey;
+	let value = prevouts[idx].value;
+	let mut cache = SighashCache::new(tx);
+
+	let legacy_check = |script: &Script, sig: &[u8], pk: &PublicKey| {
+		let sig = match ecdsa::Signature::from_slice(sig) {
+			Ok(sig) => sig,
+			Err(_) => return false,
+		};
+		match cache.legacy_signature_hash(idx, script, sig.sighash_type.to_u32()) {
+			Ok(sighash) => {
+				let msg = Message::from_digest(sighash.to_byte_array());
+				SECP.verify_ecdsa(&msg, &sig.signature, &pk.inner).is_ok()
+			}
+			Err(_) => false,
+		}
+	};
+
+	if spk.is_p2pkh() {
+		if !input.witness.is_empty() {
+			return Err("witness should be empty for p2pkh".to_owned());
+		}
+		let pushes = script_pushes(&input.script_sig)?;
+		if pushes.len() != 2 {
+			return Err("expected a signature and a public key in the scriptSig".to_owned());
+		}
+		let pk = PublicKey::from_slice(&pushes[1]).map_err(|e| e.to_string())?;
+		if ScriptBuf::new_p2pkh(&pk.pubkey_hash()) != *spk {
+			return Err("public key doesn't match the p2pkh address".to_owned());
+		}
+		if legacy_check(spk, &pushes[0], &pk) {
+			Ok(())
+		} else {
+			Err("invalid signature".to_owned())
+		}
+	} else if spk.is_p2sh() {
+		let mut pushes = script_pushes(&input.script_sig)?;
+		let redeem = ScriptBuf::from(pushes.pop().ok_or("empty scriptSig")?);
+		if ScriptBuf::new_p2sh(&redeem.script_hash()) != *spk {
+			return Err("redeem script doesn't match the p2sh address".to_owned());
+		}
+		if redeem.is_p2wpkh() || redeem.is_p2wsh() {
+			if !pushes.is_empty() {
+				return Err("scriptSig should only contain the redeem script".to_owned());
+			}
+			verify_segwit_v0(&mut cache, idx, &redeem, value, &input.witness)
+		} else {
+			if !input.witness.is_empty() {
+				return Err("witness should be empty for legacy p2sh".to_owned());
+			}
+			verify_ecdsa_script(&redeem, &pushes, |sig, pk| legacy_check(&redeem, sig, pk))
+		}
+	} else if spk.is_p2wpkh() || spk.is_p2wsh() {
+		if !input.script_sig.is_empty() {
+			return Err("scriptSig should be empty for native segwit".to_owned());
+		}
+		verify_segwit_v0(&mut cache, idx, spk, value, &input.witness)
+	} else if spk.is_p2tr() {
+		if !input.script_sig.is_empty() {
+			return Err("scriptSig should be empty for taproot".to_owned());
+		}
+		verify_taproot(&mut cache, idx, spk, prevouts, &input.witness)
+	} else {
+		Err("unsupported scriptPubKey type".to_owned())
+	}
+}
+
+/// Verify a BIP-322 simple or full signature.
+///
+/// For a proof of funds, the previous outputs of the additional inputs must be provided.
+///
+/// Returns the signature format and the signed to_sign transaction.
+pub fn verify(
+	script_pubkey: &Script,
+	message: &[u8],
+	signature: &[u8],
+	proof_of_funds: &[(OutPoint, TxOut)],
+) -> Result<(SignatureFormat, Transaction), String> {
+	let to_spend = to_spend(script_pubkey, message);
+	let (format, tx) = decode_signature(&to_spend, signature)?;
+
+	if tx.input.is_empty() || tx.input[0].previous_output != OutPoint::new(to_spend.compute_txid(), 0) {
+		return Err("the first input doesn't spend the message challenge".to_owned());
+	}
+	if tx.output.len() != 1 || tx.output[0].value != Amount::ZERO
+		|| tx.output[0].script_pubkey.as_bytes() != [OP_RETURN.to_u8()]
+	{
+		return Err("the to_sign tx should have a single empty OP_RETURN output".to_owned());
+	}
+	if tx.version != transaction::Version(0) && tx.version != transaction::Version::TWO {
+		return Err("the to_sign tx should have version 0 or 2".to_owned());
+	}
+	if tx.lock_time != absolute::LockTime::ZERO {
+		return Err("the to_sign tx should have locktime 0".to_owned());
+	}
+
+	let mut prevouts = vec![to_spend.output[0].clone()];
+	for input in &tx.input[1..] {
+		let utxo = proof_of_funds.iter().find(|u| u.0 == input.previous_output)
+			.ok_or(format!("missing previous output for proof of funds input {}", input.previous_output))?;
+		prevouts.push(utxo.1.clone());
+	}
+
+	for idx in 0..tx.input.len() {
+		verify_input(&tx, idx, &prevouts).map_err(|e| format!("input {}: {}", idx, e))?;
+	}
+	Ok((format, tx))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use bitcoin::{Address, Network};
+
+	#[test]
+	fn test_bip322_vectors() {
+		assert_eq!(
+			message_hash(b"").to_string(),
+			"c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1",
+		);
+		assert_eq!(
+			message_hash(b"Hello World").to_string(),
+			"f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a",
+		);
+
+		// The BIP-322 signatures, decoded from base64.
+		let addr = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l").unwrap()
+			.assume_checked();
+		let spk = addr.script_pubkey();
+		let sig = hex::decode(
+			"0247304402206517c8637a7bfc3a154edcba6196d64bbd5b73955cb7da7d1626bcdde466c36402\
+			2022bf10d19fc0bb69b4596e306b362acaa835293cf693bb176f7324b531f5afec012102c7f120\
+			03196442943d8588e01aee840423cc54fc1521526a3b85c2b0cbd58872",
+		).unwrap();
+		let (format, _) = verify(&spk, b"Hello World", &sig, &[]).unwrap();
+		assert_eq!(format, SignatureFormat::Simple);
+		assert!(verify(&spk, b"", &sig, &[]).is_err());
+
+		let addr = Address::from_str("bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3")
+			.unwrap().assume_checked();
+		let sig = hex::decode(
+			"0141ddebd3eb25012ffa82937d9f25f9644e047bb2f472ab6c5089bbb53588ada2884cb5bcc539\
+			11f32d8dcf9548733b694d120db6a4e485194559e8d8fe668d269f01",
+		).unwrap();
+		verify(&addr.script_pubkey(), b"Hello World", &sig, &[]).unwrap();
+
+		// roundtrip for all single-key types
+		let key = PrivateKey::from_wif("L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k").unwrap();
+		let pk = CompressedPublicKey(key.public_key(&SECP).inner);
+		for addr in &[
+			Address::p2pkh(&pk, Network::Bitcoin),
+			Address::p2wpkh(&pk, Network::Bitcoin),
+			Address::p2shwpkh(&pk, Network::Bitcoin),
+			Address::p2tr(&SECP, pk.0.into(), None, Network::Bitcoin),
+		] {
+			let spk = addr.script_pubkey();
+			let tx = sign(&key, &spk, b"test", &[]).unwrap();
+			let sig = encode_signature(&tx, SignatureFormat::Full).unwrap();
+			verify(&spk, b"test", &sig, &[]).unwrap();
+			if let Ok(sig) = encode_signature(&tx, SignatureFormat::Simple) {
+				verify(&spk, b"test", &sig, &[]).unwrap();
+				assert!(verify(&spk, b"other", &sig, &[]).is_err());
+			}
+		}
+	}
+
+	#[test]
+	fn test_verify_to_sign() {
+		let key = PrivateKey::from_wif("L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k").unwrap();
+		let pk = key.public_key(&SECP);
+		let verify_tx = |spk: &Script, tx: &Transaction| {
+			let sig = encode_signature(tx, SignatureFormat::Full).unwrap();
+			verify(spk, b"test", &sig, &[]).map(|_| ()).map_err(|e| e.to_string())
+		};
+
+		// Version 2 is allowed for time locks, the version is signed for.
+		let spk = ScriptBuf::new_p2wpkh(&CompressedPublicKey(pk.inner).wpubkey_hash());
+		let challenge = to_spend(&spk, b"test");
+		let mut tx = to_sign(&challenge, &[]);
+		tx.version = transaction::Version::TWO;
+		tx.input[0].witness = sign_input(&tx, 0, &challenge.output, &key).unwrap().1;
+		assert_eq!(verify_tx(&spk, &tx), Ok(()));
+
+		let mut bad = tx.clone();
+		bad.version = transaction::Version::ONE;
+		assert_eq!(verify_tx(&spk, &bad).unwrap_err(), "the to_sign tx should have version 0 or 2");
+		let mut bad = tx.clone();
+		bad.lock_time = absolute::LockTime::from_height(1).unwrap();
+		assert_eq!(verify_tx(&spk, &bad).unwrap_err(), "the to_sign tx should have locktime 0");
+		let mut bad = tx.clone();
+		bad.output[0].script_pubkey = ScriptBuf::new_op_return([1]);
+		assert_eq!(
+			verify_tx(&spk, &bad).unwrap_err(),
+			"the to_sign tx should have a single empty OP_RETURN output",
+		);
+
+		// Legacy inputs can't have a witness.
+		let spk = ScriptBuf::new_p2pkh(&pk.pubkey_hash());
+		let mut tx = sign(&key, &spk, b"test", &[]).unwrap();
+		tx.input[0].witness.push([1]);
+		assert_eq!(verify_tx(&spk, &tx).unwrap_err(), "input 0: witness should be empty for p2pkh");
+
+		// A legacy p2sh-p2pk spend, signed by hand.
+		let redeem = Builder::new().push_key(&pk).push_opcode(OP_CHECKSIG).into_script();
+		let spk = ScriptBuf::new_p2sh(&redeem.script_hash());
+		let mut tx = to_sign(&to_spend(&spk, b"test"), &[]);
+		let sighash = SighashCache::new(&tx)
+			.legacy_signature_hash(0, &redeem, EcdsaSighashType::All.to_u32()).unwrap();
+		tx.input[0].script_sig = Builder::new()
+			.push_slice(ecdsa_sign(sighash.to_byte_array(), &key).serialize())
+			.push_slice(PushBytesBuf::try_from(redeem.to_bytes()).unwrap())
+			.into_script();
+		assert_eq!(verify_tx(&spk, &tx), Ok(()));
+		tx.input[0].witness.push([1]);
+		assert_eq!(
+			verify_tx(&spk, &tx).unwrap_err(),
+			"input 0: witness should be empty for legacy p2sh",
+		);
+	}
+}
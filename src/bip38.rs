@@ -0,0 +1,381 @@
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use bitcoin::hashes::{sha256d, Hash, HashEngine};
+use bitcoin::{base58, Address, Network, NetworkKind, PrivateKey, PublicKey};
+use secp256k1::{Scalar, SecretKey};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::SECP;
+
+/// Prefix of encrypted keys without EC multiplication.
+const PREFIX_NON_EC: [u8; 2] = [0x01, 0x42];
+/// Prefix of encrypted keys with EC multiplication.
+const PREFIX_EC: [u8; 2] = [0x01, 0x43];
+/// Prefix of intermediate codes, followed by 0x51 with or 0x53 without lot and sequence.
+const PREFIX_INTERMEDIATE: [u8; 7] = [0x2c, 0xe9, 0xb3, 0xe1, 0xff, 0x39, 0xe2];
+/// Prefix of confirmation codes.
+const PREFIX_CONFIRMATION: [u8; 5] = [0x64, 0x3b, 0xf6, 0xa8, 0x9a];
+
+const FLAG_NON_EC: u8 = 0xc0;
+const FLAG_COMPRESSED: u8 = 0x20;
+const FLAG_LOT_SEQUENCE: u8 = 0x04;
+
+/// The maximum lot number for intermediate codes.
+pub const MAX_LOT: u32 = 1048575;
+/// The maximum sequence number for intermediate codes.
+pub const MAX_SEQUENCE: u32 = 4095;
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct EcMultiplyInfo {
+	pub encrypted_private_key: String,
+	pub confirmation_code: String,
+	pub address: Address<bitcoin::address::NetworkUnchecked>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct ConfirmationInfo {
+	pub address: Address<bitcoin::address::NetworkUnchecked>,
+	pub compressed: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub lot: Option<u32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub sequence: Option<u32>,
+}
+
+/// Check whether the string looks like a BIP-38 encrypted private key.
+pub fn is_encrypted_key(s: &str) -> bool {
+	s.len() == 58 && s.starts_with("6P")
+}
+
+fn scrypt(password: &[u8], salt: &[u8], log_n: u8, r: u32, p: u32, out: &mut [u8]) {
+	let params = scrypt::Params::new(log_n, r, p, out.len()).expect("valid scrypt params");
+	scrypt::scrypt(password, salt, &params, out).expect("valid scrypt output length");
+}
+
+/// Passphrases are NFC-normalized before use.
+fn normalize(passphrase: &str) -> Vec<u8> {
+	passphrase.nfc().collect::<String>().into_bytes()
+}
+
+/// The address hash is the first 4 bytes of the double SHA-256 of the mainnet p2pkh address.
+fn address_hash(pubkey: &PublicKey) -> [u8; 4] {
+	let addr = Address::p2pkh(pubkey, Network::Bitcoin).to_string();
+	let hash = sha256d::Hash::hash(addr.as_bytes());
+	let mut ret = [0u8; 4];
+	ret.copy_from_slice(&hash[0..4]);
+	ret
+}
+
+fn xor16(a: &[u8], b: &[u8]) -> [u8; 16] {
+	let mut ret = [0u8; 16];
+	for i in 0..16 {
+		ret[i] = a[i] ^ b[i];
+	}
+	ret
+}
+
+fn aes_encrypt(key: &[u8], block: [u8; 16]) -> [u8; 16] {
+	let cipher = aes::Aes256::new(GenericArray::from_slice(key));
+	let mut block = GenericArray::from(block);
+	cipher.encrypt_block(&mut block);
+	block.into()
+}
+
+fn aes_decrypt(key: &[u8], block: &[u8]) -> [u8; 16] {
+	let cipher = aes::Aes256::new(GenericArray::from_slice(key));
+	let mut block = GenericArray::clone_from_slice(block);
+	cipher.decrypt_block(&mut block);
+	block.into()
+}
+
+/// Calculate the passfactor from the passphrase and owner entropy.
+fn passfactor(passphrase: &str, owner_entropy: &[u8], lot_sequence: bool) -> Result<SecretKey, String> {
+	let mut prefactor = [0u8; 32];
+	let salt = if lot_sequence { &owner_entropy[0..4] } else { owner_entropy };
+	scrypt(&normalize(passphrase), salt, 14, 8, 8, &mut prefactor);
+	let factor = if lot_sequence {
+		let mut engine = sha256d::Hash::engine();
+		engine.input(&prefactor);
+		engine.input(owner_entropy);
+		sha256d::Hash::from_engine(engine).to_byte_array()
+	} else {
+		prefactor
+	};
+	SecretKey::from_slice(&factor).map_err(|_| "invalid passfactor, try another salt".to_owned())
+}
+
+/// Derive the keys used to encrypt the seed and point of an EC-multiplied key.
+fn derive_ec_keys(passpoint: &secp256k1::PublicKey, address_hash: &[u8], owner_entropy: &[u8]) -> [u8; 64] {
+	let mut salt = address_hash.to_vec();
+	salt.extend_from_slice(owner_entropy);
+	let mut derived = [0u8; 64];
+	scrypt(&passpoint.serialize(), &salt, 10, 1, 1, &mut derived);
+	derived
+}
+
+/// Encrypt a private key with a passphrase, without EC multiplication.
+pub fn encrypt(privkey: &PrivateKey, passphrase: &str) -> String {
+	let addr_hash = address_hash(&privkey.public_key(&SECP));
+	let mut derived = [0u8; 64];
+	scrypt(&normalize(passphrase), &addr_hash, 14, 8, 8, &mut derived);
+	let (half1, half2) = derived.split_at(32);
+
+	let key = privkey.inner.secret_bytes();
+	let mut ret = PREFIX_NON_EC.to_vec();
+	ret.push(FLAG_NON_EC | if privkey.compressed { FLAG_COMPRESSED } else { 0 });
+	ret.extend_from_slice(&addr_hash);
+	ret.extend_from_slice(&aes_encrypt(half2, xor16(&key[0..16], &half1[0..16])));
+	ret.extend_from_slice(&aes_encrypt(half2, xor16(&key[16..32], &half1[16..32])));
+	base58::encode_check(&ret)
+}
+
+/// Decrypt a BIP-38 encrypted private key, with or without EC multiplication.
+///
+/// The resulting key is always for mainnet.
+pub fn decrypt(encrypted: &str, passphrase: &str) -> Result<PrivateKey, String> {
+	let data = base58::decode_check(encrypted).map_err(|e| format!("invalid base58: {}", e))?;
+	if data.len() != 39 {
+		return Err(format!("invalid length: {} bytes instead of 39", data.len()));
+	}
+	let flag = data[2];
+	let compressed = flag & FLAG_COMPRESSED != 0;
+	let addr_hash = &data[3..7];
+
+	let secret = if data[0..2] == PREFIX_NON_EC {
+		let mut derived = [0u8; 64];
+		scrypt(&normalize(passphrase), addr_hash, 14, 8, 8, &mut derived);
+		let (half1, half2) = derived.split_at(32);
+		let mut key = [0u8; 32];
+		key[0..16].copy_from_slice(&xor16(&aes_decrypt(half2, &data[7..23]), &half1[0..16]));
+		key[16..32].copy_from_slice(&xor16(&aes_decrypt(half2, &data[23..39]), &half1[16..32]));
+		SecretKey::from_slice(&key).map_err(|_| "wrong passphrase".to_owned())?
+	} else if data[0..2] == PREFIX_EC {
+		let owner_entropy = &data[7..15];
+		let passfactor = passfactor(passphrase, owner_entropy, flag & FLAG_LOT_SEQUENCE != 0)?;
+		let passpoint = secp256k1::PublicKey::from_secret_key(&SECP, &passfactor);
+		let derived = derive_ec_keys(&passpoint, addr_hash, owner_entropy);
+		let (half1, half2) = derived.split_at(32);
+
+		// The second encrypted part contains the second half of the first.
+		let part2 = xor16(&aes_decrypt(half2, &data[23..39]), &half1[16..32]);
+		let mut part1 = [0u8; 16];
+		part1[0..8].copy_from_slice(&data[15..23]);
+		part1[8..16].copy_from_slice(&part2[0..8]);
+		let mut seedb = [0u8; 24];
+		seedb[0..16].copy_from_slice(&xor16(&aes_decrypt(half2, &part1), &half1[0..16]));
+		seedb[16..24].copy_from_slice(&part2[8..16]);
+
+		let factorb = sha256d::Hash::hash(&seedb).to_byte_array();
+		let factorb = Scalar::from_be_bytes(factorb).map_err(|_| "wrong passphrase".to_owned())?;
+		passfactor.mul_tweak(&factorb).map_err(|_| "wrong passphrase".to_owned())?
+	} else {
+		return Err("not a BIP-38 encrypted private key".to_owned());
+	};
+
+	let privkey = PrivateKey {
+		compressed: compressed,
+		network: NetworkKind::Main,
+		inner: secret,
+	};
+	if addr_hash != address_hash(&privkey.public_key(&SECP)) {
+		return Err("wrong passphrase".to_owned());
+	}
+	Ok(privkey)
+}
+
+/// Create an intermediate code to let a third party generate EC-multiplied keys.
+///
+/// The owner salt is 8 random bytes, of which only the first 4 are used
+/// when a lot and sequence number are given.
+pub fn intermediate_code(
+	passphrase: &str,
+	owner_salt: [u8; 8],
+	lot_sequence: Option<(u32, u32)>,
+) -> Result<String, String> {
+	let mut owner_entropy = owner_salt;
+	if let Some((lot, sequence)) = lot_sequence {
+		if lot > MAX_LOT || sequence > MAX_SEQUENCE {
+			return Err(format!("lot must be at most {} and sequence at most {}", MAX_LOT, MAX_SEQUENCE));
+		}
+		owner_entropy[4..8].copy_from_slice(&(lot * 4096 + sequence).to_be_bytes());
+	}
+	let passfactor = passfactor(passphrase, &owner_entropy, lot_sequence.is_some())?;
+	let passpoint = secp256k1::PublicKey::from_secret_key(&SECP, &passfactor);
+
+	let mut ret = PREFIX_INTERMEDIATE.to_vec();
+	ret.push(if lot_sequence.is_some() { 0x51 } else { 0x53 });
+	ret.extend_from_slice(&owner_entropy);
+	ret.extend_from_slice(&passpoint.serialize());
+	Ok(base58::encode_check(&ret))
+}
+
+/// Generate an EC-multiplied encrypted key from an intermediate code.
+///
+/// The seed should be 24 random bytes. The resulting private key is only
+/// known to the owner of the passphrase.
+pub fn encrypt_ec_multiply(
+	intermediate: &str,
+	seedb: [u8; 24],
+	compressed: bool,
+) -> Result<EcMultiplyInfo, String> {
+	let data = base58::decode_check(intermediate).map_err(|e| format!("invalid base58: {}", e))?;
+	if data.len() != 49 || data[0..7] != PREFIX_INTERMEDIATE || (data[7] != 0x51 && data[7] != 0x53) {
+		return Err("not a BIP-38 intermediate code".to_owned());
+	}
+	let owner_entropy = &data[8..16];
+	let passpoint = secp256k1::PublicKey::from_slice(&data[16..49])
+		.map_err(|e| format!("invalid passpoint: {}", e))?;
+
+	let factorb = sha256d::Hash::hash(&seedb).to_byte_array();
+	let factorb = Scalar::from_be_bytes(factorb).map_err(|_| "invalid seed".to_owned())?;
+	let generated = PublicKey {
+		compressed: compressed,
+		inner: passpoint.mul_tweak(&SECP, &factorb).map_err(|_| "invalid seed".to_owned())?,
+	};
+	let addr_hash = address_hash(&generated);
+	let derived = derive_ec_keys(&passpoint, &addr_hash, owner_entropy);
+	let (half1, half2) = derived.split_at(32);
+
+	let mut flag = if compressed { FLAG_COMPRESSED } else { 0 };
+	if data[7] == 0x51 {
+		flag |= FLAG_LOT_SEQUENCE;
+	}
+
+	let part1 = aes_encrypt(half2, xor16(&seedb[0..16], &half1[0..16]));
+	let mut block2 = [0u8; 16];
+	block2[0..8].copy_from_slice(&part1[8..16]);
+	block2[8..16].copy_from_slice(&seedb[16..24]);
+	let part2 = aes_encrypt(half2, xor16(&block2, &half1[16..32]));
+
+	let mut key = PREFIX_EC.to_vec();
+	key.push(flag);
+	key.extend_from_slice(&addr_hash);
+	key.extend_from_slice(owner_entropy);
+	key.extend_from_slice(&part1[0..8]);
+	key.extend_from_slice(&part2);
+
+	let pointb = secp256k1::PublicKey::from_secret_key(
+		&SECP, &SecretKey::from_slice(&factorb.to_be_bytes()).expect("valid scalar"),
+	).serialize();
+	let mut conf = PREFIX_CONFIRMATION.to_vec();
+	conf.push(flag);
+	conf.extend_from_slice(&addr_hash);
+	conf.extend_from_slice(owner_entropy);
+	conf.push(pointb[0] ^ (half2[31] & 0x01));
+	conf.extend_from_slice(&aes_encrypt(half2, xor16(&pointb[1..17], &half1[0..16])));
+	conf.extend_from_slice(&aes_encrypt(half2, xor16(&pointb[17..33], &half1[16..32])));
+
+	Ok(EcMultiplyInfo {
+		encrypted_private_key: base58::encode_check(&key),
+		confirmation_code: base58::encode_check(&conf),
+		address: Address::p2pkh(&generated, Network::Bitcoin).as_unchecked().clone(),
+	})
+}
+
+/// Check whether the string looks like a BIP-38 confirmation code.
+pub fn is_confirmation_code(s: &str) -> bool {
+	s.starts_with("cfrm38")
+}
+
+/// Verify a confirmation code with the passphrase and get the address it confirms.
+pub fn verify_confirmation(confirmation: &str, passphrase: &str) -> Result<ConfirmationInfo, String> {
+	let data = base58::decode_check(confirmation).map_err(|e| format!("invalid base58: {}", e))?;
+	if data.len() != 51 || data[0..5] != PREFIX_CONFIRMATION {
+		return Err("not a BIP-38 confirmation code".to_owned());
+	}
+	let flag = data[5];
+	let compressed = flag & FLAG_COMPRESSED != 0;
+	let lot_sequence = flag & FLAG_LOT_SEQUENCE != 0;
+	let addr_hash = &data[6..10];
+	let owner_entropy = &data[10..18];
+
+	let passfactor = passfactor(passphrase, owner_entropy, lot_sequence)?;
+	let passpoint = secp256k1::PublicKey::from_secret_key(&SECP, &passfactor);
+	let derived = derive_ec_keys(&passpoint, addr_hash, owner_entropy);
+	let (half1, half2) = derived.split_at(32);
+
+	let mut pointb = [0u8; 33];
+	pointb[0] = data[18] ^ (half2[31] & 0x01);
+	pointb[1..17].copy_from_slice(&xor16(&aes_decrypt(half2, &data[19..35]), &half1[0..16]));
+	pointb[17..33].copy_from_slice(&xor16(&aes_decrypt(half2, &data[35..51]), &half1[16..32]));
+	let pointb = secp256k1::PublicKey::from_slice(&pointb).map_err(|_| "wrong passphrase".to_owned())?;
+
+	let generated = PublicKey {
+		compressed: compressed,
+		inner: pointb.mul_tweak(&SECP, &Scalar::from(passfactor)).map_err(|_| "wrong passphrase".to_owned())?,
+	};
+	if addr_hash != address_hash(&generated) {
+		return Err("wrong passphrase".to_owned());
+	}
+
+	let lot_sequence = if lot_sequence {
+		let n = u32::from_be_bytes([owner_entropy[4], owner_entropy[5], owner_entropy[6], owner_entropy[7]]);
+		Some((n / 4096, n % 4096))
+	} else {
+		None
+	};
+	Ok(ConfirmationInfo {
+		address: Address::p2pkh(&generated, Network::Bitcoin).as_unchecked().clone(),
+		compressed: compressed,
+		lot: lot_sequence.map(|l| l.0),
+		sequence: lot_sequence.map(|l| l.1),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::str::FromStr;
+
+	#[test]
+	fn test_bip38_vectors() {
+		// Non-EC-multiplied, uncompressed and compressed.
+		for &(pass, enc, wif) in &[
+			("TestingOneTwoThree", "6PRVWUbkzzsbcVac2qwfssoUJAN1Xhrg6bNk8J7Nzm5H7kxEbn2Nh2ZoGg",
+				"5KN7MzqK5wt2TP1fQCYyHBtDrXdJuXbUzm4A9rKAteGu3Qi5CVR"),
+			("Satoshi", "6PYLtMnXvfG3oJde97zRyLYFZCYizPU5T3LwgdYJz1fRhh16bU7u6PPmY7",
+				"KwYgW8gcxj1JWJXhPSu4Fqwzfhp5Yfi42mdYmMa4XqK7NJxXUSK7"),
+		] {
+			let privkey = PrivateKey::from_str(wif).unwrap();
+			assert!(is_encrypted_key(enc));
+			assert_eq!(encrypt(&privkey, pass), enc);
+			assert_eq!(decrypt(enc, pass).unwrap(), privkey);
+			assert!(decrypt(enc, "wrong").is_err());
+		}
+
+		// EC-multiplied, without and with lot and sequence.
+		for &(pass, enc, wif) in &[
+			("TestingOneTwoThree", "6PfQu77ygVyJLZjfvMLyhLMQbYnu5uguoJJ4kMCLqWwPEdfpwANVS76gTX",
+				"5K4caxezwjGCGfnoPTZ8tMcJBLB7Jvyjv4xxeacadhq8nLisLR2"),
+			("MOLON LABE", "6PgNBNNzDkKdhkT6uJntUXwwzQV8Rr2tZcbkDcuC9DZRsS6AtHts4Ypo1j",
+				"5JLdxTtcTHcfYcmJsNVy1v2PMDx432JPoYcBTVVRHpPaxUrdtf8"),
+		] {
+			let privkey = PrivateKey::from_str(wif).unwrap();
+			assert_eq!(decrypt(enc, pass).unwrap(), privkey);
+		}
+		let info = verify_confirmation(
+			"cfrm38V8aXBn7JWA1ESmFMUn6erxeBGZGAxJPY4e36S9QWkzZKtaVqLNMgnifETYw7BPwWC9aPD",
+			"MOLON LABE",
+		).unwrap();
+		assert_eq!(info.address, Address::from_str("1Jscj8ALrYu2y9TD8NrpvDBugPedmbj4Yh").unwrap());
+		assert_eq!((info.lot, info.sequence), (Some(263183), Some(1)));
+		assert!(verify_confirmation(
+			"cfrm38V8aXBn7JWA1ESmFMUn6erxeBGZGAxJPY4e36S9QWkzZKtaVqLNMgnifETYw7BPwWC9aPD",
+			"wrong",
+		).is_err());
+
+		// Intermediate code roundtrip.
+		let code = intermediate_code("pass", [1; 8], Some((1, 2))).unwrap();
+		assert!(code.starts_with("passphrase"));
+		let ec = encrypt_ec_multiply(&code, [2; 24], true).unwrap();
+		let privkey = decrypt(&ec.encrypted_private_key, "pass").unwrap();
+		assert!(privkey.compressed);
+		let info = verify_confirmation(&ec.confirmation_code, "pass").unwrap();
+		assert_eq!(info.address, ec.address);
+		assert_eq!(
+			ec.address,
+			Address::p2pkh(&privkey.public_key(&SECP), Network::Bitcoin).as_unchecked().clone(),
+		);
+	}
+}
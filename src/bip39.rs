@@ -1,11 +1,16 @@
 
 use std::borrow::Cow;
+use std::sync::{mpsc, Arc};
+use std::thread;
 
 use bip39lib::{Language, Mnemonic};
-use bitcoin::Network;
-use bitcoin::bip32;
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::bip32::{self, ChildNumber, Fingerprint};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::{Address, AddressType, CompressedPublicKey, Network, NetworkKind, ScriptBuf};
 use serde::{Deserialize, Serialize};
 
+use crate::wallet::AccountType;
 use crate::{SECP, GetInfo, HexBytes};
 
 #[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
@@ -109,6 +114,252 @@ pub fn parse_language(s: &str) -> Option<Language> {
 	Some(ret)
 }
 
+/// The Levenshtein distance between two words.
+fn edit_distance(a: &str, b: &str) -> usize {
+	let b = b.chars().collect::<Vec<_>>();
+	let mut row = (0..=b.len()).collect::<Vec<_>>();
+	for (i, ca) in a.chars().enumerate() {
+		let mut diag = row[0];
+		row[0] = i + 1;
+		for (j, cb) in b.iter().enumerate() {
+			let next = if ca == *cb { diag } else { 1 + diag.min(row[j]).min(row[j + 1]) };
+			diag = row[j + 1];
+			row[j + 1] = next;
+		}
+	}
+	row[b.len()]
+}
+
+/// The indices of all words in the word list the given word could be.
+///
+/// A `?` matches every word and a word in the list only itself. Otherwise the
+/// closest words within an edit distance of 2 match, as do all words starting
+/// with the given word if it is at least 3 characters long.
+pub fn word_candidates(word: &str, language: Language) -> Vec<u16> {
+	if word == "?" {
+		return (0..2048).collect();
+	}
+	if let Some(idx) = language.find_word(word) {
+		return vec![idx];
+	}
+
+	let list = language.word_list();
+	let distances = list.iter().map(|w| edit_distance(word, w)).collect::<Vec<_>>();
+	let min = *distances.iter().min().unwrap();
+	(0..2048u16).filter(|i| {
+		let w = list[*i as usize];
+		(min <= 2 && distances[*i as usize] == min) || (word.chars().count() >= 3 && w.starts_with(word))
+	}).collect()
+}
+
+/// The maximum number of word combinations [recover] will try.
+///
+/// This allows for two unknown words. Every combination with a valid checksum is kept
+/// or has to be derived to match the target, so larger searches are not practical.
+pub const MAX_SEARCH_SPACE: u64 = 1 << 24;
+
+/// The number of word combinations for the candidate word indices, if it doesn't overflow.
+pub fn search_space(candidates: &[Vec<u16>]) -> Option<u64> {
+	candidates.iter().try_fold(1u64, |acc, c| acc.checked_mul(c.len() as u64))
+}
+
+/// The mnemonic at the given index in the search space, if its checksum is valid.
+fn recover_candidate(candidates: &[Vec<u16>], mut index: u64, language: Language) -> Option<Mnemonic> {
+	let nb_words = candidates.len();
+	// Pack the 11-bit word indices into bytes, the last word varying fastest.
+	let mut bytes = [0u8; 33];
+	for (i, c) in candidates.iter().enumerate().rev() {
+		let idx = c[(index % c.len() as u64) as usize] as usize;
+		index /= c.len() as u64;
+		for b in 0..11 {
+			if idx & (1 << (10 - b)) != 0 {
+				let bit = i * 11 + b;
+				bytes[bit / 8] |= 1 << (7 - bit % 8);
+			}
+		}
+	}
+	let checksum_bits = nb_words / 3;
+	let entropy_bytes = nb_words * 4 / 3;
+	let entropy = &bytes[0..entropy_bytes];
+	let checksum = sha256::Hash::hash(entropy)[0] >> (8 - checksum_bits);
+	if bytes[entropy_bytes] >> (8 - checksum_bits) == checksum {
+		Some(Mnemonic::from_entropy_in(language, entropy).expect("valid entropy length"))
+	} else {
+		None
+	}
+}
+
+/// Find all mnemonics with a valid checksum given the candidate word indices
+/// for every position.
+///
+/// If a target and passphrase are given, only the mnemonics matching the target are
+/// returned. The search and the derivation of the candidates is spread over the threads.
+pub fn recover(
+	candidates: &[Vec<u16>],
+	language: Language,
+	target: Option<(&RecoveryTarget, &str)>,
+	threads: usize,
+) -> Result<RecoveryInfo, String> {
+	let nb_words = candidates.len();
+	if !(12..=24).contains(&nb_words) || !nb_words.is_multiple_of(3) {
+		return Err(format!("invalid number of words: {}", nb_words));
+	}
+	if let Some(i) = candidates.iter().position(|c| c.is_empty()) {
+		return Err(format!("no candidates for word {}", i + 1));
+	}
+	let search_space = search_space(candidates).filter(|s| *s <= MAX_SEARCH_SPACE)
+		.ok_or_else(|| "too many unknown words to search".to_owned())?;
+
+	let threads = (threads.max(1) as u64).min(search_space);
+	let candidates = Arc::new(candidates.to_vec());
+	let target = target.map(|(t, p)| (t.clone(), p.to_owned()));
+	let (tx, rx) = mpsc::channel();
+	let handles = (0..threads).map(|t| {
+		let candidates = candidates.clone();
+		let target = target.clone();
+		let tx = tx.clone();
+		thread::spawn(move || {
+			let start = search_space * t / threads;
+			let end = search_space * (t + 1) / threads;
+			let mut valid_checksums = 0;
+			for index in start..end {
+				let mnemonic = match recover_candidate(&candidates, index, language) {
+					Some(m) => m,
+					None => continue,
+				};
+				valid_checksums += 1;
+				let matches = target.as_ref().map(|(target, passphrase)| {
+					let seed = mnemonic.to_seed(passphrase);
+					// The network doesn't affect the derived keys.
+					let master = bip32::Xpriv::new_master(Network::Bitcoin, &seed[..]).expect("valid seed");
+					target.matches(&master)
+				}).unwrap_or(true);
+				if matches {
+					tx.send((index, mnemonic)).expect("receiver is alive");
+				}
+			}
+			valid_checksums
+		})
+	}).collect::<Vec<_>>();
+	drop(tx);
+	let mut found = rx.iter().collect::<Vec<_>>();
+	let valid_checksums = handles.into_iter()
+		.map(|h| h.join().expect("recovery thread panicked"))
+		.sum();
+	found.sort_by_key(|(i, _)| *i);
+
+	Ok(RecoveryInfo {
+		search_space: search_space,
+		valid_checksums: valid_checksums,
+		mnemonics: found.into_iter().map(|(_, m)| m.to_string()).collect(),
+	})
+}
+
+/// Something known about the wallet to filter recovered mnemonics with.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RecoveryTarget {
+	/// The first receive address of a standard BIP-44, 49, 84 or 86 account.
+	Address {
+		account_type: AccountType,
+		network: NetworkKind,
+		script_pubkey: ScriptBuf,
+	},
+	/// The master xpub or a standard account xpub.
+	Xpub(bip32::Xpub),
+	/// The master fingerprint.
+	Fingerprint(Fingerprint),
+}
+
+impl RecoveryTarget {
+	pub fn from_address(address: Address<NetworkUnchecked>) -> Result<RecoveryTarget, String> {
+		let network = if address.is_valid_for_network(Network::Bitcoin) {
+			NetworkKind::Main
+		} else {
+			NetworkKind::Test
+		};
+		let address = address.assume_checked();
+		let account_type = match address.address_type() {
+			Some(AddressType::P2pkh) => AccountType::Bip44,
+			Some(AddressType::P2sh) => AccountType::Bip49,
+			Some(AddressType::P2wpkh) => AccountType::Bip84,
+			Some(AddressType::P2tr) => AccountType::Bip86,
+			_ => return Err("only p2pkh, p2sh-p2wpkh, p2wpkh and p2tr addresses are supported".into()),
+		};
+		Ok(RecoveryTarget::Address {
+			account_type: account_type,
+			network: network,
+			script_pubkey: address.script_pubkey(),
+		})
+	}
+
+	/// Check whether the master key matches the target.
+	pub fn matches(&self, master: &bip32::Xpriv) -> bool {
+		match self {
+			RecoveryTarget::Fingerprint(fp) => master.fingerprint(&SECP) == *fp,
+			RecoveryTarget::Xpub(xpub) if xpub.depth == 0 => {
+				bip32::Xpub::from_priv(&SECP, master).public_key == xpub.public_key
+			}
+			RecoveryTarget::Xpub(xpub) => {
+				let account = match xpub.child_number {
+					ChildNumber::Hardened { index } if xpub.depth == 3 => index,
+					_ => return false,
+				};
+				[AccountType::Bip44, AccountType::Bip49, AccountType::Bip84, AccountType::Bip86]
+					.iter().any(|ty| {
+						let path = ty.path(xpub.network, account).expect("valid account index");
+						let derived = master.derive_priv(&SECP, &path).expect("valid path");
+						bip32::Xpub::from_priv(&SECP, &derived).public_key == xpub.public_key
+					})
+			}
+			RecoveryTarget::Address { account_type, network, script_pubkey } => {
+				let path = account_type.path(*network, 0).expect("account 0 is valid")
+					.child(ChildNumber::from_normal_idx(0).unwrap())
+					.child(ChildNumber::from_normal_idx(0).unwrap());
+				let key = master.derive_priv(&SECP, &path).expect("valid path").private_key;
+				let pubkey = CompressedPublicKey(key.public_key(&SECP));
+				let spk = match account_type {
+					AccountType::Bip44 => ScriptBuf::new_p2pkh(&pubkey.pubkey_hash()),
+					AccountType::Bip49 => {
+						ScriptBuf::new_p2sh(&ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash()).script_hash())
+					}
+					AccountType::Bip84 => ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash()),
+					_ => ScriptBuf::new_p2tr(&SECP, key.x_only_public_key(&SECP).0, None),
+				};
+				spk == *script_pubkey
+			}
+		}
+	}
+}
+
+/// A master secret recovered from secret shares.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct SecretInfo {
+	pub master_secret: HexBytes,
+	/// The BIP-39 mnemonic with the secret as entropy, if it has a valid length.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub bip39_mnemonic: Option<String>,
+}
+
+impl SecretInfo {
+	pub fn new(secret: &[u8], language: Language) -> SecretInfo {
+		SecretInfo {
+			master_secret: secret.to_vec().into(),
+			bip39_mnemonic: Mnemonic::from_entropy_in(language, secret).ok().map(|m| m.to_string()),
+		}
+	}
+}
+
+/// The result of a mnemonic recovery.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct RecoveryInfo {
+	/// The number of word combinations tried.
+	pub search_space: u64,
+	/// The number of combinations with a valid checksum.
+	pub valid_checksums: usize,
+	/// The recovered mnemonics, only those matching the target if one was given.
+	pub mnemonics: Vec<String>,
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -121,4 +372,80 @@ mod tests {
 			assert_eq!(Some(*l), parse_language(&l.to_string().to_uppercase()), "lang: {}", l);
 		}
 	}
+
+	fn abandon_about(lang: Language) -> Vec<Vec<u16>> {
+		"abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"
+			.split(' ').map(|w| vec![lang.find_word(w).unwrap()]).collect()
+	}
+
+	#[test]
+	fn test_recover_checksums() {
+		let lang = Language::English;
+		let mut words = abandon_about(lang);
+		let info = recover(&words, lang, None, 1).unwrap();
+		assert_eq!((info.search_space, info.valid_checksums), (1, 1));
+
+		// With an unknown last word, 128 of the 2048 words have a valid checksum.
+		words[11] = (0..2048).collect();
+		let info = recover(&words, lang, None, 3).unwrap();
+		assert_eq!((info.search_space, info.valid_checksums), (2048, 128));
+		assert_eq!(info.mnemonics.len(), 128);
+		assert!(info.mnemonics[0].ends_with(" about"));
+		assert_eq!(info, recover(&words, lang, None, 1).unwrap());
+	}
+
+	#[test]
+	fn test_recover_target() {
+		let lang = Language::English;
+		let mut words = abandon_about(lang);
+		words[11] = (0..2048).collect();
+		let about = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+		let target = RecoveryTarget::from_address(
+			"bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu".parse().unwrap(),
+		).unwrap();
+		let info = recover(&words, lang, Some((&target, "")), 4).unwrap();
+		assert_eq!(info.valid_checksums, 128);
+		assert_eq!(info.mnemonics, vec![about]);
+		assert!(recover(&words, lang, Some((&target, "TREZOR")), 4).unwrap().mnemonics.is_empty());
+
+		let words = abandon_about(lang);
+		let target = RecoveryTarget::Fingerprint("73c5da0a".parse().unwrap());
+		assert_eq!(recover(&words, lang, Some((&target, "")), 1).unwrap().mnemonics, vec![about]);
+		let target = RecoveryTarget::Xpub("xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V".parse().unwrap());
+		assert_eq!(recover(&words, lang, Some((&target, "")), 1).unwrap().mnemonics, vec![about]);
+	}
+
+	#[test]
+	fn test_word_candidates() {
+		let lang = Language::English;
+		let words = |w| word_candidates(w, lang).into_iter()
+			.map(|i| lang.word_list()[i as usize]).collect::<Vec<_>>();
+		assert_eq!(word_candidates("?", lang).len(), 2048);
+		assert_eq!(words("abandon"), vec!["abandon"]);
+		assert_eq!(words("abandn"), vec!["abandon"]);
+		assert_eq!(words("zooo"), vec!["zoo"]);
+		assert_eq!(words("abando"), vec!["abandon"]);
+		// Short abbreviations also match the closest words.
+		let aban = words("aban");
+		assert!(aban.contains(&"abandon") && aban.contains(&"bean"));
+		assert!(words("xyzxyzxyz").is_empty());
+		assert_eq!(edit_distance("kitten", "sitting"), 3);
+		assert_eq!(edit_distance("", "abc"), 3);
+	}
+
+	#[test]
+	fn test_recover_errors() {
+		let lang = Language::English;
+		let mut words = abandon_about(lang);
+		words[0] = vec![];
+		assert_eq!(recover(&words, lang, None, 1).unwrap_err(), "no candidates for word 1");
+		assert!(recover(&words[..11], lang, None, 1).is_err());
+
+		// The search space of 24 unknown words overflows a u64.
+		let words = vec![(0..2048).collect::<Vec<u16>>(); 24];
+		assert_eq!(search_space(&words), None);
+		assert_eq!(recover(&words, lang, None, 1).unwrap_err(), "too many unknown words to search");
+		assert_eq!(search_space(&words[..3]), Some(1 << 33));
+	}
 }
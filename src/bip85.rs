@@ -0,0 +1,428 @@
+use bip39lib::{Language, Mnemonic};
+use bitcoin::bip32::{self, ChildNumber, DerivationPath};
+use bitcoin::hashes::{hmac, sha512, Hash, HashEngine};
+use bitcoin::PrivateKey;
+use serde::{Deserialize, Serialize};
+
+use crate::{HexBytes, SECP};
+
+/// The BIP-32 purpose of BIP-85 derivations.
+pub const PURPOSE: u32 = 83696968;
+
+/// The BIP-85 applications.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Application {
+	Bip39 {
+		language: Language,
+		words: usize,
+	},
+	HdSeedWif,
+	Xprv,
+	Hex {
+		bytes: usize,
+	},
+	PasswordBase64 {
+		length: usize,
+	},
+	PasswordBase85 {
+		length: usize,
+	},
+	Dice {
+		sides: u32,
+		rolls: u32,
+	},
+}
+
+/// The BIP-85 language code of a BIP-39 language.
+fn language_code(language: Language) -> u32 {
+	match language {
+		Language::English => 0,
+		Language::Japanese => 1,
+		Language::Korean => 2,
+		Language::Spanish => 3,
+		Language::SimplifiedChinese => 4,
+		Language::TraditionalChinese => 5,
+		Language::French => 6,
+		Language::Italian => 7,
+		Language::Czech => 8,
+		Language::Portuguese => 9,
+	}
+}
+
+/// The maximum number of dice rolls that can be derived at once.
+pub const MAX_DICE_ROLLS: u32 = 10_000;
+
+impl Application {
+	/// Check the parameters of the application.
+	pub fn validate(&self) -> Result<(), String> {
+		match *self {
+			Application::Bip39 { words, .. } if words != 12 && words != 18 && words != 24 => {
+				Err(format!("invalid number of words: {}, must be 12, 18 or 24", words))
+			}
+			Application::Hex { bytes } if !(16..=64).contains(&bytes) => {
+				Err(format!("invalid number of bytes: {}, must be between 16 and 64", bytes))
+			}
+			Application::PasswordBase64 { length } if !(20..=86).contains(&length) => {
+				Err(format!("invalid password length: {}, must be between 20 and 86", length))
+			}
+			Application::PasswordBase85 { length } if !(10..=80).contains(&length) => {
+				Err(format!("invalid password length: {}, must be between 10 and 80", length))
+			}
+			Application::Dice { sides, rolls } if sides < 2 || rolls < 1 => {
+				Err("dice need at least 2 sides and 1 roll".to_owned())
+			}
+			Application::Dice { rolls, .. } if rolls > MAX_DICE_ROLLS => {
+				Err(format!("too many dice rolls: {}, must be at most {}", rolls, MAX_DICE_ROLLS))
+			}
+			_ => Ok(()),
+		}
+	}
+
+	/// The derivation path of the application, relative to the BIP-85 root.
+	fn path(&self, index: u32) -> Result<Vec<ChildNumber>, bip32::Error> {
+		let numbers = match *self {
+			Application::Bip39 { language, words } => vec![39, language_code(language), words as u32],
+			Application::HdSeedWif => vec![2],
+			Application::Xprv => vec![32],
+			Application::Hex { bytes } => vec![128169, bytes as u32],
+			Application::PasswordBase64 { length } => vec![707764, length as u32],
+			Application::PasswordBase85 { length } => vec![707785, length as u32],
+			Application::Dice { sides, rolls } => vec![89101, sides, rolls],
+		};
+		let mut ret = vec![ChildNumber::from_hardened_idx(PURPOSE)?];
+		for n in numbers.into_iter().chain(Some(index)) {
+			ret.push(ChildNumber::from_hardened_idx(n)?);
+		}
+		Ok(ret)
+	}
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct Bip85Info {
+	pub path: DerivationPath,
+	/// The full 64 bytes of derived entropy.
+	pub derived_entropy: HexBytes,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub mnemonic: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub wif: Option<PrivateKey>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub xprv: Option<bip32::Xpriv>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub hex: Option<HexBytes>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub password: Option<String>,
+	/// The dice rolls, from 0 to sides-1.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub rolls: Option<Vec<u32>>,
+}
+
+/// Derive the 64 bytes of BIP-85 entropy at the given path.
+pub fn derive_entropy(master: &bip32::Xpriv, path: &DerivationPath) -> Result<[u8; 64], bip32::Error> {
+	let derived = master.derive_priv(&SECP, path)?;
+	let mut engine = hmac::HmacEngine::<sha512::Hash>::new(b"bip-entropy-from-k");
+	engine.input(&derived.private_key.secret_bytes());
+	Ok(hmac::Hmac::<sha512::Hash>::from_engine(engine).to_byte_array())
+}
+
+/// Derive the result of a BIP-85 application from a master key.
+pub fn derive(master: &bip32::Xpriv, app: Application, index: u32) -> Result<Bip85Info, String> {
+	app.validate()?;
+	let path = DerivationPath::from(app.path(index).map_err(|e| e.to_string())?);
+	let entropy = derive_entropy(master, &path).map_err(|e| e.to_string())?;
+
+	let mut info = Bip85Info {
+		path: path,
+		derived_entropy: entropy.to_vec().into(),
+		mnemonic: None,
+		wif: None,
+		xprv: None,
+		hex: None,
+		password: None,
+		rolls: None,
+	};
+	match app {
+		Application::Bip39 { language, words } => {
+			let mnemonic = Mnemonic::from_entropy_in(language, &entropy[0..words * 4 / 3])
+				.map_err(|e| e.to_string())?;
+			info.mnemonic = Some(mnemonic.to_string());
+		}
+		Application::HdSeedWif => {
+			let key = secp256k1::SecretKey::from_slice(&entropy[0..32]).map_err(|e| e.to_string())?;
+			info.wif = Some(PrivateKey::new(key, master.network));
+		}
+		Application::Xprv => {
+			let key = secp256k1::SecretKey::from_slice(&entropy[32..64]).map_err(|e| e.to_string())?;
+			let mut chain_code = [0u8; 32];
+			chain_code.copy_from_slice(&entropy[0..32]);
+			info.xprv = Some(bip32::Xpriv {
+				network: master.network,
+				depth: 0,
+				parent_fingerprint: Default::default(),
+				child_number: ChildNumber::from_normal_idx(0).unwrap(),
+				private_key: key,
+				chain_code: bip32::ChainCode::from(chain_code),
+			});
+		}
+		Application::Hex { bytes } => info.hex = Some(entropy[0..bytes].to_vec().into()),
+		Application::PasswordBase64 { length } => {
+			info.password = Some(base64_encode(&entropy)[0..length].to_owned());
+		}
+		Application::PasswordBase85 { length } => {
+			info.password = Some(base85_encode(&entropy)[0..length].to_owned());
+		}
+		Application::Dice { sides, rolls } => info.rolls = Some(dice_rolls(&entropy, sides, rolls)),
+	}
+	Ok(info)
+}
+
+fn base64_encode(data: &[u8]) -> String {
+	const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+	let mut ret = String::new();
+	for chunk in data.chunks(3) {
+		let mut buf = [0u8; 3];
+		buf[0..chunk.len()].copy_from_slice(chunk);
+		let n = (buf[0] as u32) << 16 | (buf[1] as u32) << 8 | buf[2] as u32;
+		for i in 0..4 {
+			if i <= chunk.len() {
+				ret.push(CHARS[(n >> (18 - 6 * i) & 63) as usize] as char);
+			} else {
+				ret.push('=');
+			}
+		}
+	}
+	ret
+}
+
+/// Base85 with the RFC 1924 character set, like Python's base64.b85encode.
+///
+/// Only used for 64 bytes, so no padding is needed.
+fn base85_encode(data: &[u8]) -> String {
+	const CHARS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+	assert_eq!(data.len() % 4, 0);
+	let mut ret = String::new();
+	for chunk in data.chunks(4) {
+		let mut n = (chunk[0] as u32) << 24 | (chunk[1] as u32) << 16 | (chunk[2] as u32) << 8 | chunk[3] as u32;
+		let mut digits = [0u8; 5];
+		for d in digits.iter_mut().rev() {
+			*d = CHARS[(n % 85) as usize];
+			n /= 85;
+		}
+		ret.extend(digits.iter().map(|d| *d as char));
+	}
+	ret
+}
+
+/// Roll dice using the BIP85-DRNG, discarding values that are out of range.
+fn dice_rolls(entropy: &[u8; 64], sides: u32, rolls: u32) -> Vec<u32> {
+	let bits_per_roll = 32 - (sides - 1).leading_zeros();
+	let bytes_per_roll = bits_per_roll.div_ceil(8) as usize;
+	let mut drng = Shake256::new(entropy);
+	let mut ret = Vec::new();
+	while ret.len() < rolls as usize {
+		// Take the most significant bits of the bytes read.
+		let mut buf = [0u8; 4];
+		drng.read(&mut buf[0..bytes_per_roll]);
+		let trial = u32::from_be_bytes(buf) >> (32 - bits_per_roll);
+		if trial < sides {
+			ret.push(trial);
+		}
+	}
+	ret
+}
+
+/// A minimal SHAKE256 extendable-output function for the BIP85-DRNG.
+struct Shake256 {
+	state: [u64; 25],
+	buffer: [u8; Shake256::RATE],
+	pos: usize,
+}
+
+impl Shake256 {
+	const RATE: usize = 136;
+
+	/// Absorb the input, which must be shorter than the rate.
+	fn new(input: &[u8]) -> Shake256 {
+		assert!(input.len() < Shake256::RATE);
+		let mut block = [0u8; Shake256::RATE];
+		block[0..input.len()].copy_from_slice(input);
+		block[input.len()] ^= 0x1f;
+		block[Shake256::RATE - 1] ^= 0x80;
+
+		let mut state = [0u64; 25];
+		for (i, lane) in block.chunks(8).enumerate() {
+			let mut bytes = [0u8; 8];
+			bytes.copy_from_slice(lane);
+			state[i] ^= u64::from_le_bytes(bytes);
+		}
+		keccak_f(&mut state);
+		let mut ret = Shake256 { state: state, buffer: [0; Shake256::RATE], pos: 0 };
+		ret.fill_buffer();
+		ret
+	}
+
+	fn fill_buffer(&mut self) {
+		for (i, lane) in self.state[0..Shake256::RATE / 8].iter().enumerate() {
+			self.buffer[i * 8..i * 8 + 8].copy_from_slice(&lane.to_le_bytes());
+		}
+		self.pos = 0;
+	}
+
+	fn read(&mut self, out: &mut [u8]) {
+		for b in out.iter_mut() {
+			if self.pos == Shake256::RATE {
+				keccak_f(&mut self.state);
+				self.fill_buffer();
+			}
+			*b = self.buffer[self.pos];
+			self.pos += 1;
+		}
+	}
+}
+
+fn keccak_f(state: &mut [u64; 25]) {
+	const RC: [u64; 24] = [
+		0x0000000000000001, 0x0000000000008082, 0x800000000000808a, 0x8000000080008000,
+		0x000000000000808b, 0x0000000080000001, 0x8000000080008081, 0x8000000000008009,
+		0x000000000000008a, 0x0000000000000088, 0x0000000080008009, 0x000000008000000a,
+		0x000000008000808b, 0x800000000000008b, 0x8000000000008089, 0x8000000000008003,
+		0x8000000000008002, 0x8000000000000080, 0x000000000000800a, 0x800000008000000a,
+		0x8000000080008081, 0x8000000000008080, 0x0000000080000001, 0x8000000080008008,
+	];
+	const ROTATIONS: [u32; 24] = [
+		1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44,
+	];
+	const LANES: [usize; 24] = [
+		10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1,
+	];
+
+	for rc in RC.iter() {
+		// theta
+		let mut c = [0u64; 5];
+		for x in 0..5 {
+			c[x] = state[x] ^ state[x + 5] ^ state[x + 10] ^ state[x + 15] ^ state[x + 20];
+		}
+		for x in 0..5 {
+			let d = c[(x + 4) % 5] ^ c[(x + 1) % 5].rotate_left(1);
+			for y in 0..5 {
+				state[y * 5 + x] ^= d;
+			}
+		}
+		// rho and pi
+		let mut last = state[1];
+		for (rot, lane) in ROTATIONS.iter().zip(LANES.iter()) {
+			let tmp = state[*lane];
+			state[*lane] = last.rotate_left(*rot);
+			last = tmp;
+		}
+		// chi
+		for y in 0..5 {
+			let row = [state[y * 5], state[y * 5 + 1], state[y * 5 + 2], state[y * 5 + 3], state[y * 5 + 4]];
+			for x in 0..5 {
+				state[y * 5 + x] = row[x] ^ (!row[(x + 1) % 5] & row[(x + 2) % 5]);
+			}
+		}
+		// iota
+		state[0] ^= rc;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::str::FromStr;
+
+	#[test]
+	fn test_bip85() {
+		// The test vectors from BIP-85.
+		let master = bip32::Xpriv::from_str("xprv9s21ZrQH143K2LBWUUQRFXhucrQqBpKdRRxNVq2zBqsx8HVqFk2uYo8kmbaLLHRdqtQpUm98uKfu3vca1LqdGhUtyoFnCNkfmXRyPXLjbKb").unwrap();
+
+		let path = DerivationPath::from_str("m/83696968'/0'/0'").unwrap();
+		assert_eq!(
+			hex::encode(&derive_entropy(&master, &path).unwrap()[..]),
+			"efecfbccffea313214232d29e71563d941229afb4338c21f9517c41aaa0d16f0\
+			0b83d2a09ef747e7a64e8e2bd5a14869e693da66ce94ac2da570ab7ee48618f7",
+		);
+
+		let derive = |app| derive(&master, app, 0).unwrap();
+		let bip39 = |words| Application::Bip39 { language: Language::English, words: words };
+		assert_eq!(
+			derive(bip39(12)).mnemonic.unwrap(),
+			"girl mad pet galaxy egg matter matrix prison refuse sense ordinary nose",
+		);
+		assert_eq!(
+			derive(bip39(24)).mnemonic.unwrap(),
+			"puppy ocean match cereal symbol another shed magic wrap hammer bulb intact gadget \
+			divorce twin tonight reason outdoor destroy simple truth cigar social volcano",
+		);
+		assert_eq!(
+			derive(Application::HdSeedWif).wif.unwrap().to_string(),
+			"Kzyv4uF39d4Jrw2W7UryTHwZr1zQVNk4dAFyqE6BuMrMh1Za7uhp",
+		);
+		assert_eq!(
+			derive(Application::Xprv).xprv.unwrap().to_string(),
+			"xprv9s21ZrQH143K2srSbCSg4m4kLvPMzcWydgmKEnMmoZUurYuBuYG46c6P71UGXMzmriLzCCBvKQWBUv3vPB3m1SATMhp3uEjXHJ42jFg7myX",
+		);
+		assert_eq!(
+			hex::encode(&derive(Application::Hex { bytes: 64 }).hex.unwrap().0),
+			"492db4698cf3b73a5a24998aa3e9d7fa96275d85724a91e71aa2d645442f8785\
+			55d078fd1f1f67e368976f04137b1f7a0d19232136ca50c44614af72b5582a5c",
+		);
+		assert_eq!(
+			derive(Application::PasswordBase64 { length: 21 }).password.unwrap(),
+			"dKLoepugzdVJvdL56ogNV",
+		);
+		assert_eq!(
+			derive(Application::PasswordBase85 { length: 12 }).password.unwrap(),
+			"_s`{TW89)i4`",
+		);
+		assert_eq!(
+			derive(Application::Dice { sides: 6, rolls: 10 }).rolls.unwrap(),
+			vec![1, 0, 0, 2, 0, 1, 5, 5, 2, 4],
+		);
+		assert!(Application::Hex { bytes: 8 }.validate().is_err());
+		assert!(Application::Hex { bytes: 16 }.validate().is_ok());
+		assert!(Application::Bip39 { language: Language::English, words: 13 }.validate().is_err());
+		assert!(Application::PasswordBase64 { length: 87 }.validate().is_err());
+		assert!(Application::PasswordBase85 { length: 9 }.validate().is_err());
+		assert!(Application::Dice { sides: 1, rolls: 10 }.validate().is_err());
+		assert!(Application::Dice { sides: 6, rolls: MAX_DICE_ROLLS }.validate().is_ok());
+		assert!(Application::Dice { sides: 6, rolls: MAX_DICE_ROLLS + 1 }.validate().is_err());
+	}
+
+	#[test]
+	fn test_shake256() {
+		let mut out = [0u8; 8];
+		Shake256::new(b"").read(&mut out);
+		assert_eq!(hex::encode(&out), "46b9dd2b0ba88d13");
+	}
+
+	#[test]
+	fn test_shake256_multi_block() {
+		// Squeeze past the 136-byte rate twice, in reads that straddle the
+		// block boundaries. Reference values from Python's hashlib.shake_256.
+		let input = (0u8..64).collect::<Vec<_>>();
+		let mut out = [0u8; 300];
+		let mut drng = Shake256::new(&input);
+		for chunk in out.chunks_mut(7) {
+			drng.read(chunk);
+		}
+		assert_eq!(hex::encode(&out[0..8]), "755e8863a2b2bc06");
+		assert_eq!(hex::encode(&out[128..144]), "afdf02b90ad70cea55f748afd37ca921");
+		assert_eq!(hex::encode(&out[264..280]), "4078e5423d46504bcc6c4c6b86efcdea");
+		assert_eq!(hex::encode(&out[292..300]), "b242ad7e224ccf89");
+	}
+
+	#[test]
+	fn test_base64() {
+		// The test vectors from RFC 4648.
+		assert_eq!(base64_encode(b""), "");
+		assert_eq!(base64_encode(b"f"), "Zg==");
+		assert_eq!(base64_encode(b"fo"), "Zm8=");
+		assert_eq!(base64_encode(b"foo"), "Zm9v");
+		assert_eq!(base64_encode(b"foob"), "Zm9vYg==");
+		assert_eq!(base64_encode(b"fooba"), "Zm9vYmE=");
+		assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+	}
+}
@@ -1,36 +1,110 @@
-use bitcoin::{block, Block, BlockHash, Network, TxMerkleNode, Txid};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use bitcoin::bip158::{BlockFilter, FilterHash, FilterHeader};
+use bitcoin::blockdata::script::{self, PushBytesBuf};
+use bitcoin::consensus::encode::{deserialize_partial, VarInt};
+use bitcoin::hashes::Hash;
+use bitcoin::pow::Work;
+use bitcoin::{
+	block, absolute, merkle_tree, opcodes, transaction, Amount, Block, BlockHash, CompactTarget,
+	Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxMerkleNode, TxOut, Txid, Weight,
+	Witness, Wtxid,
+};
+use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 
 use crate::tx::TransactionInfo;
 use crate::{GetInfo, HexBytes};
 
+/// Known BIP-9 deployments by their version bit, as used on mainnet.
+const DEPLOYMENTS: &[(u8, &str)] = &[
+	(0, "csv"),
+	(1, "segwit"),
+	(2, "taproot"),
+	(28, "testdummy"),
+];
+
 #[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct VersionBitsInfo {
+	/// The version bits that are set.
+	pub bits: Vec<u8>,
+	/// The names of the known deployments the bits signal for.
+	pub deployments: Vec<String>,
+}
+
+impl VersionBitsInfo {
+	/// Decode the BIP-9 version bits, [None] if the version doesn't use them.
+	pub fn from_version(version: block::Version) -> Option<VersionBitsInfo> {
+		if (version.to_consensus() as u32) >> 29 != 0b001 {
+			return None;
+		}
+		let bits = (0..29).filter(|b| version.is_signalling_soft_fork(*b)).collect::<Vec<_>>();
+		Some(VersionBitsInfo {
+			deployments: DEPLOYMENTS.iter().filter(|(b, _)| bits.contains(b))
+				.map(|(_, name)| name.to_string()).collect(),
+			bits: bits,
+		})
+	}
+}
+
+/// The details that are derived from the other fields are ignored when creating headers.
+///
+/// Note that this type no longer implements [Eq] because [BlockHeaderInfo::difficulty]
+/// is a floating point number.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
 pub struct BlockHeaderInfo {
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub block_hash: Option<BlockHash>,
 	pub version: i32,
+	/// The BIP-9 version bits, if the version uses them.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub version_bits: Option<VersionBitsInfo>,
 	pub previous_block_hash: BlockHash,
 	pub merkle_root: TxMerkleNode,
 	pub time: u32,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub datetime: Option<DateTime<Utc>>,
 	pub bits: u32,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub target: Option<HexBytes>,
+	/// The difficulty relative to the minimum mainnet difficulty.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub difficulty: Option<f64>,
+	/// The expected number of hashes needed to find a block at the target.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub work: Option<HexBytes>,
+	/// Whether the block hash meets the target.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub valid_pow: Option<bool>,
 	pub nonce: u32,
 }
 
 impl<'a> GetInfo<BlockHeaderInfo> for block::Header {
 	fn get_info(&self, _network: Network) -> BlockHeaderInfo {
+		let block_hash = self.block_hash();
 		BlockHeaderInfo {
-			block_hash: Some(self.block_hash()),
+			block_hash: Some(block_hash),
 			version: self.version.to_consensus(),
+			version_bits: VersionBitsInfo::from_version(self.version),
 			previous_block_hash: self.prev_blockhash,
 			merkle_root: self.merkle_root,
 			time: self.time,
+			datetime: Utc.timestamp_opt(self.time as i64, 0).single(),
 			bits: self.bits.to_consensus(),
+			target: Some(self.target().to_be_bytes().to_vec().into()),
+			difficulty: Some(self.difficulty_float()),
+			work: Some(self.work().to_be_bytes().to_vec().into()),
+			valid_pow: Some(self.target().is_met_by(block_hash)),
 			nonce: self.nonce,
 		}
 	}
 }
 
-#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
 pub struct BlockInfo {
 	pub header: BlockHeaderInfo,
 	pub bip34_block_height: Option<u64>,
@@ -41,16 +115,974 @@ pub struct BlockInfo {
 	pub txids: Option<Vec<Txid>>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub raw_transactions: Option<Vec<HexBytes>>,
+
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub checks: Option<BlockCheckInfo>,
 }
 
 impl GetInfo<BlockInfo> for Block {
 	fn get_info(&self, network: Network) -> BlockInfo {
 		BlockInfo {
 			header: self.header.get_info(network),
-			bip34_block_height: self.bip34_block_height().ok(),
+			bip34_block_height: bip34_block_height(self),
 			transactions: Some(self.txdata.iter().map(|t| t.get_info(network)).collect()),
 			txids: None,
 			raw_transactions: None,
+			checks: None,
 		}
 	}
 }
+
+/// The height encoded in the coinbase according to BIP-34.
+///
+/// Unlike [Block::bip34_block_height], this also accepts the OP_1 to OP_16 opcodes that
+/// Bitcoin Core uses for the first 16 blocks.
+pub fn bip34_block_height(block: &Block) -> Option<u64> {
+	if let Ok(height) = block.bip34_block_height() {
+		return Some(height);
+	}
+	if block.header.version < block::Version::TWO {
+		return None;
+	}
+	let script_sig = &block.txdata.first()?.input.first()?.script_sig;
+	match script_sig.instructions_minimal().next()? {
+		Ok(script::Instruction::Op(op)) => match op.classify(opcodes::ClassifyContext::Legacy) {
+			opcodes::Class::PushNum(n) if n > 0 => Some(n as u64),
+			_ => None,
+		},
+		_ => None,
+	}
+}
+
+/// The block subsidy at the given height.
+pub fn block_subsidy(height: u32, network: Network) -> Amount {
+	let interval = match network {
+		Network::Regtest => 150,
+		_ => bitcoin::constants::SUBSIDY_HALVING_INTERVAL,
+	};
+	let halvings = height / interval;
+	if halvings >= 64 {
+		return Amount::ZERO;
+	}
+	Amount::from_sat(Amount::from_int_btc(50).to_sat() >> halvings)
+}
+
+/// The header of the signet solution in the witness commitment output, see BIP-325.
+pub const SIGNET_HEADER: [u8; 4] = [0xec, 0xc7, 0xda, 0xa2];
+
+/// What to put in a coinbase transaction built with [create_coinbase].
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct CoinbaseParams {
+	pub height: u32,
+	/// The payout outputs. The one without an amount gets the rest of the subsidy and fees.
+	pub outputs: Vec<(ScriptBuf, Option<Amount>)>,
+	pub fees: Amount,
+	/// Data to push in the coinbase script after the height.
+	pub extra_data: Vec<u8>,
+	/// An extranonce, pushed as 8 bytes after the extra data.
+	pub extranonce: Option<u64>,
+	/// The wtxids of the other transactions in the block to commit to, if any.
+	pub witness_commitment: Option<Vec<Wtxid>>,
+	/// The signet solution to add to the witness commitment output, empty for a placeholder.
+	pub signet_solution: Option<Vec<u8>>,
+}
+
+/// Build a coinbase transaction with a BIP-34 height and an optional witness commitment.
+pub fn create_coinbase(params: &CoinbaseParams, network: Network) -> Result<Transaction, String> {
+	let mut builder = script::Builder::new().push_int(params.height as i64);
+	if !params.extra_data.is_empty() {
+		let data = PushBytesBuf::try_from(params.extra_data.clone()).map_err(|_| "extra data too long")?;
+		builder = builder.push_slice(data);
+	}
+	if let Some(extranonce) = params.extranonce {
+		builder = builder.push_slice(extranonce.to_le_bytes());
+	}
+	// The coinbase script must be at least 2 bytes.
+	if builder.len() < 2 {
+		builder = builder.push_opcode(opcodes::OP_0);
+	}
+	let script_sig = builder.into_script();
+	if script_sig.len() > 100 {
+		return Err(format!("coinbase script is {} bytes, the maximum is 100", script_sig.len()));
+	}
+
+	let reward = block_subsidy(params.height, network) + params.fees;
+	let fixed = params.outputs.iter().filter_map(|o| o.1).fold(Amount::ZERO, |a, b| a + b);
+	let remainder = reward.checked_sub(fixed).ok_or_else(|| {
+		format!("outputs pay {} but the subsidy and fees are only {}", fixed, reward)
+	})?;
+	if params.outputs.iter().filter(|o| o.1.is_none()).count() > 1 {
+		return Err("only one output can get the remainder of the reward".to_owned());
+	}
+	let mut outputs = params.outputs.iter().map(|(script, amount)| TxOut {
+		value: amount.unwrap_or(remainder),
+		script_pubkey: script.clone(),
+	}).collect::<Vec<_>>();
+
+	let mut witness = Witness::new();
+	if params.witness_commitment.is_some() || params.signet_solution.is_some() {
+		let wtxids = params.witness_commitment.clone().unwrap_or_default();
+		let hashes = Some(Wtxid::all_zeros()).into_iter().chain(wtxids).map(|w| w.to_raw_hash());
+		let witness_root = merkle_tree::calculate_root(hashes).expect("includes the coinbase").into();
+		let reserved = [0u8; 32];
+		witness.push(reserved);
+		let commitment = Block::compute_witness_commitment(&witness_root, &reserved);
+
+		let mut script = WITNESS_COMMITMENT_MAGIC.to_vec();
+		script.extend_from_slice(commitment.as_ref());
+		if let Some(ref solution) = params.signet_solution {
+			let mut data = SIGNET_HEADER.to_vec();
+			data.extend_from_slice(solution);
+			let data = PushBytesBuf::try_from(data).map_err(|_| "signet solution too long")?;
+			script = script::Builder::from(script).push_slice(data).into_script().into_bytes();
+		}
+		outputs.push(TxOut {
+			value: Amount::ZERO,
+			script_pubkey: ScriptBuf::from(script),
+		});
+	}
+
+	Ok(Transaction {
+		version: transaction::Version::TWO,
+		lock_time: absolute::LockTime::ZERO,
+		input: vec![TxIn {
+			previous_output: OutPoint::null(),
+			script_sig: script_sig,
+			sequence: Sequence::MAX,
+			witness: witness,
+		}],
+		output: outputs,
+	})
+}
+
+/// Map the outpoints spent by the block to the given prevouts.
+///
+/// The prevouts are given in block order for all inputs except the coinbase.
+fn prevout_map(block: &Block, prevouts: &[TxOut]) -> Result<HashMap<OutPoint, TxOut>, String> {
+	let inputs = block.txdata.iter().skip(1).flat_map(|t| t.input.iter()).collect::<Vec<_>>();
+	if prevouts.len() != inputs.len() {
+		return Err(format!("{} prevouts given for {} inputs", prevouts.len(), inputs.len()));
+	}
+	Ok(inputs.iter().map(|i| i.previous_output).zip(prevouts.iter().cloned()).collect())
+}
+
+/// Sum the amounts if each of them and the total are at most 21 million bitcoin.
+fn money_range_total<I: IntoIterator<Item = Amount>>(amounts: I) -> Option<Amount> {
+	amounts.into_iter().try_fold(Amount::ZERO, |acc, a| {
+		acc.checked_add(a).filter(|t| a <= Amount::MAX_MONEY && *t <= Amount::MAX_MONEY)
+	})
+}
+
+/// The result of a consensus check on a block.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct BlockCheck {
+	pub check: String,
+	pub valid: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct BlockCheckInfo {
+	pub valid: bool,
+	pub weight: u64,
+	/// The sigop cost, which only includes p2sh and witness sigops if prevouts are given.
+	pub sigop_cost: usize,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub fees: Option<u64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub subsidy: Option<u64>,
+	/// The value of the coinbase outputs, if it is within the money range.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub coinbase_value: Option<u64>,
+	pub checks: Vec<BlockCheck>,
+}
+
+/// Check the block for consistency.
+///
+/// The merkle root and witness commitment, the coinbase and its BIP-34 height, duplicate
+/// txids, the weight and the sigop cost are checked. If the outputs spent by the inputs
+/// are given, in block order without the coinbase, the fees and the value of the coinbase
+/// are checked too, which needs the height from either the coinbase or the argument.
+pub fn check_block(
+	block: &Block,
+	prevouts: Option<&[TxOut]>,
+	height: Option<u32>,
+	network: Network,
+) -> Result<BlockCheckInfo, String> {
+	let spent = match prevouts {
+		Some(prevouts) => prevout_map(block, prevouts)?,
+		None => HashMap::new(),
+	};
+
+	let mut checks = Vec::new();
+	let mut check = |name: &str, error: Option<String>| checks.push(BlockCheck {
+		check: name.to_owned(),
+		valid: error.is_none(),
+		error: error,
+	});
+
+	check("coinbase", if block.txdata.first().map(|t| t.is_coinbase()) != Some(true) {
+		Some("the first transaction is not a coinbase".to_owned())
+	} else {
+		block.txdata.iter().skip(1).position(|t| t.is_coinbase())
+			.map(|i| format!("transaction {} is a coinbase", i + 1))
+	});
+
+	check("merkle-root", match block.compute_merkle_root() {
+		None => Some("the block has no transactions".to_owned()),
+		Some(root) if root != block.header.merkle_root => Some(format!("merkle root should be {}", root)),
+		Some(_) => None,
+	});
+	check("witness-commitment", if !block.check_witness_commitment() {
+		Some("the witness commitment in the coinbase doesn't match the witness merkle root".to_owned())
+	} else {
+		None
+	});
+
+	let bip34_height = bip34_block_height(block);
+	check("bip34-height", match (bip34_height, height) {
+		(None, _) if block.header.version >= block::Version::TWO => {
+			Some("the coinbase doesn't start with the height".to_owned())
+		}
+		(Some(h), Some(expected)) if h != expected as u64 => {
+			Some(format!("the coinbase has height {} instead of {}", h, expected))
+		}
+		_ => None,
+	});
+
+	let mut txids = HashSet::new();
+	let duplicates = block.txdata.iter().map(|t| t.compute_txid()).filter(|t| !txids.insert(*t))
+		.map(|t| t.to_string()).collect::<Vec<_>>();
+	check("duplicate-txids", if duplicates.is_empty() {
+		None
+	} else {
+		Some(format!("duplicate txids: {}", duplicates.join(", ")))
+	});
+
+	let weight = block.weight();
+	check("weight", if weight > Weight::MAX_BLOCK {
+		Some(format!("weight {} is above the maximum of {}", weight.to_wu(), Weight::MAX_BLOCK.to_wu()))
+	} else {
+		None
+	});
+	let sigop_cost = block.txdata.iter().map(|t| t.total_sigop_cost(|o| spent.get(o).cloned())).sum::<usize>();
+	check("sigops", if sigop_cost > bitcoin::constants::MAX_BLOCK_SIGOPS_COST as usize {
+		Some(format!("sigop cost {} is above the maximum of {}",
+			sigop_cost, bitcoin::constants::MAX_BLOCK_SIGOPS_COST))
+	} else {
+		None
+	});
+
+	// Amounts are summed like consensus does, so that absurd amounts are reported
+	// instead of overflowing.
+	let mut out_of_range = Vec::new();
+	let output_totals = block.txdata.iter().enumerate().map(|(i, tx)| {
+		let total = money_range_total(tx.output.iter().map(|o| o.value));
+		if total.is_none() {
+			out_of_range.push(format!("outputs of transaction {}", i));
+		}
+		total
+	}).collect::<Vec<_>>();
+	let input_totals = block.txdata.iter().enumerate().skip(1).filter(|_| prevouts.is_some()).map(|(i, tx)| {
+		let total = money_range_total(tx.input.iter().map(|i| spent[&i.previous_output].value));
+		if total.is_none() {
+			out_of_range.push(format!("prevouts of transaction {}", i));
+		}
+		(i, total)
+	}).collect::<Vec<_>>();
+	check("amount-range", if out_of_range.is_empty() {
+		None
+	} else {
+		Some(format!("amounts above 21 million bitcoin: {}", out_of_range.join(", ")))
+	});
+	let coinbase_value = output_totals.first().cloned().flatten();
+
+	let mut fees = None;
+	let mut subsidy = None;
+	if prevouts.is_some() {
+		let mut total = Some(Amount::ZERO);
+		let mut negative = Vec::new();
+		for (i, input) in input_totals {
+			match (input, output_totals[i]) {
+				(Some(input), Some(output)) => match input.checked_sub(output) {
+					Some(fee) => total = total.and_then(|t| t.checked_add(fee)),
+					None => negative.push(i.to_string()),
+				},
+				_ => total = None,
+			}
+		}
+		check("fees", if negative.is_empty() {
+			None
+		} else {
+			Some(format!("transactions spend more than their inputs: {}", negative.join(", ")))
+		});
+		fees = total;
+
+		let height = height.map(|h| h as u64).or(bip34_height);
+		subsidy = height.and_then(|h| u32::try_from(h).ok()).map(|h| block_subsidy(h, network));
+		let max = subsidy.zip(fees).and_then(|(s, f)| s.checked_add(f));
+		check("coinbase-value", match (height, subsidy, max, coinbase_value) {
+			(None, _, _, _) => Some("the height is needed to know the subsidy".to_owned()),
+			(Some(h), None, _, _) => Some(format!("the height {} is out of range", h)),
+			(_, _, Some(max), Some(value)) if value <= max => None,
+			(_, _, Some(max), Some(value)) => Some(format!(
+				"the coinbase claims {} sat, but the subsidy and fees are only {} sat",
+				value.to_sat(), max.to_sat(),
+			)),
+			_ => Some("amounts out of range".to_owned()),
+		});
+	}
+
+	Ok(BlockCheckInfo {
+		valid: checks.iter().all(|c| c.valid),
+		weight: weight.to_wu(),
+		sigop_cost: sigop_cost,
+		fees: fees.map(|f| f.to_sat()),
+		subsidy: subsidy.map(|s| s.to_sat()),
+		coinbase_value: coinbase_value.map(|v| v.to_sat()),
+		checks: checks,
+	})
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct FilterMatch {
+	pub script: HexBytes,
+	pub matches: bool,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct BlockFilterInfo {
+	pub block_hash: BlockHash,
+	pub filter: HexBytes,
+	pub nb_elements: u64,
+	pub filter_hash: FilterHash,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub filter_header: Option<FilterHeader>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub matches: Option<Vec<FilterMatch>>,
+}
+
+/// Build the BIP-158 basic filter of the block.
+///
+/// The filter contains the output scripts of the block and the scripts spent by its
+/// inputs, which are given in block order without the coinbase.
+pub fn block_filter(block: &Block, prevouts: &[TxOut]) -> Result<BlockFilter, String> {
+	let spent = prevout_map(block, prevouts)?;
+	BlockFilter::new_script_filter(block, |o| {
+		spent.get(o).map(|txout| txout.script_pubkey.clone())
+			.ok_or(bitcoin::bip158::Error::UtxoMissing(*o))
+	}).map_err(|e| format!("failed to build filter: {}", e))
+}
+
+/// Test which of the scripts match the filter of the block with the given hash.
+pub fn match_filter(
+	filter: &BlockFilter,
+	block_hash: &BlockHash,
+	scripts: &[ScriptBuf],
+) -> Result<Vec<FilterMatch>, String> {
+	scripts.iter().map(|script| {
+		let query = std::iter::once(script.as_bytes());
+		Ok(FilterMatch {
+			script: script.as_bytes().into(),
+			matches: filter.match_any(block_hash, query).map_err(|e| format!("invalid filter: {}", e))?,
+		})
+	}).collect()
+}
+
+/// Compute the chain of filter headers for consecutive filters, following the header of
+/// the filter before them, which is all zeros for the genesis block.
+pub fn filter_header_chain(filters: &[BlockFilter], previous: FilterHeader) -> Vec<FilterHeader> {
+	filters.iter().scan(previous, |prev, filter| {
+		*prev = filter.filter_header(prev);
+		Some(*prev)
+	}).collect()
+}
+
+/// Build the filter of the block with its hash and header and match the scripts against it.
+pub fn block_filter_info(
+	block: &Block,
+	prevouts: &[TxOut],
+	previous_header: Option<FilterHeader>,
+	scripts: Option<&[ScriptBuf]>,
+) -> Result<BlockFilterInfo, String> {
+	let filter = block_filter(block, prevouts)?;
+	let block_hash = block.block_hash();
+	let (nb_elements, _) = deserialize_partial::<VarInt>(&filter.content)
+		.map_err(|e| format!("invalid filter: {}", e))?;
+	Ok(BlockFilterInfo {
+		block_hash: block_hash,
+		nb_elements: nb_elements.0,
+		filter_hash: FilterHash::hash(&filter.content),
+		filter_header: previous_header.map(|h| filter.filter_header(&h)),
+		matches: scripts.map(|s| match_filter(&filter, &block_hash, s)).transpose()?,
+		filter: filter.content.into(),
+	})
+}
+
+/// A problem with a header in a chain of headers.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct HeaderError {
+	/// The position of the header in the given chain.
+	pub index: usize,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub height: Option<u32>,
+	pub block_hash: BlockHash,
+	pub error: String,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct HeaderChainInfo {
+	pub valid: bool,
+	pub headers: usize,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub start_height: Option<u32>,
+	pub first_block_hash: BlockHash,
+	pub tip_block_hash: BlockHash,
+	/// The total work in the headers, not including the work before the first header.
+	pub chainwork: HexBytes,
+	pub errors: Vec<HeaderError>,
+}
+
+/// The bits the header at the given index should have, [None] if the headers before
+/// it are not enough to know.
+fn expected_bits(
+	headers: &[block::Header],
+	idx: usize,
+	height: u32,
+	params: &bitcoin::params::Params,
+) -> Option<CompactTarget> {
+	let interval = params.difficulty_adjustment_interval() as usize;
+	let prev = headers[idx - 1];
+	if params.no_pow_retargeting {
+		return Some(prev.bits);
+	}
+	if (height as usize).is_multiple_of(interval) {
+		// The first header of the previous period is needed for the timespan.
+		let first = headers.get(idx.checked_sub(interval)?)?;
+		let timespan = prev.time.saturating_sub(first.time);
+		return Some(CompactTarget::from_next_work_required(prev.bits, timespan as u64, params));
+	}
+	if !params.allow_min_difficulty_blocks {
+		return Some(prev.bits);
+	}
+
+	// Testnet allows minimum difficulty blocks if no block was found for 20 minutes,
+	// the blocks after them return to the difficulty of the last regular block.
+	let min_bits = params.max_attainable_target.to_compact_lossy();
+	if headers[idx].time as u64 > prev.time as u64 + params.pow_target_spacing * 2 {
+		return Some(min_bits);
+	}
+	let mut i = idx - 1;
+	while headers[i].bits == min_bits && !(height as usize - (idx - i)).is_multiple_of(interval) {
+		i = i.checked_sub(1)?;
+	}
+	Some(headers[i].bits)
+}
+
+/// Check a chain of headers for linkage, proof of work, the median time rule and
+/// difficulty retargeting and sum their work.
+///
+/// Difficulty retargeting can only be checked if the height of the first header is known.
+/// The median time is taken over the previous headers in the chain only, so it's checked
+/// against fewer than 11 headers at the start of the chain.
+pub fn check_header_chain(
+	headers: &[block::Header],
+	start_height: Option<u32>,
+	network: Network,
+) -> Result<HeaderChainInfo, String> {
+	if headers.is_empty() {
+		return Err("no headers".to_owned());
+	}
+	if let Some(h) = start_height {
+		u32::try_from(headers.len() - 1).ok().and_then(|n| h.checked_add(n))
+			.ok_or("the height of the last header doesn't fit in 32 bits")?;
+	}
+	let params = bitcoin::params::Params::new(network);
+
+	let mut errors = Vec::new();
+	let mut chainwork = Work::from_be_bytes([0; 32]);
+	for (idx, header) in headers.iter().enumerate() {
+		let block_hash = header.block_hash();
+		let height = start_height.map(|h| h + idx as u32);
+		let mut error = |e: String| errors.push(HeaderError {
+			index: idx,
+			height: height,
+			block_hash: block_hash,
+			error: e,
+		});
+
+		chainwork = chainwork + header.work();
+		if header.target() > params.max_attainable_target {
+			error("target is above the proof-of-work limit".to_owned());
+		}
+		if !header.target().is_met_by(block_hash) {
+			error("block hash doesn't meet the target".to_owned());
+		}
+		if idx == 0 {
+			continue;
+		}
+
+		if header.prev_blockhash != headers[idx - 1].block_hash() {
+			error("previous block hash doesn't match the previous header".to_owned());
+		}
+
+		let mut times = headers[idx.saturating_sub(11)..idx].iter().map(|h| h.time).collect::<Vec<_>>();
+		times.sort();
+		let median = times[times.len() / 2];
+		if header.time <= median {
+			error(format!("time {} is not after the median time {} of the previous {} headers",
+				header.time, median, times.len()));
+		}
+
+		if let Some(height) = height {
+			if let Some(expected) = expected_bits(headers, idx, height, &params) {
+				if header.bits != expected {
+					error(format!("bits {:08x} don't match the expected {:08x}", header.bits, expected));
+				}
+			}
+		}
+	}
+
+	Ok(HeaderChainInfo {
+		valid: errors.is_empty(),
+		headers: headers.len(),
+		start_height: start_height,
+		first_block_hash: headers[0].block_hash(),
+		tip_block_hash: headers[headers.len() - 1].block_hash(),
+		chainwork: chainwork.to_be_bytes().to_vec().into(),
+		errors: errors,
+	})
+}
+
+/// The start of the script of the coinbase output with the witness commitment.
+const WITNESS_COMMITMENT_MAGIC: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+fn is_witness_commitment(output: &TxOut) -> bool {
+	let script = output.script_pubkey.as_bytes();
+	script.len() >= 38 && script[0..6] == WITNESS_COMMITMENT_MAGIC
+}
+
+/// Recompute the witness commitment in the coinbase and the merkle root.
+///
+/// The witness commitment is only updated if any transaction has a witness or the coinbase
+/// already has a commitment. If the coinbase has no witness reserved value, zeros are used.
+pub fn fix_merkle(block: &mut Block) -> Result<(), String> {
+	if block.txdata.is_empty() {
+		return Err("block has no transactions".to_owned());
+	}
+
+	let segwit = block.txdata[1..].iter().any(|t| t.input.iter().any(|i| !i.witness.is_empty()));
+	if segwit || block.txdata[0].output.iter().any(is_witness_commitment) {
+		let witness_root = block.witness_root().expect("block has transactions");
+		let coinbase = &mut block.txdata[0];
+		if !coinbase.is_coinbase() {
+			return Err("the first transaction is not a coinbase".to_owned());
+		}
+		if coinbase.input[0].witness.is_empty() {
+			coinbase.input[0].witness.push([0u8; 32]);
+		}
+		let witness = &coinbase.input[0].witness;
+		if witness.len() != 1 || witness[0].len() != 32 {
+			return Err("the coinbase witness is not a 32-byte witness reserved value".to_owned());
+		}
+		let commitment = Block::compute_witness_commitment(&witness_root, &witness[0]);
+
+		match coinbase.output.iter().rposition(is_witness_commitment) {
+			Some(idx) => {
+				let mut script = coinbase.output[idx].script_pubkey.to_bytes();
+				script[6..38].copy_from_slice(commitment.as_ref());
+				coinbase.output[idx].script_pubkey = ScriptBuf::from(script);
+			}
+			None => {
+				let mut script = WITNESS_COMMITMENT_MAGIC.to_vec();
+				script.extend_from_slice(commitment.as_ref());
+				coinbase.output.push(TxOut {
+					value: Amount::ZERO,
+					script_pubkey: ScriptBuf::from(script),
+				});
+			}
+		}
+	}
+
+	block.header.merkle_root = block.compute_merkle_root().expect("block has transactions");
+	Ok(())
+}
+
+/// Set the extranonce by pushing it to the end of the original coinbase script.
+///
+/// An extranonce of zero leaves the coinbase unchanged.
+fn set_extranonce(block: &mut Block, coinbase_script: &ScriptBuf, extranonce: u64) {
+	let mut script = coinbase_script.to_bytes();
+	if extranonce != 0 {
+		script.push(8);
+		script.extend_from_slice(&extranonce.to_le_bytes());
+	}
+	block.txdata[0].input[0].script_sig = ScriptBuf::from(script);
+	block.header.merkle_root = block.compute_merkle_root().expect("block has transactions");
+}
+
+/// Grind the nonce until the block hash meets the target, moving on to the next
+/// extranonce in the coinbase when all nonces are tried.
+///
+/// The work is split over the threads by extranonce. The merkle root is recomputed,
+/// so it doesn't have to be correct in the given block.
+pub fn mine(block: &Block, threads: usize) -> Result<Block, String> {
+	if block.txdata.is_empty() {
+		return Err("block has no transactions".to_owned());
+	}
+	let has_coinbase = block.txdata[0].is_coinbase();
+	let coinbase_script = block.txdata[0].input.first().map(|i| i.script_sig.clone()).unwrap_or_default();
+	// The extranonce is appended as a push of 8 bytes.
+	if has_coinbase && coinbase_script.len() + 9 > 100 {
+		return Err(format!(
+			"coinbase script is {} bytes, the maximum is 91 to leave room for the extranonce",
+			coinbase_script.len(),
+		));
+	}
+	let target = block.header.target();
+
+	// Without a coinbase there is no extranonce to split the work by.
+	let threads = if has_coinbase { threads.max(1) } else { 1 };
+	let stop = Arc::new(AtomicBool::new(false));
+	let (tx, rx) = mpsc::channel();
+	let handles = (0..threads).map(|t| {
+		let mut block = block.clone();
+		let coinbase_script = coinbase_script.clone();
+		let stop = stop.clone();
+		let tx = tx.clone();
+		thread::spawn(move || {
+			let mut extranonce = t as u64;
+			loop {
+				if has_coinbase {
+					set_extranonce(&mut block, &coinbase_script, extranonce);
+				} else {
+					block.header.merkle_root = block.compute_merkle_root().expect("block has transactions");
+				}
+				for nonce in 0..=u32::MAX {
+					if nonce % (1 << 16) == 0 && stop.load(Ordering::Relaxed) {
+						return;
+					}
+					block.header.nonce = nonce;
+					if target.is_met_by(block.header.block_hash()) {
+						stop.store(true, Ordering::Relaxed);
+						let _ = tx.send(block);
+						return;
+					}
+				}
+				if !has_coinbase {
+					return;
+				}
+				extranonce += threads as u64;
+			}
+		})
+	}).collect::<Vec<_>>();
+	drop(tx);
+	let found = rx.recv().map_err(|_| "no nonce found that meets the target".to_owned());
+	stop.store(true, Ordering::Relaxed);
+	for h in handles {
+		h.join().expect("mining thread panicked");
+	}
+	found
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use bitcoin::consensus::encode::deserialize;
+
+	// Mainnet headers 0 to 2.
+	const HEADERS: &[&str] = &[
+		"0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c",
+		"010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299",
+		"010000004860eb18bf1b1620e37e9490fc8a427514416fd75159ab86688e9a8300000000d5fdcc541e25de1c7a5addedf24858b8bb665c9f36ef744ee42c316022c90f9bb0bc6649ffff001d08d2bd61",
+	];
+
+	#[test]
+	fn test_header_chain() {
+		let mut headers = HEADERS.iter().map(|h| deserialize::<block::Header>(&hex::decode(h).unwrap()).unwrap())
+			.collect::<Vec<_>>();
+
+		let info = headers[0].get_info(Network::Bitcoin);
+		assert_eq!(info.difficulty, Some(1.0));
+		assert_eq!(info.valid_pow, Some(true));
+		assert_eq!(info.version_bits, None);
+		assert_eq!(info.datetime.unwrap().to_rfc3339(), "2009-01-03T18:15:05+00:00");
+
+		let info = check_header_chain(&headers, Some(0), Network::Bitcoin).unwrap();
+		assert!(info.valid, "{:?}", info.errors);
+		assert_eq!(hex::encode(info.chainwork.bytes()),
+			"0000000000000000000000000000000000000000000000000000000300030003");
+		assert!(check_header_chain(&headers, Some(u32::MAX - 2), Network::Bitcoin).is_ok());
+		assert!(check_header_chain(&headers, Some(u32::MAX - 1), Network::Bitcoin).is_err());
+
+		headers[2].time = headers[0].time;
+		headers[1].nonce += 1;
+		let info = check_header_chain(&headers, Some(0), Network::Bitcoin).unwrap();
+		let errors = info.errors.iter().map(|e| (e.index, e.error.as_str())).collect::<Vec<_>>();
+		assert_eq!(errors, vec![
+			(1, "block hash doesn't meet the target"),
+			(2, "block hash doesn't meet the target"),
+			(2, "previous block hash doesn't match the previous header"),
+			(2, "time 1231006505 is not after the median time 1231469665 of the previous 2 headers"),
+		]);
+
+		let version = block::Version::from_consensus(0x20000006);
+		let bits = VersionBitsInfo::from_version(version).unwrap();
+		assert_eq!(bits.bits, vec![1, 2]);
+		assert_eq!(bits.deployments, vec!["segwit", "taproot"]);
+	}
+
+	#[test]
+	fn test_coinbase() {
+		let mut params = CoinbaseParams {
+			height: 5,
+			outputs: vec![
+				(ScriptBuf::new_op_return(&[1]), Some(Amount::from_sat(1000))),
+				(ScriptBuf::new_op_return(&[2]), None),
+			],
+			fees: Amount::from_sat(500),
+			..Default::default()
+		};
+		let coinbase = create_coinbase(&params, Network::Regtest).unwrap();
+		assert!(coinbase.is_coinbase());
+		assert_eq!(coinbase.input[0].script_sig.as_bytes(), &[0x55, 0x00]);
+		assert_eq!(coinbase.output[1].value, Amount::from_sat(50_0000_0000 + 500 - 1000));
+		assert_eq!(block_subsidy(300, Network::Regtest), Amount::from_sat(12_5000_0000));
+
+		let mut block = bitcoin::constants::genesis_block(Network::Regtest);
+		block.header.version = block::Version::TWO;
+		block.txdata = vec![coinbase];
+		assert_eq!(bip34_block_height(&block), Some(5));
+
+		let mut spend = block.txdata[0].clone();
+		spend.input[0].previous_output.vout = 0;
+		spend.input[0].witness.push([1u8]);
+		params.height = 1000;
+		params.extranonce = Some(7);
+		params.witness_commitment = Some(vec![spend.compute_wtxid()]);
+		params.signet_solution = Some(vec![]);
+		block.txdata = vec![create_coinbase(&params, Network::Regtest).unwrap(), spend];
+		assert_eq!(bip34_block_height(&block), Some(1000));
+		assert!(block.check_witness_commitment());
+		let commitment = block.txdata[0].output[2].script_pubkey.as_bytes();
+		assert_eq!(&commitment[38..], &[0x04, 0xec, 0xc7, 0xda, 0xa2]);
+
+		params = CoinbaseParams {
+			height: 5,
+			outputs: vec![(ScriptBuf::new_op_return(&[1]), Some(Amount::from_sat(50_0000_0001)))],
+			..Default::default()
+		};
+		assert_eq!(
+			create_coinbase(&params, Network::Regtest).unwrap_err(),
+			"outputs pay 50.00000001 BTC but the subsidy and fees are only 50 BTC",
+		);
+
+		params = CoinbaseParams {
+			height: 5,
+			outputs: vec![(ScriptBuf::new_op_return(&[1]), None), (ScriptBuf::new_op_return(&[2]), None)],
+			..Default::default()
+		};
+		assert_eq!(
+			create_coinbase(&params, Network::Regtest).unwrap_err(),
+			"only one output can get the remainder of the reward",
+		);
+
+		params = CoinbaseParams { height: 5, extra_data: vec![1; 99], ..Default::default() };
+		assert_eq!(
+			create_coinbase(&params, Network::Regtest).unwrap_err(),
+			"coinbase script is 102 bytes, the maximum is 100",
+		);
+	}
+
+	/// A regtest block at height 1 with the same transaction twice, spending 1000 sat
+	/// to 900 sat each, and a coinbase claiming 300 sat of fees.
+	fn check_test_block() -> (Block, TxOut) {
+		let mut block = bitcoin::constants::genesis_block(Network::Regtest);
+		block.header.version = block::Version::TWO;
+		let params = CoinbaseParams {
+			height: 1,
+			outputs: vec![(ScriptBuf::new_op_return(&[1]), None)],
+			fees: Amount::from_sat(300),
+			..Default::default()
+		};
+		let coinbase = create_coinbase(&params, Network::Regtest).unwrap();
+		let mut spend = coinbase.clone();
+		spend.input[0].previous_output.vout = 0;
+		spend.output[0].value = Amount::from_sat(900);
+		block.txdata = vec![coinbase, spend.clone(), spend];
+		fix_merkle(&mut block).unwrap();
+
+		let prevout = TxOut {
+			value: Amount::from_sat(1000),
+			script_pubkey: ScriptBuf::new(),
+		};
+		(block, prevout)
+	}
+
+	fn failed_checks(info: &BlockCheckInfo) -> Vec<&str> {
+		info.checks.iter().filter(|c| !c.valid).map(|c| c.check.as_str()).collect()
+	}
+
+	#[test]
+	fn test_check_block_fees() {
+		let (block, prevout) = check_test_block();
+		let info = check_block(&block, Some(&[prevout.clone(), prevout.clone()]), None, Network::Regtest).unwrap();
+		assert_eq!(failed_checks(&info), vec!["duplicate-txids", "coinbase-value"]);
+		assert_eq!(info.fees, Some(200));
+		assert_eq!(info.subsidy, Some(50_0000_0000));
+		assert_eq!(info.coinbase_value, Some(50_0000_0300));
+
+		assert!(check_block(&block, Some(&[prevout]), None, Network::Regtest).is_err());
+	}
+
+	#[test]
+	fn test_check_block_structure() {
+		let (mut block, _) = check_test_block();
+		block.header.merkle_root = TxMerkleNode::all_zeros();
+		let info = check_block(&block, None, Some(2), Network::Regtest).unwrap();
+		assert_eq!(failed_checks(&info), vec!["merkle-root", "bip34-height", "duplicate-txids"]);
+		assert_eq!(info.fees, None);
+
+		block.txdata.swap(0, 1);
+		let info = check_block(&block, None, None, Network::Regtest).unwrap();
+		assert_eq!(info.checks[0].error.as_deref(), Some("the first transaction is not a coinbase"));
+
+		block.txdata.clear();
+		let info = check_block(&block, None, None, Network::Regtest).unwrap();
+		assert_eq!(info.checks[1].check, "merkle-root");
+		assert_eq!(info.checks[1].error.as_deref(), Some("the block has no transactions"));
+	}
+
+	#[test]
+	fn test_check_block_amount_range() {
+		let (mut block, prevout) = check_test_block();
+		block.txdata[1].output[0].value = Amount::MAX_MONEY + Amount::from_sat(1);
+		let mut extra = block.txdata[0].output[0].clone();
+		extra.value = Amount::from_sat(u64::MAX);
+		block.txdata[0].output.push(extra);
+		fix_merkle(&mut block).unwrap();
+		let huge = TxOut {
+			value: Amount::from_sat(u64::MAX),
+			..prevout.clone()
+		};
+		let info = check_block(&block, Some(&[huge.clone(), huge]), None, Network::Regtest).unwrap();
+		assert_eq!(failed_checks(&info), vec!["amount-range", "coinbase-value"]);
+		assert_eq!(
+			info.checks.iter().find(|c| c.check == "amount-range").unwrap().error.as_deref(),
+			Some("amounts above 21 million bitcoin: outputs of transaction 0, outputs of transaction 1, \
+				prevouts of transaction 1, prevouts of transaction 2"),
+		);
+		assert_eq!((info.fees, info.coinbase_value), (None, None));
+	}
+
+	#[test]
+	fn test_block_filter_genesis() {
+		// The testnet genesis block from the BIP-158 test vectors.
+		let block = bitcoin::constants::genesis_block(Network::Testnet);
+		let genesis_script = block.txdata[0].output[0].script_pubkey.clone();
+		let other_script = ScriptBuf::from_bytes(vec![0x51]);
+		let info = block_filter_info(
+			&block, &[], Some(FilterHeader::all_zeros()), Some(&[genesis_script, other_script]),
+		).unwrap();
+		assert_eq!(info.filter.0, hex::decode("019dfca8").unwrap());
+		assert_eq!(info.nb_elements, 1);
+		assert_eq!(
+			info.filter_header.unwrap().to_string(),
+			"21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750",
+		);
+		assert_eq!(info.matches.unwrap().iter().map(|m| m.matches).collect::<Vec<_>>(), vec![true, false]);
+	}
+
+	/// A block with a p2wpkh output in the coinbase next to an OP_RETURN and an empty
+	/// output, a transaction spending a p2pkh output to a p2sh output and the same p2wpkh
+	/// script, and a transaction spending that p2sh output within the block and an output
+	/// with an empty script, to a p2tr output.
+	const FILTER_TEST_BLOCK: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003f58c7b3ec613ef94c66b6a2\
+		c60b7ee3434e21d27db721ecf8fb78c7515b6457dae5494dffff001d1aa4ae1803020000000100000000000000000000\
+		00000000000000000000000000000000000000000000ffffffff025100ffffffff0300f2052a01000000160014751e76\
+		e8199196d454941c45d1b3a323f1433bd6e803000000000000056a03010203e803000000000000000000000002000000\
+		0101010101010101010101010101010101010101010101010101010101010101010000000000ffffffff02e803000000\
+		00000017a914b472a266d0bd89c13706a4132ccfb16f7c3b9fcb87e803000000000000160014751e76e8199196d45494\
+		1c45d1b3a323f1433bd6000000000200000002c842e66d4f516f855d6479e21a9ef4578846a76a805aa9effa0789cd10\
+		f67c3d0000000000ffffffff020202020202020202020202020202020202020202020202020202020202020205000000\
+		00ffffffff01e80300000000000022512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f817\
+		9800000000";
+
+	#[test]
+	fn test_block_filter_prevouts() {
+		let block: Block = deserialize(&hex::decode(FILTER_TEST_BLOCK).unwrap()).unwrap();
+		let script = |h: &str| ScriptBuf::from_bytes(hex::decode(h).unwrap());
+		let p2pkh = script("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac");
+		let p2sh = script("a914b472a266d0bd89c13706a4132ccfb16f7c3b9fcb87");
+		let prevouts = [&p2pkh, &p2sh, &ScriptBuf::new()].iter().map(|s| TxOut {
+			value: Amount::from_sat(1000),
+			script_pubkey: (*s).clone(),
+		}).collect::<Vec<_>>();
+
+		// Computed with an independent implementation of BIP-158: the filter has the
+		// p2wpkh, p2pkh, p2sh and p2tr scripts, without duplicates and without the
+		// empty and OP_RETURN scripts.
+		let info = block_filter_info(&block, &prevouts, None, None).unwrap();
+		assert_eq!(info.filter.hex(), "04ba1a21d532bc948ada5480");
+		assert_eq!(info.nb_elements, 4);
+
+		let filter = BlockFilter::new(&info.filter.0);
+		let scripts = [
+			p2pkh, p2sh, block.txdata[0].output[0].script_pubkey.clone(),
+			block.txdata[2].output[0].script_pubkey.clone(), block.txdata[0].output[1].script_pubkey.clone(),
+		];
+		let matches = match_filter(&filter, &block.block_hash(), &scripts).unwrap();
+		assert_eq!(matches.iter().map(|m| m.matches).collect::<Vec<_>>(), vec![true, true, true, true, false]);
+
+		assert_eq!(block_filter(&block, &prevouts[..2]).unwrap_err(), "2 prevouts given for 3 inputs");
+	}
+
+	#[test]
+	fn test_filter_header_chain() {
+		// The testnet blocks 2 and 3 from the BIP-158 test vectors.
+		let filters = ["0174a170", "016cf7a0"].iter().map(|f| BlockFilter::new(&hex::decode(f).unwrap()))
+			.collect::<Vec<_>>();
+		let previous = "d7bdac13a59d745b1add0d2ce852f1a0442e8945fc1bf3848d3cbffd88c24fe1".parse().unwrap();
+		let headers = filter_header_chain(&filters, previous);
+		assert_eq!(headers.iter().map(|h| h.to_string()).collect::<Vec<_>>(), vec![
+			"186afd11ef2b5e7e3504f2e8cbf8df28a1fd251fe53d60dff8b1467d1b386cf0",
+			"8d63aadf5ab7257cb6d2316a57b16f517bff1c6388f124ec4c04af1212729d2a",
+		]);
+		assert!(filter_header_chain(&[], previous).is_empty());
+	}
+
+	#[test]
+	fn test_mine() {
+		let genesis = bitcoin::constants::genesis_block(Network::Regtest);
+		let mut block = genesis.clone();
+		block.header.nonce = 0;
+		block.header.merkle_root = TxMerkleNode::all_zeros();
+		fix_merkle(&mut block).unwrap();
+		assert_eq!(block.header.merkle_root, genesis.header.merkle_root);
+		// No witnesses, so no commitment.
+		assert_eq!(block.txdata, genesis.txdata);
+
+		let mined = mine(&block, 2).unwrap();
+		assert!(mined.header.validate_pow(mined.header.target()).is_ok());
+		assert!(mined.check_merkle_root());
+
+		let mut long = block.clone();
+		long.txdata[0].input[0].script_sig = ScriptBuf::from(vec![0x51; 92]);
+		assert_eq!(
+			mine(&long, 1).unwrap_err(),
+			"coinbase script is 92 bytes, the maximum is 91 to leave room for the extranonce",
+		);
+
+		// A segwit spend gets a commitment.
+		let mut spend = block.txdata[0].clone();
+		spend.input[0].previous_output.vout = 0;
+		spend.input[0].witness.push([1u8]);
+		block.txdata.push(spend);
+		fix_merkle(&mut block).unwrap();
+		assert_eq!(block.txdata[0].output.len(), 2);
+		assert!(block.check_witness_commitment());
+		assert!(block.check_merkle_root());
+	}
+}
@@ -0,0 +1,356 @@
+//! codex32 (BIP-93) secret shares.
+
+use std::fmt;
+
+use bitcoin::bip32;
+use bitcoin::NetworkKind;
+use secp256k1::rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::{HexBytes, SECP};
+
+const CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const HRP: &str = "ms";
+const SHORT_CHECKSUM_LENGTH: usize = 13;
+const LONG_CHECKSUM_LENGTH: usize = 15;
+const MS32_CONST: u128 = 0x10ce0795c2fd1e62a;
+const MS32_LONG_CONST: u128 = 0x43381e570bf4798ab26;
+/// The share index of the secret itself.
+pub const SECRET_INDEX: char = 's';
+/// The indices of generated shares, in order.
+pub const SHARE_INDICES: &str = "acdefghjklmnpqrtuvwxyz023456789";
+
+fn polymod(values: &[u8], long: bool) -> u128 {
+	const GEN: [u128; 5] = [
+		0x19dc500ce73fde210, 0x1bfae00def77fe529, 0x1fbd920fffe7bee52,
+		0x1739640bdeee3fdad, 0x07729a039cfc75f5a,
+	];
+	const LONG_GEN: [u128; 5] = [
+		0x3d59d273535ea62d897, 0x7a9becb6361c6c51507, 0x543f9b7e6c38d8a2a0e,
+		0x0c577eaeccf1990d13c, 0x1887f74f8dc71b10651,
+	];
+	let (gen, shift) = if long { (&LONG_GEN, 70) } else { (&GEN, 60) };
+	let mut residue: u128 = 0x23181b3;
+	for v in values {
+		let b = residue >> shift;
+		residue = (residue & ((1 << shift) - 1)) << 5 ^ *v as u128;
+		for (i, g) in gen.iter().enumerate() {
+			if b >> i & 1 == 1 {
+				residue ^= g;
+			}
+		}
+	}
+	residue
+}
+
+fn checksum_params(data_len: usize) -> (bool, usize, u128) {
+	if data_len <= 80 {
+		(false, SHORT_CHECKSUM_LENGTH, MS32_CONST)
+	} else {
+		(true, LONG_CHECKSUM_LENGTH, MS32_LONG_CONST)
+	}
+}
+
+fn char_value(c: char) -> Result<u8, String> {
+	CHARSET.iter().position(|x| *x as char == c).map(|i| i as u8)
+		.ok_or_else(|| format!("invalid character: {}", c))
+}
+
+/// A codex32 share or secret.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Share {
+	/// The threshold, or 0 for an unshared secret.
+	pub threshold: u8,
+	pub identifier: String,
+	pub index: char,
+	/// The payload in 5-bit values.
+	pub payload: Vec<u8>,
+}
+
+impl Share {
+	/// Create a share from a secret, padding it with zero bits.
+	pub fn from_secret(threshold: u8, identifier: &str, index: char, secret: &[u8]) -> Result<Share, String> {
+		if !(16..=64).contains(&secret.len()) {
+			return Err("the secret must be between 16 and 64 bytes".to_owned());
+		}
+		let nb_values = (secret.len() * 8).div_ceil(5);
+		let bit = |i: usize| i < secret.len() * 8 && secret[i / 8] >> (7 - i % 8) & 1 == 1;
+		let payload = (0..nb_values).map(|v| {
+			(0..5).fold(0u8, |acc, b| acc << 1 | bit(v * 5 + b) as u8)
+		}).collect();
+		Share::new(threshold, identifier, index, payload)
+	}
+
+	fn new(threshold: u8, identifier: &str, index: char, payload: Vec<u8>) -> Result<Share, String> {
+		if threshold == 1 || threshold > 9 {
+			return Err("the threshold must be 0 or between 2 and 9".to_owned());
+		}
+		if threshold == 0 && index != SECRET_INDEX {
+			return Err("unshared secrets must have share index s".to_owned());
+		}
+		let identifier = identifier.to_lowercase();
+		if identifier.chars().count() != 4 {
+			return Err("the identifier must be 4 characters".to_owned());
+		}
+		for c in identifier.chars().chain(Some(index)) {
+			char_value(c)?;
+		}
+		Ok(Share {
+			threshold: threshold,
+			identifier: identifier,
+			index: index,
+			payload: payload,
+		})
+	}
+
+	/// Parse a codex32 string and verify its checksum.
+	pub fn parse(s: &str) -> Result<Share, String> {
+		if s.chars().any(|c| c.is_lowercase()) && s.chars().any(|c| c.is_uppercase()) {
+			return Err("mixed case".to_owned());
+		}
+		let s = s.to_lowercase();
+		let data = match s.rfind('1') {
+			Some(pos) if &s[..pos] == HRP => &s[pos + 1..],
+			_ => return Err(format!("a codex32 string must start with {}1", HRP)),
+		};
+		let (long, checksum_len, constant) = match s.len() {
+			48..=93 => (false, SHORT_CHECKSUM_LENGTH, MS32_CONST),
+			125..=127 => (true, LONG_CHECKSUM_LENGTH, MS32_LONG_CONST),
+			n => return Err(format!("invalid length: {}, must be 48 to 93 or 125 to 127 characters", n)),
+		};
+		let values = data.chars().map(char_value).collect::<Result<Vec<_>, _>>()?;
+		if polymod(&values, long) != constant {
+			return Err("invalid checksum".to_owned());
+		}
+
+		let mut chars = data.chars();
+		let threshold = chars.next().unwrap().to_digit(10)
+			.ok_or("the threshold must be a digit")? as u8;
+		let identifier = chars.by_ref().take(4).collect::<String>();
+		let index = chars.next().unwrap();
+		let share = Share::new(threshold, &identifier, index, values[6..values.len() - checksum_len].to_vec())?;
+		if share.payload.len() * 5 % 8 > 4 {
+			return Err("invalid payload length".to_owned());
+		}
+		Ok(share)
+	}
+
+	/// All 5-bit values of the share, without the checksum.
+	fn values(&self) -> Vec<u8> {
+		let mut ret = vec![char_value(std::char::from_digit(self.threshold as u32, 10).unwrap()).unwrap()];
+		ret.extend(self.identifier.chars().map(|c| char_value(c).unwrap()));
+		ret.push(char_value(self.index).unwrap());
+		ret.extend(&self.payload);
+		ret
+	}
+
+	/// All 5-bit values of the share, including the checksum.
+	fn values_with_checksum(&self) -> Vec<u8> {
+		let mut values = self.values();
+		let (long, checksum_len, constant) = checksum_params(values.len());
+		let mut padded = values.clone();
+		padded.extend(vec![0; checksum_len]);
+		let residue = polymod(&padded, long) ^ constant;
+		values.extend((0..checksum_len).map(|i| (residue >> (5 * (checksum_len - 1 - i)) & 31) as u8));
+		values
+	}
+
+	/// The secret bytes in the payload.
+	pub fn secret(&self) -> Vec<u8> {
+		let bit = |i: usize| self.payload[i / 5] >> (4 - i % 5) & 1;
+		(0..self.payload.len() * 5 / 8).map(|byte| {
+			(0..8).fold(0u8, |acc, b| acc << 1 | bit(byte * 8 + b))
+		}).collect()
+	}
+}
+
+impl fmt::Display for Share {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}1", HRP)?;
+		for v in self.values_with_checksum() {
+			write!(f, "{}", CHARSET[v as usize] as char)?;
+		}
+		Ok(())
+	}
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct ShareInfo {
+	pub threshold: u8,
+	pub identifier: String,
+	pub share_index: char,
+	/// The secret, or the share data for shares other than s.
+	pub payload: HexBytes,
+}
+
+impl Share {
+	pub fn get_info(&self) -> ShareInfo {
+		ShareInfo {
+			threshold: self.threshold,
+			identifier: self.identifier.clone(),
+			share_index: self.index,
+			payload: self.secret().into(),
+		}
+	}
+}
+
+fn gf32_mul(a: u8, b: u8) -> u8 {
+	let mut ret = 0;
+	let mut a = a;
+	for i in 0..5 {
+		if b >> i & 1 == 1 {
+			ret ^= a;
+		}
+		// Multiply by x modulo x^5 + x^3 + 1.
+		a <<= 1;
+		if a & 32 != 0 {
+			a ^= 0b101001;
+		}
+	}
+	ret
+}
+
+fn gf32_inv(a: u8) -> u8 {
+	// a^30 = 1 for all non-zero a.
+	(0..29).fold(a, |acc, _| gf32_mul(acc, a))
+}
+
+/// Interpolate the shares at the given index.
+fn interpolate(shares: &[Share], index: char) -> Share {
+	let x = char_value(index).unwrap();
+	let xs = shares.iter().map(|s| char_value(s.index).unwrap()).collect::<Vec<_>>();
+	let values = shares.iter().map(|s| s.values_with_checksum()).collect::<Vec<_>>();
+
+	let mut result = vec![0u8; values[0].len()];
+	for (i, share_values) in values.iter().enumerate() {
+		let mut coef = 1;
+		for (j, xj) in xs.iter().enumerate() {
+			if i != j {
+				coef = gf32_mul(coef, gf32_mul(x ^ xj, gf32_inv(xs[i] ^ xj)));
+			}
+		}
+		for (r, v) in result.iter_mut().zip(share_values.iter()) {
+			*r ^= gf32_mul(coef, *v);
+		}
+	}
+
+	let checksum_len = checksum_params(shares[0].values().len()).1;
+	Share {
+		threshold: shares[0].threshold,
+		identifier: shares[0].identifier.clone(),
+		index: index,
+		payload: result[6..result.len() - checksum_len].to_vec(),
+	}
+}
+
+/// The identifier recommended by BIP-93: the first 20 bits of the BIP-32 fingerprint
+/// of the secret.
+pub fn fingerprint_identifier(secret: &[u8]) -> Result<String, bip32::Error> {
+	let fp = bip32::Xpriv::new_master(NetworkKind::Main, secret)?.fingerprint(&SECP).to_bytes();
+	let bits = (fp[0] as u32) << 12 | (fp[1] as u32) << 4 | (fp[2] as u32) >> 4;
+	Ok((0..4).map(|i| CHARSET[(bits >> (15 - 5 * i) & 31) as usize] as char).collect())
+}
+
+/// Split a secret into the given number of shares.
+///
+/// A threshold of 1 returns the unshared secret with threshold 0.
+pub fn split<R: RngCore>(
+	secret: &[u8],
+	threshold: u8,
+	count: u8,
+	identifier: &str,
+	rng: &mut R,
+) -> Result<Vec<Share>, String> {
+	if threshold == 1 {
+		return Ok(vec![Share::from_secret(0, identifier, SECRET_INDEX, secret)?]);
+	}
+	if !(2..=9).contains(&threshold) {
+		return Err("the threshold must be between 1 and 9".to_owned());
+	}
+	if count < threshold || count as usize > SHARE_INDICES.len() {
+		return Err(format!("the number of shares must be between the threshold and {}", SHARE_INDICES.len()));
+	}
+
+	let secret = Share::from_secret(threshold, identifier, SECRET_INDEX, secret)?;
+	let mut indices = SHARE_INDICES.chars();
+	let mut base = vec![secret.clone()];
+	for index in indices.by_ref().take(threshold as usize - 1) {
+		let mut payload = vec![0u8; secret.payload.len()];
+		rng.fill_bytes(&mut payload);
+		base.push(Share::new(threshold, identifier, index, payload.iter().map(|v| v & 31).collect())?);
+	}
+	let mut ret = base[1..].to_vec();
+	ret.extend(indices.take((count - threshold + 1) as usize).map(|i| interpolate(&base, i)));
+	Ok(ret)
+}
+
+/// Recover the secret from enough shares.
+pub fn combine(shares: &[Share]) -> Result<Share, String> {
+	let first = shares.first().ok_or("no shares given")?;
+	for share in shares {
+		if share.threshold != first.threshold || share.identifier != first.identifier {
+			return Err("the shares don't belong to the same secret".to_owned());
+		}
+		if share.payload.len() != first.payload.len() {
+			return Err("the shares have different lengths".to_owned());
+		}
+	}
+	if let Some(secret) = shares.iter().find(|s| s.index == SECRET_INDEX) {
+		return Ok(secret.clone());
+	}
+	let mut unique = Vec::<Share>::new();
+	for share in shares {
+		match unique.iter().find(|s| s.index == share.index) {
+			Some(s) if s != share => return Err(format!("conflicting shares with index {}", share.index)),
+			Some(_) => {}
+			None => unique.push(share.clone()),
+		}
+	}
+	if unique.len() < first.threshold as usize {
+		return Err(format!("need {} shares, only have {}", first.threshold, unique.len()));
+	}
+	unique.truncate(first.threshold as usize);
+	Ok(interpolate(&unique, SECRET_INDEX))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use secp256k1::rand::thread_rng;
+
+	#[test]
+	fn test_codex32() {
+		// Test vectors from BIP-93.
+		let s = Share::parse("ms10testsxxxxxxxxxxxxxxxxxxxxxxxxxx4nzvca9cmczlw").unwrap();
+		assert_eq!(hex::encode(&s.secret()), "318c6318c6318c6318c6318c6318c631");
+		assert_eq!(s.to_string(), "ms10testsxxxxxxxxxxxxxxxxxxxxxxxxxx4nzvca9cmczlw");
+		assert!(Share::parse("ms10testsxxxxxxxxxxxxxxxxxxxxxxxxxx4nzvca9cmczlx").is_err());
+		// Too short, and between the short and long lengths.
+		for len in [47, 94, 124] {
+			let s = format!("ms10tests{}", "x".repeat(len - 9));
+			assert!(Share::parse(&s).unwrap_err().starts_with("invalid length"), "len {}", len);
+		}
+
+		let a = Share::parse("MS12NAMEA320ZYXWVUTSRQPNMLKJHGFEDCAXRPP870HKKQRM").unwrap();
+		let c = Share::parse("MS12NAMECACDEFGHJKLMNPQRSTUVWXYZ023FTR2GDZMPY6PN").unwrap();
+		let secret = combine(&[a.clone(), c.clone()]).unwrap();
+		assert_eq!(secret.to_string(), "ms12names6xqguzttxkeqnjsjzv4jv3nz5k3kwgsphuh6evw");
+		assert_eq!(hex::encode(&secret.secret()), "d1808e096b35b209ca12132b264662a5");
+		assert!(combine(std::slice::from_ref(&a)).is_err());
+		assert_eq!(combine(&[a.clone(), secret.clone()]).unwrap(), secret);
+		assert!(combine(&[s, a]).is_err());
+
+		let secret = (0u8..32).collect::<Vec<_>>();
+		let shares = split(&secret, 3, 5, "cash", &mut thread_rng()).unwrap();
+		assert_eq!(shares.len(), 5);
+		let parsed = shares.iter().map(|s| Share::parse(&s.to_string()).unwrap()).collect::<Vec<_>>();
+		assert_eq!(combine(&[parsed[4].clone(), parsed[0].clone(), parsed[2].clone()]).unwrap().secret(), secret);
+
+		// A 512-bit secret uses the long checksum.
+		let secret = vec![0xffu8; 64];
+		let share = &split(&secret, 2, 2, "lxng", &mut thread_rng()).unwrap()[0];
+		assert_eq!(share.to_string().len(), 127);
+		assert_eq!(&Share::parse(&share.to_string()).unwrap(), share);
+	}
+}
@@ -0,0 +1,276 @@
+use bip39lib::{Language, Mnemonic};
+use bitcoin::bip32::{self, ChildNumber, DerivationPath};
+use bitcoin::hashes::{hmac, sha512, Hash, HashEngine};
+use bitcoin::Network;
+use secp256k1::rand::RngCore;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::char::canonical_combining_class;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::bip32::{format_key_origin, ScriptType, SLIP132_VERSIONS};
+use crate::bip39::SeedInfo;
+use crate::{GetInfo, SECP};
+
+/// The Electrum v2 seed types.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub enum SeedType {
+	#[serde(rename = "standard")]
+	Standard,
+	#[serde(rename = "segwit")]
+	Segwit,
+	#[serde(rename = "2fa")]
+	TwoFactor,
+	#[serde(rename = "2fa_segwit")]
+	TwoFactorSegwit,
+}
+
+impl SeedType {
+	pub const ALL: &'static [SeedType] = &[
+		SeedType::Standard,
+		SeedType::Segwit,
+		SeedType::TwoFactor,
+		SeedType::TwoFactorSegwit,
+	];
+
+	pub fn from_name(name: &str) -> Option<SeedType> {
+		SeedType::ALL.iter().find(|t| t.name() == name).copied()
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			SeedType::Standard => "standard",
+			SeedType::Segwit => "segwit",
+			SeedType::TwoFactor => "2fa",
+			SeedType::TwoFactorSegwit => "2fa_segwit",
+		}
+	}
+
+	/// The prefix of the hex-encoded version hash of seeds of this type.
+	pub fn prefix(self) -> &'static str {
+		match self {
+			SeedType::Standard => "01",
+			SeedType::Segwit => "100",
+			SeedType::TwoFactor => "101",
+			SeedType::TwoFactorSegwit => "102",
+		}
+	}
+
+	/// The script type of the wallets Electrum creates for this seed type.
+	///
+	/// 2fa wallets are 2-of-3 multisig with a TrustedCoin cosigner.
+	pub fn script_type(self) -> ScriptType {
+		match self {
+			SeedType::Standard => ScriptType::P2pkh,
+			SeedType::Segwit => ScriptType::P2wpkh,
+			SeedType::TwoFactor => ScriptType::P2sh,
+			SeedType::TwoFactorSegwit => ScriptType::P2wsh,
+		}
+	}
+
+	/// The derivation paths of the keystores Electrum derives from the seed.
+	///
+	/// 2fa wallets have two keys from the seed, only the first one is used to sign.
+	pub fn paths(self) -> Vec<DerivationPath> {
+		let hardened = |i| ChildNumber::from_hardened_idx(i).unwrap();
+		match self {
+			SeedType::Standard => vec![DerivationPath::master()],
+			SeedType::Segwit => vec![vec![hardened(0)].into()],
+			SeedType::TwoFactor | SeedType::TwoFactorSegwit => {
+				vec![vec![hardened(0)].into(), vec![hardened(1)].into()]
+			}
+		}
+	}
+}
+
+/// Whether the character is in one of the CJK scripts, which are written without spaces.
+fn is_cjk(c: char) -> bool {
+	matches!(c as u32,
+		0x1100..=0x11ff | 0x2e80..=0x2fdf | 0x3000..=0x31ff | 0x3200..=0x33ff | 0x3400..=0x4dbf
+			| 0x4e00..=0x9fff | 0xa960..=0xa97f | 0xac00..=0xd7ff | 0xf900..=0xfaff
+			| 0xfe30..=0xfe4f | 0xff00..=0xffef | 0x20000..=0x2fa1f
+	)
+}
+
+/// Normalize the mnemonic or passphrase the way Electrum does.
+///
+/// The text is NFKD-normalized and lowercased, accents are removed and whitespace is
+/// collapsed to single spaces, except between CJK characters where it's removed.
+pub fn normalize_text(s: &str) -> String {
+	let s = s.nfkd().collect::<String>().to_lowercase();
+	let s = s.chars().filter(|c| canonical_combining_class(*c) == 0).collect::<String>();
+	let chars = s.split_whitespace().collect::<Vec<_>>().join(" ").chars().collect::<Vec<_>>();
+	chars.iter().enumerate().filter(|(i, c)| {
+		!(c.is_whitespace() && is_cjk(chars[i - 1]) && is_cjk(chars[i + 1]))
+	}).map(|(_, c)| *c).collect()
+}
+
+/// The hex-encoded version hash that determines the seed type.
+fn version_hash(mnemonic: &str) -> String {
+	let mut engine = hmac::HmacEngine::<sha512::Hash>::new(b"Seed version");
+	engine.input(normalize_text(mnemonic).as_bytes());
+	hex::encode(&hmac::Hmac::<sha512::Hash>::from_engine(engine)[..])
+}
+
+/// Detect the type of an Electrum v2 seed, [None] if it isn't one.
+pub fn seed_type(mnemonic: &str) -> Option<SeedType> {
+	let hash = version_hash(mnemonic);
+	SeedType::ALL.iter().find(|t| hash.starts_with(t.prefix())).copied()
+}
+
+/// Derive the BIP-32 seed from an Electrum mnemonic and passphrase.
+pub fn to_seed(mnemonic: &str, passphrase: &str) -> [u8; 64] {
+	let password = normalize_text(mnemonic);
+	let salt = format!("electrum{}", normalize_text(passphrase));
+
+	let mut seed = [0u8; 64];
+	pbkdf2::pbkdf2_hmac::<sha2::Sha512>(password.as_bytes(), salt.as_bytes(), 2048, &mut seed);
+	seed
+}
+
+/// Divide the big-endian number in place and return the remainder.
+fn divmod(num: &mut [u8], divisor: u32) -> u32 {
+	let mut rem = 0u32;
+	for b in num.iter_mut() {
+		let cur = (rem << 8) | *b as u32;
+		*b = (cur / divisor) as u8;
+		rem = cur % divisor;
+	}
+	rem
+}
+
+/// Encode a big-endian number as words, least significant word first.
+fn encode_words(num: &[u8], language: Language) -> String {
+	let mut num = num.to_vec();
+	let mut words = Vec::new();
+	while num.iter().any(|b| *b != 0) {
+		words.push(language.word_list()[divmod(&mut num, 2048) as usize]);
+	}
+	words.join(" ")
+}
+
+/// Generate a new 12-word Electrum seed of the given type.
+///
+/// Like Electrum, seeds that happen to be valid BIP-39 mnemonics are skipped.
+pub fn generate<R: RngCore>(seed_type: SeedType, language: Language, rng: &mut R) -> String {
+	// 132 bits of entropy, with at least one bit in the top word so that we get 12 words.
+	let mut entropy = [0u8; 17];
+	while entropy[0] == 0 && entropy[1] < 2 {
+		rng.fill_bytes(&mut entropy);
+		entropy[0] &= 0x0f;
+	}
+
+	loop {
+		// Increment the nonce.
+		for b in entropy.iter_mut().rev() {
+			*b = b.wrapping_add(1);
+			if *b != 0 {
+				break;
+			}
+		}
+
+		let mnemonic = encode_words(&entropy, language);
+		if Mnemonic::parse_in(language, &mnemonic).is_ok() {
+			continue;
+		}
+		if version_hash(&mnemonic).starts_with(seed_type.prefix()) {
+			return mnemonic;
+		}
+	}
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct KeystoreInfo {
+	pub path: DerivationPath,
+	/// The key with its origin, to use in descriptors.
+	pub key: String,
+	pub xpub: bip32::Xpub,
+	/// The xpub in the SLIP-132 version Electrum shows, like zpub for segwit wallets.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub slip132_xpub: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub receive_descriptor: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub change_descriptor: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct ElectrumSeedInfo {
+	pub mnemonic: String,
+	pub seed_type: SeedType,
+	pub passphrase: String,
+	pub seed: SeedInfo,
+	pub script_type: ScriptType,
+	pub keystores: Vec<KeystoreInfo>,
+}
+
+/// Derive the seed and keystores of an Electrum mnemonic.
+pub fn seed_info(mnemonic: &str, passphrase: &str, network: Network) -> Result<ElectrumSeedInfo, String> {
+	let seed_type = seed_type(mnemonic).ok_or("not an Electrum seed")?;
+	if seed_type == SeedType::TwoFactor && normalize_text(mnemonic).split(' ').count() > 12 {
+		return Err("2fa seeds from before Electrum 3.0 are not supported".to_owned());
+	}
+
+	let seed = to_seed(mnemonic, passphrase).get_info(network);
+	let master = seed.bip32_xpriv;
+	let master_fingerprint = master.fingerprint(&SECP);
+	let script_type = seed_type.script_type();
+	let version = SLIP132_VERSIONS.iter().find(|v| {
+		v.network == master.network && v.script_type == Some(script_type)
+	});
+	let keystores = seed_type.paths().into_iter().map(|path| {
+		let xpriv = master.derive_priv(&SECP, &path).map_err(|e| e.to_string())?;
+		let xpub = bip32::Xpub::from_priv(&SECP, &xpriv);
+		let key = format!("{}{}", format_key_origin(master_fingerprint, &path), xpub);
+		let descriptor = |chain: u32| match script_type {
+			ScriptType::P2pkh => Some(format!("pkh({}/{}/*)", key, chain)),
+			ScriptType::P2wpkh => Some(format!("wpkh({}/{}/*)", key, chain)),
+			_ => None,
+		};
+		Ok(KeystoreInfo {
+			path: path,
+			xpub: xpub,
+			slip132_xpub: version.map(|v| v.encode_xpub(&xpub)),
+			receive_descriptor: descriptor(0),
+			change_descriptor: descriptor(1),
+			key: key,
+		})
+	}).collect::<Result<Vec<_>, String>>()?;
+
+	Ok(ElectrumSeedInfo {
+		mnemonic: normalize_text(mnemonic),
+		seed_type: seed_type,
+		passphrase: passphrase.to_owned(),
+		seed: seed,
+		script_type: script_type,
+		keystores: keystores,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_electrum_seed() {
+		let mnemonic = "wild father tree among universe such mobile favorite target dynamic credit identify";
+		assert_eq!(seed_type(mnemonic), Some(SeedType::Segwit));
+		assert_eq!(seed_type(" Wild  father tree among universe such mobile favorite target dynamic credit identify"),
+			Some(SeedType::Segwit));
+		assert_eq!(hex::encode(&to_seed(mnemonic, "")[..]),
+			"aac2a6302e48577ab4b46f23dbae0774e2e62c796f797d0a1b5faeb528301e3064342dafb79069e7c4c6b8c38ae11d7a973bec0d4f70626f8cc5184a8d0b0756");
+		assert_eq!(hex::encode(&to_seed(mnemonic, "Did you ever hear the tragedy of Darth Plagueis the Wise?")[..]),
+			"4aa29f2aeb0127efb55138ab9e7be83b36750358751906f86c662b21a1ea1370f949e6d1a12fa56d3d93cadda93038c76ac8118597364e46f5156fde6183c82f");
+
+		// BIP-39 mnemonics are generally not Electrum seeds.
+		assert_eq!(seed_type("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"), None);
+
+		let mut rng = secp256k1::rand::thread_rng();
+		for &t in SeedType::ALL {
+			let mnemonic = generate(t, Language::English, &mut rng);
+			assert_eq!(mnemonic.split(' ').count(), 12);
+			assert_eq!(seed_type(&mnemonic), Some(t));
+			let info = seed_info(&mnemonic, "", Network::Bitcoin).unwrap();
+			assert_eq!(info.keystores.len(), t.paths().len());
+		}
+	}
+}
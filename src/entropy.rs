@@ -0,0 +1,181 @@
+use std::collections::BTreeSet;
+
+/// The ranks and suits of the cards, in the order used to number them.
+const CARD_RANKS: &str = "A23456789TJQK";
+const CARD_SUITS: &str = "SHDC";
+
+/// Entropy collected from a physical source like dice, cards or coins.
+///
+/// Each outcome is converted to bits without bias by splitting the possible outcomes
+/// into blocks with a power-of-two size. A d6 roll of 1 to 4 gives two bits and a roll
+/// of 5 or 6 gives one bit, so a d6 gives 1.67 bits per roll on average.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PhysicalEntropy {
+	/// The number of dice rolls, cards or coin flips.
+	pub inputs: usize,
+	bits: Vec<bool>,
+}
+
+impl PhysicalEntropy {
+	fn new() -> PhysicalEntropy {
+		PhysicalEntropy {
+			inputs: 0,
+			bits: Vec::new(),
+		}
+	}
+
+	/// Add an outcome between 0 and the number of possible outcomes.
+	fn push(&mut self, value: u32, outcomes: u32) {
+		debug_assert!(value < outcomes);
+		self.inputs += 1;
+		let mut offset = 0;
+		let mut remaining = outcomes;
+		while remaining > 0 {
+			let block = 1 << (31 - remaining.leading_zeros());
+			if value < offset + block {
+				let value = value - offset;
+				for i in (0..block.trailing_zeros()).rev() {
+					self.bits.push((value >> i) & 1 == 1);
+				}
+				return;
+			}
+			offset += block;
+			remaining -= block;
+		}
+	}
+
+	/// Parse dice rolls from 1 to the number of sides.
+	///
+	/// Rolls are separated by spaces or commas, or can be given without separators
+	/// for dice with less than 10 sides.
+	pub fn from_dice(rolls: &str, sides: u32) -> Result<PhysicalEntropy, String> {
+		if sides < 2 {
+			return Err("dice need at least 2 sides".to_owned());
+		}
+		let rolls = if rolls.contains(|c: char| c.is_whitespace() || c == ',') {
+			rolls.split(|c: char| c.is_whitespace() || c == ',')
+				.filter(|r| !r.is_empty()).map(|r| r.to_owned()).collect::<Vec<_>>()
+		} else if sides < 10 {
+			rolls.chars().map(|c| c.to_string()).collect()
+		} else {
+			vec![rolls.to_owned()]
+		};
+
+		let mut ret = PhysicalEntropy::new();
+		for roll in rolls {
+			let value = roll.parse::<u32>().map_err(|_| format!("invalid dice roll: {}", roll))?;
+			if value < 1 || value > sides {
+				return Err(format!("invalid roll for a {}-sided die: {}", sides, value));
+			}
+			ret.push(value - 1, sides);
+		}
+		Ok(ret)
+	}
+
+	/// Parse cards drawn from shuffled 52-card decks, like "AS 10H QD 2C / 7S KH".
+	///
+	/// Ranks are A, 2-10 (or T), J, Q and K, suits are S, H, D and C. Each card can be
+	/// drawn only once from a deck, so each card gives less entropy than the one before.
+	/// A full deck gives about 179 bits, multiple decks are separated by a slash.
+	pub fn from_cards(cards: &str) -> Result<PhysicalEntropy, String> {
+		let mut ret = PhysicalEntropy::new();
+		for deck in cards.split('/') {
+			ret.push_deck(deck)?;
+		}
+		Ok(ret)
+	}
+
+	fn push_deck(&mut self, deck: &str) -> Result<(), String> {
+		let mut drawn = BTreeSet::new();
+		for card in deck.split(|c: char| c.is_whitespace() || c == ',').filter(|c| !c.is_empty()) {
+			let upper = card.to_uppercase().replace("10", "T");
+			let mut chars = upper.chars();
+			let (rank, suit) = match (chars.next(), chars.next(), chars.next()) {
+				(Some(r), Some(s), None) => (r, s),
+				_ => return Err(format!("invalid card: {}", card)),
+			};
+			let rank = CARD_RANKS.find(rank).ok_or_else(|| format!("invalid card rank: {}", card))?;
+			let suit = CARD_SUITS.find(suit).ok_or_else(|| format!("invalid card suit: {}", card))?;
+			let index = (suit * CARD_RANKS.len() + rank) as u32;
+
+			// Number the card among the ones still in the deck.
+			let value = index - drawn.range(..index).count() as u32;
+			if !drawn.insert(index) {
+				return Err(format!("card drawn twice from the same deck: {}", card));
+			}
+			self.push(value, 52 - (drawn.len() as u32 - 1));
+		}
+		Ok(())
+	}
+
+	/// Parse coin flips as H and T or as 1 and 0, optionally separated by spaces.
+	pub fn from_coins(flips: &str) -> Result<PhysicalEntropy, String> {
+		let mut ret = PhysicalEntropy::new();
+		for c in flips.chars().filter(|c| !c.is_whitespace() && *c != ',') {
+			match c {
+				'H' | 'h' | '1' => ret.push(1, 2),
+				'T' | 't' | '0' => ret.push(0, 2),
+				_ => return Err(format!("invalid coin flip: {}", c)),
+			}
+		}
+		Ok(ret)
+	}
+
+	/// The number of unbiased bits collected.
+	pub fn nb_bits(&self) -> usize {
+		self.bits.len()
+	}
+
+	/// Take the given number of bytes of entropy.
+	///
+	/// Fails if not enough bits were collected.
+	pub fn to_bytes(&self, nb_bytes: usize) -> Result<Vec<u8>, String> {
+		if self.bits.len() < nb_bytes * 8 {
+			return Err(format!(
+				"not enough entropy: {} bits needed, but only {} provided",
+				nb_bytes * 8, self.bits.len(),
+			));
+		}
+		Ok(self.bits[..nb_bytes * 8].chunks(8).map(|byte| {
+			byte.iter().fold(0u8, |acc, b| (acc << 1) | *b as u8)
+		}).collect())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_physical_entropy() {
+		// 1 and 4 give two bits, 5 and 6 give one bit.
+		let e = PhysicalEntropy::from_dice("1456", 6).unwrap();
+		assert_eq!(e.bits, vec![false, false, true, true, false, true]);
+		assert_eq!(e, PhysicalEntropy::from_dice("1 4, 5 6", 6).unwrap());
+		assert!(PhysicalEntropy::from_dice("1457", 6).is_err());
+		assert_eq!(PhysicalEntropy::from_dice("16 17 20", 20).unwrap().nb_bits(), 8);
+
+		let e = PhysicalEntropy::from_coins("HHTH HTTT").unwrap();
+		assert_eq!(e.to_bytes(1).unwrap(), vec![0xd8]);
+		assert!(e.to_bytes(2).is_err());
+
+		// The king of clubs is card 51 of 52, the ace of spades 0 of 51 and
+		// the ten of hearts 21 of 50, as the ace of spades was drawn before it.
+		let e = PhysicalEntropy::from_cards("KC AS kc").unwrap_err();
+		assert_eq!(e, "card drawn twice from the same deck: kc");
+		let e = PhysicalEntropy::from_cards("KC AS 10h").unwrap();
+		assert_eq!(e.inputs, 3);
+		assert_eq!(e.bits, vec![
+			true, true,
+			false, false, false, false, false,
+			true, false, true, false, true,
+		]);
+		let deck = CARD_SUITS.chars().flat_map(|s| CARD_RANKS.chars().map(move |r| format!("{}{}", r, s)))
+			.collect::<Vec<_>>();
+		// An unshuffled deck always draws the first card left, which is in the largest block.
+		let e = PhysicalEntropy::from_cards(&deck.join(" ")).unwrap();
+		assert_eq!((e.inputs, e.nb_bits()), (52, 203));
+		let e = PhysicalEntropy::from_cards(&format!("{0} / {0}", deck.join(" "))).unwrap();
+		assert_eq!((e.inputs, e.nb_bits()), (104, 406));
+	}
+}
@@ -0,0 +1,230 @@
+use std::{fmt, io};
+use std::str::FromStr;
+
+use bitcoin::hashes::{hash160, hmac, ripemd160, sha256, sha256d, sha512, siphash24, Hash, HashEngine};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Algorithm {
+	Sha256,
+	Sha256d,
+	Sha512,
+	Ripemd160,
+	Hash160,
+	HmacSha256,
+	HmacSha512,
+	Siphash,
+}
+
+impl Algorithm {
+	/// Whether this algorithm requires a key.
+	pub fn needs_key(self) -> bool {
+		matches!(self, Algorithm::HmacSha256 | Algorithm::HmacSha512 | Algorithm::Siphash)
+	}
+
+	/// Whether this hash is conventionally displayed in reverse byte order, like txids.
+	pub fn display_backward(self) -> bool {
+		self == Algorithm::Sha256d
+	}
+}
+
+impl fmt::Display for Algorithm {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Algorithm::Sha256 => "sha256",
+			Algorithm::Sha256d => "sha256d",
+			Algorithm::Sha512 => "sha512",
+			Algorithm::Ripemd160 => "ripemd160",
+			Algorithm::Hash160 => "hash160",
+			Algorithm::HmacSha256 => "hmac-sha256",
+			Algorithm::HmacSha512 => "hmac-sha512",
+			Algorithm::Siphash => "siphash",
+		})
+	}
+}
+
+impl FromStr for Algorithm {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Algorithm, String> {
+		match s {
+			"sha256" => Ok(Algorithm::Sha256),
+			"sha256d" => Ok(Algorithm::Sha256d),
+			"sha512" => Ok(Algorithm::Sha512),
+			"ripemd160" => Ok(Algorithm::Ripemd160),
+			"hash160" => Ok(Algorithm::Hash160),
+			"hmac-sha256" => Ok(Algorithm::HmacSha256),
+			"hmac-sha512" => Ok(Algorithm::HmacSha512),
+			"siphash" => Ok(Algorithm::Siphash),
+			_ => Err(format!("unknown hash algorithm: {}", s)),
+		}
+	}
+}
+
+enum Engine {
+	Sha256(sha256::HashEngine),
+	Sha256d(sha256::HashEngine),
+	Sha512(sha512::HashEngine),
+	Ripemd160(ripemd160::HashEngine),
+	Hash160(sha256::HashEngine),
+	HmacSha256(hmac::HmacEngine<sha256::Hash>),
+	HmacSha512(hmac::HmacEngine<sha512::Hash>),
+	Siphash(siphash24::HashEngine),
+}
+
+/// An incremental hasher for any of the supported algorithms.
+///
+/// It implements [io::Write] so that large inputs can be streamed into it
+/// using [io::copy].
+pub struct Hasher {
+	engine: Engine,
+}
+
+impl Hasher {
+	/// Create a new hasher.
+	///
+	/// HMAC requires a key of any length, SipHash-2-4 a 16-byte key.
+	pub fn new(algorithm: Algorithm, key: Option<&[u8]>) -> Result<Hasher, String> {
+		if algorithm.needs_key() != key.is_some() {
+			return Err(if algorithm.needs_key() {
+				format!("{} requires a key", algorithm)
+			} else {
+				format!("{} doesn't use a key", algorithm)
+			});
+		}
+		let engine = match algorithm {
+			Algorithm::Sha256 => Engine::Sha256(sha256::Hash::engine()),
+			Algorithm::Sha256d => Engine::Sha256d(sha256d::Hash::engine()),
+			Algorithm::Sha512 => Engine::Sha512(sha512::Hash::engine()),
+			Algorithm::Ripemd160 => Engine::Ripemd160(ripemd160::Hash::engine()),
+			Algorithm::Hash160 => Engine::Hash160(hash160::Hash::engine()),
+			Algorithm::HmacSha256 => Engine::HmacSha256(hmac::HmacEngine::new(key.unwrap())),
+			Algorithm::HmacSha512 => Engine::HmacSha512(hmac::HmacEngine::new(key.unwrap())),
+			Algorithm::Siphash => {
+				let key = key.unwrap();
+				if key.len() != 16 {
+					return Err("siphash requires a 16-byte key".to_owned());
+				}
+				let mut k0 = [0u8; 8];
+				let mut k1 = [0u8; 8];
+				k0.copy_from_slice(&key[0..8]);
+				k1.copy_from_slice(&key[8..16]);
+				Engine::Siphash(siphash24::HashEngine::with_keys(
+					u64::from_le_bytes(k0), u64::from_le_bytes(k1),
+				))
+			}
+		};
+		Ok(Hasher { engine: engine })
+	}
+
+	/// Create a hasher for a BIP-340 tagged hash: SHA256(SHA256(tag) || SHA256(tag) || data).
+	pub fn tagged(tag: &str) -> Hasher {
+		let tag_hash = sha256::Hash::hash(tag.as_bytes());
+		let mut engine = sha256::Hash::engine();
+		engine.input(&tag_hash[..]);
+		engine.input(&tag_hash[..]);
+		Hasher { engine: Engine::Sha256(engine) }
+	}
+
+	pub fn input(&mut self, data: &[u8]) {
+		match self.engine {
+			Engine::Sha256(ref mut e) => e.input(data),
+			Engine::Sha256d(ref mut e) => e.input(data),
+			Engine::Sha512(ref mut e) => e.input(data),
+			Engine::Ripemd160(ref mut e) => e.input(data),
+			Engine::Hash160(ref mut e) => e.input(data),
+			Engine::HmacSha256(ref mut e) => e.input(data),
+			Engine::HmacSha512(ref mut e) => e.input(data),
+			Engine::Siphash(ref mut e) => e.input(data),
+		}
+	}
+
+	/// Finish hashing and return the hash in natural byte order.
+	///
+	/// SipHash results are returned as the little-endian encoding of the 64-bit value.
+	pub fn finalize(self) -> Vec<u8> {
+		match self.engine {
+			Engine::Sha256(e) => sha256::Hash::from_engine(e)[..].to_vec(),
+			Engine::Sha256d(e) => sha256d::Hash::from_engine(e)[..].to_vec(),
+			Engine::Sha512(e) => sha512::Hash::from_engine(e)[..].to_vec(),
+			Engine::Ripemd160(e) => ripemd160::Hash::from_engine(e)[..].to_vec(),
+			Engine::Hash160(e) => hash160::Hash::from_engine(e)[..].to_vec(),
+			Engine::HmacSha256(e) => hmac::Hmac::<sha256::Hash>::from_engine(e)[..].to_vec(),
+			Engine::HmacSha512(e) => hmac::Hmac::<sha512::Hash>::from_engine(e)[..].to_vec(),
+			Engine::Siphash(e) => siphash24::Hash::from_engine(e).as_u64().to_le_bytes().to_vec(),
+		}
+	}
+}
+
+impl io::Write for Hasher {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.input(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Hash the data with the given algorithm.
+pub fn hash(algorithm: Algorithm, key: Option<&[u8]>, data: &[u8]) -> Result<Vec<u8>, String> {
+	let mut hasher = Hasher::new(algorithm, key)?;
+	hasher.input(data);
+	Ok(hasher.finalize())
+}
+
+/// Calculate a BIP-340 tagged hash.
+pub fn tagged_hash(tag: &str, data: &[u8]) -> sha256::Hash {
+	let mut hasher = Hasher::tagged(tag);
+	hasher.input(data);
+	sha256::Hash::from_slice(&hasher.finalize()).expect("32 bytes")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn hex_hash(algorithm: Algorithm, key: Option<&[u8]>, data: &[u8]) -> String {
+		hex::encode(hash(algorithm, key, data).unwrap())
+	}
+
+	#[test]
+	fn test_hashes() {
+		assert_eq!(
+			hex_hash(Algorithm::Sha256, None, b"abc"),
+			"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+		);
+		assert_eq!(
+			hex_hash(Algorithm::Ripemd160, None, b"abc"),
+			"8eb208f7e05d987a9b044a8e98c6b087f15a0bfc",
+		);
+		assert_eq!(
+			hex_hash(Algorithm::Sha512, None, b"abc"),
+			"ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+			2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+		);
+		// RFC 4231 test case 2
+		assert_eq!(
+			hex_hash(Algorithm::HmacSha256, Some(b"Jefe"), b"what do ya want for nothing?"),
+			"5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+		);
+		// The SipHash-2-4 paper's test vector
+		let key = (0u8..16).collect::<Vec<_>>();
+		let data = (0u8..15).collect::<Vec<_>>();
+		assert_eq!(hex_hash(Algorithm::Siphash, Some(&key), &data), "e545be4961ca29a1");
+		assert!(Hasher::new(Algorithm::Siphash, Some(b"short")).is_err());
+		assert!(Hasher::new(Algorithm::HmacSha512, None).is_err());
+
+		// Streaming gives the same result.
+		let mut hasher = Hasher::new(Algorithm::Hash160, None).unwrap();
+		io::Write::write_all(&mut hasher, b"a").unwrap();
+		io::Write::write_all(&mut hasher, b"bc").unwrap();
+		assert_eq!(hasher.finalize(), hash(Algorithm::Hash160, None, b"abc").unwrap());
+
+		// The leaf hash of OP_TRUE with leaf version 0xc0.
+		assert_eq!(
+			tagged_hash("TapLeaf", &[0xc0, 0x01, 0x51]).to_string(),
+			"a85b2107f791b26a84e7586c28cec7cb61202ed3d01944d832500f363782d675",
+		);
+	}
+}
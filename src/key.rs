@@ -1,5 +1,6 @@
 
-use bitcoin::{secp256k1, Network, PrivateKey, PublicKey, XOnlyPublicKey};
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::{secp256k1, Address, Network, PrivateKey, PublicKey, XOnlyPublicKey};
 use serde::{Deserialize, Serialize};
 
 use crate::{SECP, address, GetInfo, HexBytes};
@@ -101,3 +102,243 @@ impl GetInfo<EcdsaSignatureInfo> for secp256k1::ecdsa::Signature {
 		}
 	}
 }
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct RecoveredKeyInfo {
+	pub recovery_id: u8,
+	pub public_key: PublicKey,
+	pub uncompressed_public_key: PublicKey,
+	pub addresses: address::Addresses,
+	/// The p2pkh address of the uncompressed key, when compression is unknown.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub uncompressed_p2pkh: Option<Address<NetworkUnchecked>>,
+	/// The layout of the signature the key was recovered with, if it was ambiguous.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub signature_layout: Option<SignatureLayout>,
+	/// Whether the key matches the expected address or signature, if any.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub matches: Option<bool>,
+}
+
+impl RecoveredKeyInfo {
+	/// Describe a recovered public key.
+	///
+	/// When it's unknown whether the signer used a compressed key, the compressed key
+	/// is assumed and the uncompressed p2pkh address is added.
+	pub fn new(
+		recovery_id: u8,
+		pubkey: secp256k1::PublicKey,
+		compressed: Option<bool>,
+		network: Network,
+	) -> RecoveredKeyInfo {
+		let public_key = PublicKey {
+			compressed: compressed.unwrap_or(true),
+			inner: pubkey,
+		};
+		let uncompressed = PublicKey::new_uncompressed(pubkey);
+		RecoveredKeyInfo {
+			recovery_id: recovery_id,
+			public_key: public_key,
+			uncompressed_public_key: uncompressed,
+			addresses: address::Addresses::from_pubkey(&public_key, network),
+			uncompressed_p2pkh: if compressed.is_none() {
+				Some(Address::p2pkh(&uncompressed, network).as_unchecked().clone())
+			} else {
+				None
+			},
+			signature_layout: None,
+			matches: None,
+		}
+	}
+
+	/// Whether any of the addresses of the key is the given address.
+	pub fn has_address(&self, addr: &Address<NetworkUnchecked>) -> bool {
+		let a = &self.addresses;
+		[&a.p2pkh, &a.p2wpkh, &a.p2shwpkh, &a.p2tr, &self.uncompressed_p2pkh].iter()
+			.any(|a| a.as_ref() == Some(addr))
+	}
+}
+
+/// Where the recovery id is in a 65-byte recoverable signature.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureLayout {
+	/// A BIP-137 header byte in front, as in signed messages.
+	Header,
+	/// The recovery id after the signature, as 0-3 or 27-28.
+	Trailing,
+}
+
+impl SignatureLayout {
+	pub fn from_name(name: &str) -> Option<SignatureLayout> {
+		match name {
+			"header" => Some(SignatureLayout::Header),
+			"trailing" => Some(SignatureLayout::Trailing),
+			_ => None,
+		}
+	}
+}
+
+/// A compact ECDSA signature with what is known about the key that made it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RecoverableSignature {
+	pub compact: [u8; 64],
+	pub recovery_id: Option<u8>,
+	/// Whether the key is compressed, as given by a BIP-137 header byte.
+	pub compressed: Option<bool>,
+	pub layout: Option<SignatureLayout>,
+}
+
+/// Parse a 64-byte compact signature or a 65-byte signature with a recovery id.
+///
+/// A header byte between 27 and 42 can also be the first byte of r with a trailing
+/// recovery id, so both interpretations are returned unless the layout is given.
+pub fn parse_recoverable_signature(
+	bytes: &[u8],
+	layout: Option<SignatureLayout>,
+) -> Result<Vec<RecoverableSignature>, String> {
+	let compact = |b: &[u8]| {
+		let mut ret = [0u8; 64];
+		ret.copy_from_slice(b);
+		ret
+	};
+	if bytes.len() == 64 {
+		return Ok(vec![RecoverableSignature {
+			compact: compact(bytes),
+			recovery_id: None,
+			compressed: None,
+			layout: None,
+		}]);
+	} else if bytes.len() != 65 {
+		return Err(format!("invalid signature length: {} bytes instead of 64 or 65", bytes.len()));
+	}
+
+	let mut ret = Vec::new();
+	if layout != Some(SignatureLayout::Trailing) && (27..=42).contains(&bytes[0]) {
+		// 27-30 for uncompressed keys, 31-42 for compressed keys of any address type.
+		let header = bytes[0] - 27;
+		ret.push(RecoverableSignature {
+			compact: compact(&bytes[1..]),
+			recovery_id: Some(header & 0x03),
+			compressed: Some(header >= 4),
+			layout: Some(SignatureLayout::Header),
+		});
+	}
+	if layout != Some(SignatureLayout::Header) {
+		let recid = match bytes[64] {
+			id @ 0..=3 => Some(id),
+			id @ 27..=28 => Some(id - 27),
+			_ => None,
+		};
+		if let Some(id) = recid {
+			ret.push(RecoverableSignature {
+				compact: compact(&bytes[..64]),
+				recovery_id: Some(id),
+				compressed: None,
+				layout: Some(SignatureLayout::Trailing),
+			});
+		}
+	}
+	if ret.is_empty() {
+		return Err("can't find a valid recovery id in the signature".to_owned());
+	}
+	Ok(ret)
+}
+
+/// Recover the public keys that can have made the compact signature on the digest.
+///
+/// When no recovery id is given, all four are tried.
+pub fn recover_pubkeys(
+	digest: &secp256k1::Message,
+	signature: &[u8; 64],
+	recovery_id: Option<u8>,
+) -> Result<Vec<(u8, secp256k1::PublicKey)>, String> {
+	let recids = match recovery_id {
+		Some(id) if id > 3 => return Err(format!("invalid recovery id: {}", id)),
+		Some(id) => vec![id],
+		None => vec![0, 1, 2, 3],
+	};
+	Ok(recids.into_iter().filter_map(|id| {
+		let recid = secp256k1::ecdsa::RecoveryId::from_i32(id as i32).ok()?;
+		let sig = secp256k1::ecdsa::RecoverableSignature::from_compact(signature, recid).ok()?;
+		SECP.recover_ecdsa(digest, &sig).ok().map(|pk| (id, pk))
+	}).collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use bitcoin::hashes::Hash;
+
+	/// The signed message test from Bitcoin Core's rpc_signmessage.py.
+	const CORE_ADDRESS: &str = "mpLQjfK79b7CCV4VMJWEWAj5Mpx8Up5zxB";
+	const CORE_MESSAGE: &str = "This is just a test message";
+	const CORE_SIGNATURE: &str = "20d6d59d6e1ee8f7919acbf6420bbc36ea29beb56391cc686feb17f0e7191b44802e15b26d48f330b3dd02c5c8e3a61919bd0a4134628bec16210cd1a46fd4f92d";
+
+	fn core_digest() -> secp256k1::Message {
+		let hash = bitcoin::sign_message::signed_msg_hash(CORE_MESSAGE);
+		secp256k1::Message::from_digest(hash.to_byte_array())
+	}
+
+	#[test]
+	fn test_recover_signed_message() {
+		let sig = parse_recoverable_signature(&hex::decode(CORE_SIGNATURE).unwrap(), None).unwrap();
+		// The signature is a BIP-137 one with a compressed key and recovery id 1.
+		assert_eq!(sig.len(), 1);
+		assert_eq!((sig[0].recovery_id, sig[0].compressed), (Some(1), Some(true)));
+
+		let keys = recover_pubkeys(&core_digest(), &sig[0].compact, sig[0].recovery_id).unwrap();
+		assert_eq!(keys.len(), 1);
+		let info = RecoveredKeyInfo::new(keys[0].0, keys[0].1, sig[0].compressed, Network::Testnet);
+		assert!(info.has_address(&CORE_ADDRESS.parse().unwrap()));
+		assert_eq!(info.uncompressed_p2pkh, None);
+
+		// Without the recovery id, the other candidates have other addresses.
+		let keys = recover_pubkeys(&core_digest(), &sig[0].compact, None).unwrap();
+		let infos = keys.into_iter().map(|(id, pk)| RecoveredKeyInfo::new(id, pk, None, Network::Testnet))
+			.filter(|i| i.has_address(&CORE_ADDRESS.parse().unwrap()))
+			.collect::<Vec<_>>();
+		assert_eq!(infos.len(), 1);
+		assert_eq!(infos[0].recovery_id, 1);
+		assert!(infos[0].uncompressed_p2pkh.is_some());
+	}
+
+	#[test]
+	fn test_recover_invalid_recovery_id() {
+		let sig = parse_recoverable_signature(&hex::decode(CORE_SIGNATURE).unwrap(), None).unwrap();
+		assert_eq!(recover_pubkeys(&core_digest(), &sig[0].compact, Some(4)).unwrap_err(), "invalid recovery id: 4");
+
+		let mut bytes = hex::decode(CORE_SIGNATURE).unwrap();
+		bytes[0] = 43;
+		bytes[64] = 4;
+		assert!(parse_recoverable_signature(&bytes, None).is_err());
+		assert!(parse_recoverable_signature(&bytes[..63], None).is_err());
+	}
+
+	#[test]
+	fn test_recover_ambiguous_layout() {
+		// Find a signature with r starting with a byte that is also a valid header byte.
+		let sk = secp256k1::SecretKey::from_slice(&[0x42; 32]).unwrap();
+		let pk = secp256k1::PublicKey::from_secret_key(&SECP, &sk);
+		let (msg, sig) = (0u8..=255).map(|i| {
+			let msg = secp256k1::Message::from_digest([i; 32]);
+			(msg, SECP.sign_ecdsa_recoverable(&msg, &sk))
+		}).find(|(_, sig)| (27..=42).contains(&sig.serialize_compact().1[0])).unwrap();
+		let (recid, compact) = sig.serialize_compact();
+		let mut bytes = compact.to_vec();
+		bytes.push(recid.to_i32() as u8);
+
+		let sigs = parse_recoverable_signature(&bytes, None).unwrap();
+		assert_eq!(sigs.iter().map(|s| s.layout.unwrap()).collect::<Vec<_>>(),
+			vec![SignatureLayout::Header, SignatureLayout::Trailing]);
+		let trailing = recover_pubkeys(&msg, &sigs[1].compact, sigs[1].recovery_id).unwrap();
+		assert_eq!(trailing, vec![(recid.to_i32() as u8, pk)]);
+		let header = recover_pubkeys(&msg, &sigs[0].compact, sigs[0].recovery_id).unwrap();
+		assert!(header.iter().all(|(_, k)| *k != pk));
+
+		let sigs = parse_recoverable_signature(&bytes, Some(SignatureLayout::Trailing)).unwrap();
+		assert_eq!(sigs.len(), 1);
+		assert_eq!(sigs[0].compact, compact);
+	}
+}
@@ -1,26 +1,47 @@
+extern crate aes;
 extern crate bip39 as bip39lib;
 extern crate bitcoin;
 extern crate byteorder;
+extern crate cbc;
 extern crate chrono;
 extern crate hex;
 #[macro_use]
 extern crate lazy_static;
 extern crate lightning_invoice;
 extern crate miniscript as miniscriptlib;
+extern crate pbkdf2;
+extern crate scrypt;
 extern crate secp256k1;
 extern crate serde;
+extern crate sha2;
+extern crate unicode_normalization;
 
+pub mod adaptor;
 pub mod address;
+pub mod audit;
 pub mod bech32;
 pub mod bip32;
+pub mod bip322;
+pub mod bip38;
 pub mod bip39;
+pub mod bip85;
 pub mod block;
+pub mod codex32;
+pub mod ecies;
+pub mod electrum;
+pub mod entropy;
+pub mod hash;
 pub mod key;
 pub mod lightning;
 pub mod message;
 pub mod miniscript;
 pub mod psbt;
+pub mod sig;
+pub mod slip39;
+pub mod taproot;
 pub mod tx;
+pub mod wallet;
+mod scalar;
 mod serde_utils;
 pub use serde_utils::HexBytes;
 
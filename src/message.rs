@@ -1,9 +1,148 @@
+use std::fmt;
+
 use bitcoin::hashes::{sha256, sha256d};
 use serde::{Deserialize, Serialize};
 
+/// The marker starting an armored signed message.
+pub const ARMOR_BEGIN: &str = "-----BEGIN BITCOIN SIGNED MESSAGE-----";
+/// The marker separating the message from the signature.
+pub const ARMOR_SIGNATURE: &str = "-----BEGIN SIGNATURE-----";
+/// The marker ending an armored signed message.
+pub const ARMOR_END: &str = "-----END BITCOIN SIGNED MESSAGE-----";
+
+/// Alternative markers used by some wallets instead of [ARMOR_SIGNATURE] and [ARMOR_END].
+const ARMOR_SIGNATURE_ALT: &str = "-----BEGIN BITCOIN SIGNATURE-----";
+const ARMOR_END_ALT: &str = "-----END BITCOIN SIGNATURE-----";
+
 #[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
 pub struct MessageHash {
 	pub sha256: sha256::Hash,
 	pub sha256d: sha256d::Hash,
 	pub sign_hash: sha256d::Hash,
+	/// The BIP-322 tagged message hash.
+	pub bip322: sha256::Hash,
+}
+
+/// A signed message in the armored format used by Electrum and others:
+///
+/// ```text
+/// -----BEGIN BITCOIN SIGNED MESSAGE-----
+/// <message>
+/// -----BEGIN SIGNATURE-----
+/// <address>
+/// <signature>
+/// -----END BITCOIN SIGNED MESSAGE-----
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct ArmoredMessage {
+	pub message: String,
+	/// The signer's address or public key.
+	pub signer: String,
+	/// The signature in base64.
+	pub signature: String,
+}
+
+/// Check whether the string looks like an armored signed message.
+pub fn is_armored(s: &str) -> bool {
+	s.trim_start_matches('\u{feff}').trim_start().starts_with(ARMOR_BEGIN)
+}
+
+impl ArmoredMessage {
+	/// Parse an armored signed message.
+	///
+	/// Line endings are normalized to `\n` and whitespace around the markers and in the
+	/// signature section is ignored. The signature section may also use the
+	/// `Address: <addr>` header format, and the signature may be wrapped over multiple lines.
+	pub fn parse(s: &str) -> Result<ArmoredMessage, String> {
+		let normalized = s.trim_start_matches('\u{feff}').replace("\r\n", "\n").replace('\r', "\n");
+		let lines = normalized.split('\n').collect::<Vec<_>>();
+		let is_marker = |line: &str, markers: &[&str]| markers.contains(&line.trim());
+
+		let begin = lines.iter().position(|l| is_marker(l, &[ARMOR_BEGIN]))
+			.ok_or("missing BEGIN BITCOIN SIGNED MESSAGE marker")?;
+		let sig = begin + 1 + lines[begin + 1..].iter()
+			.position(|l| is_marker(l, &[ARMOR_SIGNATURE, ARMOR_SIGNATURE_ALT]))
+			.ok_or("missing BEGIN SIGNATURE marker")?;
+		let end = sig + 1 + lines[sig + 1..].iter()
+			.position(|l| is_marker(l, &[ARMOR_END, ARMOR_END_ALT]))
+			.ok_or("missing END BITCOIN SIGNED MESSAGE marker")?;
+
+		let mut signer = None;
+		let mut signature = String::new();
+		for line in lines[sig + 1..end].iter().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+			if let Some(idx) = line.find(':') {
+				let (key, value) = (line[..idx].trim(), line[idx + 1..].trim());
+				if key.eq_ignore_ascii_case("address") {
+					signer = Some(value.to_owned());
+				}
+				// Ignore other headers like Version or Comment.
+				continue;
+			}
+			if signer.is_none() {
+				signer = Some(line.to_owned());
+			} else {
+				signature.push_str(line);
+			}
+		}
+
+		let signer = signer.ok_or("missing signer address")?;
+		if signature.is_empty() {
+			return Err("missing signature".to_owned());
+		}
+		Ok(ArmoredMessage {
+			message: lines[begin + 1..sig].join("\n"),
+			signer: signer,
+			signature: signature,
+		})
+	}
+
+	/// The message with trailing whitespace stripped from all lines.
+	///
+	/// Useful to verify messages mangled by copy-pasting or email clients.
+	pub fn trimmed_message(&self) -> String {
+		self.message.split('\n').map(|l| l.trim_end()).collect::<Vec<_>>().join("\n")
+	}
+}
+
+impl fmt::Display for ArmoredMessage {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(f, "{}", ARMOR_BEGIN)?;
+		writeln!(f, "{}", self.message)?;
+		writeln!(f, "{}", ARMOR_SIGNATURE)?;
+		writeln!(f, "{}", self.signer)?;
+		writeln!(f, "{}", self.signature)?;
+		write!(f, "{}", ARMOR_END)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_armored_message() {
+		let armored = ArmoredMessage {
+			message: "Hello\nWorld".to_owned(),
+			signer: "1F3sAm6ZtwLAUnj7d38pGFxtP3RVEvtsbV".to_owned(),
+			signature: "IBWoGlMuOWmeYsGGbhNYdzaZeVaOHZdhv8ToGdq3bc7mZrxH6RcJajdoOJFXCmMFAdDiAuqnPAe1n7XlOAGIw3s=".to_owned(),
+		};
+		let s = armored.to_string();
+		assert!(is_armored(&s));
+		assert_eq!(ArmoredMessage::parse(&s).unwrap(), armored);
+
+		// CRLF line endings and surrounding whitespace
+		let crlf = format!("\n  {}  \n", s.replace('\n', "\r\n"));
+		assert_eq!(ArmoredMessage::parse(&crlf).unwrap(), armored);
+
+		// header format with a wrapped signature
+		let headers = format!(
+			"{}\nHello\nWorld\n-----BEGIN BITCOIN SIGNATURE-----\nVersion: Bitcoin-qt (1.0)\n\
+			Address: {}\n\n{}\n{}\n-----END BITCOIN SIGNATURE-----\n",
+			ARMOR_BEGIN, armored.signer, &armored.signature[..40], &armored.signature[40..],
+		);
+		assert_eq!(ArmoredMessage::parse(&headers).unwrap(), armored);
+
+		let trailing = ArmoredMessage { message: "Hello \nWorld\t".to_owned(), ..armored.clone() };
+		assert_eq!(trailing.trimmed_message(), armored.message);
+	}
 }
@@ -0,0 +1,119 @@
+//! Arithmetic modulo the secp256k1 curve order.
+//!
+//! Non-zero scalars are represented as [SecretKey]s so that we can use the
+//! tweak operations of libsecp256k1 instead of a bignum library.
+
+use secp256k1::{Scalar, SecretKey};
+
+use crate::hash::Hasher;
+
+/// The curve order.
+const ORDER: [u8; 32] = [
+	0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+	0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41,
+];
+
+/// The curve order minus 2, used to calculate modular inverses.
+const ORDER_MINUS_TWO: [u8; 32] = [
+	0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+	0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x3f,
+];
+
+pub fn scalar(sk: &SecretKey) -> Scalar {
+	Scalar::from(*sk)
+}
+
+pub fn from_bytes(bytes: &[u8; 32]) -> Result<SecretKey, String> {
+	SecretKey::from_slice(bytes).map_err(|_| "value is zero or not below the curve order".to_owned())
+}
+
+/// Interpret the bytes as a number reduced modulo the curve order, [None] if zero.
+pub fn reduce(bytes: &[u8; 32]) -> Option<SecretKey> {
+	if let Ok(sk) = SecretKey::from_slice(bytes) {
+		return Some(sk);
+	}
+	// Zero or at least the order, which is less than twice the order.
+	let mut ret = [0u8; 32];
+	let mut borrow = 0i16;
+	for i in (0..32).rev() {
+		let d = bytes[i] as i16 - ORDER[i] as i16 - borrow;
+		borrow = (d < 0) as i16;
+		ret[i] = d.rem_euclid(256) as u8;
+	}
+	if borrow != 0 {
+		return None; // zero
+	}
+	SecretKey::from_slice(&ret).ok()
+}
+
+pub fn mul(a: &SecretKey, b: &SecretKey) -> SecretKey {
+	a.mul_tweak(&scalar(b)).expect("product of non-zero scalars is non-zero")
+}
+
+/// Calculate a + b, [None] if zero.
+pub fn add(a: &SecretKey, b: &SecretKey) -> Option<SecretKey> {
+	a.add_tweak(&scalar(b)).ok()
+}
+
+/// Calculate a - b, [None] if zero.
+pub fn sub(a: &SecretKey, b: &SecretKey) -> Option<SecretKey> {
+	add(a, &b.negate())
+}
+
+/// Calculate the modular inverse as a^(n-2).
+pub fn inv(a: &SecretKey) -> SecretKey {
+	let mut ret: Option<SecretKey> = None;
+	for byte in ORDER_MINUS_TWO.iter() {
+		for bit in (0..8).rev() {
+			if let Some(r) = ret {
+				ret = Some(mul(&r, &r));
+			}
+			if (byte >> bit) & 1 == 1 {
+				ret = Some(match ret {
+					Some(r) => mul(&r, a),
+					None => *a,
+				});
+			}
+		}
+	}
+	ret.expect("exponent is non-zero")
+}
+
+/// Calculate a BIP-340 style tagged hash over the concatenation of the data.
+pub fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+	let mut hasher = Hasher::tagged(tag);
+	for d in data {
+		hasher.input(d);
+	}
+	let mut ret = [0u8; 32];
+	ret.copy_from_slice(&hasher.finalize());
+	ret
+}
+
+/// Calculate the BIP-340 challenge e = H(R || P || m).
+pub fn schnorr_challenge(r: &[u8; 32], pubkey: &[u8], msg: &[u8]) -> Result<SecretKey, String> {
+	from_bytes(&tagged_hash("BIP0340/challenge", &[r, pubkey, msg]))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_reduce() {
+		let one = reduce(&{
+			let mut one = [0u8; 32];
+			one[31] = 1;
+			one
+		}).unwrap();
+		assert_eq!(reduce(&[0; 32]), None);
+		assert_eq!(reduce(&ORDER), None);
+		let mut order_plus_one = ORDER;
+		order_plus_one[31] += 1;
+		assert_eq!(reduce(&order_plus_one), Some(one));
+		assert_eq!(
+			hex::encode(reduce(&[0xff; 32]).unwrap().secret_bytes()),
+			"000000000000000000000000000000014551231950b75fc4402da1732fc9bebe",
+		);
+	}
+}
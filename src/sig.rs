@@ -0,0 +1,367 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::sighash::{EcdsaSighashType, TapSighashType};
+use secp256k1::{ecdsa, schnorr, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+
+use crate::HexBytes;
+
+/// The encoding of a signature.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SignatureFormat {
+	/// DER-encoded ECDSA, strict or lax.
+	Der,
+	/// 64-byte compact ECDSA.
+	Compact,
+	/// BIP-340 Schnorr.
+	Schnorr,
+}
+
+impl fmt::Display for SignatureFormat {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SignatureFormat::Der => f.write_str("der"),
+			SignatureFormat::Compact => f.write_str("compact"),
+			SignatureFormat::Schnorr => f.write_str("schnorr"),
+		}
+	}
+}
+
+impl FromStr for SignatureFormat {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"der" => Ok(SignatureFormat::Der),
+			"compact" => Ok(SignatureFormat::Compact),
+			"schnorr" => Ok(SignatureFormat::Schnorr),
+			_ => Err(format!("unknown signature format: {}", s)),
+		}
+	}
+}
+
+/// A parsed signature with its optional trailing sighash byte.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Signature {
+	Ecdsa {
+		signature: ecdsa::Signature,
+		format: SignatureFormat,
+		/// The original encoding, without sighash byte.
+		encoded: Vec<u8>,
+		sighash: Option<u8>,
+	},
+	Schnorr {
+		signature: schnorr::Signature,
+		sighash: Option<u8>,
+	},
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct SignatureInfo {
+	pub format: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub sighash_byte: Option<u8>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub sighash_type: Option<String>,
+	pub r: HexBytes,
+	pub s: HexBytes,
+	/// Whether the encoding is valid according to BIP-66 strict DER rules.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub strict_der: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub der_error: Option<String>,
+	/// Whether s is in the lower half of the order, as required by BIP-146.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub low_s: Option<bool>,
+	/// Whether r fits in 32 bytes DER-encoded, as produced by Bitcoin Core since v0.17.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub low_r: Option<bool>,
+	/// Whether the R value of a Schnorr signature is a valid x-coordinate.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub valid_r: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub der: Option<HexBytes>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub compact: Option<HexBytes>,
+	/// The signature normalized to low-S, if it wasn't.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub normalized: Option<HexBytes>,
+}
+
+/// Check a DER signature without sighash byte against the BIP-66 encoding rules.
+///
+/// This mirrors `IsValidSignatureEncoding` from Bitcoin Core.
+pub fn check_strict_der(der: &[u8]) -> Result<(), String> {
+	// Core's rules account for the sighash byte.
+	let size = der.len() + 1;
+	if size < 9 {
+		return Err("signature too short".to_owned());
+	}
+	if size > 73 {
+		return Err("signature too long".to_owned());
+	}
+	if der[0] != 0x30 {
+		return Err("no compound structure marker".to_owned());
+	}
+	if der[1] as usize != size - 3 {
+		return Err("length doesn't cover the entire signature".to_owned());
+	}
+	let len_r = der[3] as usize;
+	if 5 + len_r >= der.len() {
+		return Err("length of s is missing".to_owned());
+	}
+	let len_s = der[5 + len_r] as usize;
+	if len_r + len_s + 7 != size {
+		return Err("lengths of r and s don't match the signature length".to_owned());
+	}
+	if der[2] != 0x02 {
+		return Err("r is not an integer".to_owned());
+	}
+	if len_r == 0 {
+		return Err("r has zero length".to_owned());
+	}
+	if der[4] & 0x80 != 0 {
+		return Err("r is negative".to_owned());
+	}
+	if len_r > 1 && der[4] == 0x00 && der[5] & 0x80 == 0 {
+		return Err("r has excessive padding".to_owned());
+	}
+	if der[len_r + 4] != 0x02 {
+		return Err("s is not an integer".to_owned());
+	}
+	if len_s == 0 {
+		return Err("s has zero length".to_owned());
+	}
+	if der[len_r + 6] & 0x80 != 0 {
+		return Err("s is negative".to_owned());
+	}
+	if len_s > 1 && der[len_r + 6] == 0x00 && der[len_r + 7] & 0x80 == 0 {
+		return Err("s has excessive padding".to_owned());
+	}
+	Ok(())
+}
+
+fn parse_der(bytes: &[u8]) -> Result<Signature, String> {
+	if bytes.len() < 2 || bytes[0] != 0x30 {
+		return Err("not a DER signature".to_owned());
+	}
+	// Try to use the length to see if there is a sighash byte.
+	let der_len = bytes[1] as usize + 2;
+	let (der, sighash) = if bytes.len() == der_len + 1 {
+		(&bytes[..der_len], Some(bytes[der_len]))
+	} else {
+		(bytes, None)
+	};
+	let signature = ecdsa::Signature::from_der_lax(der)
+		.map_err(|e| format!("invalid DER signature: {}", e))?;
+	Ok(Signature::Ecdsa {
+		signature: signature,
+		format: SignatureFormat::Der,
+		encoded: der.to_vec(),
+		sighash: sighash,
+	})
+}
+
+impl Signature {
+	/// Parse a signature with optional sighash byte.
+	///
+	/// Without explicit format, DER is tried first and other 64 or 65-byte
+	/// signatures are assumed to be Schnorr signatures.
+	pub fn parse(bytes: &[u8], format: Option<SignatureFormat>) -> Result<Signature, String> {
+		// DER signatures have a length byte matching the length with or without sighash byte.
+		let looks_der = bytes.len() > 2 && bytes[0] == 0x30
+			&& (bytes.len() == bytes[1] as usize + 2 || bytes.len() == bytes[1] as usize + 3);
+		let format = match format {
+			Some(f) => f,
+			None if !looks_der && (bytes.len() == 64 || bytes.len() == 65) => SignatureFormat::Schnorr,
+			None => SignatureFormat::Der,
+		};
+		match format {
+			SignatureFormat::Der => parse_der(bytes),
+			SignatureFormat::Compact => {
+				if bytes.len() != 64 && bytes.len() != 65 {
+					return Err(format!("invalid compact signature length: {}", bytes.len()));
+				}
+				let signature = ecdsa::Signature::from_compact(&bytes[0..64])
+					.map_err(|e| format!("invalid compact signature: {}", e))?;
+				Ok(Signature::Ecdsa {
+					signature: signature,
+					format: format,
+					encoded: bytes[0..64].to_vec(),
+					sighash: bytes.get(64).cloned(),
+				})
+			}
+			SignatureFormat::Schnorr => {
+				if bytes.len() != 64 && bytes.len() != 65 {
+					return Err(format!("invalid Schnorr signature length: {}", bytes.len()));
+				}
+				let signature = schnorr::Signature::from_slice(&bytes[0..64])
+					.map_err(|e| format!("invalid Schnorr signature: {}", e))?;
+				Ok(Signature::Schnorr {
+					signature: signature,
+					sighash: bytes.get(64).cloned(),
+				})
+			}
+		}
+	}
+
+	/// Normalize an ECDSA signature to low-S, keeping its format and sighash byte.
+	///
+	/// DER signatures are re-encoded as strict DER.
+	pub fn normalize_s(&self) -> Result<Vec<u8>, String> {
+		match self {
+			Signature::Ecdsa { signature, format, sighash, .. } => {
+				let mut normalized = *signature;
+				normalized.normalize_s();
+				let mut ret = match format {
+					SignatureFormat::Compact => normalized.serialize_compact().to_vec(),
+					_ => normalized.serialize_der().to_vec(),
+				};
+				ret.extend(sighash);
+				Ok(ret)
+			}
+			Signature::Schnorr { .. } => Err("Schnorr signatures can't be normalized".to_owned()),
+		}
+	}
+
+	/// Get a description of the signature.
+	pub fn get_info(&self) -> SignatureInfo {
+		match self {
+			Signature::Ecdsa { signature, format, encoded, sighash } => {
+				let compact = signature.serialize_compact();
+				let mut normalized = *signature;
+				normalized.normalize_s();
+				let low_s = normalized == *signature;
+				let der_error = if *format == SignatureFormat::Der {
+					check_strict_der(encoded).err()
+				} else {
+					None
+				};
+				SignatureInfo {
+					format: format.to_string(),
+					sighash_byte: *sighash,
+					sighash_type: sighash.map(|b| {
+						match EcdsaSighashType::from_standard(b as u32) {
+							Ok(t) => t.to_string(),
+							Err(_) => "non-standard".to_owned(),
+						}
+					}),
+					r: compact[0..32].into(),
+					s: compact[32..64].into(),
+					strict_der: if *format == SignatureFormat::Der {
+						Some(der_error.is_none())
+					} else {
+						None
+					},
+					der_error: der_error,
+					low_s: Some(low_s),
+					low_r: Some(compact[0] < 0x80),
+					valid_r: None,
+					der: Some(signature.serialize_der().as_ref().into()),
+					compact: Some(compact[..].into()),
+					normalized: if low_s {
+						None
+					} else {
+						Some(self.normalize_s().expect("ECDSA").into())
+					},
+				}
+			}
+			Signature::Schnorr { signature, sighash } => {
+				let bytes = signature.as_ref();
+				SignatureInfo {
+					format: SignatureFormat::Schnorr.to_string(),
+					sighash_byte: *sighash,
+					sighash_type: sighash.map(|b| {
+						match TapSighashType::from_consensus_u8(b) {
+							// The default type must be omitted when explicit.
+							Ok(TapSighashType::Default) => "invalid: SIGHASH_DEFAULT must be omitted".to_owned(),
+							Ok(t) => t.to_string(),
+							Err(_) => "invalid".to_owned(),
+						}
+					}),
+					r: bytes[0..32].into(),
+					s: bytes[32..64].into(),
+					strict_der: None,
+					der_error: None,
+					low_s: None,
+					low_r: None,
+					valid_r: Some(XOnlyPublicKey::from_slice(&bytes[0..32]).is_ok()),
+					der: None,
+					compact: None,
+					normalized: None,
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_signature_inspect() {
+		let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+		let msg = secp256k1::Message::from_digest([2; 32]);
+		let sig = crate::SECP.sign_ecdsa_low_r(&msg, &sk);
+		let compact = sig.serialize_compact();
+
+		// Make a high-S version with SIGHASH_ALL.
+		let neg_s = secp256k1::SecretKey::from_slice(&compact[32..]).unwrap().negate();
+		let mut high = compact;
+		high[32..].copy_from_slice(&neg_s.secret_bytes());
+		let mut high_der = ecdsa::Signature::from_compact(&high).unwrap().serialize_der().to_vec();
+		high_der.push(0x01);
+
+		let parsed = Signature::parse(&high_der, None).unwrap();
+		let info = parsed.get_info();
+		assert_eq!(info.format, "der");
+		assert_eq!(info.sighash_type.as_deref(), Some("SIGHASH_ALL"));
+		assert_eq!(info.strict_der, Some(true));
+		assert_eq!(info.low_s, Some(false));
+		assert_eq!(info.low_r, Some(true));
+		let mut expected = sig.serialize_der().to_vec();
+		expected.push(0x01);
+		assert_eq!(parsed.normalize_s().unwrap(), expected);
+		assert_eq!(info.normalized.unwrap().0, expected);
+		assert_eq!(Signature::parse(&expected, None).unwrap().get_info().low_s, Some(true));
+
+		// Excessive padding of r is valid lax DER but not BIP-66.
+		let mut r = vec![0x00];
+		r.extend_from_slice(&compact[0..32]);
+		let der = sig.serialize_der();
+		let s = der[4 + der[3] as usize..].to_vec();
+		let mut padded = vec![0x30, (2 + r.len() + s.len()) as u8, 0x02, r.len() as u8];
+		padded.extend(r);
+		padded.extend(s);
+		let info = Signature::parse(&padded, Some(SignatureFormat::Der)).unwrap().get_info();
+		assert_eq!(info.strict_der, Some(false));
+		assert_eq!(info.der_error.as_deref(), Some("r has excessive padding"));
+		assert_eq!(info.compact.unwrap().0, compact.to_vec());
+
+		let schnorr = [1u8; 65];
+		let info = Signature::parse(&schnorr, None).unwrap().get_info();
+		assert_eq!(info.format, "schnorr");
+		assert_eq!(info.sighash_type.as_deref(), Some("SIGHASH_ALL"));
+	}
+
+	#[test]
+	fn test_check_strict_der() {
+		let check = |h: &str| check_strict_der(&hex::decode(h).unwrap());
+		assert_eq!(check("3006020101020101"), Ok(()));
+		// The length of r runs up to the end, so there is no byte left for the length of s.
+		assert_eq!(check("3006020301010102"), Err("length of s is missing".to_owned()));
+		assert_eq!(check("3006020401010101"), Err("length of s is missing".to_owned()));
+		assert_eq!(check("300602010102"), Err("signature too short".to_owned()));
+		assert_eq!(
+			check("3007020101020101"),
+			Err("length doesn't cover the entire signature".to_owned()),
+		);
+		assert_eq!(
+			check("3006020101020201"),
+			Err("lengths of r and s don't match the signature length".to_owned()),
+		);
+		assert_eq!(check("3006020181020101"), Err("r is negative".to_owned()));
+		assert_eq!(check("3006020101030101"), Err("s is not an integer".to_owned()));
+	}
+}
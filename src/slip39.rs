@@ -0,0 +1,651 @@
+//! SLIP-39 Shamir's secret sharing for mnemonic codes.
+
+use std::collections::BTreeMap;
+
+use bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+use secp256k1::rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::HexBytes;
+
+const RADIX_BITS: usize = 10;
+const CHECKSUM_WORDS: usize = 3;
+/// The words for the identifier, flags, group and member parameters and the checksum.
+const METADATA_WORDS: usize = 7;
+const MIN_SECRET_LENGTH: usize = 16;
+const MAX_SHARE_COUNT: u8 = 16;
+const BASE_ITERATION_COUNT: u32 = 10000;
+const ROUND_COUNT: u8 = 4;
+const DIGEST_LENGTH: usize = 4;
+const DIGEST_INDEX: u8 = 254;
+const SECRET_INDEX: u8 = 255;
+
+const CUSTOMIZATION: &[u8] = b"shamir";
+const CUSTOMIZATION_EXTENDABLE: &[u8] = b"shamir_extendable";
+
+/// A single SLIP-39 share.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct Share {
+	pub identifier: u16,
+	pub extendable: bool,
+	pub iteration_exponent: u8,
+	pub group_index: u8,
+	pub group_threshold: u8,
+	pub group_count: u8,
+	pub member_index: u8,
+	pub member_threshold: u8,
+	pub value: HexBytes,
+}
+
+impl Share {
+	/// Encode the share as a mnemonic.
+	pub fn to_mnemonic(&self) -> String {
+		let id_exp = (self.identifier as u32) << 5
+			| (self.extendable as u32) << 4
+			| self.iteration_exponent as u32;
+		let params = (self.group_index as u32) << 16
+			| (self.group_threshold as u32 - 1) << 12
+			| (self.group_count as u32 - 1) << 8
+			| (self.member_index as u32) << 4
+			| (self.member_threshold as u32 - 1);
+
+		let value_words = (self.value.0.len() * 8).div_ceil(RADIX_BITS);
+		let mut words = vec![
+			(id_exp >> 10) as u16, (id_exp & 1023) as u16,
+			(params >> 10) as u16, (params & 1023) as u16,
+		];
+		words.extend(bytes_to_words(&self.value.0, value_words));
+		let checksum = create_checksum(customization(self.extendable), &words);
+		words.extend(&checksum);
+		words.iter().map(|w| WORDLIST[*w as usize]).collect::<Vec<_>>().join(" ")
+	}
+
+	/// Decode a share from its mnemonic and verify its checksum.
+	pub fn from_mnemonic(mnemonic: &str) -> Result<Share, String> {
+		let words = mnemonic.split_whitespace().map(|w| {
+			let w = w.to_lowercase();
+			WORDLIST.binary_search(&w.as_str()).map(|i| i as u16)
+				.map_err(|_| format!("invalid word: {}", w))
+		}).collect::<Result<Vec<_>, _>>()?;
+
+		if words.len() < METADATA_WORDS + (MIN_SECRET_LENGTH * 8).div_ceil(RADIX_BITS) {
+			return Err(format!("invalid mnemonic length: {} words", words.len()));
+		}
+		let padding = (RADIX_BITS * (words.len() - METADATA_WORDS)) % 16;
+		if padding > 8 {
+			return Err(format!("invalid mnemonic length: {} words", words.len()));
+		}
+
+		let id_exp = (words[0] as u32) << 10 | words[1] as u32;
+		let extendable = id_exp >> 4 & 1 == 1;
+		if !verify_checksum(customization(extendable), &words) {
+			return Err("invalid mnemonic checksum".to_owned());
+		}
+
+		let params = (words[2] as u32) << 10 | words[3] as u32;
+		let group_threshold = (params >> 12 & 15) as u8 + 1;
+		let group_count = (params >> 8 & 15) as u8 + 1;
+		if group_threshold > group_count {
+			return Err("group threshold can't be higher than the group count".to_owned());
+		}
+
+		let value_words = &words[4..words.len() - CHECKSUM_WORDS];
+		let value_len = (value_words.len() * RADIX_BITS - padding) / 8;
+		Ok(Share {
+			identifier: (id_exp >> 5) as u16,
+			extendable: extendable,
+			iteration_exponent: (id_exp & 15) as u8,
+			group_index: (params >> 16) as u8,
+			group_threshold: group_threshold,
+			group_count: group_count,
+			member_index: (params >> 4 & 15) as u8,
+			member_threshold: (params & 15) as u8 + 1,
+			value: words_to_bytes(value_words, value_len)?.into(),
+		})
+	}
+}
+
+/// The shares of a group, together with the group's member threshold.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct GroupShares {
+	pub member_threshold: u8,
+	pub shares: Vec<String>,
+}
+
+/// The shares of a split master secret.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct SplitInfo {
+	pub identifier: u16,
+	pub group_threshold: u8,
+	pub groups: Vec<GroupShares>,
+}
+
+/// Split a master secret into groups of mnemonic shares.
+///
+/// The groups are given as (member threshold, member count).
+pub fn split<R: RngCore>(
+	master_secret: &[u8],
+	passphrase: &str,
+	group_threshold: u8,
+	groups: &[(u8, u8)],
+	extendable: bool,
+	iteration_exponent: u8,
+	rng: &mut R,
+) -> Result<SplitInfo, String> {
+	if master_secret.len() < MIN_SECRET_LENGTH || !master_secret.len().is_multiple_of(2) {
+		return Err(format!(
+			"the master secret must be at least {} bytes and have an even length", MIN_SECRET_LENGTH,
+		));
+	}
+	if groups.is_empty() || groups.len() > MAX_SHARE_COUNT as usize {
+		return Err(format!("the number of groups must be between 1 and {}", MAX_SHARE_COUNT));
+	}
+	if group_threshold < 1 || group_threshold as usize > groups.len() {
+		return Err("the group threshold must be between 1 and the number of groups".to_owned());
+	}
+	for (threshold, count) in groups {
+		if *threshold < 1 || threshold > count || *count > MAX_SHARE_COUNT {
+			return Err(format!("invalid group {}-of-{}", threshold, count));
+		}
+		if *threshold == 1 && *count > 1 {
+			return Err("a member threshold of 1 requires a single share, use 1-of-1".to_owned());
+		}
+	}
+	if iteration_exponent > 15 {
+		return Err("the iteration exponent must be at most 15".to_owned());
+	}
+	check_passphrase(passphrase)?;
+
+	let identifier = (rng.next_u32() & 0x7fff) as u16;
+	let encrypted = crypt(
+		master_secret, passphrase, iteration_exponent, identifier, extendable, false,
+	);
+	let group_secrets = split_secret(group_threshold, groups.len() as u8, &encrypted, rng);
+	let groups = groups.iter().zip(group_secrets).map(|(&(threshold, count), (group_index, secret))| {
+		let shares = split_secret(threshold, count, &secret, rng).into_iter().map(|(idx, value)| {
+			Share {
+				identifier: identifier,
+				extendable: extendable,
+				iteration_exponent: iteration_exponent,
+				group_index: group_index,
+				group_threshold: group_threshold,
+				group_count: groups.len() as u8,
+				member_index: idx,
+				member_threshold: threshold,
+				value: value.into(),
+			}.to_mnemonic()
+		}).collect();
+		GroupShares {
+			member_threshold: threshold,
+			shares: shares,
+		}
+	}).collect();
+	Ok(SplitInfo {
+		identifier: identifier,
+		group_threshold: group_threshold,
+		groups: groups,
+	})
+}
+
+/// Recover the master secret from enough shares.
+pub fn combine(shares: &[Share], passphrase: &str) -> Result<Vec<u8>, String> {
+	let first = shares.first().ok_or("no shares given")?;
+	for share in shares {
+		if share.identifier != first.identifier
+			|| share.extendable != first.extendable
+			|| share.iteration_exponent != first.iteration_exponent
+		{
+			return Err("the shares don't belong to the same secret".to_owned());
+		}
+		if share.group_threshold != first.group_threshold || share.group_count != first.group_count {
+			return Err("the shares have different group parameters".to_owned());
+		}
+		if share.value.0.len() != first.value.0.len() {
+			return Err("the shares have different lengths".to_owned());
+		}
+	}
+	check_passphrase(passphrase)?;
+
+	// Group index -> (member threshold, member index -> value)
+	let mut groups = BTreeMap::<u8, (u8, BTreeMap<u8, &[u8]>)>::new();
+	for share in shares {
+		let group = groups.entry(share.group_index)
+			.or_insert_with(|| (share.member_threshold, BTreeMap::new()));
+		if group.0 != share.member_threshold {
+			return Err(format!("group {} has shares with different thresholds", share.group_index));
+		}
+		match group.1.insert(share.member_index, &share.value.0) {
+			Some(v) if v != &share.value.0[..] => return Err(format!(
+				"conflicting shares with member index {} in group {}", share.member_index, share.group_index,
+			)),
+			_ => {}
+		}
+	}
+
+	let mut group_secrets = Vec::new();
+	for (index, (threshold, members)) in groups.iter() {
+		if members.len() < *threshold as usize {
+			continue;
+		}
+		let members = members.iter().take(*threshold as usize).map(|(i, v)| (*i, *v)).collect::<Vec<_>>();
+		group_secrets.push((*index, recover_secret(*threshold, &members)?));
+		if group_secrets.len() == first.group_threshold as usize {
+			break;
+		}
+	}
+	if group_secrets.len() < first.group_threshold as usize {
+		return Err(format!(
+			"need shares of {} complete groups, only have {}", first.group_threshold, group_secrets.len(),
+		));
+	}
+
+	let group_secrets = group_secrets.iter().map(|(i, v)| (*i, &v[..])).collect::<Vec<_>>();
+	let encrypted = recover_secret(first.group_threshold, &group_secrets)?;
+	Ok(crypt(
+		&encrypted, passphrase, first.iteration_exponent, first.identifier, first.extendable, true,
+	))
+}
+
+fn check_passphrase(passphrase: &str) -> Result<(), String> {
+	if passphrase.bytes().all(|b| (32..=126).contains(&b)) {
+		Ok(())
+	} else {
+		Err("the passphrase must contain only printable ASCII characters".to_owned())
+	}
+}
+
+fn customization(extendable: bool) -> &'static [u8] {
+	if extendable {
+		CUSTOMIZATION_EXTENDABLE
+	} else {
+		CUSTOMIZATION
+	}
+}
+
+fn rs1024_polymod(values: impl Iterator<Item = u32>) -> u32 {
+	const GEN: [u32; 10] = [
+		0xe0e040, 0x1c1c080, 0x3838100, 0x7070200, 0xe0e0009,
+		0x1c0c2412, 0x38086c24, 0x3090fc48, 0x21b1f890, 0x3f3f120,
+	];
+	let mut chk = 1;
+	for v in values {
+		let b = chk >> 20;
+		chk = (chk & 0xfffff) << 10 ^ v;
+		for (i, g) in GEN.iter().enumerate() {
+			if b >> i & 1 == 1 {
+				chk ^= g;
+			}
+		}
+	}
+	chk
+}
+
+fn create_checksum(customization: &[u8], words: &[u16]) -> [u16; CHECKSUM_WORDS] {
+	let values = customization.iter().map(|c| *c as u32)
+		.chain(words.iter().map(|w| *w as u32))
+		.chain(vec![0; CHECKSUM_WORDS]);
+	let polymod = rs1024_polymod(values) ^ 1;
+	[(polymod >> 20) as u16 & 1023, (polymod >> 10) as u16 & 1023, polymod as u16 & 1023]
+}
+
+fn verify_checksum(customization: &[u8], words: &[u16]) -> bool {
+	let values = customization.iter().map(|c| *c as u32).chain(words.iter().map(|w| *w as u32));
+	rs1024_polymod(values) == 1
+}
+
+/// Encode bytes as the given number of 10-bit words, padding with leading zero bits.
+fn bytes_to_words(data: &[u8], nb_words: usize) -> Vec<u16> {
+	let padding = nb_words * RADIX_BITS - data.len() * 8;
+	let bit = |i: usize| i >= padding && data[(i - padding) / 8] >> (7 - (i - padding) % 8) & 1 == 1;
+	(0..nb_words).map(|w| {
+		(0..RADIX_BITS).fold(0u16, |acc, b| acc << 1 | bit(w * RADIX_BITS + b) as u16)
+	}).collect()
+}
+
+fn words_to_bytes(words: &[u16], len: usize) -> Result<Vec<u8>, String> {
+	let padding = words.len() * RADIX_BITS - len * 8;
+	let bit = |i: usize| words[i / RADIX_BITS] >> (RADIX_BITS - 1 - i % RADIX_BITS) & 1;
+	if (0..padding).any(|i| bit(i) != 0) {
+		return Err("invalid mnemonic padding".to_owned());
+	}
+	Ok((0..len).map(|byte| {
+		(0..8).fold(0u8, |acc, b| acc << 1 | bit(padding + byte * 8 + b) as u8)
+	}).collect())
+}
+
+/// Encrypt or decrypt the master secret with the 4-round Feistel network.
+fn crypt(
+	input: &[u8],
+	passphrase: &str,
+	iteration_exponent: u8,
+	identifier: u16,
+	extendable: bool,
+	decrypt: bool,
+) -> Vec<u8> {
+	let mut salt = Vec::new();
+	if !extendable {
+		salt.extend(CUSTOMIZATION);
+		salt.extend(&identifier.to_be_bytes());
+	}
+	let iterations = (BASE_ITERATION_COUNT << iteration_exponent) / ROUND_COUNT as u32;
+
+	let half = input.len() / 2;
+	let mut l = input[..half].to_vec();
+	let mut r = input[half..].to_vec();
+	for i in 0..ROUND_COUNT {
+		let round = if decrypt { ROUND_COUNT - 1 - i } else { i };
+		let mut password = vec![round];
+		password.extend(passphrase.as_bytes());
+		let mut round_salt = salt.clone();
+		round_salt.extend(&r);
+		let mut f = vec![0u8; r.len()];
+		pbkdf2::pbkdf2_hmac::<sha2::Sha256>(&password, &round_salt, iterations, &mut f);
+		let new_r = l.iter().zip(f.iter()).map(|(a, b)| a ^ b).collect();
+		l = r;
+		r = new_r;
+	}
+	r.extend(l);
+	r
+}
+
+lazy_static! {
+	/// The exponent and logarithm tables of GF(256) with the Rijndael polynomial.
+	static ref GF256_TABLES: ([u8; 255], [u8; 256]) = {
+		let mut exp = [0u8; 255];
+		let mut log = [0u8; 256];
+		let mut poly: u16 = 1;
+		for (i, e) in exp.iter_mut().enumerate() {
+			*e = poly as u8;
+			log[poly as usize] = i as u8;
+			// Multiply by the generator x + 1.
+			poly = (poly << 1) ^ poly;
+			if poly & 0x100 != 0 {
+				poly ^= 0x11b;
+			}
+		}
+		(exp, log)
+	};
+}
+
+/// Evaluate the polynomial through the shares at x using Lagrange interpolation.
+fn interpolate(shares: &[(u8, &[u8])], x: u8) -> Vec<u8> {
+	if let Some((_, v)) = shares.iter().find(|(i, _)| *i == x) {
+		return v.to_vec();
+	}
+	let (ref exp, ref log) = *GF256_TABLES;
+	let log_prod: usize = shares.iter().map(|(i, _)| log[(i ^ x) as usize] as usize).sum();
+
+	let mut ret = vec![0u8; shares[0].1.len()];
+	for (i, value) in shares {
+		let others: usize = shares.iter().filter(|(j, _)| j != i)
+			.map(|(j, _)| log[(i ^ j) as usize] as usize).sum();
+		let log_basis = (log_prod + 255 * shares.len() - log[(i ^ x) as usize] as usize - others) % 255;
+		for (r, v) in ret.iter_mut().zip(value.iter()) {
+			if *v != 0 {
+				*r ^= exp[(log[*v as usize] as usize + log_basis) % 255];
+			}
+		}
+	}
+	ret
+}
+
+fn hmac_digest(random_part: &[u8], secret: &[u8]) -> [u8; DIGEST_LENGTH] {
+	let mut engine = hmac::HmacEngine::<sha256::Hash>::new(random_part);
+	engine.input(secret);
+	let mut ret = [0u8; DIGEST_LENGTH];
+	ret.copy_from_slice(&hmac::Hmac::<sha256::Hash>::from_engine(engine)[0..DIGEST_LENGTH]);
+	ret
+}
+
+fn split_secret<R: RngCore>(threshold: u8, count: u8, secret: &[u8], rng: &mut R) -> Vec<(u8, Vec<u8>)> {
+	if threshold == 1 {
+		return (0..count).map(|i| (i, secret.to_vec())).collect();
+	}
+
+	let random = |rng: &mut R, len| {
+		let mut ret = vec![0u8; len];
+		rng.fill_bytes(&mut ret);
+		ret
+	};
+	let mut shares = (0..threshold - 2).map(|i| (i, random(rng, secret.len()))).collect::<Vec<_>>();
+	let random_part = random(rng, secret.len() - DIGEST_LENGTH);
+	let mut digest_share = hmac_digest(&random_part, secret).to_vec();
+	digest_share.extend(&random_part);
+
+	let mut base = shares.iter().map(|(i, v)| (*i, &v[..])).collect::<Vec<_>>();
+	base.push((DIGEST_INDEX, &digest_share));
+	base.push((SECRET_INDEX, secret));
+	let rest = (threshold - 2..count).map(|i| (i, interpolate(&base, i))).collect::<Vec<_>>();
+	shares.extend(rest);
+	shares
+}
+
+fn recover_secret(threshold: u8, shares: &[(u8, &[u8])]) -> Result<Vec<u8>, String> {
+	if threshold == 1 {
+		return Ok(shares[0].1.to_vec());
+	}
+	let secret = interpolate(shares, SECRET_INDEX);
+	let digest_share = interpolate(shares, DIGEST_INDEX);
+	if digest_share[0..DIGEST_LENGTH] != hmac_digest(&digest_share[DIGEST_LENGTH..], &secret) {
+		return Err("invalid digest of the shared secret".to_owned());
+	}
+	Ok(secret)
+}
+
+/// The SLIP-39 English word list.
+const WORDLIST: [&str; 1024] = [
+	"academic", "acid", "acne", "acquire", "acrobat", "activity", "actress", "adapt", "adequate",
+	"adjust", "admit", "adorn", "adult", "advance", "advocate", "afraid", "again", "agency",
+	"agree", "aide", "aircraft", "airline", "airport", "ajar", "alarm", "album", "alcohol",
+	"alien", "alive", "alpha", "already", "alto", "aluminum", "always", "amazing", "ambition",
+	"amount", "amuse", "analysis", "anatomy", "ancestor", "ancient", "angel", "angry", "animal",
+	"answer", "antenna", "anxiety", "apart", "aquatic", "arcade", "arena", "argue", "armed",
+	"artist", "artwork", "aspect", "auction", "august", "aunt", "average", "aviation", "avoid",
+	"award", "away", "axis", "axle", "beam", "beard", "beaver", "become", "bedroom", "behavior",
+	"being", "believe", "belong", "benefit", "best", "beyond", "bike", "biology", "birthday",
+	"bishop", "black", "blanket", "blessing", "blimp", "blind", "blue", "body", "bolt", "boring",
+	"born", "both", "boundary", "bracelet", "branch", "brave", "breathe", "briefing", "broken",
+	"brother", "browser", "bucket", "budget", "building", "bulb", "bulge", "bumpy", "bundle",
+	"burden", "burning", "busy", "buyer", "cage", "calcium", "camera", "campus", "canyon",
+	"capacity", "capital", "capture", "carbon", "cards", "careful", "cargo", "carpet", "carve",
+	"category", "cause", "ceiling", "center", "ceramic", "champion", "change", "charity", "check",
+	"chemical", "chest", "chew", "chubby", "cinema", "civil", "class", "clay", "cleanup", "client",
+	"climate", "clinic", "clock", "clogs", "closet", "clothes", "club", "cluster", "coal",
+	"coastal", "coding", "column", "company", "corner", "costume", "counter", "course", "cover",
+	"cowboy", "cradle", "craft", "crazy", "credit", "cricket", "criminal", "crisis", "critical",
+	"crowd", "crucial", "crunch", "crush", "crystal", "cubic", "cultural", "curious", "curly",
+	"custody", "cylinder", "daisy", "damage", "dance", "darkness", "database", "daughter",
+	"deadline", "deal", "debris", "debut", "decent", "decision", "declare", "decorate", "decrease",
+	"deliver", "demand", "density", "deny", "depart", "depend", "depict", "deploy", "describe",
+	"desert", "desire", "desktop", "destroy", "detailed", "detect", "device", "devote", "diagnose",
+	"dictate", "diet", "dilemma", "diminish", "dining", "diploma", "disaster", "discuss",
+	"disease", "dish", "dismiss", "display", "distance", "dive", "divorce", "document", "domain",
+	"domestic", "dominant", "dough", "downtown", "dragon", "dramatic", "dream", "dress", "drift",
+	"drink", "drove", "drug", "dryer", "duckling", "duke", "duration", "dwarf", "dynamic", "early",
+	"earth", "easel", "easy", "echo", "eclipse", "ecology", "edge", "editor", "educate", "either",
+	"elbow", "elder", "election", "elegant", "element", "elephant", "elevator", "elite", "else",
+	"email", "emerald", "emission", "emperor", "emphasis", "employer", "empty", "ending",
+	"endless", "endorse", "enemy", "energy", "enforce", "engage", "enjoy", "enlarge", "entrance",
+	"envelope", "envy", "epidemic", "episode", "equation", "equip", "eraser", "erode", "escape",
+	"estate", "estimate", "evaluate", "evening", "evidence", "evil", "evoke", "exact", "example",
+	"exceed", "exchange", "exclude", "excuse", "execute", "exercise", "exhaust", "exotic",
+	"expand", "expect", "explain", "express", "extend", "extra", "eyebrow", "facility", "fact",
+	"failure", "faint", "fake", "false", "family", "famous", "fancy", "fangs", "fantasy", "fatal",
+	"fatigue", "favorite", "fawn", "fiber", "fiction", "filter", "finance", "findings", "finger",
+	"firefly", "firm", "fiscal", "fishing", "fitness", "flame", "flash", "flavor", "flea",
+	"flexible", "flip", "float", "floral", "fluff", "focus", "forbid", "force", "forecast",
+	"forget", "formal", "fortune", "forward", "founder", "fraction", "fragment", "frequent",
+	"freshman", "friar", "fridge", "friendly", "frost", "froth", "frozen", "fumes", "funding",
+	"furl", "fused", "galaxy", "game", "garbage", "garden", "garlic", "gasoline", "gather",
+	"general", "genius", "genre", "genuine", "geology", "gesture", "glad", "glance", "glasses",
+	"glen", "glimpse", "goat", "golden", "graduate", "grant", "grasp", "gravity", "gray",
+	"greatest", "grief", "grill", "grin", "grocery", "gross", "group", "grownup", "grumpy",
+	"guard", "guest", "guilt", "guitar", "gums", "hairy", "hamster", "hand", "hanger", "harvest",
+	"have", "havoc", "hawk", "hazard", "headset", "health", "hearing", "heat", "helpful", "herald",
+	"herd", "hesitate", "hobo", "holiday", "holy", "home", "hormone", "hospital", "hour", "huge",
+	"human", "humidity", "hunting", "husband", "hush", "husky", "hybrid", "idea", "identify",
+	"idle", "image", "impact", "imply", "improve", "impulse", "include", "income", "increase",
+	"index", "indicate", "industry", "infant", "inform", "inherit", "injury", "inmate", "insect",
+	"inside", "install", "intend", "intimate", "invasion", "involve", "iris", "island", "isolate",
+	"item", "ivory", "jacket", "jerky", "jewelry", "join", "judicial", "juice", "jump", "junction",
+	"junior", "junk", "jury", "justice", "kernel", "keyboard", "kidney", "kind", "kitchen",
+	"knife", "knit", "laden", "ladle", "ladybug", "lair", "lamp", "language", "large", "laser",
+	"laundry", "lawsuit", "leader", "leaf", "learn", "leaves", "lecture", "legal", "legend",
+	"legs", "lend", "length", "level", "liberty", "library", "license", "lift", "likely", "lilac",
+	"lily", "lips", "liquid", "listen", "literary", "living", "lizard", "loan", "lobe", "location",
+	"losing", "loud", "loyalty", "luck", "lunar", "lunch", "lungs", "luxury", "lying", "lyrics",
+	"machine", "magazine", "maiden", "mailman", "main", "makeup", "making", "mama", "manager",
+	"mandate", "mansion", "manual", "marathon", "march", "market", "marvel", "mason", "material",
+	"math", "maximum", "mayor", "meaning", "medal", "medical", "member", "memory", "mental",
+	"merchant", "merit", "method", "metric", "midst", "mild", "military", "mineral", "minister",
+	"miracle", "mixed", "mixture", "mobile", "modern", "modify", "moisture", "moment", "morning",
+	"mortgage", "mother", "mountain", "mouse", "move", "much", "mule", "multiple", "muscle",
+	"museum", "music", "mustang", "nail", "national", "necklace", "negative", "nervous", "network",
+	"news", "nuclear", "numb", "numerous", "nylon", "oasis", "obesity", "object", "observe",
+	"obtain", "ocean", "often", "olympic", "omit", "oral", "orange", "orbit", "order", "ordinary",
+	"organize", "ounce", "oven", "overall", "owner", "paces", "pacific", "package", "paid",
+	"painting", "pajamas", "pancake", "pants", "papa", "paper", "parcel", "parking", "party",
+	"patent", "patrol", "payment", "payroll", "peaceful", "peanut", "peasant", "pecan", "penalty",
+	"pencil", "percent", "perfect", "permit", "petition", "phantom", "pharmacy", "photo", "phrase",
+	"physics", "pickup", "picture", "piece", "pile", "pink", "pipeline", "pistol", "pitch",
+	"plains", "plan", "plastic", "platform", "playoff", "pleasure", "plot", "plunge", "practice",
+	"prayer", "preach", "predator", "pregnant", "premium", "prepare", "presence", "prevent",
+	"priest", "primary", "priority", "prisoner", "privacy", "prize", "problem", "process",
+	"profile", "program", "promise", "prospect", "provide", "prune", "public", "pulse", "pumps",
+	"punish", "puny", "pupal", "purchase", "purple", "python", "quantity", "quarter", "quick",
+	"quiet", "race", "racism", "radar", "railroad", "rainbow", "raisin", "random", "ranked",
+	"rapids", "raspy", "reaction", "realize", "rebound", "rebuild", "recall", "receiver",
+	"recover", "regret", "regular", "reject", "relate", "remember", "remind", "remove", "render",
+	"repair", "repeat", "replace", "require", "rescue", "research", "resident", "response",
+	"result", "retailer", "retreat", "reunion", "revenue", "review", "reward", "rhyme", "rhythm",
+	"rich", "rival", "river", "robin", "rocky", "romantic", "romp", "roster", "round", "royal",
+	"ruin", "ruler", "rumor", "sack", "safari", "salary", "salon", "salt", "satisfy", "satoshi",
+	"saver", "says", "scandal", "scared", "scatter", "scene", "scholar", "science", "scout",
+	"scramble", "screw", "script", "scroll", "seafood", "season", "secret", "security", "segment",
+	"senior", "shadow", "shaft", "shame", "shaped", "sharp", "shelter", "sheriff", "short",
+	"should", "shrimp", "sidewalk", "silent", "silver", "similar", "simple", "single", "sister",
+	"skin", "skunk", "slap", "slavery", "sled", "slice", "slim", "slow", "slush", "smart", "smear",
+	"smell", "smirk", "smith", "smoking", "smug", "snake", "snapshot", "sniff", "society",
+	"software", "soldier", "solution", "soul", "source", "space", "spark", "speak", "species",
+	"spelling", "spend", "spew", "spider", "spill", "spine", "spirit", "spit", "spray", "sprinkle",
+	"square", "squeeze", "stadium", "staff", "standard", "starting", "station", "stay", "steady",
+	"step", "stick", "stilt", "story", "strategy", "strike", "style", "subject", "submit", "sugar",
+	"suitable", "sunlight", "superior", "surface", "surprise", "survive", "sweater", "swimming",
+	"swing", "switch", "symbolic", "sympathy", "syndrome", "system", "tackle", "tactics",
+	"tadpole", "talent", "task", "taste", "taught", "taxi", "teacher", "teammate", "teaspoon",
+	"temple", "tenant", "tendency", "tension", "terminal", "testify", "texture", "thank", "that",
+	"theater", "theory", "therapy", "thorn", "threaten", "thumb", "thunder", "ticket", "tidy",
+	"timber", "timely", "ting", "tofu", "together", "tolerate", "total", "toxic", "tracks",
+	"traffic", "training", "transfer", "trash", "traveler", "treat", "trend", "trial", "tricycle",
+	"trip", "triumph", "trouble", "true", "trust", "twice", "twin", "type", "typical", "ugly",
+	"ultimate", "umbrella", "uncover", "undergo", "unfair", "unfold", "unhappy", "union",
+	"universe", "unkind", "unknown", "unusual", "unwrap", "upgrade", "upstairs", "username",
+	"usher", "usual", "valid", "valuable", "vampire", "vanish", "various", "vegan", "velvet",
+	"venture", "verdict", "verify", "very", "veteran", "vexed", "victim", "video", "view",
+	"vintage", "violence", "viral", "visitor", "visual", "vitamins", "vocal", "voice", "volume",
+	"voter", "voting", "walnut", "warmth", "warn", "watch", "wavy", "wealthy", "weapon", "webcam",
+	"welcome", "welfare", "western", "width", "wildlife", "window", "wine", "wireless", "wisdom",
+	"withdraw", "wits", "wolf", "woman", "work", "worthy", "wrap", "wrist", "writing", "wrote",
+	"year", "yelp", "yield", "yoga", "zero",
+];
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use secp256k1::rand::thread_rng;
+
+	fn parse(mnemonics: &[&str]) -> Vec<Share> {
+		mnemonics.iter().map(|m| Share::from_mnemonic(m).unwrap()).collect()
+	}
+
+	// Test vectors from SLIP-39, all with the passphrase "TREZOR".
+
+	#[test]
+	fn test_vector_single_share() {
+		let share = Share::from_mnemonic("duckling enlarge academic academic agency result length \
+			solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard").unwrap();
+		assert!(!share.extendable);
+		assert_eq!(hex::encode(&combine(std::slice::from_ref(&share), "TREZOR").unwrap()), "bb54aac4b89dc868ba37d9cc21b2cece");
+		assert_eq!(Share::from_mnemonic(&share.to_mnemonic()).unwrap(), share);
+		assert!(Share::from_mnemonic("duckling enlarge academic academic agency result length \
+			solution fridge kidney coal piece deal husband erode duke ajar critical decision kidney").is_err());
+	}
+
+	#[test]
+	fn test_vector_two_of_three() {
+		let shares = parse(&[
+			"shadow pistol academic always adequate wildlife fancy gross oasis cylinder mustang wrist \
+				rescue view short owner flip making coding armed",
+			"shadow pistol academic acid actress prayer class unknown daughter sweater depict flip \
+				twice unkind craft early superior advocate guest smoking",
+		]);
+		assert_eq!(hex::encode(&combine(&shares, "TREZOR").unwrap()), "b43ceb7e57a0ea8766221624d01b0864");
+		assert_eq!(hex::encode(&combine(&[shares[1].clone(), shares[0].clone()], "TREZOR").unwrap()),
+			"b43ceb7e57a0ea8766221624d01b0864");
+		assert!(combine(&shares[..1], "TREZOR").is_err());
+	}
+
+	#[test]
+	fn test_vector_groups() {
+		// Two of the four groups, with 3 and 2 shares.
+		let shares = parse(&[
+			"eraser senior decision roster beard treat identify grumpy salt index fake aviation \
+				theater cubic bike cause research dragon emphasis counter",
+			"eraser senior ceramic snake clay various huge numb argue hesitate auction category \
+				timber browser greatest hanger petition script leaf pickup",
+			"eraser senior ceramic shaft dynamic become junior wrist silver peasant force math alto \
+				coal amazing segment yelp velvet image paces",
+			"eraser senior ceramic round column hawk trust auction smug shame alive greatest sheriff \
+				living perfect corner chest sled fumes adequate",
+			"eraser senior decision smug corner ruin rescue cubic angel tackle skin skunk program \
+				roster trash rumor slush angel flea amazing",
+		]);
+		assert_eq!(hex::encode(&combine(&shares, "TREZOR").unwrap()), "7c3397a292a5941682d7a4ae2d898d11");
+		assert_eq!(
+			combine(&shares[..4], "TREZOR").unwrap_err(),
+			"need shares of 2 complete groups, only have 1",
+		);
+	}
+
+	#[test]
+	fn test_split_combine() {
+		let secret = (0u8..32).collect::<Vec<_>>();
+		let groups = split(&secret, "pass", 2, &[(2, 3), (1, 1), (3, 5)], true, 0, &mut thread_rng()).unwrap();
+		let shares = |g: usize, idx: &[usize]| {
+			idx.iter().map(|i| Share::from_mnemonic(&groups.groups[g].shares[*i]).unwrap()).collect::<Vec<_>>()
+		};
+		let mut two_groups = shares(0, &[2, 0]);
+		two_groups.extend(shares(2, &[4, 1, 3]));
+		assert_eq!(combine(&two_groups, "pass").unwrap(), secret);
+		assert_ne!(combine(&two_groups, "other").unwrap(), secret);
+
+		let mut with_single = shares(1, &[0]);
+		with_single.extend(shares(0, &[1, 2]));
+		assert_eq!(combine(&with_single, "pass").unwrap(), secret);
+
+		// Not enough members in the second group.
+		let mut incomplete = shares(0, &[0, 1]);
+		incomplete.extend(shares(2, &[0, 1]));
+		assert_eq!(
+			combine(&incomplete, "pass").unwrap_err(),
+			"need shares of 2 complete groups, only have 1",
+		);
+	}
+
+	#[test]
+	fn test_combine_conflicting_shares() {
+		let secret = [0x42u8; 16];
+		let groups = split(&secret, "", 1, &[(2, 3)], false, 0, &mut thread_rng()).unwrap();
+		let mut shares = groups.groups[0].shares.iter().map(|m| Share::from_mnemonic(m).unwrap())
+			.collect::<Vec<_>>();
+		// The same share twice is fine.
+		assert_eq!(combine(&[shares[0].clone(), shares[0].clone(), shares[1].clone()], "").unwrap(), secret);
+
+		shares[2].member_index = shares[0].member_index;
+		assert_eq!(
+			combine(&[shares[0].clone(), shares[2].clone()], "").unwrap_err(),
+			format!("conflicting shares with member index {} in group 0", shares[0].member_index),
+		);
+	}
+}
@@ -0,0 +1,374 @@
+use std::str::FromStr;
+
+use bitcoin::taproot::{
+	LeafVersion, TapLeafHash, TapNodeHash, TapTweakHash, TaprootBuilder, TaprootSpendInfo,
+};
+use bitcoin::key::{Keypair, TapTweak};
+use bitcoin::secp256k1::{self, Parity};
+use bitcoin::{address, Address, Network, PrivateKey, ScriptBuf, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+
+use crate::{HexBytes, SECP};
+
+lazy_static! {
+	/// The H point as used in BIP-341 which is constructed by taking the hash
+	/// of the standard uncompressed encoding of the secp256k1 base point G as
+	/// X coordinate.
+	///
+	/// See: https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki#constructing-and-spending-taproot-outputs
+	pub static ref NUMS_H: secp256k1::PublicKey = secp256k1::PublicKey::from_str(
+		"0250929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0"
+	).unwrap();
+}
+
+/// Create a NUMS point H + r*G from the given entropy r.
+pub fn nums(entropy: &secp256k1::Scalar) -> Result<secp256k1::PublicKey, String> {
+	NUMS_H.add_exp_tweak(&SECP, entropy).map_err(|e| format!("invalid NUMS entropy: {}", e))
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct TapLeafInfo {
+	pub script: HexBytes,
+	pub script_asm: String,
+	pub leaf_version: u8,
+	pub leaf_hash: TapLeafHash,
+	pub depth: usize,
+	/// The control block to spend this leaf, as used in the witness
+	/// and as the key for PSBT tap_scripts.
+	pub control_block: HexBytes,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct TaprootInfo {
+	pub internal_key: XOnlyPublicKey,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub merkle_root: Option<TapNodeHash>,
+	pub tweak: TapTweakHash,
+	pub output_key: XOnlyPublicKey,
+	pub output_key_parity: u8,
+	pub script_pub_key: HexBytes,
+	pub address: Address<address::NetworkUnchecked>,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub leaves: Vec<TapLeafInfo>,
+}
+
+impl TaprootInfo {
+	/// Describe the taproot output of the given spend info.
+	///
+	/// The leaves are shown in the order of the provided scripts.
+	pub fn from_spend_info(
+		info: &TaprootSpendInfo,
+		scripts: &[ScriptBuf],
+		network: Network,
+	) -> TaprootInfo {
+		let address = Address::p2tr_tweaked(info.output_key(), network);
+		TaprootInfo {
+			internal_key: info.internal_key(),
+			merkle_root: info.merkle_root(),
+			tweak: info.tap_tweak(),
+			output_key: info.output_key().to_inner(),
+			output_key_parity: info.output_key_parity().to_u8(),
+			script_pub_key: address.script_pubkey().to_bytes().into(),
+			address: address.as_unchecked().clone(),
+			leaves: scripts.iter().map(|script| {
+				let ver = LeafVersion::TapScript;
+				let cb = info.control_block(&(script.clone(), ver))
+					.expect("script not in tree");
+				TapLeafInfo {
+					script: script.to_bytes().into(),
+					script_asm: script.to_asm_string(),
+					leaf_version: ver.to_consensus(),
+					leaf_hash: TapLeafHash::from_script(&script, ver),
+					depth: cb.merkle_branch.len(),
+					control_block: cb.serialize().into(),
+				}
+			}).collect(),
+		}
+	}
+}
+
+/// Parse a tap tree expression into a list of leaves with their depth in DFS order.
+///
+/// The format is the one used in `tr()` descriptors, with hex scripts as leaves:
+/// `{A,{B,C}}`, where A, B and C are scripts in hex.
+pub fn parse_tree_expression(s: &str) -> Result<Vec<(u8, ScriptBuf)>, String> {
+	fn parse_node<'a>(
+		s: &'a str,
+		depth: u8,
+		leaves: &mut Vec<(u8, ScriptBuf)>,
+	) -> Result<&'a str, String> {
+		let s = s.trim_start();
+		if let Some(rest) = s.strip_prefix('{') {
+			if depth == 128 {
+				return Err("tree too deep".to_owned());
+			}
+			let rest = parse_node(rest, depth + 1, leaves)?.trim_start();
+			let rest = rest.strip_prefix(',').ok_or("expected ',' in tree expression")?;
+			let rest = parse_node(rest, depth + 1, leaves)?.trim_start();
+			let rest = rest.strip_prefix('}').ok_or("expected '}' in tree expression")?;
+			Ok(rest)
+		} else {
+			let end = s.find([',', '}']).unwrap_or(s.len());
+			let hex = s[..end].trim();
+			if hex.is_empty() {
+				return Err("empty leaf in tree expression".to_owned());
+			}
+			let script = hex::decode(hex).map_err(|e| format!("invalid leaf script hex: {}", e))?;
+			leaves.push((depth, script.into()));
+			Ok(&s[end..])
+		}
+	}
+
+	let mut leaves = Vec::new();
+	let rest = parse_node(s, 0, &mut leaves)?;
+	if !rest.trim().is_empty() {
+		return Err(format!("trailing characters in tree expression: {}", rest));
+	}
+	Ok(leaves)
+}
+
+/// Parse a leaf with a weight, formatted as `<weight>:<script hex>`.
+pub fn parse_weighted_leaf(s: &str) -> Result<(u32, ScriptBuf), String> {
+	let mut parts = s.splitn(2, ':');
+	let weight = parts.next().unwrap().parse::<u32>()
+		.map_err(|e| format!("invalid tap leaf weight: {}", e))?;
+	let hex = parts.next().ok_or("tap leaves should be formatted as <weight>:<hex>")?;
+	let script = hex::decode(hex).map_err(|e| format!("invalid tap leaf script hex: {}", e))?;
+	Ok((weight, script.into()))
+}
+
+/// Build the script tree of a tap tree expression.
+///
+/// Returns the spend info and the leaf scripts in the order of the expression.
+pub fn tree_from_expression(
+	expr: &str,
+	internal_key: XOnlyPublicKey,
+) -> Result<(TaprootSpendInfo, Vec<ScriptBuf>), String> {
+	let leaves = parse_tree_expression(expr)?;
+	let mut builder = TaprootBuilder::new();
+	for (depth, script) in leaves.iter() {
+		builder = builder.add_leaf(*depth, script.clone())
+			.map_err(|e| format!("invalid tap tree: {}", e))?;
+	}
+	let info = builder.finalize(&SECP, internal_key).map_err(|_| "incomplete tap tree".to_owned())?;
+	Ok((info, leaves.into_iter().map(|(_, s)| s).collect()))
+}
+
+/// Build a script tree with the leaves placed by their weight, like a Huffman tree.
+///
+/// Returns the spend info and the leaf scripts in the given order.
+pub fn tree_from_weights(
+	leaves: Vec<(u32, ScriptBuf)>,
+	internal_key: XOnlyPublicKey,
+) -> Result<(TaprootSpendInfo, Vec<ScriptBuf>), String> {
+	let scripts = leaves.iter().map(|(_, s)| s.clone()).collect();
+	let builder = TaprootBuilder::with_huffman_tree(leaves)
+		.map_err(|e| format!("invalid tap tree: {}", e))?;
+	let info = builder.finalize(&SECP, internal_key).map_err(|_| "incomplete tap tree".to_owned())?;
+	Ok((info, scripts))
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct TweakedPrivateKeyInfo {
+	pub internal_key: XOnlyPublicKey,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub merkle_root: Option<TapNodeHash>,
+	pub tweak: TapTweakHash,
+	/// The tweaked private key to sign for the output key with.
+	pub tweaked_private_key: HexBytes,
+	pub tweaked_wif_private_key: PrivateKey,
+	pub output_key: XOnlyPublicKey,
+	pub output_key_parity: u8,
+}
+
+/// Tweak a private key for key-path spending of the output with the given merkle root.
+pub fn tweak_private_key(
+	privkey: &PrivateKey,
+	merkle_root: Option<TapNodeHash>,
+) -> TweakedPrivateKeyInfo {
+	let keypair = Keypair::from_secret_key(&SECP, &privkey.inner);
+	let internal_key = keypair.x_only_public_key().0;
+	let tweaked = keypair.tap_tweak(&SECP, merkle_root).to_inner();
+	let (output_key, parity) = tweaked.x_only_public_key();
+	TweakedPrivateKeyInfo {
+		internal_key: internal_key,
+		merkle_root: merkle_root,
+		tweak: TapTweakHash::from_key_and_tweak(internal_key, merkle_root),
+		tweaked_private_key: tweaked.secret_bytes()[..].into(),
+		tweaked_wif_private_key: PrivateKey {
+			compressed: true,
+			network: privkey.network,
+			inner: tweaked.secret_key(),
+		},
+		output_key: output_key,
+		output_key_parity: parity.to_u8(),
+	}
+}
+
+/// Check that the output key commits to the internal key and merkle root.
+///
+/// If a parity is given, it's checked as well.
+pub fn check_commitment(
+	output_key: &XOnlyPublicKey,
+	parity: Option<Parity>,
+	internal_key: &XOnlyPublicKey,
+	merkle_root: Option<TapNodeHash>,
+) -> bool {
+	let (tweaked, tweaked_parity) = internal_key.tap_tweak(&SECP, merkle_root);
+	tweaked.to_inner() == *output_key && parity.map(|p| p == tweaked_parity).unwrap_or(true)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use bitcoin::hashes::Hash;
+	use bitcoin::secp256k1::PublicKey;
+
+	#[test]
+	fn test_tweak() {
+		// From the BIP-341 wallet test vectors.
+		let internal = XOnlyPublicKey::from_str(
+			"d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d",
+		).unwrap();
+		let output = XOnlyPublicKey::from_str(
+			"53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343",
+		).unwrap();
+		assert_eq!(
+			TapTweakHash::from_key_and_tweak(internal, None).to_string(),
+			"b86e7be8f39bab32a6f2c0443abbc210f0edac0e2c53d501b36b64437d9c6c70",
+		);
+		assert!(check_commitment(&output, None, &internal, None));
+		assert!(!check_commitment(&internal, None, &internal, None));
+
+		let privkey = PrivateKey::from_slice(&[0x01; 32], Network::Bitcoin).unwrap();
+		let root = TapNodeHash::from_byte_array([0x02; 32]);
+		let info = tweak_private_key(&privkey, Some(root));
+		let parity = Parity::from_u8(info.output_key_parity).unwrap();
+		assert!(check_commitment(&info.output_key, Some(parity), &info.internal_key, Some(root)));
+		assert!(!check_commitment(&info.output_key, Some(parity), &info.internal_key, None));
+		assert_eq!(
+			PublicKey::from_secret_key(&SECP, &info.tweaked_wif_private_key.inner).x_only_public_key().0,
+			info.output_key,
+		);
+	}
+
+	#[test]
+	fn test_parse_tree_expression() {
+		let leaf = |b: u8| ScriptBuf::from(vec![b]);
+		assert_eq!(parse_tree_expression("51").unwrap(), vec![(0, leaf(0x51))]);
+		assert_eq!(
+			parse_tree_expression("{51,{52,53}}").unwrap(),
+			vec![(1, leaf(0x51)), (2, leaf(0x52)), (2, leaf(0x53))],
+		);
+		assert_eq!(
+			parse_tree_expression(" { {51, 52} , {53,{54,55}} } ").unwrap(),
+			vec![(2, leaf(0x51)), (2, leaf(0x52)), (2, leaf(0x53)), (3, leaf(0x54)), (3, leaf(0x55))],
+		);
+
+		let deep = format!("{}51{}", "{51,".repeat(128), "}".repeat(128));
+		assert_eq!(parse_tree_expression(&deep).unwrap().len(), 129);
+		let too_deep = format!("{}51{}", "{51,".repeat(129), "}".repeat(129));
+		assert_eq!(parse_tree_expression(&too_deep).unwrap_err(), "tree too deep");
+	}
+
+	#[test]
+	fn test_parse_tree_expression_malformed() {
+		assert_eq!(parse_tree_expression("{51}").unwrap_err(), "expected ',' in tree expression");
+		assert_eq!(parse_tree_expression("{51,52").unwrap_err(), "expected '}' in tree expression");
+		assert_eq!(parse_tree_expression("{51,52,53}").unwrap_err(), "expected '}' in tree expression");
+		assert_eq!(parse_tree_expression("{,52}").unwrap_err(), "empty leaf in tree expression");
+		assert_eq!(parse_tree_expression("").unwrap_err(), "empty leaf in tree expression");
+		assert_eq!(
+			parse_tree_expression("{51,52}}").unwrap_err(),
+			"trailing characters in tree expression: }",
+		);
+		assert!(parse_tree_expression("{51,zz}").unwrap_err().starts_with("invalid leaf script hex"));
+		assert!(parse_tree_expression("{51,5}").unwrap_err().starts_with("invalid leaf script hex"));
+	}
+
+	#[test]
+	fn test_from_spend_info() {
+		// The second scriptPubKey of the BIP-341 wallet test vectors.
+		let internal = XOnlyPublicKey::from_str(
+			"187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27",
+		).unwrap();
+		let expr = "20d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8ac";
+		let (spend_info, scripts) = tree_from_expression(expr, internal).unwrap();
+		let info = TaprootInfo::from_spend_info(&spend_info, &scripts, Network::Bitcoin);
+		assert_eq!(
+			info.merkle_root.unwrap().to_string(),
+			"5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21",
+		);
+		assert_eq!(
+			info.tweak.to_string(),
+			"cbd8679ba636c1110ea247542cfbd964131a6be84f873f7f3b62a777528ed001",
+		);
+		assert_eq!(
+			info.output_key.to_string(),
+			"147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3",
+		);
+		assert_eq!(
+			info.script_pub_key.hex(),
+			"5120147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3",
+		);
+		assert_eq!(
+			info.address.assume_checked_ref().to_string(),
+			"bc1pz37fc4cn9ah8anwm4xqqhvxygjf9rjf2resrw8h8w4tmvcs0863sa2e586",
+		);
+		assert_eq!(info.leaves.len(), 1);
+		assert_eq!(info.leaves[0].leaf_hash.to_string(), info.merkle_root.unwrap().to_string());
+		assert_eq!(
+			info.leaves[0].control_block.hex(),
+			"c1187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27",
+		);
+	}
+
+	#[test]
+	fn test_from_spend_info_leaves() {
+		let internal = NUMS_H.x_only_public_key().0;
+		let (spend_info, scripts) = tree_from_expression("{51,{52,53}}", internal).unwrap();
+		let info = TaprootInfo::from_spend_info(&spend_info, &scripts, Network::Bitcoin);
+		assert_eq!(info.leaves.iter().map(|l| l.depth).collect::<Vec<_>>(), vec![1, 2, 2]);
+		for (leaf, script) in info.leaves.iter().zip(scripts.iter()) {
+			assert_eq!(leaf.script.hex(), hex::encode(script.as_bytes()));
+			let cb = bitcoin::taproot::ControlBlock::decode(&leaf.control_block.0).unwrap();
+			assert!(cb.verify_taproot_commitment(&SECP, info.output_key, script));
+		}
+
+		let key_only = TaprootSpendInfo::new_key_spend(&SECP, internal, None);
+		let info = TaprootInfo::from_spend_info(&key_only, &[], Network::Bitcoin);
+		assert_eq!((info.merkle_root, info.leaves.len()), (None, 0));
+	}
+
+	#[test]
+	fn test_nums() {
+		assert_eq!(
+			NUMS_H.x_only_public_key().0.to_string(),
+			"50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0",
+		);
+		let one = secp256k1::Scalar::from_be_bytes({
+			let mut b = [0u8; 32];
+			b[31] = 1;
+			b
+		}).unwrap();
+		let g = PublicKey::from_secret_key(&SECP, &secp256k1::SecretKey::from_slice(&one.to_be_bytes()).unwrap());
+		assert_eq!(nums(&one).unwrap(), NUMS_H.combine(&g).unwrap());
+	}
+
+	#[test]
+	fn test_tree_from_weights() {
+		let leaf = |b: u8| ScriptBuf::from(vec![b]);
+		let leaves = ["3:51", "1:52", "1:53"].iter().map(|l| parse_weighted_leaf(l).unwrap()).collect::<Vec<_>>();
+		assert_eq!(leaves[0], (3, leaf(0x51)));
+		assert!(parse_weighted_leaf("51").is_err());
+		assert!(parse_weighted_leaf("x:51").is_err());
+
+		// The heaviest leaf is placed at depth 1, like {51,{52,53}}.
+		let internal = NUMS_H.x_only_public_key().0;
+		let (info, scripts) = tree_from_weights(leaves, internal).unwrap();
+		assert_eq!(scripts, vec![leaf(0x51), leaf(0x52), leaf(0x53)]);
+		let (expected, _) = tree_from_expression("{51,{52,53}}", internal).unwrap();
+		assert_eq!(info.merkle_root(), expected.merkle_root());
+		assert!(tree_from_weights(vec![], internal).is_err());
+	}
+}
@@ -0,0 +1,271 @@
+use bitcoin::bip32::{self, ChildNumber, DerivationPath, Fingerprint};
+use bitcoin::NetworkKind;
+use serde::{Deserialize, Serialize};
+
+use crate::bip32::{format_key_origin, ScriptType, SLIP132_VERSIONS};
+use crate::SECP;
+
+/// The standard account types.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub enum AccountType {
+	/// BIP-44 legacy p2pkh.
+	#[serde(rename = "bip44")]
+	Bip44,
+	/// BIP-49 p2sh-wrapped p2wpkh.
+	#[serde(rename = "bip49")]
+	Bip49,
+	/// BIP-84 native segwit p2wpkh.
+	#[serde(rename = "bip84")]
+	Bip84,
+	/// BIP-86 single-key p2tr.
+	#[serde(rename = "bip86")]
+	Bip86,
+	/// BIP-48 multisig p2sh-wrapped p2wsh, script type 1'.
+	#[serde(rename = "bip48-p2sh-p2wsh")]
+	Bip48P2shP2wsh,
+	/// BIP-48 multisig native segwit p2wsh, script type 2'.
+	#[serde(rename = "bip48-p2wsh")]
+	Bip48P2wsh,
+}
+
+impl AccountType {
+	pub const ALL: &'static [AccountType] = &[
+		AccountType::Bip44,
+		AccountType::Bip49,
+		AccountType::Bip84,
+		AccountType::Bip86,
+		AccountType::Bip48P2shP2wsh,
+		AccountType::Bip48P2wsh,
+	];
+
+	/// The derivation path of the account.
+	pub fn path(self, network: NetworkKind, account: u32) -> Result<DerivationPath, bip32::Error> {
+		let coin = match network {
+			NetworkKind::Main => 0,
+			NetworkKind::Test => 1,
+		};
+		let mut path = vec![
+			ChildNumber::from_hardened_idx(self.purpose())?,
+			ChildNumber::from_hardened_idx(coin)?,
+			ChildNumber::from_hardened_idx(account)?,
+		];
+		match self {
+			AccountType::Bip48P2shP2wsh => path.push(ChildNumber::from_hardened_idx(1)?),
+			AccountType::Bip48P2wsh => path.push(ChildNumber::from_hardened_idx(2)?),
+			_ => {}
+		}
+		Ok(path.into())
+	}
+
+	pub fn purpose(self) -> u32 {
+		match self {
+			AccountType::Bip44 => 44,
+			AccountType::Bip49 => 49,
+			AccountType::Bip84 => 84,
+			AccountType::Bip86 => 86,
+			AccountType::Bip48P2shP2wsh | AccountType::Bip48P2wsh => 48,
+		}
+	}
+
+	/// The SLIP-132 script type of the account's xpub, if any.
+	pub fn script_type(self) -> Option<ScriptType> {
+		match self {
+			AccountType::Bip44 | AccountType::Bip86 => None,
+			AccountType::Bip49 => Some(ScriptType::P2shP2wpkh),
+			AccountType::Bip84 => Some(ScriptType::P2wpkh),
+			AccountType::Bip48P2shP2wsh => Some(ScriptType::P2shP2wsh),
+			AccountType::Bip48P2wsh => Some(ScriptType::P2wsh),
+		}
+	}
+
+	/// Wrap a key expression in the single-key descriptor for this account type.
+	///
+	/// Multisig accounts don't have single-key descriptors.
+	pub fn descriptor(self, key: &str) -> Option<String> {
+		match self {
+			AccountType::Bip44 => Some(format!("pkh({})", key)),
+			AccountType::Bip49 => Some(format!("sh(wpkh({}))", key)),
+			AccountType::Bip84 => Some(format!("wpkh({})", key)),
+			AccountType::Bip86 => Some(format!("tr({})", key)),
+			AccountType::Bip48P2shP2wsh | AccountType::Bip48P2wsh => None,
+		}
+	}
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct AccountInfo {
+	#[serde(rename = "type")]
+	pub account_type: AccountType,
+	pub path: DerivationPath,
+	pub xpub: bip32::Xpub,
+	/// The account xpub in its SLIP-132 version, like zpub for BIP-84.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub slip132_xpub: Option<String>,
+	/// The key expression with its origin, to use in descriptors.
+	pub key: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub receive_descriptor: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub change_descriptor: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct WalletDescriptors {
+	#[serde(with = "crate::serde_utils::network_kind")]
+	pub network: NetworkKind,
+	pub master_fingerprint: Fingerprint,
+	pub accounts: Vec<AccountInfo>,
+}
+
+/// Derive the standard account keys and descriptors from a master key.
+///
+/// If private is set, the descriptors contain the account xpriv instead of the xpub.
+pub fn account_descriptors(
+	master: &bip32::Xpriv,
+	account: u32,
+	private: bool,
+) -> Result<WalletDescriptors, bip32::Error> {
+	let master_fingerprint = master.fingerprint(&SECP);
+	let accounts = AccountType::ALL.iter().map(|&ty| {
+		let path = ty.path(master.network, account)?;
+		let xpriv = master.derive_priv(&SECP, &path)?;
+		let xpub = bip32::Xpub::from_priv(&SECP, &xpriv);
+		let origin = format_key_origin(master_fingerprint, &path);
+		let key = if private {
+			format!("{}{}", origin, xpriv)
+		} else {
+			format!("{}{}", origin, xpub)
+		};
+		let slip132_xpub = ty.script_type().and_then(|st| {
+			SLIP132_VERSIONS.iter().find(|v| v.network == master.network && v.script_type == Some(st))
+		}).map(|v| v.encode_xpub(&xpub));
+		let descriptor = |chain: u32| {
+			ty.descriptor(&format!("{}/{}/*", key, chain)).map(|d| add_checksum(&d))
+		};
+		Ok(AccountInfo {
+			account_type: ty,
+			path: path,
+			xpub: xpub,
+			slip132_xpub: slip132_xpub,
+			receive_descriptor: descriptor(0),
+			change_descriptor: descriptor(1),
+			key: format!("{}/<0;1>/*", key),
+		})
+	}).collect::<Result<Vec<_>, bip32::Error>>()?;
+
+	Ok(WalletDescriptors {
+		network: master.network,
+		master_fingerprint: master_fingerprint,
+		accounts: accounts,
+	})
+}
+
+/// An entry of a Bitcoin Core `importdescriptors` request.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct ImportDescriptor {
+	pub desc: String,
+	pub active: bool,
+	pub internal: bool,
+	/// Either "now" or a UNIX timestamp.
+	pub timestamp: serde_json::Value,
+}
+
+impl WalletDescriptors {
+	/// The request for Bitcoin Core's `importdescriptors` RPC.
+	pub fn import_descriptors(&self, timestamp: serde_json::Value) -> Vec<ImportDescriptor> {
+		let mut ret = Vec::new();
+		for account in &self.accounts {
+			let descs = account.receive_descriptor.iter().map(|d| (d, false))
+				.chain(account.change_descriptor.iter().map(|d| (d, true)));
+			for (desc, internal) in descs {
+				ret.push(ImportDescriptor {
+					desc: desc.clone(),
+					active: true,
+					internal: internal,
+					timestamp: timestamp.clone(),
+				});
+			}
+		}
+		ret
+	}
+}
+
+const INPUT_CHARSET: &str =
+	"0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn polymod(c: u64, val: u64) -> u64 {
+	const GENERATOR: [u64; 5] = [0xf5dee51989, 0xa9fdca3312, 0x1bab10e32d, 0x3706b1677a, 0x644d626ffd];
+	let c0 = c >> 35;
+	let mut c = ((c & 0x7ffffffff) << 5) ^ val;
+	for (i, g) in GENERATOR.iter().enumerate() {
+		if c0 & (1 << i) != 0 {
+			c ^= g;
+		}
+	}
+	c
+}
+
+/// Calculate the BIP-380 checksum of a descriptor.
+pub fn descriptor_checksum(desc: &str) -> Result<String, String> {
+	let mut c = 1;
+	let mut cls = 0;
+	let mut clscount = 0;
+	for ch in desc.chars() {
+		let pos = INPUT_CHARSET.find(ch)
+			.ok_or_else(|| format!("invalid character in descriptor: {}", ch))? as u64;
+		c = polymod(c, pos & 31);
+		cls = cls * 3 + (pos >> 5);
+		clscount += 1;
+		if clscount == 3 {
+			c = polymod(c, cls);
+			cls = 0;
+			clscount = 0;
+		}
+	}
+	if clscount > 0 {
+		c = polymod(c, cls);
+	}
+	for _ in 0..8 {
+		c = polymod(c, 0);
+	}
+	c ^= 1;
+	Ok((0..8).map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char).collect())
+}
+
+/// Append the checksum to a descriptor we built ourselves.
+fn add_checksum(desc: &str) -> String {
+	format!("{}#{}", desc, descriptor_checksum(desc).expect("only valid characters"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::str::FromStr;
+
+	#[test]
+	fn test_descriptors() {
+		// From BIP-380.
+		assert_eq!(descriptor_checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+		assert!(descriptor_checksum("raw(deadbeef)\u{e9}").is_err());
+
+		// The "abandon ... about" mnemonic.
+		let master = bip32::Xpriv::from_str("xprv9s21ZrQH143K3GJpoapnV8SFfukcVBSfeCficPSGfubmSFDxo1kuHnLisriDvSnRRuL2Qrg5ggqHKNVpxR86QEC8w35uxmGoggxtQTPvfUu").unwrap();
+		let wallet = account_descriptors(&master, 0, false).unwrap();
+		assert_eq!(wallet.master_fingerprint.to_string(), "73c5da0a");
+		let bip84 = wallet.accounts.iter().find(|a| a.account_type == AccountType::Bip84).unwrap();
+		assert_eq!(bip84.path.to_string(), "84'/0'/0'");
+		assert_eq!(
+			bip84.slip132_xpub.as_ref().unwrap(),
+			"zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs",
+		);
+		let desc = bip84.receive_descriptor.as_ref().unwrap();
+		assert!(desc.starts_with("wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/0/*)#"));
+
+		let bip48 = wallet.accounts.iter().find(|a| a.account_type == AccountType::Bip48P2wsh).unwrap();
+		assert_eq!(bip48.path.to_string(), "48'/0'/0'/2'");
+		assert!(bip48.receive_descriptor.is_none());
+		assert_eq!(wallet.import_descriptors("now".into()).len(), 8);
+	}
+}